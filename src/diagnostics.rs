@@ -0,0 +1,139 @@
+use crate::config::ShellFormat;
+use crate::templates::SHELL_NIX_FILE;
+
+/**
+ * @struct Diagnosis
+ * @brief A recognized reason a shell failed to build, with the commands that fix it.
+ */
+#[derive(Debug)]
+pub struct Diagnosis {
+    /// @brief What went wrong, in one sentence.
+    pub problem: String,
+    /// @brief Commands to run, the most likely fix first.
+    pub fixes: Vec<String>,
+}
+
+/**
+ * @brief Recognizes common nix errors in the output of a failed shell build.
+ *
+ * Knows missing attributes, hash mismatches, unfree packages nixpkgs refuses to
+ * evaluate and files flakes can't see because git doesn't track them.
+ *
+ * @param stderr What nix printed.
+ * @param format Whether the project is built from flake.nix or shell.nix.
+ * @return The diagnoses, empty if nothing was recognized.
+ */
+pub fn diagnose(stderr: &str, format: ShellFormat) -> Vec<Diagnosis> {
+    let mut found = Vec::new();
+
+    let missing = [
+        "cannot find attribute",
+        "error: attribute",
+        "undefined variable",
+    ]
+    .iter()
+    .find_map(|marker| quoted_after(stderr, marker));
+    if let Some(attr) = missing {
+        found.push(Diagnosis {
+            problem: format!(
+                "the package '{}' doesn't exist in the project's nixpkgs",
+                attr
+            ),
+            fixes: vec![
+                format!("nix-shell-gen search {}", attr),
+                format!("nix-shell-gen remove {}", attr),
+            ],
+        });
+    } else if let Some(attr) = quoted_after(stderr, "does not provide attribute") {
+        found.push(Diagnosis {
+            problem: format!("the flake has no output '{}'", attr),
+            fixes: vec!["nix-shell-gen show".to_string()],
+        });
+    }
+
+    if let Some(input) = quoted_after(stderr, "NAR hash mismatch in input") {
+        found.push(Diagnosis {
+            problem: format!(
+                "the source of {} changed since it was locked (hash mismatch)",
+                input
+            ),
+            fixes: vec!["nix-shell-gen update".to_string()],
+        });
+    } else if stderr.contains("hash mismatch in fixed-output derivation") {
+        let got = stderr
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("got:"))
+            .map(str::trim);
+        let mut fixes = Vec::new();
+        if format == ShellFormat::ShellNix {
+            fixes.push(format!(
+                "delete the sha256 line in {} and run `nix-shell-gen sync`",
+                SHELL_NIX_FILE
+            ));
+        }
+        if let Some(got) = got {
+            fixes.push(format!(
+                "replace the hash in your package expression with {}",
+                got
+            ));
+        }
+        found.push(Diagnosis {
+            problem: "a download didn't match its expected hash".to_string(),
+            fixes,
+        });
+    }
+
+    if stderr.contains("has an unfree license")
+        && let Some(package) = quoted_after(stderr, "Package")
+    {
+        let name = strip_version(package);
+        found.push(Diagnosis {
+            problem: format!("nixpkgs refuses to evaluate the unfree package '{}'", name),
+            fixes: vec![
+                format!("nix-shell-gen remove {}", name),
+                "NIXPKGS_ALLOW_UNFREE=1 nix develop --impure".to_string(),
+            ],
+        });
+    }
+
+    let untracked = quoted_after(stderr, "error: Path")
+        .filter(|_| stderr.contains("is not tracked by Git"))
+        .map(str::to_string)
+        .or_else(|| {
+            // Untracked files are missing from the copy of the flake nix evaluates.
+            let path = quoted_after(stderr, "getting status of")?;
+            Some(path.split_once("-source/")?.1.to_string())
+        });
+    if let Some(path) = untracked {
+        found.push(Diagnosis {
+            problem: format!("git doesn't track {}, so the flake can't see it", path),
+            fixes: vec![format!("git add {}", path)],
+        });
+    }
+
+    found
+}
+
+/**
+ * @brief Returns the quoted text following a marker: 'x', ‘x’, `x' or "x".
+ * @param text The text to search.
+ * @param marker The words before the quotes.
+ */
+fn quoted_after<'a>(text: &'a str, marker: &str) -> Option<&'a str> {
+    let rest = &text[text.find(marker)? + marker.len()..];
+    let rest = rest.trim_start();
+    let open = rest.chars().next().filter(|c| "'‘`\"".contains(*c))?;
+    let rest = &rest[open.len_utf8()..];
+    let end = rest.find(['\'', '’', '"'])?;
+    Some(&rest[..end])
+}
+
+/**
+ * @brief Drops the version from a derivation name: "terraform-1.6.0" -> "terraform".
+ * @param name The name nix reported.
+ */
+fn strip_version(name: &str) -> &str {
+    name.match_indices('-')
+        .find(|(at, _)| name[at + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map_or(name, |(at, _)| &name[..at])
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeSet;
+
+use crate::config::DevShellConfig;
+
+/// @brief nixpkgs attributes that were renamed, as (old, new). The old names are
+/// aliases that warn, or are already gone, in current nixpkgs.
+pub const RENAMED_PACKAGES: &[(&str, &str)] = &[
+    ("nodejs-16_x", "nodejs_16"),
+    ("nodejs-18_x", "nodejs_18"),
+    ("nodejs-20_x", "nodejs_20"),
+    ("nodejs-slim-18_x", "nodejs-slim_18"),
+    ("nodejs-slim-20_x", "nodejs-slim_20"),
+    ("nixFlakes", "nixVersions.stable"),
+    ("exa", "eza"),
+    ("elixir_ls", "elixir-ls"),
+    ("python3Packages.pytorch", "python3Packages.torch"),
+    ("nodePackages.pnpm", "pnpm"),
+    ("nodePackages.yarn", "yarn"),
+    (
+        "nodePackages.typescript-language-server",
+        "typescript-language-server",
+    ),
+];
+
+/// @brief URLs of well-known flakes, by the input key they are usually declared under.
+pub const KNOWN_INPUTS: &[(&str, &str)] = &[
+    ("crane", "github:ipetkov/crane"),
+    ("devenv", "github:cachix/devenv"),
+    ("fenix", "github:nix-community/fenix"),
+    ("flake-utils", "github:numtide/flake-utils"),
+    ("naersk", "github:nix-community/naersk"),
+    ("poetry2nix", "github:nix-community/poetry2nix"),
+    ("pre-commit-hooks", "github:cachix/pre-commit-hooks.nix"),
+    ("rust-overlay", "github:oxalica/rust-overlay"),
+    ("zig-overlay", "github:mitchellh/zig-overlay"),
+];
+
+/// @brief Output sets whose presence after the first segment marks a package or overlay of an input.
+const INPUT_OUTPUTS: &[&str] = &["packages", "legacyPackages", "overlays"];
+
+/**
+ * @brief Looks up the URL of a well-known flake.
+ * @param key The input key.
+ */
+pub fn known_input_url(key: &str) -> Option<&'static str> {
+    KNOWN_INPUTS
+        .iter()
+        .find(|(known, _)| *known == key)
+        .map(|(_, url)| *url)
+}
+
+/**
+ * @brief Drops packages that are listed twice and so have no effect.
+ *
+ * Removes packages of the personal layer that the team config already has, and
+ * `[platform-packages]` entries that every platform gets anyway.
+ *
+ * @param team The team config.
+ * @param local The personal layer.
+ * @return What was removed, one description per package; empty if nothing was.
+ */
+pub fn remove_duplicates(team: &mut DevShellConfig, local: &mut DevShellConfig) -> Vec<String> {
+    let mut removed = Vec::new();
+    let shared: Vec<String> = local
+        .packages
+        .intersection(&team.packages)
+        .cloned()
+        .collect();
+    for pkg in shared {
+        local.packages.remove(&pkg);
+        removed.push(format!(
+            "'{}' from the personal layer (the team config has it)",
+            pkg
+        ));
+    }
+    for config in [team, local] {
+        for (platform, packages) in &mut config.platform_packages {
+            let everywhere: Vec<String> =
+                packages.intersection(&config.packages).cloned().collect();
+            for pkg in everywhere {
+                packages.remove(&pkg);
+                removed.push(format!(
+                    "'{}' from the {} packages (every platform gets it)",
+                    pkg, platform
+                ));
+            }
+        }
+        config
+            .platform_packages
+            .retain(|_, packages| !packages.is_empty());
+    }
+    removed
+}
+
+/**
+ * @brief Replaces renamed nixpkgs attributes (see `RENAMED_PACKAGES`) with their new names.
+ * @param config The config to update.
+ * @return The renames made, as (old, new).
+ */
+pub fn rename_packages(config: &mut DevShellConfig) -> Vec<(String, String)> {
+    let mut renamed = Vec::new();
+    let lists = std::iter::once(&mut config.packages)
+        .chain(config.platform_packages.values_mut())
+        .chain(config.shells.values_mut().map(|shell| &mut shell.packages));
+    for packages in lists {
+        for (old, new) in RENAMED_PACKAGES {
+            if packages.remove(*old) {
+                packages.insert(new.to_string());
+                renamed.push((old.to_string(), new.to_string()));
+            }
+        }
+    }
+    renamed
+}
+
+/**
+ * @brief Lists the inputs the config's packages and overlays refer to.
+ * @details "crane.packages.${system}.default" and "rust-overlay.overlays.default" refer
+ * to "crane" and "rust-overlay".
+ * @param config The config to read.
+ */
+pub fn referenced_inputs(config: &DevShellConfig) -> BTreeSet<String> {
+    std::iter::once(&config.packages)
+        .chain(config.platform_packages.values())
+        .chain(config.shells.values().map(|shell| &shell.packages))
+        .flatten()
+        .chain(&config.overlays)
+        .filter_map(|path| {
+            let mut segments = path.split('.');
+            let head = segments.next()?;
+            segments
+                .next()
+                .is_some_and(|output| INPUT_OUTPUTS.contains(&output))
+                .then(|| head.to_string())
+        })
+        .collect()
+}
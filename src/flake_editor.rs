@@ -1,98 +1,1677 @@
-use rnix::ast::{AttrSet, AttrpathValue, HasEntry};
-use rnix::{Root, SyntaxNode, WalkEvent};
+use rnix::SyntaxKind::{NODE_ATTR_SET, TOKEN_COMMENT, TOKEN_WHITESPACE};
+use rnix::ast::{
+    Attr, AttrSet, Attrpath, AttrpathValue, Expr, HasEntry, Inherit, InterpolPart, LetIn, Param,
+    Select, Str,
+};
+use rnix::{NixLanguage, Root, SyntaxElement, SyntaxNode};
 use rowan::ast::AstNode;
+use rowan::{GreenNode, GreenToken, Language, NodeOrToken};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use crate::messages;
+use crate::nix_syntax;
 use crate::templates::FLAKE_FILE;
 
+/// @brief Lines of context shown around an edit in trace output.
+const TRACE_CONTEXT_LINES: usize = 2;
+
+/// @brief A node or token of a green tree, as spliced into a set.
+type GreenElement = NodeOrToken<GreenNode, GreenToken>;
+
+static TRACE: AtomicBool = AtomicBool::new(false);
+
 /**
- * @brief Safely adds a new input to the `flake.nix` file.
+ * @brief Turns edit tracing on for this run (the `--trace-edit` flag).
  *
- * This function parses the `flake.nix` file, locates the `inputs` attribute set,
+ * When on, every edit logs the syntax nodes it matched, the computed insertion
+ * offset and the text around the edit before and after, on stderr.
+ *
+ * @param enabled Whether to trace.
+ */
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+/**
+ * @brief Prints a trace line if tracing is on; the message is only built when needed.
+ * @param message Builds the message.
+ */
+fn trace(message: impl FnOnce() -> String) {
+    if TRACE.load(Ordering::Relaxed) {
+        eprintln!("trace-edit: {}", message());
+    }
+}
+
+/**
+ * @brief Safely adds a new input to the contents of a `flake.nix` file.
+ *
+ * This function parses the flake, locates the `inputs` attribute set,
  * checks if the specified input already exists, and if not, inserts the new input.
  *
+ * @param content The current contents of flake.nix.
  * @param key The key/name of the flake input to add.
  * @param url The URL of the flake input.
- * @return Result<(), Error> Returns Ok(()) on success, or an Error if the operation fails.
+ * @param is_flake False for plain sources, which get `flake = false;`.
+ * @param follows_nixpkgs Whether a flake input also gets `inputs.nixpkgs.follows = "nixpkgs";`,
+ * so flake.lock doesn't hold a second nixpkgs for it.
+ * @return Result<Option<String>, Error> The updated contents, None if the input already exists,
+ * or an Error if the operation fails.
+ */
+pub fn add_flake_input(
+    content: &str,
+    key: &str,
+    url: &str,
+    is_flake: bool,
+    follows_nixpkgs: bool,
+) -> Result<Option<String>, Error> {
+    let target = find_inputs_target(content)?;
+
+    // Check if the input already exists (`key.url = ...` or `key = { ... }`)
+    if parse_flake_inputs(content)
+        .iter()
+        .any(|input| input.key == key)
+    {
+        trace(|| format!("input '{}' already declared; nothing to insert", key));
+        return Ok(None);
+    }
+
+    let new_input_text = if is_flake {
+        format!(
+            "{}.url = {};",
+            nix_syntax::attr_name(key),
+            nix_syntax::string(url)
+        )
+    } else {
+        format!(
+            "{} = {{ url = {}; flake = false; }};",
+            nix_syntax::attr_name(key),
+            nix_syntax::string(url)
+        )
+    };
+    let updated = target.insert(content, &new_input_text)?;
+    if is_flake && follows_nixpkgs && key != "nixpkgs" {
+        return Ok(Some(add_input_follows(&updated, key)?.unwrap_or(updated)));
+    }
+    Ok(Some(updated))
+}
+
+/**
+ * @brief Adds `<key>.inputs.nixpkgs.follows = "nixpkgs";` to the `inputs` set.
+ *
+ * @param content The current contents of flake.nix.
+ * @param key The key of the input that should reuse the top-level nixpkgs.
+ * @return Result<Option<String>, Error> The updated contents, or None if the input
+ * already follows nixpkgs.
+ */
+pub fn add_input_follows(content: &str, key: &str) -> Result<Option<String>, Error> {
+    add_follows(content, key, "nixpkgs")
+}
+
+/**
+ * @brief Adds `<key>.inputs.<dep>.follows = "<dep>";` to the `inputs` set, so an input
+ * reuses the top-level input of the same name instead of locking its own.
+ *
+ * @param content The current contents of flake.nix.
+ * @param key The key of the input whose dependency is redirected.
+ * @param dep The dependency, which must also be a top-level input.
+ * @return Result<Option<String>, Error> The updated contents, or None if the dependency
+ * already follows something.
+ */
+pub fn add_follows(content: &str, key: &str, dep: &str) -> Result<Option<String>, Error> {
+    if parse_flake_inputs(content)
+        .iter()
+        .any(|input| input.key == key && input.follows.contains(dep))
+    {
+        trace(|| format!("input '{}' already follows {}; nothing to insert", key, dep));
+        return Ok(None);
+    }
+
+    let target = find_inputs_target(content)?;
+    let follows_text = format!(
+        "{}.inputs.{}.follows = {};",
+        nix_syntax::attr_name(key),
+        nix_syntax::attr_name(dep),
+        nix_syntax::string(dep)
+    );
+    target.insert(content, &follows_text).map(Some)
+}
+
+/**
+ * @brief Adds an attribute to the top-level attribute set of a flake.
+ *
+ * @param content The current contents of flake.nix.
+ * @param name The top-level attribute name (e.g. "nixConfig").
+ * @param entry The full entry text, including the trailing semicolon.
+ * @return Result<Option<String>, Error> The updated contents, or None if an attribute
+ * with the same name is already present.
+ */
+pub fn add_top_level_attr(content: &str, name: &str, entry: &str) -> Result<Option<String>, Error> {
+    let (top, _) = top_level_set(content, &parse(content)?)?;
+
+    if flatten_attrset(&top)
+        .iter()
+        .any(|(path, _)| path.first().is_some_and(|first| first == name))
+    {
+        trace(|| {
+            format!(
+                "top-level attribute '{}' already exists; nothing to insert",
+                name
+            )
+        });
+        return Ok(None);
+    }
+
+    insert_entry(content, &top, entry).map(Some)
+}
+
+/**
+ * @brief Removes an input from the contents of a `flake.nix` file.
+ *
+ * Every entry declaring it goes: `key.url = ...;` or `key = { ... };` inside
+ * `inputs` (inline or let-bound), and dotted `inputs.key... = ...;` entries at the
+ * top level. So do other inputs' `inputs.<dep>.follows` entries pointing at it,
+ * which would otherwise name a missing input, and `key` in the argument pattern
+ * of `outputs`. Each entry is cut with its indentation, a comment after it and its
+ * line break; comments on lines of their own are kept.
+ *
+ * @param content The current contents of flake.nix.
+ * @param key The key of the input to remove.
+ * @return Result<Option<String>, Error> The updated contents, or None if no such input is declared.
+ */
+pub fn remove_flake_input(content: &str, key: &str) -> Result<Option<String>, Error> {
+    let root = parse(content)?;
+    let (top, let_in) = top_level_set(content, &root)?;
+
+    let mut declared = Vec::new();
+    let mut follows = Vec::new();
+    collect_input_entries(&top, &[], key, &mut declared, &mut follows);
+    if let Some(set) = let_in
+        .as_ref()
+        .filter(|_| inherits_inputs(&top))
+        .and_then(|let_in| let_bound_set(let_in, "inputs"))
+    {
+        collect_input_entries(
+            &set,
+            &["inputs".to_string()],
+            key,
+            &mut declared,
+            &mut follows,
+        );
+    }
+    if declared.is_empty() {
+        trace(|| format!("input '{}' is not declared; nothing to remove", key));
+        return Ok(None);
+    }
+
+    let mut ranges: Vec<(usize, usize)> = declared
+        .iter()
+        .chain(&follows)
+        .map(|attr| entry_range(content, attr))
+        .collect();
+    ranges.extend(output_argument_range(content, &top, key));
+    ranges.sort_unstable();
+    // Entries sharing a line can claim the same spaces.
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let mut new_content = content.to_string();
+    for &(start, end) in merged.iter().rev() {
+        trace(|| {
+            format!(
+                "removing {:?} at {} (bytes {}..{})",
+                content[start..end].trim(),
+                location(content, start),
+                start,
+                end
+            )
+        });
+        new_content.replace_range(start..end, "");
+    }
+    trace(|| {
+        format!(
+            "removed input '{}' ({} entries, {} follows)",
+            key,
+            declared.len(),
+            follows.len()
+        )
+    });
+    Ok(Some(new_content))
+}
+
+/**
+ * @brief Finds the entries of a set that declare an input or follow it.
+ *
+ * Sets under `inputs` are descended into, so `inputs = { foo = { inputs.key.follows
+ * = "key"; }; }` is found as well as its dotted forms.
+ *
+ * @param set The set to search.
+ * @param prefix The attribute path of the set, e.g. ["inputs"] for the inputs set.
+ * @param key The input key.
+ * @param declared Collects the entries declaring the input.
+ * @param follows Collects other inputs' `follows` entries naming it.
+ */
+fn collect_input_entries(
+    set: &AttrSet,
+    prefix: &[String],
+    key: &str,
+    declared: &mut Vec<AttrpathValue>,
+    follows: &mut Vec<AttrpathValue>,
+) {
+    for attr in set.attrpath_values() {
+        let Some(names) = attr.attrpath().and_then(|p| attr_names(&p)) else {
+            continue;
+        };
+        let path: Vec<String> = prefix.iter().cloned().chain(names).collect();
+        if path.first().is_none_or(|first| first != "inputs") {
+            continue;
+        }
+        if path.get(1).is_some_and(|name| name == key) {
+            declared.push(attr);
+            continue;
+        }
+        match attr.value() {
+            // `follows = "key"` or `follows = "key/nixpkgs"`.
+            Some(value)
+                if path.last().is_some_and(|last| last == "follows")
+                    && string_literal(&value)
+                        .is_some_and(|target| target.split('/').next() == Some(key)) =>
+            {
+                follows.push(attr);
+            }
+            Some(Expr::AttrSet(nested)) if nested.rec_token().is_none() => {
+                collect_input_entries(&nested, &path, key, declared, follows);
+            }
+            _ => {}
+        }
+    }
+}
+
+/**
+ * @brief The bytes an entry takes up, with the whitespace and comment around it.
+ *
+ * An entry on a line of its own takes the whole line; one sharing its line takes
+ * the spaces after it, or before it when it ends the line.
+ *
+ * @param content The source text.
+ * @param attr The entry.
+ * @return (usize, usize) The start and end offsets.
+ */
+fn entry_range(content: &str, attr: &AttrpathValue) -> (usize, usize) {
+    let range = attr.syntax().text_range();
+    let (mut start, mut end): (usize, usize) = (range.start().into(), range.end().into());
+    let line_start = content[..start].rfind('\n').map_or(0, |pos| pos + 1);
+    let line_end = content[end..]
+        .find('\n')
+        .map_or(content.len(), |pos| end + pos);
+
+    let after = content[end..line_end].trim_start_matches([' ', '\t']);
+    let rest_is_trivia = after.trim_end().is_empty() || after.starts_with('#');
+    if rest_is_trivia && content[line_start..start].trim().is_empty() {
+        start = line_start;
+        end = (line_end + 1).min(content.len());
+    } else if rest_is_trivia {
+        start = content[..start].trim_end_matches([' ', '\t']).len();
+        end = content[..line_end].trim_end_matches('\r').len();
+    } else {
+        end = line_end - after.len();
+    }
+    (start, end)
+}
+
+/**
+ * @brief Finds `key` in the argument pattern of `outputs`, with the comma that separates it.
+ * @param content The source text.
+ * @param top The flake's top-level set.
+ * @param key The input key.
+ * @return Option<(usize, usize)> The range to cut, or None if the pattern doesn't name it.
+ */
+fn output_argument_range(content: &str, top: &AttrSet, key: &str) -> Option<(usize, usize)> {
+    let outputs = top.attrpath_values().find(|attr| {
+        attr.attrpath()
+            .and_then(|p| attr_names(&p))
+            .is_some_and(|path| path == ["outputs"])
+    })?;
+    let Some(Expr::Lambda(lambda)) = outputs.value().and_then(unparen) else {
+        return None;
+    };
+    let Some(Param::Pattern(pattern)) = lambda.param() else {
+        return None;
+    };
+    let entry = pattern
+        .pat_entries()
+        .find(|entry| entry.ident().is_some_and(|ident| ident.to_string() == key))?;
+    let range = entry.syntax().text_range();
+    let (start, end): (usize, usize) = (range.start().into(), range.end().into());
+
+    // Take the comma before it, or after it when it comes first.
+    let before = content[..start].trim_end();
+    if let Some(comma) = before.strip_suffix(',') {
+        return Some((comma.trim_end().len(), end));
+    }
+    let after = &content[end..];
+    let trimmed = after.trim_start();
+    match trimmed.strip_prefix(',') {
+        Some(rest) => Some((start, content.len() - rest.trim_start().len())),
+        None => Some((start, end)),
+    }
+}
+
+/**
+ * @struct FlakeInput
+ * @brief A flake input as declared in `flake.nix`.
  */
-pub fn add_flake_input(key: &str, url: &str) -> Result<(), Error> {
+#[derive(Debug, Default, Clone)]
+pub struct FlakeInput {
+    /// @brief The input key (e.g. "nixpkgs").
+    pub key: String,
+    /// @brief The input URL, if given as a plain string.
+    pub url: Option<String>,
+    /// @brief Whether the input declares `inputs.nixpkgs.follows`.
+    pub follows_nixpkgs: bool,
+    /// @brief The inputs of this input that follow another input (`inputs.<dep>.follows`).
+    pub follows: BTreeSet<String>,
+    /// @brief Whether the input is a plain source (`flake = false`).
+    pub non_flake: bool,
+}
+
+/**
+ * @brief Reads the inputs declared in `flake.nix` without modifying it.
+ *
+ * Understands both `inputs = { foo.url = ...; }` and `inputs.foo.url = ...` layouts.
+ *
+ * @return Result<Vec<FlakeInput>, Error> The inputs sorted by key.
+ */
+pub fn read_flake_inputs() -> Result<Vec<FlakeInput>, Error> {
     let content = fs::read_to_string(FLAKE_FILE)?;
-    let ast = Root::parse(&content);
-
-    // Find the `inputs` attribute set
-    let inputs_set_node = find_node(ast.syntax(), |node| {
-        if let Some(attr) = AttrpathValue::cast(node.clone()) {
-            // Use attrpath() instead of key()
-            if attr.attrpath()?.to_string().trim() == "inputs" {
-                // Unwrap Expr, then cast its syntax
-                if let Some(expr) = attr.value() {
-                    return AttrSet::cast(expr.syntax().clone());
-                }
+    Ok(parse_flake_inputs(&content))
+}
+
+/**
+ * @brief Extracts flake inputs from the given flake source.
+ *
+ * Besides inline `inputs`, this follows `inherit inputs;` to a binding in a
+ * `let` around the flake. Unparsable flakes yield no inputs.
+ *
+ * @param content The contents of a flake.nix file.
+ * @return The inputs sorted by key.
+ */
+pub fn parse_flake_inputs(content: &str) -> Vec<FlakeInput> {
+    let Ok(entries) = input_entries(content) else {
+        return Vec::new();
+    };
+
+    let mut inputs: BTreeMap<String, FlakeInput> = BTreeMap::new();
+    for (path, value) in entries {
+        if path.len() < 3 || path[0] != "inputs" {
+            continue;
+        }
+
+        let key = &path[1];
+        let input = inputs.entry(key.clone()).or_insert_with(|| FlakeInput {
+            key: key.clone(),
+            ..Default::default()
+        });
+
+        let rest: Vec<&str> = path[2..].iter().map(String::as_str).collect();
+        match rest.as_slice() {
+            ["url"] => input.url = string_literal(&value),
+            ["inputs", dep, "follows"] => {
+                input.follows_nixpkgs |= *dep == "nixpkgs";
+                input.follows.insert(dep.to_string());
             }
+            ["flake"] => input.non_flake = value.syntax().text() == "false",
+            _ => {}
         }
-        None
-    })
-    .ok_or_else(|| {
-        Error::new(
-            ErrorKind::NotFound,
-            "Could not find `inputs` set in flake.nix",
+    }
+
+    inputs.into_values().collect()
+}
+
+/**
+ * @brief Points an existing input at a new URL, keeping the rest of the file as is.
+ *
+ * @param content The current contents of flake.nix.
+ * @param key The input key.
+ * @param url The new URL.
+ * @return Result<String, Error> The updated contents, or NotFound if the input has no
+ * plain string `url` to replace.
+ */
+pub fn set_input_url(content: &str, key: &str, url: &str) -> Result<String, Error> {
+    let value = input_entries(content)?
+        .into_iter()
+        .find(|(path, value)| path == &["inputs", key, "url"] && string_literal(value).is_some())
+        .map(|(_, value)| value)
+        .ok_or_else(|| {
+            messages::error(
+                ErrorKind::NotFound,
+                messages::E_UNKNOWN_INPUT,
+                format!(
+                    "{}: input '{}' has no plain `url` string to replace; edit it by hand",
+                    FLAKE_FILE, key
+                ),
+            )
+        })?;
+
+    let range = value.syntax().text_range();
+    let (start, end): (usize, usize) = (range.start().into(), range.end().into());
+    let mut new_content = content.to_string();
+    new_content.replace_range(start..end, &nix_syntax::string(url));
+    trace(|| {
+        format!(
+            "replacing the url of '{}' at {} (bytes {}..{})\n  before:\n{}\n  after:\n{}",
+            key,
+            location(content, start),
+            start,
+            end,
+            context(content, start, end),
+            context(&new_content, start, start),
         )
-    })?;
+    });
+    Ok(new_content)
+}
+
+/**
+ * @brief Collects the flattened entries that can declare inputs.
+ *
+ * Covers the top-level set and, when it says `inherit inputs;`, the let-bound
+ * inputs set (whose paths are prefixed with "inputs").
+ *
+ * @param content The flake source.
+ * @return Result with the (attribute path, value) pairs.
+ */
+fn input_entries(content: &str) -> Result<Vec<(Vec<String>, Expr)>, Error> {
+    let root = parse(content)?;
+    let (top, let_in) = top_level_set(content, &root)?;
 
-    // Check if the input already exists
-    if inputs_set_node.entries().any(|entry| match entry {
-        rnix::ast::Entry::AttrpathValue(attr) => attr
-            .attrpath()
-            .map_or(false, |p| p.to_string().trim() == key),
+    let mut entries = flatten_attrset(&top);
+    if let Some(set) = let_in
+        .as_ref()
+        .filter(|_| inherits_inputs(&top))
+        .and_then(|let_in| let_bound_set(let_in, "inputs"))
+    {
+        entries.extend(flatten_attrset(&set).into_iter().map(|(mut path, value)| {
+            path.insert(0, "inputs".to_string());
+            (path, value)
+        }));
+    }
+    Ok(entries)
+}
+
+/**
+ * @brief Where new inputs are inserted.
+ */
+enum InputsTarget {
+    /// @brief Inside an `inputs = { ... }` set (inline or let-bound).
+    Set(AttrSet),
+    /// @brief The top-level set, as dotted `inputs.<key>...` entries.
+    Dotted(AttrSet),
+}
+
+impl InputsTarget {
+    /**
+     * @brief Inserts an input entry written relative to the inputs set.
+     * @param content The full flake source.
+     * @param entry The entry, e.g. `foo.url = "...";`.
+     */
+    fn insert(&self, content: &str, entry: &str) -> Result<String, Error> {
+        match self {
+            InputsTarget::Set(set) => insert_entry(content, set, entry),
+            InputsTarget::Dotted(top) => {
+                // Next to the other `inputs.` entries, or above `outputs` if there are none.
+                let path = |attr: &AttrpathValue| {
+                    attr.attrpath()
+                        .and_then(|p| attr_names(&p))
+                        .unwrap_or_default()
+                };
+                let placement = match top
+                    .attrpath_values()
+                    .filter(|attr| path(attr).first().is_some_and(|name| name == "inputs"))
+                    .last()
+                {
+                    Some(last) => Placement::After(last),
+                    None => top
+                        .attrpath_values()
+                        .find(|attr| path(attr) == ["outputs"])
+                        .map_or(Placement::End, Placement::Before),
+                };
+                insert_entry_at(content, top, placement, &format!("inputs.{}", entry))
+            }
+        }
+    }
+}
+
+/**
+ * @brief Checks that a string is a single Nix expression, for `expr-packages`.
+ *
+ * Only the syntax is checked; whether the names exist shows when the shell is built.
+ *
+ * @param expr The expression as the user wrote it.
+ * @return Result<(), Error> Returns InvalidInput naming the first syntax error.
+ */
+pub fn check_expression(expr: &str) -> Result<(), Error> {
+    let parsed = Root::parse(expr);
+    match parsed.errors().first() {
+        Some(error) => Err(messages::error(
+            ErrorKind::InvalidInput,
+            messages::E_INVALID_EXPRESSION,
+            format!("'{}' isn't a valid Nix expression ({}).", expr, error),
+        )),
+        None if expr.trim().is_empty() => Err(messages::error(
+            ErrorKind::InvalidInput,
+            messages::E_INVALID_EXPRESSION,
+            "An empty package expression does nothing.",
+        )),
+        None => Ok(()),
+    }
+}
+
+/**
+ * @brief Checks whether a flake applies the config's overlays to nixpkgs.
+ *
+ * Flakes generated since overlay support read `config.overlays`. The syntax tree
+ * is searched for that selection, so a comment or string mentioning overlays, or
+ * overlays of the flake's own, don't count.
+ *
+ * @param content The flake source.
+ */
+pub fn applies_overlays(content: &str) -> bool {
+    Root::parse(content)
+        .syntax()
+        .descendants()
+        .filter_map(Select::cast)
+        .any(|select| {
+            matches!(select.expr(), Some(Expr::Ident(ident)) if ident.to_string() == "config")
+                && select
+                    .attrpath()
+                    .and_then(|path| attr_names(&path))
+                    .is_some_and(|names| names == ["overlays"])
+        })
+}
+
+/**
+ * @brief Parses a flake, refusing sources with syntax errors.
+ *
+ * Editing a tree with error nodes could splice text at the wrong place, so
+ * any parse error stops the edit.
+ *
+ * @param content The flake source.
+ * @return Result<Root, Error> The syntax tree, or InvalidData naming the first error.
+ */
+fn parse(content: &str) -> Result<Root, Error> {
+    let parsed = Root::parse(content);
+    if let Some(error) = parsed.errors().first() {
+        return Err(messages::error(
+            ErrorKind::InvalidData,
+            messages::E_FLAKE_SYNTAX,
+            format!(
+                "{} has a syntax error ({}); fix it before editing",
+                FLAKE_FILE, error
+            ),
+        ));
+    }
+    Ok(parsed.tree())
+}
+
+/**
+ * @brief Returns the flake's top-level attribute set.
+ *
+ * Accepts `{ ... }`, `( ... )` and `let ... in { ... }`.
+ *
+ * @param content The flake source, for error locations.
+ * @param root The parsed flake.
+ * @return The set, and the surrounding `let` if there is one.
+ */
+fn top_level_set(content: &str, root: &Root) -> Result<(AttrSet, Option<LetIn>), Error> {
+    let mut expr = root.expr();
+    let mut let_in = None;
+    loop {
+        match expr {
+            Some(Expr::Paren(paren)) => expr = paren.expr(),
+            Some(Expr::LetIn(inner)) => {
+                expr = inner.body();
+                let_in = Some(inner);
+            }
+            Some(Expr::AttrSet(set)) => {
+                trace(|| {
+                    format!(
+                        "top-level set at {}{}",
+                        location(content, set.syntax().text_range().start().into()),
+                        if let_in.is_some() {
+                            " (inside let ... in)"
+                        } else {
+                            ""
+                        }
+                    )
+                });
+                return Ok((set, let_in));
+            }
+            Some(other) => {
+                return Err(messages::error(
+                    ErrorKind::InvalidData,
+                    messages::E_FLAKE_LAYOUT,
+                    format!(
+                        "{}: the flake at {} is not an attribute set, so it can't be edited automatically",
+                        FLAKE_FILE,
+                        location(content, other.syntax().text_range().start().into())
+                    ),
+                ));
+            }
+            None => {
+                return Err(messages::error(
+                    ErrorKind::InvalidData,
+                    messages::E_FLAKE_LAYOUT,
+                    format!("{} is empty", FLAKE_FILE),
+                ));
+            }
+        }
+    }
+}
+
+/**
+ * @brief Decides where inputs go, explaining precisely when they can't be edited.
+ * @param content The flake source.
+ * @return Result<InputsTarget, Error> The target, or InvalidData with the reason and location.
+ */
+fn find_inputs_target(content: &str) -> Result<InputsTarget, Error> {
+    let root = parse(content)?;
+    let (top, let_in) = top_level_set(content, &root)?;
+
+    for attr in top.attrpath_values() {
+        let Some(path) = attr.attrpath().and_then(|p| attr_names(&p)) else {
+            continue;
+        };
+        if path != ["inputs"] {
+            continue;
+        }
+        return match attr.value().map(unparen) {
+            Some(Some(Expr::AttrSet(set))) => {
+                trace(|| {
+                    format!(
+                        "matched `inputs = {{ ... }}` set at {}",
+                        location(content, set.syntax().text_range().start().into())
+                    )
+                });
+                Ok(InputsTarget::Set(set))
+            }
+            Some(Some(value)) => Err(messages::error(
+                ErrorKind::InvalidData,
+                messages::E_FLAKE_LAYOUT,
+                format!(
+                    "{}: `inputs` at {} is not a literal attribute set; add the input by hand",
+                    FLAKE_FILE,
+                    location(content, value.syntax().text_range().start().into())
+                ),
+            )),
+            _ => Err(messages::error(
+                ErrorKind::InvalidData,
+                messages::E_FLAKE_LAYOUT,
+                format!("{}: `inputs` has no value", FLAKE_FILE),
+            )),
+        };
+    }
+
+    if let Some(inherit) = top
+        .inherits()
+        .find(|inherit| inherits_name(inherit, "inputs"))
+    {
+        let position = location(content, inherit.syntax().text_range().start().into());
+        if inherit.from().is_some() {
+            return Err(messages::error(
+                ErrorKind::InvalidData,
+                messages::E_FLAKE_LAYOUT,
+                format!(
+                    "{}: `inputs` is inherited from another expression at {}; add the input where it is defined",
+                    FLAKE_FILE, position
+                ),
+            ));
+        }
+        return let_in
+            .as_ref()
+            .and_then(|let_in| let_bound_set(let_in, "inputs"))
+            .inspect(|set| {
+                trace(|| {
+                    format!(
+                        "matched `inherit inputs` at {}, bound to the `let` set at {}",
+                        position,
+                        location(content, set.syntax().text_range().start().into())
+                    )
+                })
+            })
+            .map(InputsTarget::Set)
+            .ok_or_else(|| {
+                messages::error(
+                    ErrorKind::InvalidData,
+                    messages::E_FLAKE_LAYOUT,
+                    format!(
+                        "{}: `inherit inputs` at {} doesn't refer to a `let` binding with a literal attribute set; add the input by hand",
+                        FLAKE_FILE, position
+                    ),
+                )
+            });
+    }
+
+    // `inputs.foo.url = ...` at the top level, or no inputs at all yet.
+    trace(|| {
+        "no `inputs` set; using dotted `inputs.<key>` entries in the top-level set".to_string()
+    });
+    Ok(InputsTarget::Dotted(top))
+}
+
+/**
+ * @brief Checks whether a set contains a plain `inherit inputs;`.
+ * @param set The attribute set.
+ */
+fn inherits_inputs(set: &AttrSet) -> bool {
+    set.inherits()
+        .filter(|inherit| inherit.from().is_none())
+        .any(|inherit| inherits_name(&inherit, "inputs"))
+}
+
+/**
+ * @brief Checks whether an `inherit` entry brings in `name`.
+ * @param inherit The inherit entry.
+ * @param name The inherited attribute name.
+ */
+fn inherits_name(inherit: &Inherit, name: &str) -> bool {
+    inherit.attrs().any(|attr| match attr {
+        Attr::Ident(ident) => ident.to_string() == name,
         _ => false,
-    }) {
-        println!(
-            "Input '{}' already exists in {}. Skipping.",
-            key, FLAKE_FILE
-        );
-        return Ok(());
+    })
+}
+
+/**
+ * @brief Finds a `let` binding whose value is a literal attribute set.
+ * @param let_in The `let ... in` expression.
+ * @param name The binding name.
+ */
+fn let_bound_set(let_in: &LetIn, name: &str) -> Option<AttrSet> {
+    let_in.attrpath_values().find_map(|attr| {
+        let path = attr_names(&attr.attrpath()?)?;
+        if path != [name] {
+            return None;
+        }
+        match unparen(attr.value()?) {
+            Some(Expr::AttrSet(set)) => Some(set),
+            _ => None,
+        }
+    })
+}
+
+/**
+ * @brief Strips parentheses around an expression.
+ * @param expr The expression.
+ */
+pub fn unparen(expr: Expr) -> Option<Expr> {
+    match expr {
+        Expr::Paren(paren) => paren.expr().and_then(unparen),
+        other => Some(other),
     }
+}
 
-    // Find closing brace '}' of the inputs set
-    let closing_brace = inputs_set_node
+/**
+ * @brief Where in an attribute set a new entry goes.
+ */
+enum Placement {
+    /// @brief After the last entry or comment, before the closing brace.
+    End,
+    /// @brief On the line after this entry (and a comment following it on its line).
+    After(AttrpathValue),
+    /// @brief On the line before this entry.
+    Before(AttrpathValue),
+}
+
+/**
+ * @brief Inserts an entry at the end of an attribute set.
+ * @param content The full source text the set was parsed from.
+ * @param set The attribute set to insert into.
+ * @param entry The entry text, including the trailing semicolon.
+ * @return Result<String, Error> The updated source text.
+ */
+fn insert_entry(content: &str, set: &AttrSet, entry: &str) -> Result<String, Error> {
+    insert_entry_at(content, set, Placement::End, entry)
+}
+
+/**
+ * @brief Inserts an entry into an attribute set by editing its syntax tree.
+ *
+ * The entry is parsed on its own and spliced into the set's green node between
+ * whitespace tokens, so the rest of the tree, comments included, is reused as is.
+ * A set written on one line stays on one line: `{ a = 1; }` becomes
+ * `{ a = 1; b = 2; }`. In a set spread over lines the entry gets a line of its
+ * own, indented like the set's entries (tabs or spaces, as the file uses), and a
+ * closing brace that shared the last entry's line moves to a line of its own.
+ * CRLF files stay CRLF.
+ *
+ * @param content The full source text the set was parsed from.
+ * @param set The attribute set to insert into.
+ * @param placement Where the entry goes.
+ * @param entry The entry text, including the trailing semicolon.
+ * @return Result<String, Error> The updated source text, or InvalidInput if the entry
+ * isn't a single `name = value;` entry.
+ */
+fn insert_entry_at(
+    content: &str,
+    set: &AttrSet,
+    placement: Placement,
+    entry: &str,
+) -> Result<String, Error> {
+    let start: usize = set.syntax().text_range().start().into();
+    let (Some(open), Some(close)) = (set.l_curly_token(), set.r_curly_token()) else {
+        return Err(messages::error(
+            ErrorKind::InvalidData,
+            messages::E_FLAKE_SYNTAX,
+            format!(
+                "{}: the attribute set at {} has no braces",
+                FLAKE_FILE,
+                location(content, start)
+            ),
+        ));
+    };
+
+    // An entry that doesn't start its line can't have one inserted above it.
+    let placement = match placement {
+        Placement::Before(node) if !starts_line(content, &node) => Placement::After(node),
+        placement => placement,
+    };
+
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let entry = parse_entry(&entry.replace('\n', newline))?;
+    let outer_indent = line_indent(content, start);
+    let single_line = !content
+        [usize::from(open.text_range().end())..usize::from(close.text_range().start())]
+        .contains('\n');
+
+    let children: Vec<SyntaxElement> = set.syntax().children_with_tokens().collect();
+    let entry_indent = |from: &SyntaxElement| {
+        Some(line_indent(content, from.text_range().start().into()))
+            .filter(|indent| indent.len() > outer_indent.len())
+            .unwrap_or_else(|| format!("{}{}", outer_indent, indent_unit(content)))
+    };
+
+    // (children to replace, new children)
+    let (replaced, inserted) = match placement {
+        Placement::Before(node) => {
+            let at = node.syntax().index();
+            let indent = line_indent(content, node.syntax().text_range().start().into());
+            (
+                at..at,
+                vec![entry, whitespace(&format!("{}{}", newline, indent))],
+            )
+        }
+        placement => {
+            let anchor = match placement {
+                // Keep a comment after the entry on its line with it.
+                Placement::After(node) => {
+                    let mut anchor = node.syntax().index();
+                    for next in &children[anchor + 1..] {
+                        match next.kind() {
+                            TOKEN_WHITESPACE if !next.to_string().contains('\n') => {}
+                            TOKEN_COMMENT => {
+                                anchor = next.index();
+                                break;
+                            }
+                            _ => break,
+                        }
+                    }
+                    anchor
+                }
+                // The last entry or comment; whitespace and the braces don't count.
+                _ => children
+                    .iter()
+                    .rfind(|child| child.as_node().is_some() || child.kind() == TOKEN_COMMENT)
+                    .map_or(open.index(), |child| child.index()),
+            };
+            let close_at = close.index();
+            let indent_from = if anchor == open.index() {
+                SyntaxElement::Node(set.syntax().clone())
+            } else {
+                children[anchor].clone()
+            };
+            if single_line {
+                let mut new = vec![whitespace(" "), entry];
+                if anchor + 1 == close_at {
+                    new.push(whitespace(" "));
+                }
+                (anchor + 1..anchor + 1, new)
+            } else if children[anchor + 1..close_at]
+                .iter()
+                .all(|child| child.kind() == TOKEN_WHITESPACE && !child.to_string().contains('\n'))
+            {
+                // `last = 1; }`: the brace goes to a line of its own.
+                (
+                    anchor + 1..close_at,
+                    vec![
+                        whitespace(&format!("{}{}", newline, entry_indent(&indent_from))),
+                        entry,
+                        whitespace(&format!("{}{}", newline, outer_indent)),
+                    ],
+                )
+            } else if children[anchor].to_string().ends_with('\r') {
+                // A comment ends before the `\n` of a CRLF line break but takes the `\r`,
+                // so the entry carries the `\r` on to the line break after it.
+                let after = children[anchor + 1].to_string();
+                (
+                    anchor + 1..anchor + 2,
+                    vec![
+                        whitespace(&format!("\n{}", entry_indent(&indent_from))),
+                        entry,
+                        whitespace(&format!("\r{}", after)),
+                    ],
+                )
+            } else {
+                (
+                    anchor + 1..anchor + 1,
+                    vec![
+                        whitespace(&format!("{}{}", newline, entry_indent(&indent_from))),
+                        entry,
+                    ],
+                )
+            }
+        }
+    };
+
+    let offset: usize = children[replaced.start].text_range().start().into();
+    let removed: usize = children[replaced.clone()]
+        .iter()
+        .map(|child| usize::from(child.text_range().len()))
+        .sum();
+    let mut green: Vec<GreenElement> = set
         .syntax()
-        .children_with_tokens()
-        .filter_map(|el| el.into_token())
-        .find(|token| token.text() == "}")
-        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Could not find closing brace"))?;
+        .green()
+        .children()
+        .map(|child| child.to_owned())
+        .collect();
+    green.splice(replaced, inserted);
+    let new_set = GreenNode::new(NixLanguage::kind_to_raw(NODE_ATTR_SET), green);
+    let new_content = SyntaxNode::new_root(set.syntax().replace_with(new_set)).to_string();
+
+    trace(|| {
+        let added = new_content.len() + removed - content.len();
+        format!(
+            "set at {} ({}); inserting {} bytes at byte {}\n  before:\n{}\n  after:\n{}",
+            location(content, start),
+            if single_line {
+                "one line"
+            } else {
+                "multi-line"
+            },
+            added,
+            offset,
+            context(content, offset, offset),
+            context(&new_content, offset, offset + added),
+        )
+    });
+    Ok(new_content)
+}
+
+/**
+ * @brief Parses an entry on its own, for splicing into a set.
+ * @param entry The entry text, e.g. `foo.url = "...";`.
+ * @return Result<GreenElement, Error> Its syntax node, or InvalidInput if the text isn't
+ * exactly one `name = value;` entry.
+ */
+fn parse_entry(entry: &str) -> Result<GreenElement, Error> {
+    let parsed = Root::parse(&format!("{{ {} }}", entry));
+    let node = match parsed.tree().expr() {
+        Some(Expr::AttrSet(set)) if parsed.errors().is_empty() => set
+            .attrpath_values()
+            .next()
+            .filter(|attr| attr.syntax().to_string() == entry.trim()),
+        _ => None,
+    };
+    node.map(|attr| GreenElement::Node(attr.syntax().green().into_owned()))
+        .ok_or_else(|| {
+            messages::error(
+                ErrorKind::InvalidInput,
+                messages::E_INVALID_EXPRESSION,
+                format!("'{}' is not a single Nix attribute entry", entry),
+            )
+        })
+}
 
-    // Position to insert the new input
-    let insert_pos: usize = closing_brace.text_range().start().into();
-    let new_input_text = format!("\n    {}.url = \"{}\";\n  ", key, url);
+/**
+ * @brief A whitespace token.
+ * @param text The whitespace.
+ */
+fn whitespace(text: &str) -> GreenElement {
+    GreenElement::Token(GreenToken::new(
+        NixLanguage::kind_to_raw(TOKEN_WHITESPACE),
+        text,
+    ))
+}
 
-    let mut new_content = content;
-    new_content.insert_str(insert_pos, &new_input_text);
-    fs::write(FLAKE_FILE, new_content)?;
+/**
+ * @brief Checks whether only whitespace comes before an entry on its line.
+ * @param content The source text.
+ * @param node The entry.
+ */
+fn starts_line(content: &str, node: &AttrpathValue) -> bool {
+    let at: usize = node.syntax().text_range().start().into();
+    let line_start = content[..at].rfind('\n').map_or(0, |pos| pos + 1);
+    content[line_start..at].trim().is_empty()
+}
 
-    Ok(())
+/**
+ * @brief The indentation step of a file: a tab, or the smallest indent its lines use.
+ * @param content The source text.
+ */
+fn indent_unit(content: &str) -> String {
+    let indents = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .filter(|indent| !indent.is_empty());
+    let mut smallest: Option<&str> = None;
+    for indent in indents {
+        if indent.starts_with('\t') {
+            return "\t".to_string();
+        }
+        if smallest.is_none_or(|current| indent.len() < current.len()) {
+            smallest = Some(indent);
+        }
+    }
+    smallest.unwrap_or("  ").to_string()
 }
 
 /**
- * @brief Helper function to find the first matching AST node.
+ * @brief Cuts the lines around a byte range out of a source, for trace output.
+ * @param content The source text.
+ * @param start Start of the range.
+ * @param end End of the range.
+ * @return The lines, numbered and indented.
+ */
+fn context(content: &str, start: usize, end: usize) -> String {
+    let first_line = content[..start].matches('\n').count();
+    let last_line = content[..end].matches('\n').count();
+    content
+        .lines()
+        .enumerate()
+        .skip(first_line.saturating_sub(TRACE_CONTEXT_LINES))
+        .take(last_line - first_line.saturating_sub(TRACE_CONTEXT_LINES) + TRACE_CONTEXT_LINES + 1)
+        .map(|(number, line)| format!("    {:>4} | {}", number + 1, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/**
+ * @brief Returns the leading whitespace of the line containing a byte offset.
+ * @param content The source text.
+ * @param offset A byte offset into it.
+ */
+fn line_indent(content: &str, offset: usize) -> String {
+    let line_start = content[..offset].rfind('\n').map_or(0, |pos| pos + 1);
+    content[line_start..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect()
+}
+
+/**
+ * @brief Describes a byte offset as a line and column for error messages.
+ * @param content The source text.
+ * @param offset A byte offset into it.
+ * @return E.g. "line 4, column 12" (1-based, columns in characters).
+ */
+fn location(content: &str, offset: usize) -> String {
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+    let column = before[line_start..].chars().count() + 1;
+    format!("line {}, column {}", line, column)
+}
+
+/**
+ * @brief Flattens an attribute set into (attribute path, value) pairs.
  *
- * This function traverses the syntax tree in preorder and applies the provided closure
- * to each node, returning the first non-None result.
+ * Nested attribute sets are descended into, so `a = { b.c = 1; }` yields the path
+ * `["a", "b", "c"]`. Entries with dynamic attribute names are skipped.
  *
- * @tparam T The type returned by the closure.
- * @tparam F The closure type, which takes a SyntaxNode and returns `Option<T>`.
- * @param node The root SyntaxNode to start traversal from.
- * @param f The closure to apply to each node.
- * @return `Option<T>` The first non-None result from the closure, or None if not found.
+ * @param set The attribute set to flatten.
+ * @return The flattened entries in source order.
  */
-fn find_node<T, F>(node: SyntaxNode, mut f: F) -> Option<T>
-where
-    F: FnMut(SyntaxNode) -> Option<T>,
-{
-    node.preorder()
-        .filter_map(|event| match event {
-            WalkEvent::Enter(node) => f(node),
-            _ => None,
+fn flatten_attrset(set: &AttrSet) -> Vec<(Vec<String>, Expr)> {
+    let mut entries = Vec::new();
+    for attr in set.attrpath_values() {
+        let Some(path) = attr.attrpath().and_then(|p| attr_names(&p)) else {
+            continue;
+        };
+        let Some(value) = attr.value() else {
+            continue;
+        };
+
+        match &value {
+            Expr::AttrSet(nested) if nested.rec_token().is_none() => {
+                for (mut sub_path, sub_value) in flatten_attrset(nested) {
+                    let mut full_path = path.clone();
+                    full_path.append(&mut sub_path);
+                    entries.push((full_path, sub_value));
+                }
+            }
+            _ => entries.push((path, value)),
+        }
+    }
+    entries
+}
+
+/**
+ * @brief Converts an attribute path into plain names.
+ * @param path The attribute path.
+ * @return The names, or None if any component is dynamic or interpolated.
+ */
+pub fn attr_names(path: &Attrpath) -> Option<Vec<String>> {
+    path.attrs()
+        .map(|attr| match attr {
+            Attr::Ident(ident) => Some(ident.to_string()),
+            Attr::Str(s) => literal_parts(&s),
+            Attr::Dynamic(_) => None,
         })
-        .next()
+        .collect()
+}
+
+/**
+ * @brief Returns the value of a string expression without interpolations.
+ * @param expr The expression to inspect.
+ * @return The string contents, or None if the expression is not a plain string.
+ */
+pub fn string_literal(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Str(s) => literal_parts(s),
+        _ => None,
+    }
+}
+
+/**
+ * @brief Joins the literal parts of a string node.
+ * @param s The string node.
+ * @return The string contents, or None if it contains an interpolation.
+ */
+fn literal_parts(s: &Str) -> Option<String> {
+    s.normalized_parts()
+        .into_iter()
+        .map(|part| match part {
+            InterpolPart::Literal(text) => Some(text),
+            InterpolPart::Interpolation(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::generate_flake_nix;
+
+    /// @brief The `outputs` line shared by the test flakes.
+    const OUTPUTS: &str = "  outputs = { self, nixpkgs }: { };\n";
+
+    /**
+     * @brief Checks that a flake parses without errors.
+     * @param content The flake source.
+     */
+    fn assert_parses(content: &str) {
+        let parsed = Root::parse(content);
+        assert!(
+            parsed.errors().is_empty(),
+            "{:?} in:\n{}",
+            parsed.errors(),
+            content
+        );
+    }
+
+    /**
+     * @brief Adds the flake input `foo`, checking that the result parses and declares it.
+     * @param content The flake source.
+     */
+    fn add_foo(content: &str) -> String {
+        let updated = add_flake_input(content, "foo", "github:o/foo", true, false)
+            .expect("input is added")
+            .expect("input is new");
+        assert_parses(&updated);
+        assert!(
+            parse_flake_inputs(&updated)
+                .iter()
+                .any(|input| input.key == "foo" && input.url.as_deref() == Some("github:o/foo")),
+            "foo missing from:\n{}",
+            updated
+        );
+        updated
+    }
+
+    #[test]
+    fn insertion_layouts() {
+        let cases: &[(&str, String, String)] = &[
+            (
+                "single-line set",
+                format!("{{\n  inputs = {{ nixpkgs.url = \"a\"; }};\n{}}}\n", OUTPUTS),
+                format!(
+                    "{{\n  inputs = {{ nixpkgs.url = \"a\"; foo.url = \"github:o/foo\"; }};\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "empty set",
+                format!("{{\n  inputs = {{ }};\n{}}}\n", OUTPUTS),
+                format!(
+                    "{{\n  inputs = {{ foo.url = \"github:o/foo\"; }};\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "empty set without spaces",
+                format!("{{\n  inputs = {{}};\n{}}}\n", OUTPUTS),
+                format!(
+                    "{{\n  inputs = {{ foo.url = \"github:o/foo\"; }};\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "comment before the closing brace",
+                format!(
+                    "{{\n  inputs = {{\n    nixpkgs.url = \"a\";\n    # more inputs here\n  }};\n{}}}\n",
+                    OUTPUTS
+                ),
+                format!(
+                    "{{\n  inputs = {{\n    nixpkgs.url = \"a\";\n    # more inputs here\n    foo.url = \"github:o/foo\";\n  }};\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "comment after the last entry",
+                format!(
+                    "{{\n  inputs = {{\n    nixpkgs.url = \"a\"; # pinned\n  }};\n{}}}\n",
+                    OUTPUTS
+                ),
+                format!(
+                    "{{\n  inputs = {{\n    nixpkgs.url = \"a\"; # pinned\n    foo.url = \"github:o/foo\";\n  }};\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "closing brace on the last entry's line",
+                format!(
+                    "{{\n  inputs = {{\n    nixpkgs.url = \"a\"; }};\n{}}}\n",
+                    OUTPUTS
+                ),
+                format!(
+                    "{{\n  inputs = {{\n    nixpkgs.url = \"a\";\n    foo.url = \"github:o/foo\";\n  }};\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "three-space indentation",
+                "{\n   inputs = {\n      nixpkgs.url = \"a\";\n   };\n   outputs = { self, nixpkgs }: { };\n}\n"
+                    .to_string(),
+                "{\n   inputs = {\n      nixpkgs.url = \"a\";\n      foo.url = \"github:o/foo\";\n   };\n   outputs = { self, nixpkgs }: { };\n}\n"
+                    .to_string(),
+            ),
+            (
+                "tab indentation",
+                "{\n\tinputs = {\n\t\tnixpkgs.url = \"a\";\n\t};\n\toutputs = { self, nixpkgs }: { };\n}\n"
+                    .to_string(),
+                "{\n\tinputs = {\n\t\tnixpkgs.url = \"a\";\n\t\tfoo.url = \"github:o/foo\";\n\t};\n\toutputs = { self, nixpkgs }: { };\n}\n"
+                    .to_string(),
+            ),
+            (
+                "empty multi-line set with tabs",
+                "{\n\tinputs = {\n\t};\n\toutputs = { self }: { };\n}\n".to_string(),
+                "{\n\tinputs = {\n\t\tfoo.url = \"github:o/foo\";\n\t};\n\toutputs = { self }: { };\n}\n"
+                    .to_string(),
+            ),
+            (
+                "CRLF line endings",
+                "{\r\n  inputs = {\r\n    nixpkgs.url = \"a\";\r\n    # more\r\n  };\r\n  outputs = { self, nixpkgs }: { };\r\n}\r\n"
+                    .to_string(),
+                "{\r\n  inputs = {\r\n    nixpkgs.url = \"a\";\r\n    # more\r\n    foo.url = \"github:o/foo\";\r\n  };\r\n  outputs = { self, nixpkgs }: { };\r\n}\r\n"
+                    .to_string(),
+            ),
+            (
+                "CRLF and tabs, dotted input with a trailing comment",
+                "{\r\n\tinputs.nixpkgs.url = \"a\"; # pinned\r\n\toutputs = { self }: { };\r\n}\r\n"
+                    .to_string(),
+                "{\r\n\tinputs.nixpkgs.url = \"a\"; # pinned\r\n\tinputs.foo.url = \"github:o/foo\";\r\n\toutputs = { self }: { };\r\n}\r\n"
+                    .to_string(),
+            ),
+            (
+                "dotted inputs",
+                format!(
+                    "{{\n  description = \"d\";\n  inputs.nixpkgs.url = \"a\";\n{}}}\n",
+                    OUTPUTS
+                ),
+                format!(
+                    "{{\n  description = \"d\";\n  inputs.nixpkgs.url = \"a\";\n  inputs.foo.url = \"github:o/foo\";\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "dotted inputs with a trailing comment",
+                format!(
+                    "{{\n  inputs.nixpkgs.url = \"a\"; # pinned\n  description = \"d\";\n{}}}\n",
+                    OUTPUTS
+                ),
+                format!(
+                    "{{\n  inputs.nixpkgs.url = \"a\"; # pinned\n  inputs.foo.url = \"github:o/foo\";\n  description = \"d\";\n{}}}\n",
+                    OUTPUTS
+                ),
+            ),
+            (
+                "no inputs yet",
+                "{\n  description = \"d\";\n  outputs = { self }: { };\n}\n".to_string(),
+                "{\n  description = \"d\";\n  inputs.foo.url = \"github:o/foo\";\n  outputs = { self }: { };\n}\n"
+                    .to_string(),
+            ),
+            (
+                "no inputs, outputs on the brace line",
+                "{ description = \"d\"; outputs = { self }: { }; }\n".to_string(),
+                "{ description = \"d\"; outputs = { self }: { }; inputs.foo.url = \"github:o/foo\"; }\n"
+                    .to_string(),
+            ),
+            (
+                "let-bound inputs",
+                "let\n  inputs = {\n    nixpkgs.url = \"a\";\n  };\nin\n{\n  inherit inputs;\n  outputs = { self, nixpkgs }: { };\n}\n"
+                    .to_string(),
+                "let\n  inputs = {\n    nixpkgs.url = \"a\";\n    foo.url = \"github:o/foo\";\n  };\nin\n{\n  inherit inputs;\n  outputs = { self, nixpkgs }: { };\n}\n"
+                    .to_string(),
+            ),
+        ];
+        for (name, before, after) in cases {
+            assert_parses(before);
+            assert_eq!(&add_foo(before), after, "{}", name);
+        }
+    }
+
+    #[test]
+    fn single_line_set_gets_follows_on_the_same_line() {
+        let flake = format!(
+            "{{\n  inputs = {{ nixpkgs.url = \"a\"; }};\n{}}}\n",
+            OUTPUTS
+        );
+        let updated = add_flake_input(&flake, "foo", "github:o/foo", true, true)
+            .unwrap()
+            .unwrap();
+        assert_parses(&updated);
+        assert!(updated.contains(
+            "inputs = { nixpkgs.url = \"a\"; foo.url = \"github:o/foo\"; foo.inputs.nixpkgs.follows = \"nixpkgs\"; };"
+        ));
+    }
+
+    #[test]
+    fn overlay_support() {
+        let generated = generate_flake_nix(&BTreeMap::new(), "devshell.toml", "github:a/b");
+        assert!(applies_overlays(&generated));
+        for flake in [
+            "{ outputs = _: { }; } # config.overlays",
+            "{ outputs = _: { overlays = \"config.overlays\"; }; }",
+            "{ outputs = { nixpkgs, ... }: import nixpkgs { overlays = [ self.overlays.default ]; }; }",
+        ] {
+            assert!(!applies_overlays(flake), "{}", flake);
+        }
+        assert!(applies_overlays(
+            "{ outputs = _: { x = config.overlays or [ ]; }; }"
+        ));
+    }
+
+    #[test]
+    fn generated_flake_keeps_its_layout() {
+        let mut inputs = BTreeMap::new();
+        inputs.insert("nixpkgs".to_string(), "github:a/b".to_string());
+        let flake = generate_flake_nix(&inputs, "devshell.toml", "github:a/b");
+        let updated = add_foo(&flake);
+        let added = updated
+            .lines()
+            .find(|line| line.contains("foo.url"))
+            .unwrap();
+        let neighbour = updated
+            .lines()
+            .find(|line| line.contains("nixpkgs.url"))
+            .unwrap();
+        assert_eq!(line_indent(added, 0), line_indent(neighbour, 0));
+
+        let with_config = add_top_level_attr(
+            &updated,
+            "nixConfig",
+            "nixConfig = {\n    eval-cache = true;\n  };",
+        )
+        .unwrap()
+        .unwrap();
+        assert_parses(&with_config);
+        assert!(with_config.contains("\n  nixConfig = {\n    eval-cache = true;\n  };\n}"));
+        assert!(
+            add_top_level_attr(&with_config, "nixConfig", "nixConfig = { };")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn rejects_entries_that_are_not_one_attribute() {
+        let flake = format!("{{\n  inputs = {{ }};\n{}}}\n", OUTPUTS);
+        for entry in ["a = 1; b = 2;", "a = ;", "inherit a;", "a = 1"] {
+            let error = add_top_level_attr(&flake, "a", entry).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput, "{}", entry);
+        }
+    }
+
+    #[test]
+    fn indentation_unit() {
+        assert_eq!(indent_unit("{\n  a = {\n    b = 1;\n  };\n}"), "  ");
+        assert_eq!(indent_unit("{\n    a = 1;\n}"), "    ");
+        assert_eq!(indent_unit("{\n   a = {\n      b = 1;\n   };\n}"), "   ");
+        assert_eq!(indent_unit("{\n\ta = {\n\t\tb = 1;\n\t};\n}"), "\t");
+        assert_eq!(indent_unit("{\r\n  a = 1;\r\n}"), "  ");
+        assert_eq!(indent_unit("{ a = 1; }"), "  ");
+    }
+
+    #[test]
+    fn entries_starting_their_line() {
+        let content = "{\n  a = 1; b = 2;\n\tc = 3;\n}";
+        let (top, _) = top_level_set(content, &parse(content).unwrap()).unwrap();
+        let starts: Vec<bool> = top
+            .attrpath_values()
+            .map(|attr| starts_line(content, &attr))
+            .collect();
+        assert_eq!(starts, [true, false, true]);
+    }
+
+    #[test]
+    fn before_falls_back_to_after_mid_line() {
+        let content = "{ description = \"d\"; outputs = { self }: { };\n}\n";
+        let (top, _) = top_level_set(content, &parse(content).unwrap()).unwrap();
+        let outputs = top.attrpath_values().nth(1).unwrap();
+        let updated = insert_entry_at(content, &top, Placement::Before(outputs), "x = 1;").unwrap();
+        assert_parses(&updated);
+        assert_eq!(
+            updated,
+            "{ description = \"d\"; outputs = { self }: { };\n  x = 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn removal_layouts() {
+        let cases: &[(&str, &str, &str)] = &[
+            (
+                "set with follows, a nested follows and an outputs argument",
+                "{\n  inputs = {\n    nixpkgs.url = \"a\";\n    foo.url = \"github:o/foo\"; # pinned\n    foo.inputs.nixpkgs.follows = \"nixpkgs\";\n    bar = {\n      url = \"github:o/bar\";\n      inputs.foo.follows = \"foo\";\n    };\n  };\n  outputs = { self, nixpkgs, foo, bar }: { };\n}\n",
+                "{\n  inputs = {\n    nixpkgs.url = \"a\";\n    bar = {\n      url = \"github:o/bar\";\n    };\n  };\n  outputs = { self, nixpkgs, bar }: { };\n}\n",
+            ),
+            (
+                "single-line set, first outputs argument",
+                "{\n  inputs = { nixpkgs.url = \"a\"; foo.url = \"b\"; };\n  outputs = { foo, self }: { };\n}\n",
+                "{\n  inputs = { nixpkgs.url = \"a\"; };\n  outputs = { self }: { };\n}\n",
+            ),
+            (
+                "entry sharing its line",
+                "{\n  inputs = {\n    nixpkgs.url = \"a\"; foo.url = \"b\";\n  };\n  outputs = { self, ... }: { };\n}\n",
+                "{\n  inputs = {\n    nixpkgs.url = \"a\";\n  };\n  outputs = { self, ... }: { };\n}\n",
+            ),
+            (
+                "follows of a nested input",
+                "{\n  inputs = {\n    foo.url = \"b\";\n    bar.url = \"c\";\n    bar.inputs.nixpkgs.follows = \"foo/nixpkgs\";\n  };\n  outputs = inputs: { };\n}\n",
+                "{\n  inputs = {\n    bar.url = \"c\";\n  };\n  outputs = inputs: { };\n}\n",
+            ),
+            (
+                "comment on its own line is kept",
+                "{\n  inputs = {\n    # the foo input\n    foo.url = \"b\";\n  };\n  outputs = { self, foo }: { };\n}\n",
+                "{\n  inputs = {\n    # the foo input\n  };\n  outputs = { self }: { };\n}\n",
+            ),
+            (
+                "dotted inputs with CRLF and tabs",
+                "{\r\n\tinputs.nixpkgs.url = \"a\";\r\n\tinputs.foo.url = \"b\";\r\n\tinputs.foo.flake = false; # source only\r\n\toutputs = { self, ... }@inputs: { };\r\n}\r\n",
+                "{\r\n\tinputs.nixpkgs.url = \"a\";\r\n\toutputs = { self, ... }@inputs: { };\r\n}\r\n",
+            ),
+            (
+                "let-bound inputs",
+                "let\n  inputs = {\n    foo.url = \"b\";\n    nixpkgs.url = \"a\";\n  };\nin\n{\n  inherit inputs;\n  outputs = { self, nixpkgs, foo }: { };\n}\n",
+                "let\n  inputs = {\n    nixpkgs.url = \"a\";\n  };\nin\n{\n  inherit inputs;\n  outputs = { self, nixpkgs }: { };\n}\n",
+            ),
+        ];
+        for (name, before, after) in cases {
+            assert_parses(before);
+            let removed = remove_flake_input(before, "foo")
+                .expect("flake parses")
+                .expect("foo is declared");
+            assert_parses(&removed);
+            assert_eq!(&removed, after, "{}", name);
+            assert!(
+                parse_flake_inputs(&removed)
+                    .iter()
+                    .all(|input| input.key != "foo" && !input.follows.contains("foo")),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn removing_an_undeclared_input_changes_nothing() {
+        let flake = "{\n  inputs = {\n    bar.url = \"c\";\n    bar.inputs.foo.follows = \"foo\";\n  };\n  outputs = { self }: { };\n}\n";
+        assert!(remove_flake_input(flake, "foo").unwrap().is_none());
+        assert!(remove_flake_input(flake, "nixpkgs").unwrap().is_none());
+    }
+
+    #[test]
+    fn added_input_removes_cleanly() {
+        let mut inputs = BTreeMap::new();
+        inputs.insert("nixpkgs".to_string(), "github:a/b".to_string());
+        let flake = generate_flake_nix(&inputs, "devshell.toml", "github:a/b");
+        let added = add_flake_input(&flake, "foo", "github:o/foo", true, true)
+            .unwrap()
+            .unwrap();
+        let removed = remove_flake_input(&added, "foo").unwrap().unwrap();
+        assert_eq!(removed, flake);
+    }
+
+    /**
+     * @brief Describes where inputs would go, for comparing in tests.
+     * @param content The flake source.
+     * @return "set at <location>", "dotted", or the error message.
+     */
+    fn target(content: &str) -> Result<String, String> {
+        match find_inputs_target(content) {
+            Ok(InputsTarget::Set(set)) => Ok(format!(
+                "set at {}",
+                location(content, set.syntax().text_range().start().into())
+            )),
+            Ok(InputsTarget::Dotted(_)) => Ok("dotted".to_string()),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    #[test]
+    fn inputs_targets() {
+        let found: &[(&str, &str, &str)] = &[
+            (
+                "inline set",
+                "{\n  inputs = {\n    nixpkgs.url = \"a\";\n  };\n}",
+                "set at line 2, column 12",
+            ),
+            (
+                "CRLF and tabs",
+                "{\r\n\tinputs = {\r\n\t\tnixpkgs.url = \"a\";\r\n\t};\r\n}\r\n",
+                "set at line 2, column 11",
+            ),
+            (
+                "unicode before the set",
+                "{\n  description = \"Ünïcödé ✓\"; inputs = { };\n}",
+                "set at line 2, column 39",
+            ),
+            (
+                "parenthesized set",
+                "({ inputs = ({ }); })",
+                "set at line 1, column 14",
+            ),
+            (
+                "inherit from let",
+                "let\n  inputs = { };\nin\n{\n  inherit inputs;\n}",
+                "set at line 2, column 12",
+            ),
+            (
+                "nested let",
+                "let a = 1; in\nlet\n  inputs = { };\nin\n{ inherit inputs; }",
+                "set at line 3, column 12",
+            ),
+            (
+                "dotted inputs",
+                "{\n  inputs.nixpkgs.url = \"a\";\n  outputs = _: { };\n}",
+                "dotted",
+            ),
+            ("no inputs", "{ outputs = _: { }; }", "dotted"),
+        ];
+        for (name, content, expected) in found {
+            assert_eq!(target(content).as_deref(), Ok(*expected), "{}", name);
+        }
+
+        let refused: &[(&str, &str, &str)] = &[
+            (
+                "inputs from an import",
+                "{ description = \"é\"; inputs = import ./i.nix; }",
+                "is not a literal attribute set",
+            ),
+            (
+                "inherit from another expression",
+                "{\n  inherit (import ./i.nix) inputs;\n}",
+                "inherited from another expression at line 2, column 3",
+            ),
+            (
+                "inherit without a let binding",
+                "{ inherit inputs; }",
+                "doesn't refer to a `let` binding",
+            ),
+            (
+                "let binding that isn't a set",
+                "let inputs = import ./i.nix; in { inherit inputs; }",
+                "doesn't refer to a `let` binding",
+            ),
+            (
+                "flake that is a function",
+                "x: { }",
+                "is not an attribute set",
+            ),
+        ];
+        for (name, content, expected) in refused {
+            let error = target(content).unwrap_err();
+            assert!(error.contains(expected), "{}: {}", name, error);
+        }
+        let import = target("{ description = \"é\"; inputs = import ./i.nix; }").unwrap_err();
+        assert!(import.contains("line 1, column 31"), "{}", import);
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_not_edited() {
+        let broken = "{\n  inputs = { nixpkgs.url = \"a\" };\n}";
+        let error = add_flake_input(broken, "foo", "b", true, false).unwrap_err();
+        assert_eq!(messages::code(&error), messages::E_FLAKE_SYNTAX);
+        assert!(remove_flake_input(broken, "nixpkgs").is_err());
+        assert!(parse_flake_inputs(broken).is_empty());
+    }
 }
@@ -0,0 +1,126 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use crate::process::Process;
+
+/// @brief Inputs known to be consumed through their overlay, used when `nix flake show` isn't available.
+pub const KNOWN_OVERLAY_INPUTS: &[&str] =
+    &["rust-overlay", "zig-overlay", "fenix", "emacs-overlay"];
+
+/**
+ * @struct FlakeOutputs
+ * @brief The outputs of a flake, as reported by `nix flake show --json`.
+ */
+#[derive(Debug, Default)]
+pub struct FlakeOutputs {
+    /// @brief Package names per system.
+    pub packages: BTreeMap<String, Vec<String>>,
+    /// @brief Dev shell names per system.
+    pub dev_shells: BTreeMap<String, Vec<String>>,
+    /// @brief Overlay names.
+    pub overlays: Vec<String>,
+}
+
+impl FlakeOutputs {
+    /**
+     * @brief Runs `nix flake show --json` on a flake reference.
+     * @param flake_ref The flake URL (e.g. "github:oxalica/rust-overlay").
+     * @param timeout Timeout for the nix call.
+     * @return Result containing the outputs.
+     */
+    pub fn show(flake_ref: &str, timeout: Option<Duration>) -> Result<Self, Error> {
+        let output = Process::nix(["flake", "show", "--json", "--all-systems", flake_ref])
+            .timeout(timeout)
+            .output()?;
+        let json: Value =
+            serde_json::from_slice(&output).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        Ok(FlakeOutputs {
+            packages: per_system(json.get("packages")),
+            dev_shells: per_system(json.get("devShells")),
+            overlays: names(json.get("overlays").and_then(Value::as_object)),
+        })
+    }
+
+    /**
+     * @brief Returns the attribute path of the overlay to use, relative to the input.
+     * @return "overlays.default", or the only overlay if there is no default.
+     */
+    pub fn preferred_overlay(&self) -> Option<String> {
+        if self.overlays.iter().any(|name| name == "default") {
+            return Some("overlays.default".to_string());
+        }
+        match self.overlays.as_slice() {
+            [only] => Some(format!("overlays.{}", only)),
+            _ => None,
+        }
+    }
+
+    /**
+     * @brief Lists the package names across all systems, with the systems providing each.
+     */
+    pub fn package_names(&self) -> BTreeMap<String, Vec<String>> {
+        invert(&self.packages)
+    }
+
+    /**
+     * @brief Lists the dev shell names across all systems, with the systems providing each.
+     */
+    pub fn dev_shell_names(&self) -> BTreeMap<String, Vec<String>> {
+        invert(&self.dev_shells)
+    }
+
+    /**
+     * @brief Whether the flake has a default package for any system.
+     */
+    pub fn has_default_package(&self) -> bool {
+        self.packages
+            .values()
+            .any(|names| names.iter().any(|name| name == "default"))
+    }
+}
+
+/**
+ * @brief Collects `{ system = { name = ...; }; }` output sets.
+ * @param value The output attribute, if present.
+ */
+fn per_system(value: Option<&Value>) -> BTreeMap<String, Vec<String>> {
+    value
+        .and_then(Value::as_object)
+        .map(|systems| {
+            systems
+                .iter()
+                .map(|(system, outputs)| (system.clone(), names(outputs.as_object())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/**
+ * @brief Turns a system -> names map into a name -> systems map.
+ * @param per_system Output names per system.
+ */
+fn invert(per_system: &BTreeMap<String, Vec<String>>) -> BTreeMap<String, Vec<String>> {
+    let mut by_name: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (system, names) in per_system {
+        for name in names {
+            by_name
+                .entry(name.clone())
+                .or_default()
+                .push(system.clone());
+        }
+    }
+    by_name
+}
+
+/**
+ * @brief Returns the attribute names of an object, sorted.
+ * @param object The JSON object, if present.
+ */
+fn names(object: Option<&Map<String, Value>>) -> Vec<String> {
+    object
+        .map(|object| object.keys().cloned().collect())
+        .unwrap_or_default()
+}
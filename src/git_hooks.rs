@@ -0,0 +1,88 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::messages;
+use crate::process::Process;
+
+/// @brief Hooks that run after the working tree changes under the user.
+const HOOK_NAMES: &[&str] = &["post-checkout", "post-merge"];
+
+/// @brief Marks hook files written by nix-shell-gen, so they can be replaced safely.
+const MARKER: &str = "# Installed by nix-shell-gen install-git-hooks.";
+
+/**
+ * @brief Renders the hook script.
+ *
+ * The hook never fails: git ignores the exit status of these hooks anyway, and a
+ * missing nix-shell-gen binary shouldn't make every checkout print an error.
+ *
+ * @param sync Whether the hook regenerates the files with `sync` before the doctor runs.
+ */
+fn hook_script(sync: bool) -> String {
+    let sync = if sync {
+        "nix-shell-gen sync || true\n"
+    } else {
+        ""
+    };
+    format!(
+        "#!/bin/sh\n{}\ncommand -v nix-shell-gen >/dev/null 2>&1 || exit 0\n{}nix-shell-gen doctor --quiet || true\n",
+        MARKER, sync
+    )
+}
+
+/**
+ * @brief Returns the directory git runs hooks from, honouring `core.hooksPath`.
+ */
+pub fn hooks_dir() -> Result<PathBuf, Error> {
+    let output = Process::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()?;
+    let dir = String::from_utf8_lossy(&output).trim().to_string();
+    if dir.is_empty() {
+        return Err(messages::error(
+            ErrorKind::NotFound,
+            messages::E_NOT_A_REPOSITORY,
+            "Not inside a git repository.",
+        ));
+    }
+    Ok(PathBuf::from(dir))
+}
+
+/**
+ * @brief Writes all hooks, refusing to overwrite hooks that nix-shell-gen didn't write.
+ * @param dir The hooks directory.
+ * @param force Overwrite foreign hooks too.
+ * @param sync Run `nix-shell-gen sync` in the hooks as well.
+ * @return The paths of the written hooks.
+ */
+pub fn install(dir: &Path, force: bool, sync: bool) -> Result<Vec<PathBuf>, Error> {
+    let paths: Vec<PathBuf> = HOOK_NAMES.iter().map(|name| dir.join(name)).collect();
+    if !force {
+        for path in &paths {
+            if let Ok(existing) = fs::read_to_string(path)
+                && !existing.contains(MARKER)
+            {
+                return Err(messages::error(
+                    ErrorKind::AlreadyExists,
+                    messages::E_FILE_EXISTS,
+                    format!(
+                        "{} already exists and wasn't written by nix-shell-gen; use --force to replace it.",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+    for path in &paths {
+        fs::write(path, hook_script(sync))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(paths)
+}
@@ -0,0 +1,379 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Meta;
+use crate::lockfile::format_date;
+use crate::paths;
+use crate::process::Process;
+
+/// @brief Base URL of the GitHub REST API.
+const API_URL: &str = "https://api.github.com";
+
+/// @brief How long a single API request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// @brief Environment variables holding an API token, in order of preference.
+const TOKEN_VARS: &[&str] = &["GITHUB_TOKEN", "GH_TOKEN"];
+
+/**
+ * @struct CachedResponse
+ * @brief A GitHub API response kept on disk, revalidated with its ETag.
+ *
+ * Conditional requests answered with 304 don't count against the rate limit.
+ */
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    etag: String,
+    body: String,
+}
+
+/**
+ * @struct Response
+ * @brief Status, headers and body of an HTTP response, as printed by `curl --include`.
+ */
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    /**
+     * @brief Splits `curl --include` output into the final response's parts.
+     *
+     * Redirects and `100 Continue` produce several header blocks; the last one wins.
+     */
+    fn parse(output: &[u8]) -> Result<Self, Error> {
+        let mut rest = String::from_utf8_lossy(output).into_owned();
+        let mut status = 0;
+        let mut headers = Vec::new();
+        while rest.starts_with("HTTP/") {
+            let (block, body) = rest
+                .split_once("\r\n\r\n")
+                .or_else(|| rest.split_once("\n\n"))
+                .unwrap_or((rest.as_str(), ""));
+            let mut lines = block.lines();
+            status = lines
+                .next()
+                .and_then(|line| line.split_whitespace().nth(1))
+                .and_then(|code| code.parse().ok())
+                .unwrap_or(0);
+            headers = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+                .collect();
+            rest = body.to_string();
+        }
+        if status == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected response from the GitHub API",
+            ));
+        }
+        Ok(Response {
+            status,
+            headers,
+            body: rest,
+        })
+    }
+
+    /// @brief Returns a header value by (lowercase) name.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/**
+ * @brief Returns the API token from the environment, if one is set.
+ */
+fn token() -> Option<String> {
+    TOKEN_VARS
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|token| !token.trim().is_empty())
+        .map(|token| token.trim().to_string())
+}
+
+/**
+ * @brief Returns the cache file for an API path.
+ * @param path The API path, e.g. "/repos/o/r/compare/a...b".
+ */
+fn cache_file(path: &str) -> Option<PathBuf> {
+    let name: String = path
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    Some(
+        paths::cache_dir()?
+            .join("github")
+            .join(format!("{}.json", name)),
+    )
+}
+
+/**
+ * @brief GETs an API path, authenticated with GITHUB_TOKEN when set and cached by ETag.
+ *
+ * The token is passed to curl on stdin, not on the command line, so it doesn't
+ * show up in the process list.
+ *
+ * @param path The API path, e.g. "/repos/o/r/compare/a...b".
+ * @return Result containing the response body, or an Error explaining rate limits,
+ * rejected tokens and SSO requirements.
+ */
+fn get(path: &str) -> Result<String, Error> {
+    let cache_file = cache_file(path);
+    let cached: Option<CachedResponse> = cache_file
+        .as_ref()
+        .and_then(|file| fs::read_to_string(file).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok());
+
+    let mut config = String::from("header = \"Accept: application/vnd.github+json\"\n");
+    if let Some(token) = token() {
+        config.push_str(&format!("header = \"Authorization: Bearer {}\"\n", token));
+    }
+    if let Some(cached) = &cached {
+        config.push_str(&format!(
+            "header = \"If-None-Match: {}\"\n",
+            cached.etag.replace('"', "\\\"")
+        ));
+    }
+
+    let output = Process::new("curl")
+        .args(["--silent", "--show-error", "--include", "--location"])
+        .args(["--config", "-"])
+        .arg(format!("{}{}", API_URL, path))
+        .stdin_bytes(config.into_bytes())?
+        .timeout(Some(REQUEST_TIMEOUT))
+        .output()?;
+    let response = Response::parse(&output)?;
+
+    match response.status {
+        304 => {
+            if let Some(cached) = cached {
+                return Ok(cached.body);
+            }
+        }
+        200..=299 => {
+            if let (Some(etag), Some(file)) = (response.header("etag"), &cache_file) {
+                let entry = CachedResponse {
+                    etag: etag.to_string(),
+                    body: response.body.clone(),
+                };
+                // The cache only saves requests; failing to write it is harmless.
+                if let Some(parent) = file.parent() {
+                    let _ = fs::create_dir_all(parent);
+                }
+                if let Ok(json) = serde_json::to_string(&entry) {
+                    let _ = fs::write(file, json);
+                }
+            }
+            return Ok(response.body);
+        }
+        _ => {}
+    }
+    Err(response_error(&response))
+}
+
+/**
+ * @brief Explains a failed API response.
+ * @param response The non-success response.
+ */
+fn response_error(response: &Response) -> Error {
+    if let Some(sso) = response.header("x-github-sso") {
+        let url = sso.split("url=").nth(1).unwrap_or(sso);
+        return Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "the organization requires SSO; authorize the token at {}",
+                url
+            ),
+        );
+    }
+    if matches!(response.status, 403 | 429) && response.header("x-ratelimit-remaining") == Some("0")
+    {
+        let reset = response
+            .header("x-ratelimit-reset")
+            .and_then(|reset| reset.parse::<u64>().ok())
+            .map(|reset| {
+                format!(
+                    " until {} {:02}:{:02} UTC",
+                    format_date(reset),
+                    reset % 86_400 / 3_600,
+                    reset % 3_600 / 60
+                )
+            })
+            .unwrap_or_default();
+        let hint = if token().is_some() {
+            ""
+        } else {
+            "; set GITHUB_TOKEN for a higher limit"
+        };
+        return Error::new(
+            ErrorKind::QuotaExceeded,
+            format!("GitHub API rate limit reached{}{}", reset, hint),
+        );
+    }
+    if response.status == 401 {
+        return Error::new(
+            ErrorKind::PermissionDenied,
+            "GitHub rejected the token in GITHUB_TOKEN",
+        );
+    }
+    let message = serde_json::from_str::<serde_json::Value>(&response.body)
+        .ok()
+        .and_then(|body| body.get("message")?.as_str().map(str::to_string))
+        .unwrap_or_default();
+    Error::other(format!(
+        "GitHub API returned {} {}",
+        response.status, message
+    ))
+}
+
+/**
+ * @struct Commit
+ * @brief A commit between two locked revisions, as reported by GitHub.
+ */
+#[derive(Serialize, Debug)]
+pub struct Commit {
+    /// @brief Full commit hash.
+    pub sha: String,
+    /// @brief First line of the commit message.
+    pub summary: String,
+    /// @brief Link to the commit on github.com.
+    pub url: String,
+}
+
+/// @brief Response of the compare endpoint (only the fields used here).
+#[derive(Deserialize)]
+struct CompareResponse {
+    commits: Vec<CompareCommit>,
+}
+
+#[derive(Deserialize)]
+struct CompareCommit {
+    sha: String,
+    html_url: String,
+    commit: CommitDetails,
+}
+
+#[derive(Deserialize)]
+struct CommitDetails {
+    message: String,
+}
+
+/// @brief Response of the commit endpoint (only the fields used here).
+#[derive(Deserialize)]
+struct CommitResponse {
+    sha: String,
+}
+
+/**
+ * @brief Resolves a branch (or any ref) to its current commit.
+ * @param owner The repository owner.
+ * @param repo The repository name.
+ * @param branch The branch, e.g. "nixos-unstable".
+ * @return Result containing the full commit hash.
+ */
+pub fn branch_head(owner: &str, repo: &str, branch: &str) -> Result<String, Error> {
+    let body = get(&format!("/repos/{}/{}/commits/{}", owner, repo, branch)).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("GitHub request for {}/{} failed: {}", owner, repo, e),
+        )
+    })?;
+    let response: CommitResponse =
+        serde_json::from_str(&body).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(response.sha)
+}
+
+/**
+ * @brief Returns the web URL comparing two revisions of a repository.
+ * @param owner The repository owner.
+ * @param repo The repository name.
+ * @param base The old revision.
+ * @param head The new revision.
+ */
+pub fn compare_url(owner: &str, repo: &str, base: &str, head: &str) -> String {
+    format!(
+        "https://github.com/{}/{}/compare/{}...{}",
+        owner, repo, base, head
+    )
+}
+
+/**
+ * @brief Lists the commits between two revisions via the GitHub compare API.
+ *
+ * Uses `curl`, so no HTTP client is linked into the binary.
+ *
+ * @param owner The repository owner.
+ * @param repo The repository name.
+ * @param base The old revision.
+ * @param head The new revision.
+ * @return Result containing the commits, oldest first.
+ */
+pub fn compare(owner: &str, repo: &str, base: &str, head: &str) -> Result<Vec<Commit>, Error> {
+    let body = get(&format!(
+        "/repos/{}/{}/compare/{}...{}",
+        owner, repo, base, head
+    ))
+    .map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("GitHub request for {}/{} failed: {}", owner, repo, e),
+        )
+    })?;
+
+    let response: CompareResponse =
+        serde_json::from_str(&body).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    Ok(response
+        .commits
+        .into_iter()
+        .map(|c| Commit {
+            sha: c.sha,
+            summary: c.commit.message.lines().next().unwrap_or("").to_string(),
+            url: c.html_url,
+        })
+        .collect())
+}
+
+/**
+ * @struct UpdateMetadata
+ * @brief What `update --pr-metadata` prints: the bumped inputs and who owns the environment.
+ */
+#[derive(Serialize, Debug)]
+pub struct UpdateMetadata {
+    /// @brief The `[meta]` of the config (description, owners, links).
+    pub meta: Meta,
+    /// @brief One entry per input whose locked revision changed.
+    pub inputs: Vec<InputChangelog>,
+}
+
+/**
+ * @struct InputChangelog
+ * @brief Changelog entry for one bumped flake input, used for PR descriptions.
+ */
+#[derive(Serialize, Debug)]
+pub struct InputChangelog {
+    /// @brief The input key.
+    pub input: String,
+    /// @brief Previously locked revision, if any.
+    pub old_rev: Option<String>,
+    /// @brief Newly locked revision.
+    pub new_rev: Option<String>,
+    /// @brief Commit date of the old revision (YYYY-MM-DD).
+    pub old_date: Option<String>,
+    /// @brief Commit date of the new revision (YYYY-MM-DD).
+    pub new_date: Option<String>,
+    /// @brief Link to the comparison on github.com, for GitHub inputs.
+    pub compare_url: Option<String>,
+    /// @brief Commits between the two revisions, for GitHub inputs.
+    pub commits: Vec<Commit>,
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::config::CONFIG_FILE_NAMES;
+use crate::lockfile::LOCK_FILE;
+use crate::messages;
+use crate::process::Process;
+use crate::templates::FLAKE_FILE;
+
+/**
+ * @struct HistoricalEnvironment
+ * @brief The files defining the shell at a past git revision, written to a temporary directory.
+ *
+ * The directory is removed when this is dropped.
+ */
+pub struct HistoricalEnvironment {
+    /// @brief The full commit hash the files were read from.
+    pub commit: String,
+    /// @brief The directory holding the files; a flake nix can evaluate directly.
+    pub dir: PathBuf,
+    /// @brief Whether flake.lock existed at that revision.
+    pub locked: bool,
+}
+
+impl HistoricalEnvironment {
+    /**
+     * @brief Reads flake.nix, flake.lock and the config from a revision with `git show`.
+     *
+     * Paths are taken relative to the current directory, so this works for projects
+     * in a subdirectory of the repository too.
+     *
+     * @param rev Any revision git understands (a hash, tag, `HEAD~3`, ...).
+     * @return Result<Self, Error> Returns an Error if the revision is unknown or had no flake.nix.
+     */
+    pub fn checkout(rev: &str) -> Result<Self, Error> {
+        let commit = resolve_commit(rev)?;
+        let short = &commit[..commit.len().min(12)];
+
+        let Some(flake) = show_file(&commit, FLAKE_FILE) else {
+            return Err(messages::error(
+                ErrorKind::NotFound,
+                messages::E_NOT_INITIALIZED,
+                format!("{} didn't have a {} here.", rev, FLAKE_FILE),
+            ));
+        };
+
+        let dir =
+            std::env::temp_dir().join(format!("nix-shell-gen-at-{}-{}", short, std::process::id()));
+        fs::create_dir_all(&dir)?;
+        // From here on, dropping the environment on an error removes the directory.
+        let mut environment = HistoricalEnvironment {
+            commit: commit.clone(),
+            dir,
+            locked: false,
+        };
+        environment.write(FLAKE_FILE, &flake)?;
+        if let Some(lock) = show_file(&commit, LOCK_FILE) {
+            environment.write(LOCK_FILE, &lock)?;
+            environment.locked = true;
+        }
+        for name in CONFIG_FILE_NAMES {
+            if let Some(config) = show_file(&commit, name) {
+                environment.write(name, &config)?;
+            }
+        }
+        Ok(environment)
+    }
+
+    /**
+     * @brief Returns the flake reference nix should evaluate.
+     * @param shell A named shell, or None for the default one.
+     */
+    pub fn flake_ref(&self, shell: Option<&str>) -> String {
+        match shell {
+            Some(name) => format!("path:{}#{}", self.dir.display(), name),
+            None => format!("path:{}", self.dir.display()),
+        }
+    }
+
+    /**
+     * @brief Writes one file into the directory.
+     */
+    fn write(&self, name: &str, contents: &[u8]) -> Result<(), Error> {
+        fs::write(self.dir.join(name), contents)
+    }
+}
+
+impl Drop for HistoricalEnvironment {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/**
+ * @brief Resolves a revision to a full commit hash.
+ * @param rev The revision as given by the user.
+ */
+fn resolve_commit(rev: &str) -> Result<String, Error> {
+    let output = Process::new("git")
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{}^{{commit}}", rev))
+        .output()
+        .map_err(|_| {
+            messages::error(
+                ErrorKind::NotFound,
+                messages::E_UNKNOWN_REVISION,
+                format!("'{}' is not a commit in this git repository.", rev),
+            )
+        })?;
+    Ok(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+/**
+ * @brief Reads a file, relative to the current directory, as it was in a commit.
+ * @return The contents, or None if the file didn't exist there.
+ */
+fn show_file(commit: &str, name: &str) -> Option<Vec<u8>> {
+    let path = Path::new(".").join(name);
+    Process::new("git")
+        .arg("show")
+        .arg(format!("{}:{}", commit, path.display()))
+        .output()
+        .ok()
+}
@@ -0,0 +1,219 @@
+use std::io::{Error, ErrorKind};
+
+use crate::config::{DevShellConfig, EnvValue, LintConfig, Severity};
+
+/// @brief Hook length `long-hook` allows without `max-hook-lines`.
+const DEFAULT_MAX_HOOK_LINES: usize = 10;
+
+/**
+ * @struct Rule
+ * @brief A lint rule, with the severity it has unless `[lint.severity]` changes it.
+ */
+pub struct Rule {
+    /// @brief The name used in `[lint.severity]` and in findings.
+    pub name: &'static str,
+    /// @brief Severity when the config doesn't set one.
+    pub default: Severity,
+}
+
+/// @brief Every lint rule.
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: "empty-hook",
+        default: Severity::Warning,
+    },
+    Rule {
+        name: "duplicate-package",
+        default: Severity::Warning,
+    },
+    Rule {
+        name: "path-override",
+        default: Severity::Error,
+    },
+    Rule {
+        name: "long-hook",
+        default: Severity::Info,
+    },
+];
+
+/**
+ * @struct Finding
+ * @brief One problem the lint found.
+ */
+#[derive(Debug)]
+pub struct Finding {
+    /// @brief The rule that found it.
+    pub rule: &'static str,
+    /// @brief Its severity in this project.
+    pub severity: Severity,
+    /// @brief What is wrong and where, in one sentence.
+    pub message: String,
+}
+
+/**
+ * @brief Lints the team config and the personal layer.
+ * @param team The team config, which also holds the `[lint]` settings.
+ * @param team_file Its file name, for messages.
+ * @param local The personal layer.
+ * @param local_file Its file name, for messages.
+ * @return Result<Vec<Finding>, Error> The findings, most severe first, or an Error if
+ *         `[lint.severity]` names an unknown rule.
+ */
+pub fn lint(
+    team: &DevShellConfig,
+    team_file: &str,
+    local: &DevShellConfig,
+    local_file: &str,
+) -> Result<Vec<Finding>, Error> {
+    let settings = &team.lint;
+    if let Some(unknown) = settings
+        .severity
+        .keys()
+        .find(|name| !RULES.iter().any(|rule| rule.name == name.as_str()))
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{}: [lint.severity] has no rule '{}'; the rules are {}.",
+                team_file,
+                unknown,
+                RULES
+                    .iter()
+                    .map(|rule| rule.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ));
+    }
+
+    let mut found = Vec::new();
+    let mut report = |rule: &'static str, message: String| {
+        let severity = severity(settings, rule);
+        if severity != Severity::Off {
+            found.push(Finding {
+                rule,
+                severity,
+                message,
+            });
+        }
+    };
+    for (file, config) in [(team_file, team), (local_file, local)] {
+        lint_layer(file, config, settings, &mut report);
+    }
+    for pkg in local.packages.intersection(&team.packages) {
+        report(
+            "duplicate-package",
+            format!(
+                "{}: '{}' is already in {}; run `nix-shell-gen fix` to remove it",
+                local_file, pkg, team_file
+            ),
+        );
+    }
+
+    found.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    Ok(found)
+}
+
+/**
+ * @brief Runs the rules that look at one config file on its own.
+ * @param file The file name, for messages.
+ * @param config The config in it.
+ * @param settings The `[lint]` settings.
+ * @param report Records a finding of a rule.
+ */
+fn lint_layer(
+    file: &str,
+    config: &DevShellConfig,
+    settings: &LintConfig,
+    report: &mut impl FnMut(&'static str, String),
+) {
+    let max_lines = settings.max_hook_lines.unwrap_or(DEFAULT_MAX_HOOK_LINES);
+    let hooks = config
+        .hooks
+        .iter()
+        .map(|hook| (format!("hook '{}'", hook.name), hook))
+        .chain(config.shells.iter().flat_map(|(name, shell)| {
+            shell
+                .hooks
+                .iter()
+                .map(move |hook| (format!("[shells.{}] hook '{}'", name, hook.name), hook))
+        }));
+    for (place, hook) in hooks {
+        let lines = hook
+            .run
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count();
+        if lines == 0 {
+            report(
+                "empty-hook",
+                format!("{}: {} is empty; remove it", file, place),
+            );
+        } else if lines > max_lines {
+            report(
+                "long-hook",
+                format!(
+                    "{}: {} has {} lines; consider moving it to a script file and running that",
+                    file, place, lines
+                ),
+            );
+        }
+    }
+
+    for (platform, packages) in &config.platform_packages {
+        for pkg in packages.intersection(&config.packages) {
+            report(
+                "duplicate-package",
+                format!(
+                    "{}: '{}' is in packages and in the {} packages; every platform gets it already",
+                    file, pkg, platform
+                ),
+            );
+        }
+        // "x86_64-linux" packages are added to the "linux" ones on that system.
+        if let Some((_, kernel)) = platform.split_once('-')
+            && let Some(wider) = config.platform_packages.get(kernel)
+        {
+            for pkg in packages.intersection(wider) {
+                report(
+                    "duplicate-package",
+                    format!(
+                        "{}: '{}' is in the {} packages and in the {} packages",
+                        file, pkg, kernel, platform
+                    ),
+                );
+            }
+        }
+    }
+
+    if let Some(value) = config.env.get("PATH") {
+        let extends = match value {
+            EnvValue::Value(value) => value,
+            EnvValue::Command { command } => command,
+        }
+        .contains("PATH");
+        if !extends {
+            report(
+                "path-override",
+                format!(
+                    "{}: [env] PATH = \"{}\" replaces the shell's PATH, hiding its packages; include $PATH, e.g. \"{}:$PATH\"",
+                    file, value, value
+                ),
+            );
+        }
+    }
+}
+
+/**
+ * @brief The severity of a rule in this project.
+ * @param settings The `[lint]` settings.
+ * @param rule The rule name.
+ */
+fn severity(settings: &LintConfig, rule: &str) -> Severity {
+    settings.severity.get(rule).copied().unwrap_or_else(|| {
+        RULES
+            .iter()
+            .find(|known| known.name == rule)
+            .map_or(Severity::Warning, |known| known.default)
+    })
+}
@@ -0,0 +1,293 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+pub const LOCK_FILE: &str = "flake.lock";
+
+/// @brief Represents the parts of flake.lock that nix-shell-gen inspects.
+///
+/// Only the node graph is modelled; fields the tool does not use are ignored.
+#[derive(Deserialize, Debug)]
+pub struct FlakeLock {
+    /// @brief All locked nodes, keyed by node name.
+    pub nodes: BTreeMap<String, LockNode>,
+
+    /// @brief Name of the root node (normally "root").
+    pub root: String,
+}
+
+/// @brief A single node of the lock graph.
+#[derive(Deserialize, Debug)]
+pub struct LockNode {
+    /// @brief Inputs of this node: a node name, or a `follows` path such as ["nixpkgs"].
+    #[serde(default)]
+    pub inputs: BTreeMap<String, Value>,
+
+    /// @brief The exact source the node is locked to.
+    pub locked: Option<SourceRef>,
+
+    /// @brief The source as written in flake.nix when the node was locked.
+    pub original: Option<SourceRef>,
+}
+
+/// @brief A locked flake reference.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceRef {
+    /// @brief Fetcher type, e.g. "github", "git", "path".
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    /// @brief Registry name, for "indirect" references such as `nixpkgs`.
+    pub id: Option<String>,
+    /// @brief Source URL, for "git", "tarball" and similar fetchers.
+    pub url: Option<String>,
+    /// @brief Branch or tag.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    pub rev: Option<String>,
+    /// @brief Commit time as a Unix timestamp.
+    pub last_modified: Option<u64>,
+}
+
+impl SourceRef {
+    /// @brief Returns the first seven characters of the locked revision.
+    pub fn short_rev(&self) -> &str {
+        self.rev
+            .as_deref()
+            .map_or("unknown", |rev| &rev[..rev.len().min(7)])
+    }
+
+    /// @brief Returns the commit date as YYYY-MM-DD, if known.
+    pub fn date(&self) -> Option<String> {
+        self.last_modified.map(format_date)
+    }
+
+    /// @brief Renders an unlocked reference back into flake URL syntax.
+    /// @return The URL, or None for fetchers this doesn't know how to render.
+    pub fn to_url(&self) -> Option<String> {
+        let ref_or_rev = self.git_ref.as_deref().or(self.rev.as_deref());
+        let mut url = match self.kind.as_str() {
+            "github" | "gitlab" | "sourcehut" => {
+                let mut url = format!(
+                    "{}:{}/{}",
+                    self.kind,
+                    self.owner.as_deref()?,
+                    self.repo.as_deref()?
+                );
+                if let Some(ref_or_rev) = ref_or_rev {
+                    url.push('/');
+                    url.push_str(ref_or_rev);
+                }
+                return Some(url);
+            }
+            "indirect" => {
+                let mut url = self.id.clone()?;
+                if let Some(ref_or_rev) = ref_or_rev {
+                    url.push('/');
+                    url.push_str(ref_or_rev);
+                }
+                return Some(url);
+            }
+            "git" | "hg" => format!("{}+{}", self.kind, self.url.as_deref()?),
+            "tarball" | "file" => self.url.clone()?,
+            _ => return None,
+        };
+        if let Some(git_ref) = &self.git_ref {
+            url.push_str(&format!("?ref={}", git_ref));
+        }
+        Some(url)
+    }
+
+    /// @brief Returns a flake reference pinned to this exact revision, for GitHub sources.
+    pub fn flake_ref(&self) -> Option<String> {
+        if self.kind != "github" {
+            return None;
+        }
+        Some(format!(
+            "github:{}/{}/{}",
+            self.owner.as_deref()?,
+            self.repo.as_deref()?,
+            self.rev.as_deref()?
+        ))
+    }
+}
+
+/// @brief A root input whose URL in flake.nix no longer matches what flake.lock was made from.
+#[derive(Debug)]
+pub struct StaleInput {
+    /// @brief The input key.
+    pub key: String,
+    /// @brief The URL in flake.nix.
+    pub declared: String,
+    /// @brief The URL the lock was made from.
+    pub locked_from: String,
+}
+
+/// @brief The revision change of one root input between two lock files.
+#[derive(Debug)]
+pub struct InputUpdate {
+    /// @brief The input key.
+    pub key: String,
+    /// @brief The previously locked source, if the input was locked before.
+    pub old: Option<SourceRef>,
+    /// @brief The newly locked source.
+    pub new: SourceRef,
+}
+
+impl FlakeLock {
+    /// @brief Loads LOCK_FILE from the current directory.
+    /// @return Ok(None) if the project has not been locked yet.
+    pub fn load() -> Result<Option<Self>, Error> {
+        match fs::read_to_string(LOCK_FILE) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// @brief Resolves a root input key to its lock node.
+    /// @param key The input key as used in flake.nix.
+    /// @return The node, or None if the input is not locked or only follows another input.
+    pub fn input_node(&self, key: &str) -> Option<&LockNode> {
+        let name = self.nodes.get(&self.root)?.inputs.get(key)?.as_str()?;
+        self.nodes.get(name)
+    }
+
+    /// @brief Checks whether an input locks its own copy of nixpkgs instead of following ours.
+    /// @param key The input key as used in flake.nix.
+    pub fn pulls_own_nixpkgs(&self, key: &str) -> bool {
+        self.input_node(key)
+            .and_then(|node| node.inputs.get("nixpkgs"))
+            .is_some_and(Value::is_string)
+    }
+
+    /// @brief Lists the dependencies of an input that it locks itself although the
+    /// flake has a top-level input of the same name it could follow.
+    /// @param key The input key as used in flake.nix.
+    /// @param root_keys The top-level input keys.
+    pub fn duplicated_deps(&self, key: &str, root_keys: &[&str]) -> Vec<String> {
+        self.input_node(key)
+            .map(|node| {
+                node.inputs
+                    .iter()
+                    .filter(|(dep, value)| {
+                        value.is_string() && *dep != key && root_keys.contains(&dep.as_str())
+                    })
+                    .map(|(dep, _)| dep.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// @brief Lists inputs whose flake.nix URL differs from the one they were locked from.
+    ///
+    /// Inputs whose original reference can't be rendered as a URL are skipped, so
+    /// only real mismatches are reported.
+    ///
+    /// @param declared (key, url) pairs from flake.nix.
+    pub fn stale_inputs<'a>(
+        &self,
+        declared: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Vec<StaleInput> {
+        declared
+            .into_iter()
+            .filter_map(|(key, url)| {
+                let original = self.input_node(key)?.original.as_ref()?;
+                let locked_from = original.to_url()?;
+                (normalize_url(url) != normalize_url(&locked_from)).then(|| StaleInput {
+                    key: key.to_string(),
+                    declared: url.to_string(),
+                    locked_from,
+                })
+            })
+            .collect()
+    }
+
+    /// @brief Lists the root inputs whose locked source differs from an older lock file.
+    /// @param before The lock file before the update, if there was one.
+    /// @return The changed inputs, sorted by key.
+    pub fn changes_since(&self, before: Option<&FlakeLock>) -> Vec<InputUpdate> {
+        let Some(root) = self.nodes.get(&self.root) else {
+            return Vec::new();
+        };
+
+        root.inputs
+            .keys()
+            .filter_map(|key| {
+                let new = self.input_node(key)?.locked.clone()?;
+                let old = before
+                    .and_then(|lock| lock.input_node(key))
+                    .and_then(|node| node.locked.clone());
+                (old.as_ref() != Some(&new)).then(|| InputUpdate {
+                    key: key.clone(),
+                    old,
+                    new,
+                })
+            })
+            .collect()
+    }
+}
+
+/**
+ * @brief Brings equivalent spellings of a flake URL to one form for comparison.
+ *
+ * GitHub-style owners and repos are case-insensitive, `?ref=x` is the same as a
+ * `/x` path segment, and a trailing slash or `.git` doesn't change the source.
+ *
+ * @param url A flake URL.
+ */
+fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let path = path.trim_end_matches(".git");
+    let git_ref = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("ref="));
+
+    let is_forge = ["github:", "gitlab:", "sourcehut:"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme));
+    if is_forge {
+        // Only owner and repo are case-insensitive; the ref is not.
+        let mut segments: Vec<String> = path.splitn(3, '/').map(str::to_string).collect();
+        for segment in segments.iter_mut().take(2) {
+            *segment = segment.to_lowercase();
+        }
+        let mut path = segments.join("/");
+        if let Some(git_ref) = git_ref {
+            path.push('/');
+            path.push_str(git_ref);
+        }
+        return path;
+    }
+    match git_ref {
+        Some(git_ref) => format!("{}?ref={}", path, git_ref),
+        None => path.to_string(),
+    }
+}
+
+/**
+ * @brief Formats a Unix timestamp as a UTC calendar date.
+ * @param timestamp Seconds since the Unix epoch.
+ * @return The date as YYYY-MM-DD.
+ */
+pub fn format_date(timestamp: u64) -> String {
+    // Howard Hinnant's days-to-civil algorithm.
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
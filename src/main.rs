@@ -1,9 +1,40 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::time::Duration;
 
+mod analysis;
+mod bundle;
+mod changes;
 mod commands;
 mod config;
+mod diagnostics;
+mod fixes;
 mod flake_editor;
+mod flake_outputs;
+mod git_hooks;
+mod github;
+mod history;
+mod lint;
+mod lockfile;
+mod messages;
+mod nix_info;
+mod nix_syntax;
+mod package_index;
+mod paths;
+mod policy;
+mod process;
+mod project_lock;
+mod recipes;
+mod registry;
+mod safety;
+mod server;
+mod settings;
+mod shell_nix;
+mod tasks;
+mod template_registry;
 mod templates;
+mod vendor;
+mod watch;
 
 /**
  * @brief A CLI to declaratively generate and manage Nix flake development shells.
@@ -13,94 +44,1747 @@ mod templates;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+/**
+ * @struct GlobalArgs
+ * @brief Options shared by all subcommands.
+ */
+#[derive(Parser, Debug, Clone)]
+struct GlobalArgs {
+    /**
+     * @brief Print the changes a command would make as a JSON plan instead of applying them.
+     * @details Apply the plan later with `nix-shell-gen apply --plan-file <file>`.
+     */
+    #[arg(long, global = true)]
+    plan: bool,
+
+    /**
+     * @brief Print the changes a command would make as a colored diff instead of applying them.
+     * @details Nothing is written; colors are used on a terminal unless $NO_COLOR is set.
+     */
+    #[arg(long, global = true, conflicts_with = "plan")]
+    dry_run: bool,
+
+    /**
+     * @brief Don't ask for confirmation before rewriting flake.nix.
+     */
+    #[arg(long, short = 'y', global = true)]
+    yes: bool,
+
+    /**
+     * @brief Kill nix (and other spawned tools) if a call runs longer than this many seconds.
+     * @details By default, calls may run as long as they need; Ctrl-C always stops them.
+     */
+    #[arg(long, global = true, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /**
+     * @brief Store cached data in this directory instead of the user cache dir.
+     * @details Can also be set with $NIX_SHELL_GEN_CACHE_DIR.
+     */
+    #[arg(long, global = true, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /**
+     * @brief Log how flake.nix edits are placed: matched nodes, offsets and the text around each edit.
+     */
+    #[arg(long, global = true)]
+    trace_edit: bool,
+
+    /**
+     * @brief Change the project even though it is frozen (see `freeze`).
+     */
+    #[arg(long, global = true)]
+    ignore_freeze: bool,
+
+    /**
+     * @brief Refuse any command that would change the project; inspection commands still work.
+     * @details Can also be turned on with $NIX_SHELL_GEN_READ_ONLY=1, e.g. in hermetic CI.
+     */
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /**
+     * @brief Report as JSON for scripts: errors with a stable code, and what changed.
+     * @details Errors print `{"ok": false, "error": {"code": "E_INPUT_EXISTS", "message": ...}}`
+     * on stdout; match on the code, not the message, which may change.
+     */
+    #[arg(long, global = true, conflicts_with = "dry_run")]
+    json: bool,
+}
+
+impl GlobalArgs {
+    /// @brief The timeout for spawned processes, if one was given.
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    /// @brief Whether the command only describes its changes (`--plan` or `--dry-run`).
+    fn previews(&self) -> bool {
+        self.plan || self.dry_run
+    }
+}
+
+/**
+ * @enum Commands
+ * @brief Supported subcommands for the CLI.
+ */
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /**
+     * @brief Initialize a new dev shell flake.
+     * @details Creates flake.nix and devshell.toml.
+     */
+    #[command(after_help = INIT_EXAMPLES)]
+    Init(InitArgs),
+
+    /**
+     * @brief Create a new project directory with git and a dev shell.
+     * @details Like `cargo new`: mkdir, `git init`, then `init` inside the directory.
+     */
+    #[command(after_help = NEW_EXAMPLES)]
+    New(NewArgs),
+
+    /**
+     * @brief Convert a legacy shell.nix (a `pkgs.mkShell` call) into devshell.toml and flake.nix.
+     * @details A docker-compose.yml instead contributes the nixpkgs packages of its services,
+     *          a Procfile its processes.
+     */
+    Import(ImportArgs),
+
+    /**
+     * @brief Take over a hand-written flake.nix: move its dev shells into devshell.toml and regenerate it.
+     */
+    Adopt(AdoptArgs),
+
+    /**
+     * @brief Add packages or hooks to an existing devshell.toml.
+     */
+    #[command(after_help = ADD_EXAMPLES)]
+    Add(Box<AddArgs>),
+
+    /**
+     * @brief Remove packages from devshell.toml.
+     */
+    Remove(RemoveArgs),
+
+    /**
+     * @brief Regenerate flake.nix from the current template, keeping its inputs.
+     */
+    Sync,
+
+    /**
+     * @brief Enter the dev shell via `nix develop`.
+     */
+    Enter(EnterArgs),
+
+    /**
+     * @brief Change a project setting, e.g. `set nixpkgs 24.11`.
+     */
+    Set(SetArgs),
+
+    /**
+     * @brief Show the value of a project setting, e.g. `get pure`.
+     */
+    Get(GetArgs),
+
+    /**
+     * @brief Clear a project setting so its default applies again.
+     */
+    Unset(UnsetArgs),
+
+    /**
+     * @brief Freeze the environment: commands that change it refuse to run until `thaw`.
+     * @details Recorded in .nix-shell-gen/frozen, which is committed with the project.
+     */
+    Freeze(FreezeArgs),
+
+    /**
+     * @brief Lift a freeze made with `freeze`.
+     */
+    Thaw,
+
+    /**
+     * @brief Copy all flake inputs into the project and point flake.nix at the copies.
+     * @details Makes the shell buildable offline; `unvendor` undoes it.
+     */
+    Vendor(VendorArgs),
+
+    /**
+     * @brief Point vendored inputs back at their original URLs and remove the copies.
+     */
+    Unvendor,
+
+    /**
+     * @brief Run a script (`[scripts]`) or a stdenv build phase inside the dev shell.
+     * @details Phases use `nix develop --<phase>`, for debugging a build the way Nix would run it.
+     */
+    Run(RunArgs),
+
+    /**
+     * @brief Report (or apply) evaluation performance improvements for flake.nix.
+     */
+    Tune(TuneArgs),
+
+    /**
+     * @brief Time entering the dev shell, and with `--hooks` each part of its shellHook.
+     */
+    Bench(BenchArgs),
+
+    /**
+     * @brief List, remove or reorder the shell hooks (`[[hooks]]`).
+     */
+    Hooks(HooksArgs),
+
+    /**
+     * @brief Make inputs reuse the project's nixpkgs and other shared inputs via `follows`.
+     * @details Shrinks flake.lock to one copy of each shared input; see the README.
+     */
+    Dedupe(DedupeArgs),
+
+    /**
+     * @brief Export or import a shareable environment bundle.
+     */
+    Bundle(BundleArgs),
+
+    /**
+     * @brief List the language templates available to `init --lang`.
+     */
+    Templates(TemplatesArgs),
+
+    /**
+     * @brief Run a command against the environment as of a past git revision.
+     */
+    #[command(after_help = AT_EXAMPLES)]
+    At(AtArgs),
+
+    /**
+     * @brief Apply a plan previously written with `--plan`.
+     */
+    Apply(ApplyArgs),
+
+    /**
+     * @brief Update flake inputs and report the revision changes.
+     */
+    Update(UpdateArgs),
+
+    /**
+     * @brief Check that Nix and the project files are set up correctly.
+     */
+    Doctor(DoctorArgs),
+
+    /**
+     * @brief Apply safe fixes for common problems, confirming each one.
+     * @details Removes duplicate packages, renames deprecated attributes, declares missing
+     * inputs and re-syncs flake.nix; see the README.
+     */
+    Fix,
+
+    /**
+     * @brief Generate .devcontainer/devcontainer.json for VS Code dev containers and Codespaces.
+     * @details The container gets Nix, builds the dev shell on creation and opens terminals inside it.
+     */
+    Devcontainer(DevcontainerArgs),
+
+    /**
+     * @brief Generate files that set the environment up elsewhere.
+     */
+    Export(ExportArgs),
+
+    /**
+     * @brief Write or complete the .envrc that loads the shell through direnv.
+     * @details An existing .envrc is extended, never replaced; `--allow` runs `direnv allow` afterwards.
+     */
+    Direnv(DirenvArgs),
+
+    /**
+     * @brief Show whether the project's lock matches its flake and how old its nixpkgs is.
+     * @details `--all` does the same for every project in the registry (see `projects`).
+     */
+    Status(StatusArgs),
+
+    /**
+     * @brief List the projects nix-shell-gen has initialized or entered on this machine.
+     */
+    Projects(ProjectsArgs),
+
+    /**
+     * @brief Show the `[ports]` of the project and whether something already listens on them.
+     * @details `--auto-offset` shifts them all to free ones through port-offset in devshell.local.toml.
+     */
+    Ports(PortsArgs),
+
+    /**
+     * @brief Install git hooks that run `doctor` after checkouts and merges.
+     */
+    InstallGitHooks(InstallGitHooksArgs),
+
+    /**
+     * @brief Show ready-made recipes, or scaffold one.
+     */
+    #[command(after_help = GUIDE_EXAMPLES)]
+    Guide(GuideArgs),
+
+    /**
+     * @brief Check that packages exist in the project's nixpkgs.
+     */
+    Check(CheckArgs),
+
+    /**
+     * @brief Search nixpkgs for packages and add the chosen ones to the shell.
+     */
+    Search(SearchArgs),
+
+    /**
+     * @brief Show what a flake input exposes and pull outputs into the shell.
+     */
+    Inspect(InspectArgs),
+
+    /**
+     * @brief Show the flake and what the shell will contain, without running nix.
+     */
+    Render(RenderArgs),
+
+    /**
+     * @brief Show the effective configuration: packages, flake inputs, hooks and purity.
+     */
+    #[command(visible_alias = "list")]
+    Show,
+
+    /**
+     * @brief Inspect the repository and propose a devshell.toml for it.
+     * @details Looks for languages, build tools, docker-compose services and ports;
+     *          `init --from-analysis` applies the proposal.
+     */
+    Analyze,
+
+    /**
+     * @brief Report which nix-shell-gen shell the current process runs in.
+     */
+    Current,
+
+    /**
+     * @brief Serve JSON-RPC requests from editor extensions.
+     */
+    Serve(ServeArgs),
+
+    /**
+     * @brief Print a short status for shell prompts (starship, powerlevel10k).
+     */
+    PromptSegment,
+}
+
+const INIT_EXAMPLES: &str = "Examples:
+  nix-shell-gen init --lang rust
+  nix-shell-gen init --lang python -p 'uv ruff'
+  nix-shell-gen init -P github:nix-community/crane --lock
+  nix-shell-gen guide                      List ready-made recipes";
+
+const NEW_EXAMPLES: &str = "Examples:
+  nix-shell-gen new myproject --lang rust --scaffold
+  nix-shell-gen new tool --lang python --direnv";
+
+const ADD_EXAMPLES: &str = "Examples:
+  nix-shell-gen add -p 'jq ripgrep'
+  nix-shell-gen add -s 'export RUST_LOG=debug'
+  nix-shell-gen add -P github:numtide/flake-utils --as futils
+  nix-shell-gen add --local -p htop        Personal, gitignored addition";
+
+const AT_EXAMPLES: &str = "Examples:
+  nix-shell-gen at v1.2.0 enter
+  nix-shell-gen at HEAD~20 enter --shell backend";
+
+const GUIDE_EXAMPLES: &str = "Examples:
+  nix-shell-gen guide                      List topics
+  nix-shell-gen guide rust+postgres        Show the recipe
+  nix-shell-gen guide python-ml --scaffold Create it in the current directory";
+
+/**
+ * @struct InitArgs
+ * @brief Arguments for the `init` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct InitArgs {
+    /**
+     * @brief The primary language template (e.g., "cpp", "rust", "python").
+     * @details node and python take a version: "node@20" (nodejs_20), "python@3.11" (python311).
+     */
+    #[arg(short = 'l', long)]
+    lang: Option<String>,
+
+    /**
+     * @brief Extra Nixpkgs packages to add (space-separated).
+     */
+    #[arg(short = 'p', long, value_delimiter = ' ', num_args = 0..)]
+    packages: Vec<String>,
+
+    /**
+     * @brief Extra flake inputs to add (space-separated URLs).
+     * @details Example: "github:nix-community/crane" "github:ocornut/imgui"
+     */
+    #[arg(short = 'P', long, value_delimiter = ' ', num_args = 0..)]
+    inputs: Vec<String>,
+
+    /**
+     * @brief A shell hook command to run.
+     */
+    #[arg(short = 's', long)]
+    shell_hook: Option<String>,
+
+    /**
+     * @brief Create an isolated, pure shell (default is an impure shell).
+     */
+    #[arg(long)]
+    isolated: bool,
+
+    /**
+     * @brief Accept shell hooks that pipe downloads into a shell, use sudo, or write outside the project.
+     */
+    #[arg(long)]
+    allow_unsafe_hooks: bool,
+
+    /**
+     * @brief Overwrite existing flake.nix and devshell.toml.
+     */
+    #[arg(long)]
+    force: bool,
+
+    /**
+     * @brief Run `nix flake lock` after generating the files, creating flake.lock.
+     */
+    #[arg(long)]
+    lock: bool,
+
+    /**
+     * @brief Build and enter the shell once (`nix develop --command true`) to check that it works.
+     */
+    #[arg(long)]
+    build: bool,
+
+    /**
+     * @brief Also create the template's starter project files (e.g. Cargo.toml and src/main.rs); existing files are kept.
+     */
+    #[arg(long, requires = "lang")]
+    scaffold: bool,
+
+    /**
+     * @brief Seed `[scripts]` with the templates' test, build and lint commands (e.g. `cargo test`).
+     */
+    #[arg(long)]
+    scripts: bool,
+
+    /**
+     * @brief The package manager of the node template; pnpm by default.
+     */
+    #[arg(long, value_enum, value_name = "TOOL")]
+    package_manager: Option<config::NodePackageManager>,
+
+    /**
+     * @brief nixpkgs to build from: a release ("24.05"), a branch ("nixpkgs-unstable") or a flake URL.
+     * @details Defaults to the `nixpkgs-channel` setting, then "nixos-unstable". An explicit
+     * choice is recorded in devshell.toml, so `sync` keeps it.
+     */
+    #[arg(long, visible_alias = "nixpkgs")]
+    channel: Option<String>,
+
+    /**
+     * @brief Pick the language templates from the project's files (Cargo.toml, package.json, go.mod, ...).
+     * @details Several detected languages merge their templates into one shell.
+     */
+    #[arg(long, conflicts_with = "lang")]
+    detect: bool,
+
+    /**
+     * @brief Use the templates and packages `analyze` proposes for this repository.
+     * @details Combines with --lang, which then comes first.
+     */
+    #[arg(long)]
+    from_analysis: bool,
+
+    /**
+     * @brief Generate flake.nix (default) or, for Nix without flakes, a shell.nix pinned to a nixpkgs tarball.
+     * @details Recorded as `format` in devshell.toml, so `sync` and `enter` follow it.
+     */
+    #[arg(long, value_enum)]
+    format: Option<config::ShellFormat>,
+
+    /**
+     * @brief Write an .envrc that loads the shell through direnv (and nix-direnv, if installed) on `cd`.
+     */
+    #[arg(long)]
+    direnv: bool,
+
+    /**
+     * @brief Never ask questions; a bare `init` on a terminal otherwise starts the setup wizard.
+     */
+    #[arg(long)]
+    no_interactive: bool,
+
+    /**
+     * @brief Write the packages (-p) without checking that nixpkgs has them.
+     */
+    #[arg(long)]
+    no_verify: bool,
+}
+
+impl InitArgs {
+    /// @brief Whether no option was given, which is when `init` offers the setup wizard.
+    fn is_bare(&self) -> bool {
+        self.lang.is_none()
+            && self.packages.is_empty()
+            && self.inputs.is_empty()
+            && self.shell_hook.is_none()
+            && !self.isolated
+            && !self.allow_unsafe_hooks
+            && !self.force
+            && !self.lock
+            && !self.build
+            && !self.scaffold
+            && !self.scripts
+            && self.package_manager.is_none()
+            && self.channel.is_none()
+            && !self.from_analysis
+            && !self.detect
+            && self.format.is_none()
+            && !self.direnv
+            && !self.no_verify
+    }
+}
+
+/**
+ * @struct ImportArgs
+ * @brief Arguments for the `import` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct ImportArgs {
+    /**
+     * @brief The file to import: a shell.nix, a compose file (*.yml, *.yaml) or a Procfile.
+     */
+    #[arg(default_value = "shell.nix")]
+    file: PathBuf,
+
+    /**
+     * @brief Overwrite existing flake.nix and devshell.toml.
+     */
+    #[arg(long)]
+    force: bool,
+
+    /**
+     * @brief Accept a shell hook that pipes downloads into a shell, uses sudo, or writes outside the project.
+     */
+    #[arg(long)]
+    allow_unsafe_hooks: bool,
+
+    /**
+     * @brief nixpkgs to build from: a release ("24.05"), a branch or a flake URL.
+     * @details Defaults to the `nixpkgs-channel` setting, then "nixos-unstable".
+     */
+    #[arg(long, visible_alias = "nixpkgs")]
+    channel: Option<String>,
+}
+
+/**
+ * @struct AdoptArgs
+ * @brief Arguments for the `adopt` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct AdoptArgs {
+    /**
+     * @brief Adopt even if devshell.toml exists, overwriting it.
+     */
+    #[arg(long)]
+    force: bool,
+
+    /**
+     * @brief Accept shell hooks that pipe downloads into a shell, use sudo, or write outside the project.
+     */
+    #[arg(long)]
+    allow_unsafe_hooks: bool,
+}
+
+/**
+ * @struct NewArgs
+ * @brief Arguments for the `new` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct NewArgs {
+    /**
+     * @brief The directory to create.
+     */
+    dir: PathBuf,
+
+    #[command(flatten)]
+    init: InitArgs,
+}
+
+/**
+ * @struct AddArgs
+ * @brief Arguments for the `add` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct AddArgs {
+    /**
+     * @brief Nixpkgs packages to add (space-separated).
+     * @details `pkg@<rev>` takes a package from nixpkgs at that revision or branch instead.
+     */
+    #[arg(short = 'p', long, value_delimiter = ' ', num_args = 0..)]
+    packages: Vec<String>,
+
+    /**
+     * @brief Libraries to link against (space-separated), e.g. "openssl zlib".
+     * @details Added to `libraries`: buildInputs, with pkg-config and PKG_CONFIG_PATH set up to find them.
+     */
+    #[arg(long = "lib", value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    libs: Vec<String>,
+
+    /**
+     * @brief Tools the build runs (space-separated), e.g. "cmake protobuf".
+     * @details Added to `tools`, which go in nativeBuildInputs.
+     */
+    #[arg(long = "tool", value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    tools: Vec<String>,
+
+    /**
+     * @brief Flake inputs to add (space-separated URLs).
+     * @details This will automatically edit your flake.nix.
+     */
+    #[arg(short = 'P', long, value_delimiter = ' ', num_args = 0..)]
+    inputs: Vec<String>,
+
+    /**
+     * @brief A shell hook command to append.
+     */
+    #[arg(short = 's', long)]
+    shell_hook: Option<String>,
+
+    /**
+     * @brief The name of the hook (-s), for `hooks remove` and `hooks move`; "shell-hook" if not given.
+     */
+    #[arg(long, value_name = "NAME", requires = "shell_hook")]
+    hook_name: Option<String>,
+
+    /**
+     * @brief Write to the gitignored personal layer (devshell.local.toml) instead of the team config.
+     * @details The personal layer is merged on top of the team config (requires --impure).
+     */
+    #[arg(long)]
+    local: bool,
+
+    /**
+     * @brief Add the inputs (-P) to flake.nix without adding their default package.
+     * @details For overlays, library-only flakes and non-flake sources.
+     */
+    #[arg(long, requires = "inputs", conflicts_with = "package_only")]
+    input_only: bool,
+
+    /**
+     * @brief Add the default package of inputs (-P) that are already in flake.nix, without editing it.
+     */
+    #[arg(long, requires = "inputs")]
+    package_only: bool,
+
+    /**
+     * @brief Declare the inputs (-P) as plain sources (`flake = false`); implies --input-only.
+     */
+    #[arg(long, requires = "inputs", conflicts_with = "package_only")]
+    no_flake: bool,
+
+    /**
+     * @brief Let the inputs (-P) lock their own nixpkgs instead of following the project's.
+     */
+    #[arg(long, requires = "inputs", conflicts_with = "package_only")]
+    no_follows: bool,
+
+    /**
+     * @brief Key to declare the input (-P) under, instead of the one derived from its URL.
+     */
+    #[arg(long = "as", value_name = "KEY", requires = "inputs")]
+    as_key: Option<String>,
+
+    /**
+     * @brief Output of the input (-P) to use instead of `packages.${system}.default`.
+     * @details A package name ("cli") or a full path such as "packages.${system}.cli" or
+     * "overlays.default"; same as writing `<url>#<attr>`.
+     */
+    #[arg(long, value_name = "ATTR", requires = "inputs", conflicts_with_all = ["input_only", "no_flake"])]
+    attr: Option<String>,
+
+    /**
+     * @brief Python packages to make importable (space-separated), e.g. "requests numpy".
+     * @details Added to `[python]`, which builds one interpreter with all of them.
+     */
+    #[arg(long, value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    python: Vec<String>,
+
+    /**
+     * @brief Global Node packages (space-separated), e.g. "typescript prettier".
+     * @details Added to `[node]` and taken from nodePackages, or from the package set of the
+     * chosen Node version (e.g. nodejs_20.pkgs).
+     */
+    #[arg(long, value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    node: Vec<String>,
+
+    /**
+     * @brief Apply an overlay of a flake input to nixpkgs (INPUT or INPUT#NAME, repeatable).
+     * @details `rust-overlay` registers "rust-overlay.overlays.default". Well-known inputs
+     * such as rust-overlay and fenix are added to flake.nix if missing.
+     */
+    #[arg(long = "overlay", value_name = "INPUT", conflicts_with_all = ["shell", "platform"])]
+    overlays: Vec<String>,
+
+    /**
+     * @brief Add the packages (-p) only on this platform: "linux", "darwin" or a system like "aarch64-darwin".
+     */
+    #[arg(long, requires = "packages", conflicts_with = "shell")]
+    platform: Option<String>,
+
+    /**
+     * @brief Environment variables to set in the shell (KEY=VALUE, repeatable).
+     * @details Changing a variable that is already set is refused; edit the config for that.
+     */
+    #[arg(long = "env", value_name = "KEY=VALUE", conflicts_with = "shell")]
+    env: Vec<String>,
+
+    /**
+     * @brief Environment variables computed on entering the shell (KEY=COMMAND, repeatable).
+     * @details E.g. `GIT_SHA="git rev-parse --short HEAD"`; checked like shell hooks.
+     */
+    #[arg(
+        long = "env-command",
+        value_name = "KEY=COMMAND",
+        conflicts_with = "shell"
+    )]
+    env_command: Vec<String>,
+
+    /**
+     * @brief Raw Nix expressions to add as packages (repeatable), e.g. `"pkgs.callPackage ./nix/tool.nix {}"`.
+     * @details Copied into flake.nix verbatim, for packages that aren't an attribute path.
+     */
+    #[arg(long = "expr", value_name = "EXPR", conflicts_with_all = ["shell", "local", "platform"])]
+    exprs: Vec<String>,
+
+    /**
+     * @brief Nix files in the project to build with `pkgs.callPackage` (repeatable), e.g. `./nix/mytool.nix`.
+     * @details Kept in `local-packages`; with flakes, the files must be tracked by git.
+     */
+    #[arg(long = "local-package", value_name = "FILE", conflicts_with_all = ["shell", "platform"])]
+    local_packages: Vec<String>,
+
+    /**
+     * @brief Long-running processes for `dev up` (NAME=COMMAND, repeatable), e.g. `web="pnpm dev"`.
+     */
+    #[arg(
+        long = "process",
+        value_name = "NAME=COMMAND",
+        conflicts_with = "shell"
+    )]
+    processes: Vec<String>,
+
+    /**
+     * @brief Scripts to add to `[scripts]` (NAME=COMMAND, repeatable), e.g. `test="cargo test"`.
+     * @details Each becomes a command in the shell and a flake app (`nix run .#test`).
+     */
+    #[arg(
+        long = "script",
+        value_name = "NAME=COMMAND",
+        conflicts_with_all = ["shell", "platform"]
+    )]
+    scripts: Vec<String>,
+
+    /**
+     * @brief Ports the project's services use (NAME=PORT, repeatable); exported as `<NAME>_PORT`.
+     */
+    #[arg(long = "port", value_name = "NAME=PORT", conflicts_with = "shell")]
+    ports: Vec<String>,
+
+    /**
+     * @brief Add to the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+
+    /**
+     * @brief Add packages (-p) without checking that nixpkgs has them.
+     */
+    #[arg(long)]
+    no_verify: bool,
+
+    /**
+     * @brief Accept shell hooks that pipe downloads into a shell, use sudo, or write outside the project.
+     */
+    #[arg(long)]
+    allow_unsafe_hooks: bool,
+}
+
+/**
+ * @struct RemoveArgs
+ * @brief Arguments for the `remove` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct RemoveArgs {
+    /**
+     * @brief Packages to remove (space-separated).
+     */
+    #[arg(
+        short = 'p',
+        long,
+        value_delimiter = ' ',
+        num_args = 1..,
+        required_unless_present_any = ["exprs", "local_packages", "overlays", "python", "node", "libs", "tools", "scripts", "inputs"]
+    )]
+    packages: Vec<String>,
+
+    /**
+     * @brief Package expressions to remove (`expr-packages`), written as they were added.
+     */
+    #[arg(long = "expr", value_name = "EXPR", conflicts_with_all = ["shell", "local", "platform"])]
+    exprs: Vec<String>,
+
+    /**
+     * @brief Nix files to remove from `local-packages`.
+     */
+    #[arg(long = "local-package", value_name = "FILE", conflicts_with_all = ["shell", "platform"])]
+    local_packages: Vec<String>,
+
+    /**
+     * @brief Python packages to remove from `[python]` (space-separated).
+     */
+    #[arg(long, value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    python: Vec<String>,
+
+    /**
+     * @brief Scripts to remove from `[scripts]`, by name.
+     */
+    #[arg(long = "script", value_name = "NAME", conflicts_with_all = ["shell", "platform"])]
+    scripts: Vec<String>,
+
+    /**
+     * @brief Libraries to remove from `libraries` (space-separated).
+     */
+    #[arg(long = "lib", value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    libs: Vec<String>,
+
+    /**
+     * @brief Build tools to remove from `tools` (space-separated).
+     */
+    #[arg(long = "tool", value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    tools: Vec<String>,
+
+    /**
+     * @brief Node packages to remove from `[node]` (space-separated).
+     */
+    #[arg(long, value_name = "PKG", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "platform"])]
+    node: Vec<String>,
+
+    /**
+     * @brief Overlays to stop applying (INPUT or INPUT#NAME, as given to `add --overlay`).
+     * @details The input stays in flake.nix.
+     */
+    #[arg(long = "overlay", value_name = "INPUT", conflicts_with_all = ["shell", "platform"])]
+    overlays: Vec<String>,
+
+    /**
+     * @brief Flake inputs to remove from flake.nix, by key (space-separated).
+     * @details Follows lines pointing at them and their overlays go too.
+     */
+    #[arg(short = 'P', long, value_name = "KEY", value_delimiter = ' ', num_args = 1.., conflicts_with_all = ["shell", "local", "platform"])]
+    inputs: Vec<String>,
+
+    /**
+     * @brief Remove from the personal layer (devshell.local.toml) instead of the team config.
+     */
+    #[arg(long)]
+    local: bool,
+
+    /**
+     * @brief Remove from the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+
+    /**
+     * @brief Remove from the packages of one platform (`[platform-packages]`).
+     */
+    #[arg(long, conflicts_with = "shell")]
+    platform: Option<String>,
+}
+
+/**
+ * @struct EnterArgs
+ * @brief Arguments for the `enter` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct EnterArgs {
+    /**
+     * @brief Maintain a GC-rooted profile in .nix-shell-gen/shell-profile.
+     * @details Re-entering reuses the saved profile until flake.nix, flake.lock
+     * or devshell.toml change, skipping flake evaluation entirely.
+     */
+    #[arg(long)]
+    profile_link: bool,
+
+    /**
+     * @brief Enter the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME", conflicts_with = "profile_link")]
+    shell: Option<String>,
+
+    /**
+     * @brief Enter the shell on another machine over SSH (`user@host` or an ssh_config alias).
+     * @details The project is copied there with rsync first; the host needs Nix, rsync and a login shell.
+     */
+    #[arg(long, value_name = "HOST", conflicts_with = "profile_link")]
+    on: Option<String>,
+
+    /**
+     * @brief Where the project is copied to on the host, relative to the remote home directory.
+     * @details Defaults to .cache/nix-shell-gen/remote/<project directory name>.
+     */
+    #[arg(long, value_name = "DIR", requires = "on")]
+    remote_dir: Option<String>,
+
+    /**
+     * @brief Also push the locked flake inputs with `nix flake archive`, for hosts that can't fetch them.
+     */
+    #[arg(long, requires = "on")]
+    copy_inputs: bool,
+}
+
+/**
+ * @struct SetArgs
+ * @brief Arguments for the `set` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct SetArgs {
+    /**
+     * @brief The setting to change.
+     */
+    #[arg(value_enum)]
+    key: SettingKey,
+
+    /**
+     * @brief The new value.
+     */
+    value: String,
+
+    /**
+     * @brief Set it in the personal, gitignored config instead of the team's.
+     */
+    #[arg(long)]
+    local: bool,
+}
+
+/**
+ * @struct GetArgs
+ * @brief Arguments for the `get` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct GetArgs {
+    /**
+     * @brief The setting to show.
+     */
+    #[arg(value_enum)]
+    key: SettingKey,
+}
+
+/**
+ * @struct UnsetArgs
+ * @brief Arguments for the `unset` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct UnsetArgs {
+    /**
+     * @brief The setting to clear, so its default applies again.
+     */
+    #[arg(value_enum)]
+    key: SettingKey,
+
+    /**
+     * @brief Clear it in the personal, gitignored config instead of the team's.
+     */
+    #[arg(long)]
+    local: bool,
+}
+
+/**
+ * @enum SettingKey
+ * @brief Project settings `set`, `get` and `unset` work on.
+ */
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SettingKey {
+    /// @brief The generated file: "flake" or "shell-nix".
+    Format,
+    /// @brief Whether `enter` uses a pure shell: "true" or "false".
+    Pure,
+    /// @brief Whether the shell gets cacert and SSL_CERT_FILE: "true" or "false".
+    Cacert,
+    /// @brief Days after which entering reminds you to update nixpkgs; "0" turns it off.
+    UpdateReminderDays,
+    /// @brief Added to every `[ports]` entry, usually in the personal config.
+    PortOffset,
+    /// @brief The nixpkgs the shell is built from: a release ("24.05"), a branch or a flake URL.
+    Nixpkgs,
+    /// @brief The Python of `[python]`: a version like "3.11" (python311).
+    PythonVersion,
+    /// @brief The Node.js of `[node]`: a major version like "20" (nodejs_20).
+    NodeVersion,
+    /// @brief Rust from rust-overlay: a channel ("stable", "nightly-2025-01-01", "1.78.0") or "./rust-toolchain.toml".
+    RustToolchain,
+    /// @brief The package manager of `[node]`: "npm", "pnpm", "yarn" or "corepack".
+    PackageManager,
+}
+
+/**
+ * @struct FreezeArgs
+ * @brief Arguments for the `freeze` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct FreezeArgs {
+    /**
+     * @brief Why the environment is frozen, shown when a command is refused.
+     */
+    #[arg(long)]
+    reason: Option<String>,
+}
+
+/**
+ * @struct VendorArgs
+ * @brief Arguments for the `vendor` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct VendorArgs {
+    /**
+     * @brief Directory to copy the inputs into, relative to the project.
+     */
+    #[arg(long, default_value = "vendor")]
+    dir: PathBuf,
+}
+
+/**
+ * @struct RunArgs
+ * @brief Arguments for the `run` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct RunArgs {
+    /**
+     * @brief The script to run, from `[scripts]`.
+     */
+    #[arg(required_unless_present_any = ["phase", "all"], conflicts_with = "phase")]
+    script: Option<String>,
+
+    /**
+     * @brief Arguments passed on to the script.
+     */
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        requires = "script"
+    )]
+    args: Vec<String>,
+
+    /**
+     * @brief The phase to run; `[mk-shell]` attributes like configureFlags or buildPhase shape it.
+     */
+    #[arg(long, value_enum)]
+    phase: Option<Phase>,
+
+    /**
+     * @brief Run the script's `depends-on` first, independent scripts at once, with each line
+     *        prefixed by the script's name; without a script, run every script.
+     */
+    #[arg(long, conflicts_with_all = ["phase", "args"])]
+    all: bool,
+
+    /**
+     * @brief Run the phase in the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+
+    /**
+     * @brief Run it again whenever files matching the glob change, until Ctrl-C; repeatable.
+     */
+    #[arg(long, value_name = "GLOB")]
+    watch: Vec<String>,
+
+    /**
+     * @brief Run in a saved `nix print-dev-env` snapshot instead of evaluating the flake.
+     * @details The snapshot is exported again when flake.nix, flake.lock or the config changed.
+     */
+    #[arg(long, conflicts_with = "phase")]
+    cached_env: bool,
+}
+
+/**
+ * @enum Phase
+ * @brief The stdenv phases `nix develop` can run on its own.
+ */
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Phase {
+    /// @brief Unpack `src` (unpackPhase).
+    Unpack,
+    /// @brief Run configure, cmake, meson... (configurePhase).
+    Configure,
+    /// @brief Run make or the build hook (buildPhase).
+    Build,
+    /// @brief Run the tests (checkPhase).
+    Check,
+    /// @brief Install into $out (installPhase).
+    Install,
+    /// @brief Run the installed-output tests (installCheckPhase).
+    Installcheck,
+}
+
+impl Phase {
+    /// @brief The `nix develop` flag that runs the phase, e.g. "--configure".
+    fn develop_flag(self) -> &'static str {
+        match self {
+            Phase::Unpack => "--unpack",
+            Phase::Configure => "--configure",
+            Phase::Build => "--build",
+            Phase::Check => "--check",
+            Phase::Install => "--install",
+            Phase::Installcheck => "--installcheck",
+        }
+    }
+
+    /// @brief The stdenv hook name of the phase, e.g. "configurePhase".
+    fn hook(self) -> &'static str {
+        match self {
+            Phase::Unpack => "unpackPhase",
+            Phase::Configure => "configurePhase",
+            Phase::Build => "buildPhase",
+            Phase::Check => "checkPhase",
+            Phase::Install => "installPhase",
+            Phase::Installcheck => "installCheckPhase",
+        }
+    }
+}
+
+/**
+ * @struct DoctorArgs
+ * @brief Arguments for the `doctor` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct DoctorArgs {
+    /**
+     * @brief Only print failed checks.
+     */
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+/**
+ * @struct DevcontainerArgs
+ * @brief Arguments for the `devcontainer` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct DevcontainerArgs {
+    /**
+     * @brief Open terminals in the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+
+    /**
+     * @brief Overwrite an existing devcontainer.json.
+     */
+    #[arg(long)]
+    force: bool,
+}
+
+/**
+ * @struct ExportArgs
+ * @brief Arguments for the `export` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct ExportArgs {
+    #[command(subcommand)]
+    action: ExportAction,
+}
+
+/**
+ * @enum ExportAction
+ * @brief What `export` generates.
+ */
+#[derive(Subcommand, Debug)]
+enum ExportAction {
+    /**
+     * @brief Write bootstrap.sh, which installs Nix, sets up the `[substituters]` and enters the shell.
+     * @details For onboarding: new team members clone the project and run `sh bootstrap.sh`.
+     */
+    Bootstrap(ExportBootstrapArgs),
+}
+
+/**
+ * @struct ExportBootstrapArgs
+ * @brief Arguments for `export bootstrap`.
+ */
+#[derive(Parser, Debug)]
+struct ExportBootstrapArgs {
+    /**
+     * @brief The installer the script runs where Nix is missing.
+     */
+    #[arg(long, value_enum, default_value = "determinate")]
+    installer: templates::NixInstaller,
+
+    /**
+     * @brief Enter the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+
+    /**
+     * @brief Overwrite an existing bootstrap.sh.
+     */
+    #[arg(long)]
+    force: bool,
+}
+
+/**
+ * @struct DirenvArgs
+ * @brief Arguments for the `direnv` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct DirenvArgs {
+    /**
+     * @brief Run `direnv allow` once .envrc is written.
+     */
+    #[arg(long)]
+    allow: bool,
+}
+
+/**
+ * @struct StatusArgs
+ * @brief Arguments for the `status` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct StatusArgs {
+    /**
+     * @brief Report on every registered project instead of the current one.
+     */
+    #[arg(long)]
+    all: bool,
+}
+
+/**
+ * @struct DedupeArgs
+ * @brief Arguments for the `dedupe` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct DedupeArgs {
+    /**
+     * @brief Member projects in subdirectories whose shared inputs the root flake unifies.
+     */
+    #[arg(long, value_name = "DIR", num_args = 1..)]
+    members: Vec<PathBuf>,
+}
+
+/**
+ * @struct ProjectsArgs
+ * @brief Arguments for the `projects` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct ProjectsArgs {
+    /**
+     * @brief Forget projects whose directory or config no longer exists.
+     */
+    #[arg(long)]
+    prune: bool,
+}
+
+/**
+ * @struct PortsArgs
+ * @brief Arguments for the `ports` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct PortsArgs {
+    /**
+     * @brief Pick the smallest port-offset (a multiple of 10) that frees every port, and save it to the personal config.
+     */
+    #[arg(long)]
+    auto_offset: bool,
+}
+
+/**
+ * @struct InstallGitHooksArgs
+ * @brief Arguments for the `install-git-hooks` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct InstallGitHooksArgs {
+    /**
+     * @brief Replace existing hooks that nix-shell-gen didn't write.
+     */
+    #[arg(long)]
+    force: bool,
+
+    /**
+     * @brief Also regenerate the files with `nix-shell-gen sync` after each checkout or merge.
+     */
+    #[arg(long)]
+    sync: bool,
+}
+
+/**
+ * @struct SearchArgs
+ * @brief Arguments for the `search` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct SearchArgs {
+    /**
+     * @brief What to look for; packages must match every term (e.g. "json" "cli").
+     */
+    #[arg(required = true)]
+    terms: Vec<String>,
+
+    /**
+     * @brief Packages from the results to add without prompting, comma-separated.
+     */
+    #[arg(long, value_delimiter = ',', value_name = "ATTRS")]
+    add: Vec<String>,
+
+    /**
+     * @brief Show at most this many results.
+     */
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+
+    /**
+     * @brief Add to the personal layer (devshell.local.toml) instead of the team config.
+     */
+    #[arg(long)]
+    local: bool,
+}
+
+/**
+ * @struct InspectArgs
+ * @brief Arguments for the `inspect` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct InspectArgs {
+    /**
+     * @brief An input key from flake.nix, or a flake URL (e.g. "github:nix-community/fenix").
+     */
+    input: String,
+
+    /**
+     * @brief Outputs to add without prompting, comma-separated (e.g. "packages.default,overlays.default").
+     */
+    #[arg(long, value_delimiter = ',')]
+    select: Vec<String>,
 }
 
 /**
- * @enum Commands
- * @brief Supported subcommands for the CLI.
+ * @struct RenderArgs
+ * @brief Arguments for the `render` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct RenderArgs {
+    /**
+     * @brief Print the flake.nix the shell is built from instead of the summary.
+     */
+    #[arg(long)]
+    flake: bool,
+}
+
+/**
+ * @struct ServeArgs
+ * @brief Arguments for the `serve` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct ServeArgs {
+    /**
+     * @brief Talk over stdin/stdout, one JSON-RPC message per line (the only transport so far).
+     */
+    #[arg(long)]
+    stdio: bool,
+}
+
+/**
+ * @struct CheckArgs
+ * @brief Arguments for the `check` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct CheckArgs {
+    /**
+     * @brief Packages to look up; defaults to every package in devshell.toml.
+     */
+    packages: Vec<String>,
+
+    /**
+     * @brief Rebuild the cached package index.
+     */
+    #[arg(long)]
+    refresh: bool,
+}
+
+/**
+ * @struct GuideArgs
+ * @brief Arguments for the `guide` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct GuideArgs {
+    /**
+     * @brief The recipe to show (e.g. "rust+postgres"); lists all recipes if omitted.
+     */
+    topic: Option<String>,
+
+    /**
+     * @brief Create the recipe's flake.nix and devshell.toml in the current directory.
+     */
+    #[arg(long, requires = "topic")]
+    scaffold: bool,
+}
+
+/**
+ * @struct TuneArgs
+ * @brief Arguments for the `tune` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct TuneArgs {
+    /**
+     * @brief Apply the safe fixes (missing follows, nixConfig) instead of only reporting.
+     */
+    #[arg(long)]
+    apply: bool,
+}
+
+/**
+ * @struct BenchArgs
+ * @brief Arguments for the `bench` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct BenchArgs {
+    /**
+     * @brief Also report how long each hook of the shellHook took, slowest first.
+     */
+    #[arg(long)]
+    hooks: bool,
+
+    /**
+     * @brief How many times to enter the shell; the first run may include evaluating and building it.
+     */
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    runs: u32,
+
+    /**
+     * @brief Time the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+}
+
+/**
+ * @struct AtArgs
+ * @brief Arguments for the `at` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct AtArgs {
+    /**
+     * @brief The git revision (commit, tag, branch, `HEAD~3`, ...).
+     */
+    rev: String,
+
+    #[command(subcommand)]
+    action: AtAction,
+}
+
+/**
+ * @enum AtAction
+ * @brief What `at <rev>` does with the historical environment.
  */
 #[derive(Subcommand, Debug)]
-enum Commands {
+enum AtAction {
     /**
-     * @brief Initialize a new dev shell flake.
-     * @details Creates flake.nix and devshell.toml.
+     * @brief Enter the dev shell as it was at the revision.
      */
-    Init(InitArgs),
+    Enter(AtEnterArgs),
+}
 
+/**
+ * @struct AtEnterArgs
+ * @brief Arguments for `at <rev> enter`.
+ */
+#[derive(Parser, Debug)]
+struct AtEnterArgs {
     /**
-     * @brief Add packages or hooks to an existing devshell.toml.
+     * @brief Enter the named shell (`[shells.<name>]`) instead of the default one.
      */
-    Add(AddArgs),
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
 }
 
 /**
- * @struct InitArgs
- * @brief Arguments for the `init` subcommand.
+ * @struct TemplatesArgs
+ * @brief Arguments for the `templates` subcommand.
  */
 #[derive(Parser, Debug)]
-struct InitArgs {
+struct TemplatesArgs {
+    #[command(subcommand)]
+    action: TemplatesAction,
+}
+
+/**
+ * @enum TemplatesAction
+ * @brief Operations of the `templates` subcommand.
+ */
+#[derive(Subcommand, Debug)]
+enum TemplatesAction {
     /**
-     * @brief The primary language template (e.g., "cpp", "rust", "python").
+     * @brief List built-in and user templates with their packages.
      */
-    #[arg(short = 'l', long)]
-    lang: Option<String>,
+    List,
+}
+
+/**
+ * @struct HooksArgs
+ * @brief Arguments for the `hooks` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct HooksArgs {
+    #[command(subcommand)]
+    action: HooksAction,
+}
 
+/**
+ * @enum HooksAction
+ * @brief Operations of the `hooks` subcommand.
+ */
+#[derive(Subcommand, Debug)]
+enum HooksAction {
     /**
-     * @brief Extra Nixpkgs packages to add (space-separated).
+     * @brief List the hooks in the order they run, the team's before the personal ones.
      */
-    #[arg(short = 'p', long, value_delimiter = ' ', num_args = 0..)]
-    packages: Vec<String>,
+    List(HooksListArgs),
 
     /**
-     * @brief Extra flake inputs to add (space-separated URLs).
-     * @details Example: "github:nix-community/crane" "github:ocornut/imgui"
+     * @brief Remove a hook.
      */
-    #[arg(short = 'P', long, value_delimiter = ' ', num_args = 0..)]
-    inputs: Vec<String>,
+    Remove(HooksRemoveArgs),
 
     /**
-     * @brief A shell hook command to run.
+     * @brief Move a hook to run at another point.
      */
-    #[arg(short = 's', long)]
-    shell_hook: Option<String>,
+    Move(HooksMoveArgs),
+}
 
+/**
+ * @struct HooksListArgs
+ * @brief Arguments for `hooks list`.
+ */
+#[derive(Parser, Debug)]
+struct HooksListArgs {
     /**
-     * @brief Create an isolated, pure shell (default is an impure shell).
+     * @brief List the hooks of the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+}
+
+/**
+ * @struct HooksRemoveArgs
+ * @brief Arguments for `hooks remove`.
+ */
+#[derive(Parser, Debug)]
+struct HooksRemoveArgs {
+    /**
+     * @brief The hook to remove.
+     */
+    name: String,
+
+    /**
+     * @brief Remove it from the personal layer (devshell.local.toml) instead of the team config.
      */
     #[arg(long)]
-    isolated: bool,
+    local: bool,
 
     /**
-     * @brief Overwrite existing flake.nix and devshell.toml.
+     * @brief Remove it from the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+}
+
+/**
+ * @struct HooksMoveArgs
+ * @brief Arguments for `hooks move`.
+ */
+#[derive(Parser, Debug)]
+struct HooksMoveArgs {
+    /**
+     * @brief The hook to move.
+     */
+    name: String,
+
+    /**
+     * @brief Run it right before this hook.
+     */
+    #[arg(long, value_name = "HOOK", required_unless_present_any = ["after", "first", "last"])]
+    before: Option<String>,
+
+    /**
+     * @brief Run it right after this hook.
+     */
+    #[arg(long, value_name = "HOOK", conflicts_with = "before")]
+    after: Option<String>,
+
+    /**
+     * @brief Run it before all the others.
+     */
+    #[arg(long, conflicts_with_all = ["before", "after"])]
+    first: bool,
+
+    /**
+     * @brief Run it after all the others.
+     */
+    #[arg(long, conflicts_with_all = ["before", "after", "first"])]
+    last: bool,
+
+    /**
+     * @brief Move it within the personal layer (devshell.local.toml) instead of the team config.
+     */
+    #[arg(long)]
+    local: bool,
+
+    /**
+     * @brief Move it within the named shell (`[shells.<name>]`) instead of the default one.
+     */
+    #[arg(long, value_name = "NAME")]
+    shell: Option<String>,
+}
+
+/**
+ * @struct BundleArgs
+ * @brief Arguments for the `bundle` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct BundleArgs {
+    #[command(subcommand)]
+    action: BundleAction,
+}
+
+/**
+ * @enum BundleAction
+ * @brief Operations of the `bundle` subcommand.
+ */
+#[derive(Subcommand, Debug)]
+enum BundleAction {
+    /**
+     * @brief Write flake.nix, flake.lock and devshell.toml into a bundle file.
+     */
+    Export(BundleExportArgs),
+
+    /**
+     * @brief Unpack a bundle file into the current directory.
+     */
+    Import(BundleImportArgs),
+}
+
+/**
+ * @struct BundleExportArgs
+ * @brief Arguments for `bundle export`.
+ */
+#[derive(Parser, Debug)]
+struct BundleExportArgs {
+    /**
+     * @brief The bundle file to write.
+     */
+    file: PathBuf,
+
+    /**
+     * @brief Private key to sign the bundle with (writes a detached signature).
+     */
+    #[arg(long)]
+    sign: Option<PathBuf>,
+
+    /**
+     * @brief Signing tool to use.
+     */
+    #[arg(long, value_enum, default_value = "ssh")]
+    signer: bundle::Signer,
+}
+
+/**
+ * @struct BundleImportArgs
+ * @brief Arguments for `bundle import`.
+ */
+#[derive(Parser, Debug)]
+struct BundleImportArgs {
+    /**
+     * @brief The bundle file to read.
+     */
+    file: PathBuf,
+
+    /**
+     * @brief Require a valid signature, checked against this allowed_signers (ssh) or public key (minisign) file.
+     */
+    #[arg(long)]
+    verify: Option<PathBuf>,
+
+    /**
+     * @brief Signer identity to require when verifying ssh signatures.
+     */
+    #[arg(long)]
+    identity: Option<String>,
+
+    /**
+     * @brief Signing tool the bundle was signed with.
+     */
+    #[arg(long, value_enum, default_value = "ssh")]
+    signer: bundle::Signer,
+
+    /**
+     * @brief Overwrite existing files.
      */
     #[arg(long)]
     force: bool,
+
+    /**
+     * @brief Accept shell hooks that pipe downloads into a shell, use sudo, or write outside the project.
+     */
+    #[arg(long)]
+    allow_unsafe_hooks: bool,
 }
 
 /**
- * @struct AddArgs
- * @brief Arguments for the `add` subcommand.
+ * @struct ApplyArgs
+ * @brief Arguments for the `apply` subcommand.
  */
 #[derive(Parser, Debug)]
-struct AddArgs {
+struct ApplyArgs {
     /**
-     * @brief Nixpkgs packages to add (space-separated).
+     * @brief The JSON plan to apply.
      */
-    #[arg(short = 'p', long, value_delimiter = ' ', num_args = 0..)]
-    packages: Vec<String>,
+    #[arg(long)]
+    plan_file: PathBuf,
 
     /**
-     * @brief Flake inputs to add (space-separated URLs).
-     * @details This will automatically edit your flake.nix.
+     * @brief Accept shell hooks in the plan that pipe downloads into a shell, use sudo, or write outside the project.
+     */
+    #[arg(long)]
+    allow_unsafe_hooks: bool,
+}
+
+/**
+ * @struct UpdateArgs
+ * @brief Arguments for the `update` subcommand.
+ */
+#[derive(Parser, Debug)]
+struct UpdateArgs {
+    /**
+     * @brief Input keys to update (all inputs if omitted).
      */
-    #[arg(short = 'P', long, value_delimiter = ' ', num_args = 0..)]
     inputs: Vec<String>,
 
     /**
-     * @brief A shell hook command to append.
+     * @brief Print a JSON changelog (revisions, dates, GitHub commit log) for PR descriptions.
      */
-    #[arg(short = 's', long)]
-    shell_hook: Option<String>,
+    #[arg(long)]
+    pr_metadata: bool,
 }
 
 /**
@@ -108,14 +1792,100 @@ struct AddArgs {
  */
 fn main() {
     let cli = Cli::parse();
+    if let Some(dir) = &cli.global.cache_dir {
+        paths::override_cache_dir(dir.clone());
+    }
+    flake_editor::set_trace(cli.global.trace_edit);
+    messages::set_machine_output(cli.global.plan || cli.global.json);
+    project_lock::set_ignore_freeze(cli.global.ignore_freeze);
+    project_lock::set_read_only(
+        cli.global.read_only
+            || std::env::var_os(project_lock::READ_ONLY_ENV_VAR)
+                .is_some_and(|value| !value.is_empty() && value != "0"),
+    );
+
+    // These commands run tools that change files themselves, so there is no diff to show.
+    if cli.global.dry_run
+        && matches!(
+            cli.command,
+            Commands::Apply(_) | Commands::Update(_) | Commands::InstallGitHooks(_)
+        )
+    {
+        eprintln!("Error: This command doesn't support --dry-run.");
+        std::process::exit(1);
+    }
+    // Each fix is a separate change set, which doesn't fit in one plan.
+    if cli.global.plan && matches!(cli.command, Commands::Fix) {
+        eprintln!("Error: `fix` doesn't support --plan; use --dry-run to preview the fixes.");
+        std::process::exit(1);
+    }
 
     let result = match &cli.command {
-        Commands::Init(args) => commands::handle_init(args),
-        Commands::Add(args) => commands::handle_add(args),
+        Commands::Init(args) => commands::handle_init(args, &cli.global),
+        Commands::New(args) => commands::handle_new(args, &cli.global),
+        Commands::Import(args) => commands::handle_import(args, &cli.global),
+        Commands::Adopt(args) => commands::handle_adopt(args, &cli.global),
+        Commands::Add(args) => commands::handle_add(args, &cli.global),
+        Commands::Remove(args) => commands::handle_remove(args, &cli.global),
+        Commands::Sync => commands::handle_sync(&cli.global),
+        Commands::Enter(args) => commands::handle_enter(args, &cli.global),
+        Commands::Run(args) => commands::handle_run(args, &cli.global),
+        Commands::Set(args) => commands::handle_set(args, &cli.global),
+        Commands::Get(args) => commands::handle_get(args),
+        Commands::Unset(args) => commands::handle_unset(args, &cli.global),
+        Commands::Freeze(args) => commands::handle_freeze(args, &cli.global),
+        Commands::Thaw => commands::handle_thaw(&cli.global),
+        Commands::Vendor(args) => commands::handle_vendor(args, &cli.global),
+        Commands::Unvendor => commands::handle_unvendor(&cli.global),
+        Commands::Tune(args) => commands::handle_tune(args, &cli.global),
+        Commands::Bench(args) => commands::handle_bench(args, &cli.global),
+        Commands::Hooks(args) => match &args.action {
+            HooksAction::List(args) => commands::handle_hooks_list(args),
+            HooksAction::Remove(args) => commands::handle_hooks_remove(args, &cli.global),
+            HooksAction::Move(args) => commands::handle_hooks_move(args, &cli.global),
+        },
+        Commands::Dedupe(args) => commands::handle_dedupe(args, &cli.global),
+        Commands::Bundle(args) => match &args.action {
+            BundleAction::Export(args) => commands::handle_bundle_export(args),
+            BundleAction::Import(args) => commands::handle_bundle_import(args, &cli.global),
+        },
+        Commands::Templates(args) => match &args.action {
+            TemplatesAction::List => commands::handle_templates_list(),
+        },
+        Commands::At(args) => match &args.action {
+            AtAction::Enter(enter) => commands::handle_at_enter(&args.rev, enter, &cli.global),
+        },
+        Commands::Apply(args) => commands::handle_apply(args, &cli.global),
+        Commands::Update(args) => commands::handle_update(args, &cli.global),
+        Commands::Doctor(args) => commands::handle_doctor(args, &cli.global),
+        Commands::Fix => commands::handle_fix(&cli.global),
+        Commands::Devcontainer(args) => commands::handle_devcontainer(args, &cli.global),
+        Commands::Export(args) => match &args.action {
+            ExportAction::Bootstrap(args) => commands::handle_export_bootstrap(args, &cli.global),
+        },
+        Commands::Direnv(args) => commands::handle_direnv(args, &cli.global),
+        Commands::Status(args) => commands::handle_status(args),
+        Commands::Projects(args) => commands::handle_projects(args),
+        Commands::Ports(args) => commands::handle_ports(args, &cli.global),
+        Commands::InstallGitHooks(args) => commands::handle_install_git_hooks(args),
+        Commands::Guide(args) => commands::handle_guide(args, &cli.global),
+        Commands::Check(args) => commands::handle_check(args, &cli.global),
+        Commands::Search(args) => commands::handle_search(args, &cli.global),
+        Commands::Inspect(args) => commands::handle_inspect(args, &cli.global),
+        Commands::Render(args) => commands::handle_render(args),
+        Commands::Show => commands::handle_show(),
+        Commands::Analyze => commands::handle_analyze(),
+        Commands::Current => commands::handle_current(&cli.global),
+        Commands::Serve(args) => commands::handle_serve(args, &cli.global),
+        Commands::PromptSegment => commands::handle_prompt_segment(),
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
+        if cli.global.json {
+            println!("{}", messages::error_json(&e));
+        } else {
+            eprintln!("Error: {}", e);
+        }
         std::process::exit(1);
     }
 }
@@ -125,13 +1895,15 @@ fn main() {
  * @details
  * Example: "github:owner/repo" -> ("repo", "github:owner/repo")
  * Example: "github:owner/repo~branch" -> ("repo", "github:owner/repo~branch")
- * @param url The flake URL to parse.
- * @return A tuple containing the key and the original URL.
+ * Example: "github:owner/repo#cli" -> ("repo", "github:owner/repo")
+ * @param url The flake URL to parse, optionally followed by `#<output>`.
+ * @return A tuple containing the key and the URL without the output.
  */
 fn parse_flake_input(url: &str) -> (String, String) {
+    let url = url.split('#').next().unwrap_or(url);
     let key = url
         .split('/')
-        .last()
+        .next_back()
         .unwrap_or(url)
         .split('~')
         .next()
@@ -144,10 +1916,46 @@ fn parse_flake_input(url: &str) -> (String, String) {
  * @brief Parses a flake URL into a package string.
  * @details
  * Example: "github:owner/repo" -> "repo.packages.${system}.default"
+ * Example: "github:owner/repo#cli" -> "repo.packages.${system}.cli"
  * @param url The flake URL to parse.
  * @return The package string following the standard convention for flake packages.
  */
 fn parse_input_to_pkg_string(url: &str) -> String {
     let (key, _) = parse_flake_input(url);
-    format!("{}.packages.${{system}}.default", key)
+    input_output_path(&key, input_attr(url))
+}
+
+/**
+ * @brief Returns the output a flake URL names after `#`, if any.
+ * @details Example: "github:owner/repo#packages.${system}.cli" -> Some("packages.${system}.cli")
+ * @param url The flake URL.
+ */
+fn input_attr(url: &str) -> Option<&str> {
+    url.split_once('#')
+        .map(|(_, attr)| attr)
+        .filter(|attr| !attr.is_empty())
+}
+
+/**
+ * @brief Builds the attribute path of an input's output, as stored in devshell.toml.
+ * @details
+ * Example: ("repo", None) -> "repo.packages.${system}.default"
+ * Example: ("repo", Some("cli")) -> "repo.packages.${system}.cli"
+ * Example: ("repo", Some("overlays.default")) -> "repo.overlays.default"
+ * @param key The input key.
+ * @param attr The output: a package name, or a path starting with `packages.`,
+ * `legacyPackages.` or `overlays.`, which is kept as given.
+ */
+fn input_output_path(key: &str, attr: Option<&str>) -> String {
+    match attr {
+        Some(attr)
+            if ["packages.", "legacyPackages.", "overlays."]
+                .iter()
+                .any(|prefix| attr.starts_with(prefix)) =>
+        {
+            format!("{}.{}", key, attr)
+        }
+        Some(name) => format!("{}.packages.${{system}}.{}", key, name),
+        None => format!("{}.packages.${{system}}.default", key),
+    }
 }
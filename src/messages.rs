@@ -0,0 +1,245 @@
+use serde_json::json;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// @brief init, adopt or new found a project that is already set up.
+pub const E_PROJECT_EXISTS: &str = "E_PROJECT_EXISTS";
+/// @brief The project has no flake.nix, shell.nix or config yet.
+pub const E_NOT_INITIALIZED: &str = "E_NOT_INITIALIZED";
+/// @brief A flake input key is taken by an input with another URL.
+pub const E_INPUT_EXISTS: &str = "E_INPUT_EXISTS";
+/// @brief The generated flake.nix needs the input `remove -P` was asked to drop.
+pub const E_INPUT_REQUIRED: &str = "E_INPUT_REQUIRED";
+/// @brief The config already sets an env var, process, script or hook of that name differently.
+pub const E_ENTRY_EXISTS: &str = "E_ENTRY_EXISTS";
+/// @brief A generated file (devcontainer.json, bootstrap.sh, ...) exists and --force wasn't given.
+pub const E_FILE_EXISTS: &str = "E_FILE_EXISTS";
+/// @brief flake.nix has a syntax error, so it isn't edited.
+pub const E_FLAKE_SYNTAX: &str = "E_FLAKE_SYNTAX";
+/// @brief The project is frozen.
+pub const E_FROZEN: &str = "E_FROZEN";
+/// @brief Read-only mode refused a mutating command.
+pub const E_READ_ONLY: &str = "E_READ_ONLY";
+/// @brief The project is on a read-only filesystem.
+pub const E_READ_ONLY_FILESYSTEM: &str = "E_READ_ONLY_FILESYSTEM";
+/// @brief A file changed since the plan being applied was made.
+pub const E_STALE_PLAN: &str = "E_STALE_PLAN";
+/// @brief A plan being applied writes a file nix-shell-gen doesn't manage.
+pub const E_INVALID_PLAN: &str = "E_INVALID_PLAN";
+/// @brief The user declined a confirmation prompt.
+pub const E_ABORTED: &str = "E_ABORTED";
+/// @brief Ctrl-C stopped a command run on the user's behalf.
+pub const E_INTERRUPTED: &str = "E_INTERRUPTED";
+/// @brief A command run on the user's behalf (nix, git, direnv, a script, ...) failed.
+pub const E_COMMAND_FAILED: &str = "E_COMMAND_FAILED";
+/// @brief A command the operation runs isn't installed.
+pub const E_COMMAND_NOT_FOUND: &str = "E_COMMAND_NOT_FOUND";
+/// @brief A command run on the user's behalf took longer than --timeout.
+pub const E_TIMED_OUT: &str = "E_TIMED_OUT";
+/// @brief An argument isn't of the form NAME=VALUE the option expects.
+pub const E_INVALID_ENTRY: &str = "E_INVALID_ENTRY";
+/// @brief A name (input key, shell, hook, process, port or env var) has characters it can't have.
+pub const E_INVALID_NAME: &str = "E_INVALID_NAME";
+/// @brief A setting, version, platform or path has a value it can't take.
+pub const E_INVALID_VALUE: &str = "E_INVALID_VALUE";
+/// @brief Options that can't be combined, or an option the command doesn't take here.
+pub const E_INVALID_OPTIONS: &str = "E_INVALID_OPTIONS";
+/// @brief An answer isn't one of the offered choices.
+pub const E_INVALID_CHOICE: &str = "E_INVALID_CHOICE";
+/// @brief A Nix expression given on the command line doesn't parse.
+pub const E_INVALID_EXPRESSION: &str = "E_INVALID_EXPRESSION";
+/// @brief devshell.toml (or a bundled one, policy.toml or the vendor record) doesn't parse or has an invalid entry.
+pub const E_INVALID_CONFIG: &str = "E_INVALID_CONFIG";
+/// @brief A bundle file doesn't parse, has an unknown format or carries a file it can't.
+pub const E_INVALID_BUNDLE: &str = "E_INVALID_BUNDLE";
+/// @brief A bundle's signature is missing or doesn't verify against the trusted key.
+pub const E_BAD_SIGNATURE: &str = "E_BAD_SIGNATURE";
+/// @brief A shell.nix or flake.nix to import or adopt doesn't parse or has no mkShell shell.
+pub const E_IMPORT_FAILED: &str = "E_IMPORT_FAILED";
+/// @brief The operation needs a flake; the project uses shell.nix.
+pub const E_SHELL_NIX_UNSUPPORTED: &str = "E_SHELL_NIX_UNSUPPORTED";
+/// @brief flake.nix is laid out in a way that can't be edited automatically.
+pub const E_FLAKE_LAYOUT: &str = "E_FLAKE_LAYOUT";
+/// @brief The generated files lack a block nix-shell-gen needs; `sync` regenerates them.
+pub const E_NEEDS_SYNC: &str = "E_NEEDS_SYNC";
+/// @brief flake.nix declares no input of that key.
+pub const E_UNKNOWN_INPUT: &str = "E_UNKNOWN_INPUT";
+/// @brief flake.nix declares no inputs at all.
+pub const E_NO_INPUTS: &str = "E_NO_INPUTS";
+/// @brief The config has no shell of that name.
+pub const E_UNKNOWN_SHELL: &str = "E_UNKNOWN_SHELL";
+/// @brief The config has no hook of that name.
+pub const E_UNKNOWN_HOOK: &str = "E_UNKNOWN_HOOK";
+/// @brief The config has no script of that name.
+pub const E_UNKNOWN_SCRIPT: &str = "E_UNKNOWN_SCRIPT";
+/// @brief `guide` has no recipe for that topic.
+pub const E_UNKNOWN_TOPIC: &str = "E_UNKNOWN_TOPIC";
+/// @brief A file named on the command line doesn't exist.
+pub const E_FILE_NOT_FOUND: &str = "E_FILE_NOT_FOUND";
+/// @brief Packages aren't in nixpkgs (or the package index).
+pub const E_PACKAGE_NOT_FOUND: &str = "E_PACKAGE_NOT_FOUND";
+/// @brief The change breaks the organization's package policy.
+pub const E_POLICY_VIOLATION: &str = "E_POLICY_VIOLATION";
+/// @brief Shell code looks unsafe and --allow-unsafe-hooks wasn't given.
+pub const E_UNSAFE_HOOK: &str = "E_UNSAFE_HOOK";
+/// @brief `check` or `doctor` found problems.
+pub const E_CHECK_FAILED: &str = "E_CHECK_FAILED";
+/// @brief flake.lock is missing.
+pub const E_NOT_LOCKED: &str = "E_NOT_LOCKED";
+/// @brief `thaw` on a project that isn't frozen.
+pub const E_NOT_FROZEN: &str = "E_NOT_FROZEN";
+/// @brief `vendor` on a project whose inputs are vendored already.
+pub const E_ALREADY_VENDORED: &str = "E_ALREADY_VENDORED";
+/// @brief `unvendor` on a project whose inputs aren't vendored.
+pub const E_NOT_VENDORED: &str = "E_NOT_VENDORED";
+/// @brief No port-offset frees every `[ports]` port.
+pub const E_PORTS_IN_USE: &str = "E_PORTS_IN_USE";
+/// @brief `current` outside a nix-shell-gen shell.
+pub const E_NOT_IN_SHELL: &str = "E_NOT_IN_SHELL";
+/// @brief Nix is older than nix-shell-gen supports, or has flakes turned off.
+pub const E_NIX_UNSUPPORTED: &str = "E_NIX_UNSUPPORTED";
+/// @brief The command needs a git repository and runs outside one.
+pub const E_NOT_A_REPOSITORY: &str = "E_NOT_A_REPOSITORY";
+/// @brief git doesn't know the revision given.
+pub const E_UNKNOWN_REVISION: &str = "E_UNKNOWN_REVISION";
+
+static MACHINE_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Reserves stdout for JSON (`--plan`, `--json`) for this run.
+ *
+ * Messages for people printed with `say!` and `sayln!` then go to stderr, so
+ * stdout holds the plan or the JSON object and nothing else.
+ *
+ * @param enabled Whether stdout carries JSON.
+ */
+pub fn set_machine_output(enabled: bool) {
+    MACHINE_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+/**
+ * @brief Whether stdout is reserved for JSON.
+ */
+pub fn machine_output() -> bool {
+    MACHINE_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// @brief `print!` for messages to people: stdout, or stderr while stdout carries JSON.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::messages::machine_output() {
+            eprint!($($arg)*)
+        } else {
+            print!($($arg)*)
+        }
+    };
+}
+
+/// @brief `println!` for messages to people: stdout, or stderr while stdout carries JSON.
+macro_rules! sayln {
+    ($($arg:tt)*) => {
+        if $crate::messages::machine_output() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+pub(crate) use {say, sayln};
+
+/**
+ * @struct Coded
+ * @brief An error message with a stable code, carried inside an io::Error.
+ */
+#[derive(Debug)]
+struct Coded {
+    /// @brief One of the `E_*` codes.
+    code: &'static str,
+    /// @brief The message for people.
+    message: String,
+}
+
+impl fmt::Display for Coded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Coded {}
+
+/**
+ * @brief Creates an error with a stable code that `--json` reports.
+ *
+ * The error prints as the message alone, so coded and plain errors read the same.
+ *
+ * @param kind The io error kind.
+ * @param code One of the `E_*` codes.
+ * @param message The message for people.
+ */
+pub fn error(kind: ErrorKind, code: &'static str, message: impl Into<String>) -> Error {
+    Error::new(
+        kind,
+        Coded {
+            code,
+            message: message.into(),
+        },
+    )
+}
+
+/**
+ * @brief The error for a command that needs a project file that doesn't exist yet.
+ * @param file The missing file, e.g. "flake.nix".
+ */
+pub fn not_initialized(file: &str) -> Error {
+    error(
+        ErrorKind::NotFound,
+        E_NOT_INITIALIZED,
+        format!("{} not found. Run `nix-shell-gen init` first.", file),
+    )
+}
+
+/**
+ * @brief The stable code of an error.
+ *
+ * A command that exited unsuccessfully is E_COMMAND_FAILED. Other errors created
+ * without a code, which are I/O errors, get one for their kind, e.g. "E_NOT_FOUND".
+ *
+ * @param error The error.
+ */
+pub fn code(error: &Error) -> &'static str {
+    if let Some(coded) = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<Coded>())
+    {
+        return coded.code;
+    }
+    if crate::process::stderr_of(error).is_some() {
+        return E_COMMAND_FAILED;
+    }
+    match error.kind() {
+        ErrorKind::NotFound => "E_NOT_FOUND",
+        ErrorKind::InvalidInput => "E_INVALID_INPUT",
+        ErrorKind::InvalidData => "E_INVALID_DATA",
+        ErrorKind::AlreadyExists => "E_ALREADY_EXISTS",
+        ErrorKind::PermissionDenied => "E_PERMISSION_DENIED",
+        ErrorKind::Unsupported => "E_UNSUPPORTED",
+        ErrorKind::Interrupted => E_INTERRUPTED,
+        ErrorKind::TimedOut => E_TIMED_OUT,
+        ErrorKind::ReadOnlyFilesystem => E_READ_ONLY_FILESYSTEM,
+        _ => "E_IO",
+    }
+}
+
+/**
+ * @brief Formats an error for `--json`: `{"ok": false, "error": {"code": ..., "message": ...}}`.
+ * @param error The error.
+ */
+pub fn error_json(error: &Error) -> String {
+    json!({
+        "ok": false,
+        "error": { "code": code(error), "message": error.to_string() },
+    })
+    .to_string()
+}
@@ -0,0 +1,176 @@
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use crate::messages;
+use crate::process::{self, Process};
+
+/// @brief Oldest Nix release with the flake commands used here (`nix develop --profile`, `nix flake lock`).
+pub const MIN_VERSION: (u32, u32) = (2, 4);
+
+/// @brief First release where `nix flake update` takes input names; `--update-input` is deprecated there.
+const FLAKE_UPDATE_INPUTS_VERSION: (u32, u32) = (2, 19);
+
+/// @brief First release with `nix config show`; older ones only have `nix show-config`.
+const CONFIG_SHOW_VERSION: (u32, u32) = (2, 20);
+
+/// @brief Printed when nix is not on PATH.
+pub const INSTALL_HINT: &str =
+    "install Nix from https://nixos.org/download (or https://install.determinate.systems)";
+
+/// @brief Printed when the flake commands are disabled.
+pub const FLAKES_HINT: &str = "enable flakes by adding `experimental-features = nix-command flakes` to ~/.config/nix/nix.conf";
+
+/**
+ * @struct NixInfo
+ * @brief What the installed Nix supports.
+ */
+#[derive(Debug)]
+pub struct NixInfo {
+    /// @brief The version string reported by `nix --version`, e.g. "2.18.1".
+    pub version: String,
+
+    /// @brief Whether the `nix-command` and `flakes` features are enabled.
+    pub flakes_enabled: bool,
+}
+
+impl NixInfo {
+    /// @brief The major and minor version, if the version string could be parsed.
+    pub fn version_pair(&self) -> Option<(u32, u32)> {
+        let mut parts = self.version.split('.').map(|part| {
+            part.chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>()
+                .parse::<u32>()
+        });
+        match (parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor))) => Some((major, minor)),
+            _ => None,
+        }
+    }
+
+    /// @brief Whether `nix flake update` accepts the inputs to update as arguments.
+    pub fn updates_named_inputs(&self) -> bool {
+        self.version_pair()
+            .is_some_and(|version| version >= FLAKE_UPDATE_INPUTS_VERSION)
+    }
+
+    /// @brief Whether the version is at least MIN_VERSION (unknown versions are given the benefit of the doubt).
+    pub fn version_supported(&self) -> bool {
+        self.version_pair()
+            .is_none_or(|version| version >= MIN_VERSION)
+    }
+}
+
+/**
+ * @brief Detects the installed Nix and the features it has enabled.
+ * @param timeout Timeout for each nix call.
+ * @return Result containing the NixInfo, or NotFound with install instructions if nix is missing.
+ */
+pub fn detect(timeout: Option<Duration>) -> Result<NixInfo, Error> {
+    let output = Process::nix(["--version"])
+        .timeout(timeout)
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => messages::error(
+                ErrorKind::NotFound,
+                messages::E_COMMAND_NOT_FOUND,
+                format!("nix was not found on PATH; {}", INSTALL_HINT),
+            ),
+            _ => e,
+        })?;
+
+    // "nix (Nix) 2.18.1" or "nix (Determinate Nix 3.0.0) 2.26.3"
+    let version = String::from_utf8_lossy(&output)
+        .split_whitespace()
+        .next_back()
+        .unwrap_or("unknown")
+        .to_string();
+    let mut info = NixInfo {
+        version,
+        flakes_enabled: false,
+    };
+
+    // `nix config show <name>` prints just the value; older releases only have
+    // `nix show-config`, which prints every setting as `name = value`.
+    let show_config: &[&str] = if info
+        .version_pair()
+        .is_some_and(|version| version >= CONFIG_SHOW_VERSION)
+    {
+        &["config", "show", "experimental-features"]
+    } else {
+        &["show-config"]
+    };
+    // Enabling nix-command for this call only lets the check itself run when
+    // the setting is off.
+    let config = Process::nix(["--extra-experimental-features", "nix-command"])
+        .args(show_config)
+        .timeout(timeout)
+        .output()?;
+    let config = String::from_utf8_lossy(&config);
+    let features = config
+        .lines()
+        .find_map(|line| line.strip_prefix("experimental-features ="))
+        .unwrap_or(&config);
+    let enabled = |name| features.split_whitespace().any(|feature| feature == name);
+    info.flakes_enabled = enabled("flakes") && enabled("nix-command");
+
+    Ok(info)
+}
+
+/**
+ * @brief Checks that nix can run the flake commands a feature needs.
+ * @param feature What needs nix, for the error message (e.g. "nix-shell-gen enter").
+ * @param timeout Timeout for each nix call.
+ * @return Result<NixInfo, Error> Returns an Error with instructions if nix is missing, too old, or lacks flakes.
+ */
+pub fn require(feature: &str, timeout: Option<Duration>) -> Result<NixInfo, Error> {
+    let info = detect(timeout).map_err(|e| {
+        messages::error(
+            e.kind(),
+            messages::code(&e),
+            format!("{} needs nix: {}", feature, e),
+        )
+    })?;
+
+    if !info.version_supported() {
+        return Err(messages::error(
+            ErrorKind::Unsupported,
+            messages::E_NIX_UNSUPPORTED,
+            format!(
+                "{} needs Nix {}.{} or newer, found {}; upgrade Nix first",
+                feature, MIN_VERSION.0, MIN_VERSION.1, info.version
+            ),
+        ));
+    }
+    if !info.flakes_enabled {
+        return Err(messages::error(
+            ErrorKind::Unsupported,
+            messages::E_NIX_UNSUPPORTED,
+            format!("{} needs flakes: {}", feature, FLAKES_HINT),
+        ));
+    }
+
+    Ok(info)
+}
+
+/**
+ * @brief Suggests a fix for common nix errors.
+ * @param error An error returned by a nix Process.
+ * @return A hint, if the error is recognized.
+ */
+pub fn error_hint(error: &Error) -> Option<&'static str> {
+    if error.kind() == ErrorKind::NotFound {
+        return Some(INSTALL_HINT);
+    }
+
+    let stderr = process::stderr_of(error)?;
+    if stderr.contains("experimental Nix feature") || stderr.contains("experimental feature") {
+        Some(FLAKES_HINT)
+    } else if stderr.contains("is not tracked by Git") || stderr.contains("does not exist") {
+        Some("flakes only see files tracked by git; run `git add flake.nix` and the config file")
+    } else if stderr.contains("unable to download") || stderr.contains("Could not resolve host") {
+        Some("nix could not reach the network; check your connection or substituter settings")
+    } else {
+        None
+    }
+}
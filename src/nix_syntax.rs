@@ -0,0 +1,293 @@
+use rnix::SyntaxKind::{NODE_PATH, NODE_STRING, TOKEN_STRING_END, TOKEN_STRING_START};
+
+/// @brief Nix keywords, which can't be used as bare attribute names or identifiers.
+const KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/**
+ * @brief Renders a value as a double-quoted Nix string literal.
+ *
+ * Escapes backslashes, quotes, `${` (which would start an interpolation) and
+ * control characters, so arbitrary user input can't break out of the string.
+ *
+ * @param value The raw string.
+ * @return The quoted literal, e.g. `"a\"b"`.
+ */
+pub fn string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/**
+ * @brief Checks whether a name can be written as a bare Nix identifier.
+ * @param name The attribute or argument name.
+ */
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !KEYWORDS.contains(&name)
+}
+
+/**
+ * @brief Renders an attribute name, quoting it when it isn't a plain identifier.
+ * @param name The attribute name.
+ * @return `name` or `"name"` (escaped).
+ */
+pub fn attr_name(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        string(name)
+    }
+}
+
+/**
+ * @brief Renders a path relative to the flake's directory.
+ * @param file A file name relative to the flake (e.g. "devshell.toml").
+ * @return `./file`, or `(./. + "/file")` if the name isn't valid in a path literal.
+ */
+pub fn relative_path(file: &str) -> String {
+    // Path literals can't have empty segments, so "/x", "a//b" and "a/" are quoted too.
+    let plain = file.split('/').all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+'))
+    });
+    if plain {
+        format!("./{}", file)
+    } else {
+        format!("(./. + {})", string(&format!("/{}", file)))
+    }
+}
+
+/**
+ * @brief Lists the relative path literals in Nix source, such as `./nix/tool.nix`.
+ *
+ * Paths with an interpolation can't be resolved without evaluating, so they are left out.
+ *
+ * @param source Nix code, a whole file or a single expression.
+ * @return Each `./…` or `../…` literal, in order of appearance.
+ */
+pub fn path_literals(source: &str) -> Vec<String> {
+    rnix::Root::parse(source)
+        .syntax()
+        .descendants()
+        .filter(|node| node.kind() == NODE_PATH && node.children_with_tokens().count() == 1)
+        .map(|node| node.text().to_string())
+        .filter(|path| path.starts_with("./") || path.starts_with("../"))
+        .collect()
+}
+
+/**
+ * @brief Lists the contents of every string literal in Nix source, for scanning shell code.
+ *
+ * Escapes are left as written and interpolations keep their source, e.g. `${name}`.
+ *
+ * @param source Nix code.
+ * @return The text between the quotes of each `"…"` and `''…''` string, in order of appearance.
+ */
+pub fn string_literals(source: &str) -> Vec<String> {
+    rnix::Root::parse(source)
+        .syntax()
+        .descendants()
+        .filter(|node| node.kind() == NODE_STRING)
+        .map(|node| {
+            node.children_with_tokens()
+                .filter(|child| !matches!(child.kind(), TOKEN_STRING_START | TOKEN_STRING_END))
+                .map(|child| child.to_string())
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flake_editor::{parse_flake_inputs, string_literal};
+    use crate::templates::generate_flake_nix;
+    use rnix::Root;
+    use std::collections::BTreeMap;
+
+    /// @brief Keys and URLs that would break out of naive string splicing.
+    const HOSTILE: &[&str] = &[
+        "a.b",
+        "1x",
+        "\"",
+        "in",
+        "or",
+        "x\"; y = import /etc/passwd; z = \"",
+        "${builtins.currentSystem}",
+        "$${x}",
+        "back\\slash\\",
+        "line\nbreak\r\t",
+        "ends with $",
+        "\\${}",
+    ];
+
+    /**
+     * @brief Parses Nix source, failing the test on syntax errors.
+     * @param source The source.
+     */
+    fn parse_ok(source: &str) -> Root {
+        let parse = Root::parse(source);
+        assert!(
+            parse.errors().is_empty(),
+            "{:?} in:\n{}",
+            parse.errors(),
+            source
+        );
+        parse.tree()
+    }
+
+    #[test]
+    fn string_round_trips() {
+        for value in HOSTILE.iter().chain(&["", "plain", "$", "${", "}"]) {
+            let literal = string(value);
+            let expr = parse_ok(&literal).expr().expect("an expression");
+            assert_eq!(
+                string_literal(&expr).as_deref(),
+                Some(*value),
+                "{}",
+                literal
+            );
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(string("a\"b"), r#""a\"b""#);
+        assert_eq!(string("${x}"), r#""\${x}""#);
+        assert_eq!(string("$x"), r#""$x""#);
+        assert_eq!(string("a\\b"), r#""a\\b""#);
+        assert_eq!(string("a\nb"), r#""a\nb""#);
+    }
+
+    #[test]
+    fn identifiers() {
+        for name in ["nixpkgs", "flake-utils", "_private", "x'", "rust_overlay2"] {
+            assert!(is_identifier(name), "{}", name);
+            assert_eq!(attr_name(name), name);
+        }
+        for name in ["", "a.b", "1x", "\"", "-x", "a b", "in", "let", "rec", "or"] {
+            assert!(!is_identifier(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn attr_names_parse_back() {
+        for name in HOSTILE.iter().chain(&["let", "rec", "inherit"]) {
+            let source = format!("{{ {} = 1; }}", attr_name(name));
+            parse_ok(&source);
+        }
+        assert_eq!(attr_name("a.b"), r#""a.b""#);
+        assert_eq!(attr_name("in"), r#""in""#);
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path("devshell.toml"), "./devshell.toml");
+        assert_eq!(relative_path("nix/tool.nix"), "./nix/tool.nix");
+        assert_eq!(relative_path("../shared.toml"), "./../shared.toml");
+        assert_eq!(relative_path("my file.toml"), r#"(./. + "/my file.toml")"#);
+        for file in [
+            "my file.toml",
+            "../shared.toml",
+            "a/../b.nix",
+            "/abs",
+            "a//b",
+            "dir/",
+            "${x}.toml",
+            "q\".toml",
+        ] {
+            parse_ok(&format!("import {}", relative_path(file)));
+        }
+    }
+
+    #[test]
+    fn string_literals_found() {
+        assert_eq!(
+            string_literals("{ a = \"x ${y} \\\"z\"; b = ''\n  curl | sh\n''; }"),
+            ["x ${y} \\\"z", "\n  curl | sh\n"]
+        );
+    }
+
+    #[test]
+    fn generated_flake_strings_pass_hook_scan() {
+        let flake = generate_flake_nix(&BTreeMap::new(), "devshell.toml", "github:NixOS/nixpkgs");
+        for literal in string_literals(&flake) {
+            let warnings = crate::safety::scan_hook(&literal);
+            assert!(warnings.is_empty(), "{:?}", warnings);
+        }
+    }
+
+    #[test]
+    fn path_literals_found() {
+        assert_eq!(
+            path_literals("pkgs.callPackage ./nix/tool.nix { src = ../lib; }"),
+            ["./nix/tool.nix", "../lib"]
+        );
+        assert_eq!(
+            path_literals("{ a = import ./a.nix; b = /etc/hosts; c = <nixpkgs>; d = \"./s\"; }"),
+            ["./a.nix"]
+        );
+        assert_eq!(path_literals("import ./${name}.nix"), Vec::<String>::new());
+        assert_eq!(path_literals("pkgs.callPackage ./. { }"), ["./."]);
+    }
+
+    #[test]
+    fn generated_flake_survives_hostile_inputs() {
+        let inputs: BTreeMap<String, String> = HOSTILE
+            .iter()
+            .map(|key| (key.to_string(), format!("github:{}", key)))
+            .collect();
+        let flake = generate_flake_nix(&inputs, "dev shell\".toml", "github:a/b\"${c}");
+        parse_ok(&flake);
+
+        let parsed = parse_flake_inputs(&flake);
+        for (key, url) in &inputs {
+            let input = parsed
+                .iter()
+                .find(|input| &input.key == key)
+                .unwrap_or_else(|| panic!("input {:?} missing from:\n{}", key, flake));
+            assert_eq!(input.url.as_ref(), Some(url));
+        }
+    }
+
+    #[test]
+    fn added_inputs_survive_hostile_keys() {
+        let mut flake = generate_flake_nix(&BTreeMap::new(), "devshell.toml", "github:a/b");
+        for (i, key) in HOSTILE.iter().enumerate() {
+            let url = format!("git+https://x/{}?ref=\"${{y}}\"", key);
+            flake = crate::flake_editor::add_flake_input(&flake, key, &url, i % 2 == 0, true)
+                .expect("inserted")
+                .expect("new input");
+            parse_ok(&flake);
+            let input = parse_flake_inputs(&flake)
+                .into_iter()
+                .find(|input| input.key == *key)
+                .expect("input is declared");
+            assert_eq!(input.url, Some(url));
+            assert_eq!(input.non_flake, i % 2 == 1);
+        }
+    }
+}
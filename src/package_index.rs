@@ -0,0 +1,376 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::lockfile::FlakeLock;
+use crate::messages::sayln;
+use crate::paths;
+use crate::process::{self, Process};
+
+/// @brief Package sets that hold most nested packages; matches in them are suggested first.
+const NESTED_SETS: &[&str] = &[
+    "python3Packages",
+    "python312Packages",
+    "python313Packages",
+    "nodePackages",
+    "haskellPackages",
+];
+
+/**
+ * @struct PackageInfo
+ * @brief What the index knows about one package.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PackageInfo {
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/**
+ * @struct PackageIndex
+ * @brief The attribute paths of all packages in the project's nixpkgs.
+ *
+ * Built from `nix search` against the locked nixpkgs revision (or the `nixpkgs`
+ * registry entry when the project isn't locked) and cached per revision.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PackageIndex {
+    /// @brief The nixpkgs flake reference the index was built from.
+    pub source: String,
+
+    /// @brief Attribute path (e.g. "python312Packages.numpy") to package info.
+    pub packages: BTreeMap<String, PackageInfo>,
+}
+
+impl PackageIndex {
+    /**
+     * @brief Loads the index for the project's nixpkgs from the cache, building it if needed.
+     * @param refresh Rebuild even if a cached index exists.
+     * @param timeout Timeout for `nix search`.
+     * @return Result containing the index.
+     */
+    pub fn load_or_build(refresh: bool, timeout: Option<Duration>) -> Result<Self, Error> {
+        let source = nixpkgs_ref()?;
+        let cache_file = cache_file(&source)?;
+
+        if !refresh && let Some(index) = read_cache(&cache_file) {
+            return Ok(index);
+        }
+
+        sayln!("Indexing packages of {} (this takes a while)...", source);
+        let output = Process::nix(["search", "--json", &source, "^"])
+            .timeout(timeout)
+            .output()?;
+        let results: BTreeMap<String, PackageInfo> =
+            serde_json::from_slice(&output).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let packages = results
+            .into_iter()
+            .filter_map(|(key, info)| Some((attr_path(&key)?, info)))
+            .collect();
+        let index = PackageIndex { source, packages };
+
+        if let Some(parent) = cache_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &cache_file,
+            serde_json::to_string(&index).map_err(Error::other)?,
+        )?;
+        Ok(index)
+    }
+
+    /**
+     * @brief Loads the index for the project's nixpkgs only if it is already cached.
+     * @return The index, or None if it hasn't been built (see `nix-shell-gen check`).
+     */
+    pub fn load_cached() -> Option<Self> {
+        let source = nixpkgs_ref().ok()?;
+        read_cache(&cache_file(&source).ok()?)
+    }
+
+    /**
+     * @brief Describes why a package name wasn't found, suggesting nested attributes.
+     * @param attr The attribute path that is not in the index.
+     */
+    pub fn missing_message(&self, attr: &str) -> String {
+        let matches = self.nested_matches(attr);
+        if matches.is_empty() {
+            let similar: Vec<String> = self
+                .similar_names(attr)
+                .iter()
+                .map(|attr| format!("'{}'", attr))
+                .collect();
+            if similar.is_empty() {
+                return format!("'{}' is not a package in {}", attr, self.source);
+            }
+            return format!(
+                "'{}' is not a package in {}; did you mean {}?",
+                attr,
+                self.source,
+                similar.join(" or ")
+            );
+        }
+        let shown: Vec<String> = matches
+            .iter()
+            .take(3)
+            .map(|attr| format!("'{}'", attr))
+            .collect();
+        format!(
+            "'{}' is not a top-level package; did you mean {}?",
+            attr,
+            shown.join(" or ")
+        )
+    }
+
+    /**
+     * @brief Checks whether an attribute path is a package.
+     * @param attr The attribute path, e.g. "hello" or "python312Packages.numpy".
+     */
+    pub fn contains(&self, attr: &str) -> bool {
+        self.packages.contains_key(attr)
+    }
+
+    /**
+     * @brief Lists the packages whose attribute path starts with a prefix, in order.
+     * @param prefix What has been typed so far, e.g. "python3".
+     */
+    pub fn completions(&self, prefix: &str) -> Vec<&str> {
+        self.packages
+            .range(prefix.to_string()..)
+            .map(|(attr, _)| attr.as_str())
+            .take_while(|attr| attr.starts_with(prefix))
+            .collect()
+    }
+
+    /**
+     * @brief Finds packages whose name is a likely typo of `attr`, like "python3" for "pythn3".
+     *
+     * Compares against attribute paths with as many components, allowing one edit
+     * for short names and two for longer ones.
+     *
+     * @param attr The attribute path that is not in the index.
+     * @return Up to three attribute paths, closest first.
+     */
+    pub fn similar_names(&self, attr: &str) -> Vec<&str> {
+        let max_distance = if attr.chars().count() < 5 { 1 } else { 2 };
+        let depth = attr.split('.').count();
+        let mut similar: Vec<(usize, &str)> = self
+            .packages
+            .keys()
+            .map(String::as_str)
+            .filter(|candidate| {
+                candidate.split('.').count() == depth
+                    && candidate.len().abs_diff(attr.len()) <= max_distance
+            })
+            .filter_map(|candidate| {
+                let distance = edit_distance(attr, candidate);
+                (distance <= max_distance).then_some((distance, candidate))
+            })
+            .collect();
+        similar.sort_by_key(|(distance, candidate)| (*distance, candidate.len()));
+        similar
+            .into_iter()
+            .take(3)
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    /**
+     * @brief Finds packages named `name` inside nested package sets.
+     * @param name A bare package name, e.g. "numpy".
+     * @return Fully qualified attribute paths, most likely first.
+     */
+    pub fn nested_matches(&self, name: &str) -> Vec<&str> {
+        let suffix = format!(".{}", name);
+        let mut matches: Vec<&str> = self
+            .packages
+            .keys()
+            .map(String::as_str)
+            .filter(|attr| attr.ends_with(&suffix))
+            .collect();
+        matches.sort_by_key(|attr| {
+            let set = attr.split('.').next().unwrap_or("");
+            (
+                NESTED_SETS
+                    .iter()
+                    .position(|known| *known == set)
+                    .unwrap_or(NESTED_SETS.len()),
+                attr.len(),
+            )
+        });
+        matches
+    }
+}
+
+/**
+ * @brief Finds the attribute paths that aren't packages in the project's nixpkgs.
+ *
+ * Uses the cached index when it has been built; otherwise asks nix about each
+ * package with `nix eval <nixpkgs>#<attr>.meta.name`, which takes a second or two
+ * instead of the minutes an index needs.
+ *
+ * @param attrs The attribute paths to check.
+ * @param timeout Timeout for each nix call.
+ * @return One message per missing package, or an Error if nix couldn't answer.
+ */
+pub fn find_missing(attrs: &[&str], timeout: Option<Duration>) -> Result<Vec<String>, Error> {
+    if let Some(index) = PackageIndex::load_cached() {
+        return Ok(attrs
+            .iter()
+            .filter(|attr| !index.contains(attr))
+            .map(|attr| index.missing_message(attr))
+            .collect());
+    }
+
+    let source = nixpkgs_ref()?;
+    let mut missing = Vec::new();
+    for attr in attrs {
+        let installable = format!("{}#{}.meta.name", source, attr);
+        match Process::nix(["eval", "--json", &installable])
+            .timeout(timeout)
+            .output()
+        {
+            Ok(_) => {}
+            Err(e)
+                if process::stderr_of(&e)
+                    .is_some_and(|stderr| stderr.contains("does not provide attribute")) =>
+            {
+                missing.push(format!("'{}' is not a package in {}", attr, source));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(missing)
+}
+
+/**
+ * @brief Searches the project's nixpkgs for packages matching every term.
+ *
+ * Uses the cached index when it has been built, matching the terms case-insensitively
+ * against attribute paths and descriptions; otherwise runs `nix search`, which reads
+ * them as regular expressions.
+ *
+ * @param terms The search terms.
+ * @param timeout Timeout for `nix search`.
+ * @return The nixpkgs searched and the matches, exact names first, then shorter paths.
+ */
+pub fn search(
+    terms: &[String],
+    timeout: Option<Duration>,
+) -> Result<(String, Vec<(String, PackageInfo)>), Error> {
+    let (source, mut matches): (String, Vec<(String, PackageInfo)>) =
+        match PackageIndex::load_cached() {
+            Some(index) => {
+                let terms: Vec<String> = terms.iter().map(|term| term.to_lowercase()).collect();
+                let matches = index
+                    .packages
+                    .into_iter()
+                    .filter(|(attr, info)| {
+                        let attr = attr.to_lowercase();
+                        let description = info.description.to_lowercase();
+                        terms
+                            .iter()
+                            .all(|term| attr.contains(term) || description.contains(term))
+                    })
+                    .collect();
+                (index.source, matches)
+            }
+            None => {
+                let source = nixpkgs_ref()?;
+                let output = Process::nix(["search", "--json", &source])
+                    .args(terms)
+                    .timeout(timeout)
+                    .output()?;
+                let results: BTreeMap<String, PackageInfo> = serde_json::from_slice(&output)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                let matches = results
+                    .into_iter()
+                    .filter_map(|(key, info)| Some((attr_path(&key)?, info)))
+                    .collect();
+                (source, matches)
+            }
+        };
+    let exact = |attr: &str| {
+        let name = attr.rsplit('.').next().unwrap_or(attr);
+        terms.iter().any(|term| term.eq_ignore_ascii_case(name))
+    };
+    matches.sort_by_cached_key(|(attr, _)| (!exact(attr), attr.len(), attr.clone()));
+    Ok((source, matches))
+}
+
+/**
+ * @brief Strips the output and system from a `nix search` key.
+ * @param key E.g. "legacyPackages.x86_64-linux.python312Packages.numpy".
+ * @return The attribute path, e.g. "python312Packages.numpy".
+ */
+fn attr_path(key: &str) -> Option<String> {
+    let mut parts = key.splitn(3, '.');
+    parts.next()?;
+    parts.next()?;
+    Some(parts.next()?.to_string())
+}
+
+/**
+ * @brief Counts the single-character insertions, deletions and substitutions between two strings.
+ * @param a One string.
+ * @param b The other string.
+ */
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/**
+ * @brief Returns the nixpkgs flake reference packages are resolved against.
+ * @return The locked revision of the `nixpkgs` input, or "nixpkgs" if it isn't locked.
+ */
+pub fn nixpkgs_ref() -> Result<String, Error> {
+    Ok(FlakeLock::load()?
+        .as_ref()
+        .and_then(|lock| lock.input_node("nixpkgs"))
+        .and_then(|node| node.locked.as_ref())
+        .and_then(|locked| locked.flake_ref())
+        .unwrap_or_else(|| "nixpkgs".to_string()))
+}
+
+/**
+ * @brief Returns the cache file for an index source.
+ * @param source The nixpkgs flake reference.
+ */
+fn cache_file(source: &str) -> Result<PathBuf, Error> {
+    let dir = paths::cache_dir().ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            "No cache directory; set --cache-dir or $NIX_SHELL_GEN_CACHE_DIR",
+        )
+    })?;
+    let name: String = source
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    Ok(dir.join("package-index").join(format!("{}.json", name)))
+}
+
+/**
+ * @brief Reads a cached index, ignoring missing or unreadable files.
+ * @param path The cache file.
+ */
+fn read_cache(path: &Path) -> Option<PackageIndex> {
+    let contents = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
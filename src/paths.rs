@@ -0,0 +1,188 @@
+use directories::ProjectDirs;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::{CONFIG_FILE_NAMES, STATE_DIR};
+use crate::settings::Settings;
+
+/// @brief Name of the settings file, both in the user config dir and the project state dir.
+const SETTINGS_FILE: &str = "settings.toml";
+
+/**
+ * @brief Returns the project's configuration file name.
+ *
+ * Resolved once per run, in this order:
+ * 1. `config-file` in the project's `.nix-shell-gen/settings.toml`
+ * 2. whichever of the known file names already exists
+ * 3. `config-file` in the user's settings.toml (see `user_config_dir`)
+ * 4. `devshell.toml`
+ *
+ * @return The configuration file name, relative to the project root.
+ */
+pub fn config_file() -> &'static str {
+    static CONFIG_FILE: OnceLock<String> = OnceLock::new();
+    CONFIG_FILE.get_or_init(|| {
+        let project = Path::new(STATE_DIR).join(SETTINGS_FILE);
+        if let Some(name) = configured_name(&project) {
+            return name;
+        }
+
+        if let Some(name) = CONFIG_FILE_NAMES
+            .iter()
+            .find(|name| Path::new(name).exists())
+        {
+            return name.to_string();
+        }
+
+        user_config_dir()
+            .and_then(|dir| configured_name(&dir.join(SETTINGS_FILE)))
+            .unwrap_or_else(|| CONFIG_FILE_NAMES[0].to_string())
+    })
+}
+
+/**
+ * @brief Returns the `nixpkgs-channel` setting for new flakes.
+ */
+pub fn nixpkgs_channel() -> Option<String> {
+    setting(|settings| settings.nixpkgs_channel)
+}
+
+/**
+ * @brief Returns whether toolchain inputs should follow the project's nixpkgs (default true).
+ */
+pub fn follows_nixpkgs() -> bool {
+    setting(|settings| settings.follows_nixpkgs).unwrap_or(true)
+}
+
+/**
+ * @brief Reads one setting; the project's `.nix-shell-gen/settings.toml` wins over the user's.
+ * @param field Picks the setting out of a settings file.
+ */
+fn setting<T>(field: impl Fn(Settings) -> Option<T>) -> Option<T> {
+    let project = Path::new(STATE_DIR).join(SETTINGS_FILE);
+    read_settings(&project).and_then(&field).or_else(|| {
+        user_config_dir()
+            .and_then(|dir| read_settings(&dir.join(SETTINGS_FILE)))
+            .and_then(&field)
+    })
+}
+
+/**
+ * @brief Returns the personal layer's file name, derived from the config file name.
+ * @details "devshell.toml" -> "devshell.local.toml".
+ */
+pub fn local_config_file() -> &'static str {
+    static LOCAL_CONFIG_FILE: OnceLock<String> = OnceLock::new();
+    LOCAL_CONFIG_FILE.get_or_init(|| local_file_name(config_file()))
+}
+
+/**
+ * @brief Derives the personal layer's file name from a config file name.
+ * @param config_file The team config file name (e.g. "devshell.toml").
+ * @return The personal layer file name (e.g. "devshell.local.toml").
+ */
+pub fn local_file_name(config_file: &str) -> String {
+    let stem = config_file.strip_suffix(".toml").unwrap_or(config_file);
+    format!("{}.local.toml", stem)
+}
+
+/**
+ * @brief Returns the user's nix-shell-gen config directory.
+ * @details `$NIX_SHELL_GEN_CONFIG_DIR`, or the platform config dir
+ * (`$XDG_CONFIG_HOME/nix-shell-gen`, usually `~/.config/nix-shell-gen`, on Linux).
+ */
+pub fn user_config_dir() -> Option<PathBuf> {
+    env_dir("NIX_SHELL_GEN_CONFIG_DIR")
+        .or_else(|| project_dirs().map(|dirs| dirs.config_dir().to_path_buf()))
+}
+
+/**
+ * @brief Returns the directory holding the user's language templates (`<config dir>/templates`).
+ */
+pub fn templates_dir() -> Option<PathBuf> {
+    user_config_dir().map(|dir| dir.join("templates"))
+}
+
+/**
+ * @brief Returns the directory for cached data, such as downloaded indexes.
+ *
+ * In order: `--cache-dir`, `$NIX_SHELL_GEN_CACHE_DIR`, then the platform cache dir
+ * (`$XDG_CACHE_HOME/nix-shell-gen`, usually `~/.cache/nix-shell-gen`, on Linux).
+ * Everything in it can be deleted at any time.
+ */
+pub fn cache_dir() -> Option<PathBuf> {
+    CACHE_DIR_OVERRIDE
+        .get()
+        .cloned()
+        .or_else(|| env_dir("NIX_SHELL_GEN_CACHE_DIR"))
+        .or_else(|| project_dirs().map(|dirs| dirs.cache_dir().to_path_buf()))
+}
+
+/**
+ * @brief Returns the directory for persistent user state, such as backups.
+ * @details `$NIX_SHELL_GEN_STATE_DIR`, or the platform state dir
+ * (`$XDG_STATE_HOME/nix-shell-gen` on Linux, the local data dir elsewhere).
+ */
+pub fn user_state_dir() -> Option<PathBuf> {
+    env_dir("NIX_SHELL_GEN_STATE_DIR").or_else(|| {
+        project_dirs().map(|dirs| {
+            dirs.state_dir()
+                .unwrap_or_else(|| dirs.data_local_dir())
+                .to_path_buf()
+        })
+    })
+}
+
+/**
+ * @brief Overrides the cache directory for this run (the `--cache-dir` flag).
+ * @param dir The directory to use instead of the default.
+ */
+pub fn override_cache_dir(dir: PathBuf) {
+    let _ = CACHE_DIR_OVERRIDE.set(dir);
+}
+
+static CACHE_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// @brief The platform directories for nix-shell-gen, if a home directory is known.
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "nix-shell-gen")
+}
+
+/**
+ * @brief Reads a directory override from the environment.
+ * @param var The environment variable.
+ * @return The directory, if the variable is set and not empty.
+ */
+fn env_dir(var: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/**
+ * @brief Reads the `config-file` setting from a settings file.
+ *
+ * Unreadable settings files are reported and otherwise ignored, so a broken user
+ * setting can't make every command fail.
+ *
+ * @param path The settings file.
+ * @return The configured name, if set.
+ */
+fn configured_name(path: &Path) -> Option<String> {
+    read_settings(path)?.config_file
+}
+
+/**
+ * @brief Loads a settings file, reporting and ignoring unreadable ones.
+ * @param path The settings file.
+ */
+fn read_settings(path: &Path) -> Option<Settings> {
+    match Settings::load(path) {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            eprintln!("Warning: ignoring settings file: {}", e);
+            None
+        }
+    }
+}
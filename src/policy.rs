@@ -0,0 +1,428 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Policy;
+use crate::messages;
+use crate::package_index;
+use crate::paths;
+use crate::process::{self, Process};
+
+/// @brief Name of the org-wide policy file in the user config dir.
+const POLICY_FILE: &str = "policy.toml";
+
+/// @brief Binary cache closure sizes are looked up in, so checking builds and downloads nothing.
+const SIZE_STORE: &str = "https://cache.nixos.org";
+
+/// @brief Size units accepted in `max-closure-size`, with their value in bytes.
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("b", 1),
+    ("k", 1 << 10),
+    ("kib", 1 << 10),
+    ("kb", 1000),
+    ("m", 1 << 20),
+    ("mib", 1 << 20),
+    ("mb", 1_000_000),
+    ("g", 1 << 30),
+    ("gib", 1 << 30),
+    ("gb", 1_000_000_000),
+];
+
+/**
+ * @brief Loads the policy in force: the project's `[policy]` tightened by the org-wide file.
+ *
+ * The org-wide file is `$NIX_SHELL_GEN_POLICY`, or `policy.toml` in the user config dir,
+ * and holds the same keys as `[policy]` at its top level. A project can deny more
+ * packages and allow fewer licenses than the org, never the other way round: denied
+ * packages add up, allowed licenses are intersected and the smaller closure limit wins.
+ *
+ * @param project The `[policy]` section of the team config.
+ * @return Result<Policy, Error> Returns an Error if the org-wide file can't be read or parsed,
+ * or a closure limit isn't a size.
+ */
+pub fn load(project: &Policy) -> Result<Policy, Error> {
+    let mut policy = project.clone();
+    if let Some(path) = org_policy_file() {
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let org: Policy = toml::from_str(&contents).map_err(|e| {
+                    messages::error(
+                        ErrorKind::InvalidData,
+                        messages::E_INVALID_CONFIG,
+                        format!("{}: {}", path.display(), e),
+                    )
+                })?;
+                policy = combine(policy, org)?;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if let Some(limit) = &policy.max_closure_size {
+        parse_size(limit)?;
+    }
+    Ok(policy)
+}
+
+/**
+ * @brief Finds the packages the policy denies, without asking nix.
+ * @param policy The policy in force.
+ * @param packages The attribute paths to check.
+ * @return One message per denied package.
+ */
+pub fn denied(policy: &Policy, packages: &[&str]) -> Vec<String> {
+    packages
+        .iter()
+        .filter_map(|pkg| {
+            let rule =
+                policy
+                    .denied_packages
+                    .iter()
+                    .find(|rule| match rule.strip_suffix(".*") {
+                        Some(set) => pkg
+                            .strip_prefix(set)
+                            .is_some_and(|rest| rest.starts_with('.')),
+                        None => rule == pkg,
+                    })?;
+            Some(if rule == pkg {
+                format!("'{}' is denied by policy", pkg)
+            } else {
+                format!("'{}' is denied by policy ({})", pkg, rule)
+            })
+        })
+        .collect()
+}
+
+/**
+ * @brief Finds the packages whose licenses the policy doesn't allow.
+ *
+ * Reads `meta.license` of each package with `nix eval`. A package with several
+ * licenses needs all of them allowed; one without license information is reported too.
+ *
+ * @param policy The policy in force; nothing is checked when it allows any license, and
+ * every package is reported when it allows none.
+ * @param packages The attribute paths to check.
+ * @param timeout Timeout for each nix call.
+ * @return One message per package, or an Error if nix couldn't answer.
+ */
+pub fn license_violations(
+    policy: &Policy,
+    packages: &[&str],
+    timeout: Option<Duration>,
+) -> Result<Vec<String>, Error> {
+    let Some(allowed) = &policy.allowed_licenses else {
+        return Ok(Vec::new());
+    };
+    if allowed.is_empty() {
+        return Ok(packages
+            .iter()
+            .map(|pkg| format!("'{}' can't be used: the policy allows no license", pkg))
+            .collect());
+    }
+    let allowed = lowercase(allowed);
+    let source = package_index::nixpkgs_ref()?;
+    let mut violations = Vec::new();
+    for pkg in packages {
+        let installable = format!("{}#{}.meta.license", source, pkg);
+        let output = match Process::nix(["eval", "--json", &installable])
+            .timeout(timeout)
+            .output()
+        {
+            Ok(output) => output,
+            Err(e)
+                if process::stderr_of(&e)
+                    .is_some_and(|stderr| stderr.contains("does not provide attribute")) =>
+            {
+                violations.push(format!("'{}' has no license information", pkg));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let value: Value = serde_json::from_slice(&output).map_err(Error::other)?;
+        let licenses = license_names(&value);
+        let forbidden: Vec<&str> = licenses
+            .iter()
+            .filter(|names| {
+                !names
+                    .iter()
+                    .any(|name| allowed.contains(&name.to_lowercase()))
+            })
+            .filter_map(|names| names.first().map(String::as_str))
+            .collect();
+        if licenses.is_empty() {
+            violations.push(format!("'{}' has no license information", pkg));
+        } else if !forbidden.is_empty() {
+            violations.push(format!(
+                "'{}' is licensed under {}, which the policy doesn't allow",
+                pkg,
+                forbidden.join(", ")
+            ));
+        }
+    }
+    Ok(violations)
+}
+
+/**
+ * @brief Checks the packages' combined closure against the policy's limit.
+ *
+ * Sizes come from the NixOS binary cache (`nix path-info --recursive`), counting every
+ * store path once, so nothing is built or downloaded.
+ *
+ * @param policy The policy in force; nothing is checked without a limit.
+ * @param packages The attribute paths making up the shell.
+ * @param timeout Timeout for the nix call.
+ * @return A message if the closure is too large, or an Error if nix couldn't answer.
+ */
+pub fn closure_violation(
+    policy: &Policy,
+    packages: &[&str],
+    timeout: Option<Duration>,
+) -> Result<Option<String>, Error> {
+    let Some(limit) = &policy.max_closure_size else {
+        return Ok(None);
+    };
+    if packages.is_empty() {
+        return Ok(None);
+    }
+    let max = parse_size(limit)?;
+    let source = package_index::nixpkgs_ref()?;
+    let output = Process::nix(["path-info", "--json", "--recursive", "--store", SIZE_STORE])
+        .args(packages.iter().map(|pkg| format!("{}#{}", source, pkg)))
+        .timeout(timeout)
+        .output()?;
+    let value: Value = serde_json::from_slice(&output).map_err(Error::other)?;
+    let size = closure_size(&value);
+    Ok((size > max).then(|| {
+        format!(
+            "the closure is {}, over the {} limit",
+            format_size(size),
+            format_size(max)
+        )
+    }))
+}
+
+/**
+ * @brief Formats a byte count for humans: "512 KiB", "1.4 GiB".
+ * @param bytes The size.
+ */
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{} KiB", bytes / 1024),
+        1_048_576..1_073_741_824 => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1} GiB", bytes as f64 / 1_073_741_824.0),
+    }
+}
+
+/**
+ * @brief Tightens one policy by another (see `load`).
+ * @param project The project's policy.
+ * @param org The org-wide policy.
+ */
+fn combine(mut project: Policy, org: Policy) -> Result<Policy, Error> {
+    project.denied_packages.extend(org.denied_packages);
+    // An empty intersection stays Some, so it allows nothing rather than anything.
+    project.allowed_licenses = match (project.allowed_licenses, org.allowed_licenses) {
+        (Some(ours), Some(theirs)) => {
+            let theirs = lowercase(&theirs);
+            Some(
+                ours.into_iter()
+                    .filter(|license| theirs.contains(&license.to_lowercase()))
+                    .collect(),
+            )
+        }
+        (ours, theirs) => ours.or(theirs),
+    };
+    project.max_closure_size = match (project.max_closure_size, org.max_closure_size) {
+        (Some(a), Some(b)) => Some(if parse_size(&a)? <= parse_size(&b)? {
+            a
+        } else {
+            b
+        }),
+        (a, b) => a.or(b),
+    };
+    Ok(project)
+}
+
+/// @brief License names compare case-insensitively ("MIT" is nixpkgs' "mit").
+fn lowercase(licenses: &BTreeSet<String>) -> BTreeSet<String> {
+    licenses
+        .iter()
+        .map(|license| license.to_lowercase())
+        .collect()
+}
+
+/**
+ * @brief Parses a size such as "2 GiB", "500MB" or "1048576".
+ * @param size The size; a bare number is in bytes.
+ * @return Result<u64, Error> Returns an Error if the number or the unit is unknown.
+ */
+fn parse_size(size: &str) -> Result<u64, Error> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let unit = unit.trim().to_lowercase();
+    let multiplier = if unit.is_empty() {
+        Some(1)
+    } else {
+        SIZE_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, bytes)| *bytes)
+    };
+    match (number.parse::<f64>(), multiplier) {
+        (Ok(number), Some(multiplier)) => Ok((number * multiplier as f64) as u64),
+        _ => Err(messages::error(
+            ErrorKind::InvalidData,
+            messages::E_INVALID_VALUE,
+            format!(
+                "max-closure-size: '{}' is not a size (e.g. \"2 GiB\", \"500 MB\")",
+                size
+            ),
+        )),
+    }
+}
+
+/**
+ * @brief Lists the names of the licenses in a `meta.license` value.
+ * @param value A license attrset, a list of them, or a plain string.
+ * @return For each license, its SPDX id, short name and full name, as far as known.
+ */
+fn license_names(value: &Value) -> Vec<Vec<String>> {
+    match value {
+        Value::Array(licenses) => licenses.iter().flat_map(license_names).collect(),
+        Value::String(name) => vec![vec![name.clone()]],
+        Value::Object(fields) => vec![
+            ["spdxId", "shortName", "fullName"]
+                .iter()
+                .filter_map(|key| fields.get(*key)?.as_str().map(str::to_string))
+                .collect(),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/**
+ * @brief Sums the NAR sizes in `nix path-info --json` output, counting each path once.
+ * @param value The output: a list of path infos, or (Nix 2.19 and later) an object keyed by path.
+ */
+fn closure_size(value: &Value) -> u64 {
+    let mut sizes = BTreeMap::new();
+    match value {
+        Value::Array(infos) => {
+            for info in infos {
+                if let Some(path) = info.get("path").and_then(Value::as_str) {
+                    sizes.insert(path.to_string(), nar_size(info));
+                }
+            }
+        }
+        Value::Object(infos) => {
+            for (path, info) in infos {
+                sizes.insert(path.clone(), nar_size(info));
+            }
+        }
+        _ => {}
+    }
+    sizes.values().sum()
+}
+
+/// @brief The `narSize` of one path info, 0 if it isn't known.
+fn nar_size(info: &Value) -> u64 {
+    info.get("narSize").and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// @brief The org-wide policy file: `$NIX_SHELL_GEN_POLICY`, else `policy.toml` in the user config dir.
+fn org_policy_file() -> Option<PathBuf> {
+    env::var_os("NIX_SHELL_GEN_POLICY")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| paths::user_config_dir().map(|dir| dir.join(POLICY_FILE)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// @brief A policy allowing these licenses, or any license for None.
+    fn allowing(licenses: Option<&[&str]>) -> Policy {
+        Policy {
+            allowed_licenses: licenses
+                .map(|licenses| licenses.iter().map(|license| license.to_string()).collect()),
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn licenses_intersect_ignoring_case() {
+        let policy = combine(
+            allowing(Some(&["MIT", "Apache-2.0", "GPL-3.0"])),
+            allowing(Some(&["mit", "apache-2.0"])),
+        )
+        .unwrap();
+        let allowed: Vec<&str> = policy
+            .allowed_licenses
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(allowed, ["Apache-2.0", "MIT"]);
+    }
+
+    #[test]
+    fn disjoint_licenses_allow_none() {
+        let policy = combine(allowing(Some(&["MIT"])), allowing(Some(&["BSD-3-Clause"]))).unwrap();
+        assert_eq!(policy.allowed_licenses, Some(BTreeSet::new()));
+        assert!(!policy.is_empty());
+        let violations = license_violations(&policy, &["hello"], None).unwrap();
+        assert_eq!(violations.len(), 1);
+    }
+
+    #[test]
+    fn unset_licenses_defer_to_the_other_side() {
+        let policy = combine(allowing(None), allowing(Some(&["MIT"]))).unwrap();
+        assert_eq!(policy.allowed_licenses.unwrap().len(), 1);
+        let policy = combine(allowing(Some(&["MIT"])), allowing(None)).unwrap();
+        assert_eq!(policy.allowed_licenses.unwrap().len(), 1);
+        assert!(combine(allowing(None), allowing(None)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn denied_packages_add_up_and_the_smaller_limit_wins() {
+        let project = Policy {
+            denied_packages: ["telnet".to_string()].into(),
+            max_closure_size: Some("2 GiB".to_string()),
+            ..Policy::default()
+        };
+        let org = Policy {
+            denied_packages: ["python3Packages.*".to_string()].into(),
+            max_closure_size: Some("500 MB".to_string()),
+            ..Policy::default()
+        };
+        let policy = combine(project, org).unwrap();
+        assert_eq!(policy.denied_packages.len(), 2);
+        assert_eq!(policy.max_closure_size.as_deref(), Some("500 MB"));
+        assert_eq!(
+            denied(&policy, &["telnet", "python3Packages.numpy", "python3"]).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1048576").unwrap(), 1 << 20);
+        assert_eq!(parse_size("2 GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size(" 500 MB ").unwrap(), 500_000_000);
+        assert_eq!(parse_size("1.5k").unwrap(), 1536);
+        assert_eq!(parse_size("3 kb").unwrap(), 3000);
+        for size in ["", "GiB", "2 parsecs", "two GiB"] {
+            assert!(parse_size(size).is_err(), "{}", size);
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::messages;
+
+/// @brief How often a running child is checked for exit, timeout and Ctrl-C.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// @brief Number of trailing stderr lines kept in the error of a failed command.
+const STDERR_TAIL: usize = 20;
+
+/// @brief Set by the Ctrl-C handler while a child process is running; tells `wait` to kill it.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// @brief Set by the Ctrl-C handler along with INTERRUPTED, until `take_interrupted` reads it.
+static PRESSED: AtomicBool = AtomicBool::new(false);
+
+/// @brief Number of child processes currently being waited on.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+static HANDLER: Once = Once::new();
+
+/**
+ * @struct Process
+ * @brief An external command run with a timeout, Ctrl-C handling and captured stderr.
+ *
+ * Every non-interactive call to nix (and other tools) goes through this, so a
+ * hung substituter or network request can be interrupted or timed out instead of
+ * hanging the CLI, and failures carry the tool's own error output.
+ */
+pub struct Process {
+    cmd: Command,
+    timeout: Option<Duration>,
+    stdin: Option<Stdio>,
+}
+
+impl Process {
+    /**
+     * @brief Prepares a command.
+     * @param program The program to run.
+     */
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Process {
+            cmd: Command::new(program),
+            timeout: None,
+            stdin: None,
+        }
+    }
+
+    /**
+     * @brief Prepares a `nix` invocation.
+     * @param args Arguments passed to nix.
+     */
+    pub fn nix<I, S>(args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Process::new("nix").args(args)
+    }
+
+    /// @brief Appends an argument.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.cmd.arg(arg);
+        self
+    }
+
+    /// @brief Appends several arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.cmd.args(args);
+        self
+    }
+
+    /// @brief Sets an environment variable for the command.
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.cmd.env(key, value);
+        self
+    }
+
+    /// @brief Kills the command if it runs longer than `timeout` (None waits forever).
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /**
+     * @brief Feeds bytes to the command's standard input.
+     *
+     * Used for secrets, which would be visible to other users as arguments.
+     */
+    pub fn stdin_bytes(mut self, data: Vec<u8>) -> Result<Self, Error> {
+        let (reader, mut writer) = std::io::pipe()?;
+        // Written from a thread so large inputs can't fill the pipe before the child reads.
+        thread::spawn(move || writer.write_all(&data));
+        self.stdin = Some(reader.into());
+        Ok(self)
+    }
+
+    /**
+     * @brief Runs the command with stdout and stderr shown to the user.
+     *
+     * Stderr is forwarded line by line (nix reports progress there) and the last
+     * lines are kept in the error if the command fails (see `stderr_of`).
+     *
+     * @return Result<(), Error> Returns Ok if the command exits successfully.
+     */
+    pub fn run(self) -> Result<(), Error> {
+        self.spawn_and_wait(Stdio::inherit(), true).map(|_| ())
+    }
+
+    /**
+     * @brief Runs the command quietly and returns its standard output.
+     * @return Result containing stdout, or an Error including stderr on failure.
+     */
+    pub fn output(self) -> Result<Vec<u8>, Error> {
+        self.spawn_and_wait(Stdio::piped(), false)
+    }
+
+    /**
+     * @brief Runs an interactive command, such as a shell, attached to the terminal.
+     *
+     * No timeout applies and Ctrl-C is left to the child, which owns the terminal.
+     *
+     * @return Result containing the exit status.
+     */
+    pub fn interactive(mut self) -> Result<ExitStatus, Error> {
+        let program = self.program();
+        if let Some(stdin) = self.stdin.take() {
+            self.cmd.stdin(stdin);
+        }
+        let mut child = self.cmd.spawn().map_err(|e| spawn_error(&program, e))?;
+
+        RUNNING.fetch_add(1, Ordering::SeqCst);
+        let status = child.wait();
+        stop_running();
+        status
+    }
+
+    /**
+     * @brief Runs the command with each line of its stdout and stderr passed to `line`.
+     *
+     * Lets several commands run at once with their output told apart. No timeout
+     * applies and stdin is closed.
+     *
+     * @param line Receives each line, without the newline.
+     * @return Result containing the exit status.
+     */
+    pub fn run_lines(mut self, line: impl Fn(&str) + Sync) -> Result<ExitStatus, Error> {
+        let program = self.program();
+        let mut child = self
+            .cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| spawn_error(&program, e))?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        RUNNING.fetch_add(1, Ordering::SeqCst);
+        let status = thread::scope(|scope| {
+            let line = &line;
+            if let Some(pipe) = stdout {
+                scope.spawn(move || forward_lines(pipe, line));
+            }
+            if let Some(pipe) = stderr {
+                scope.spawn(move || forward_lines(pipe, line));
+            }
+            child.wait()
+        });
+        stop_running();
+        status
+    }
+
+    /// @brief The program name, for error messages.
+    fn program(&self) -> String {
+        self.cmd.get_program().to_string_lossy().into_owned()
+    }
+
+    /**
+     * @brief Spawns the command, collects its output and waits for it under the timeout.
+     * @param stdout How stdout is handled; it is returned when piped.
+     * @param echo_stderr Whether stderr is forwarded to the terminal while captured.
+     */
+    fn spawn_and_wait(mut self, stdout: Stdio, echo_stderr: bool) -> Result<Vec<u8>, Error> {
+        install_handler();
+
+        let program = self.program();
+        let command_line = std::iter::once(program.clone())
+            .chain(
+                self.cmd
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().into_owned()),
+            )
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if let Some(stdin) = self.stdin.take() {
+            self.cmd.stdin(stdin);
+        }
+        let mut child = self
+            .cmd
+            .stdout(stdout)
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| spawn_error(&program, e))?;
+
+        let stdout_reader = child.stdout.take().map(|mut pipe| {
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                pipe.read_to_end(&mut buf).map(|_| buf)
+            })
+        });
+        let stderr_reader = child.stderr.take().map(|pipe| {
+            thread::spawn(move || {
+                let mut lines = Vec::new();
+                for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                    if echo_stderr {
+                        eprintln!("{}", line);
+                    }
+                    lines.push(line);
+                }
+                lines
+            })
+        });
+
+        // Readers are only joined once the child exited on its own: a killed
+        // child may leave grandchildren holding the pipes open.
+        let status = wait(&mut child, self.timeout, &command_line)?;
+        let stderr = stderr_reader
+            .map(|reader| reader.join().unwrap_or_default())
+            .unwrap_or_default();
+        let stdout = match stdout_reader {
+            Some(reader) => reader
+                .join()
+                .map_err(|_| Error::other("stdout reader panicked"))??,
+            None => Vec::new(),
+        };
+
+        if !status.success() {
+            let tail = &stderr[stderr.len().saturating_sub(STDERR_TAIL)..];
+            return Err(Error::other(Failure {
+                message: format!("`{}` exited with {}", command_line, status),
+                stderr: tail.join("\n"),
+                // Echoed stderr is already on screen.
+                show_stderr: !echo_stderr,
+            }));
+        }
+
+        Ok(stdout)
+    }
+}
+
+/**
+ * @struct Failure
+ * @brief Error payload of a command that exited unsuccessfully.
+ */
+#[derive(Debug)]
+struct Failure {
+    message: String,
+    stderr: String,
+    show_stderr: bool,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if self.show_stderr && !self.stderr.is_empty() {
+            write!(f, ":\n{}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/**
+ * @brief Returns the captured stderr of a failed command, for recognizing known errors.
+ * @param error An error returned by `Process::run` or `Process::output`.
+ */
+pub fn stderr_of(error: &Error) -> Option<&str> {
+    error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<Failure>())
+        .map(|failure| failure.stderr.as_str())
+}
+
+/**
+ * @brief Waits for a child, killing it on timeout or Ctrl-C.
+ * @param child The running child.
+ * @param timeout Maximum run time, if any.
+ * @param command_line The command, for error messages.
+ * @return Result containing the exit status, TimedOut, or Interrupted.
+ */
+fn wait(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    command_line: &str,
+) -> Result<ExitStatus, Error> {
+    let start = Instant::now();
+    RUNNING.fetch_add(1, Ordering::SeqCst);
+    let result = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) => {}
+            Err(e) => break Err(e),
+        }
+
+        if INTERRUPTED.swap(false, Ordering::SeqCst) {
+            let _ = child.kill();
+            let _ = child.wait();
+            break Err(messages::error(
+                ErrorKind::Interrupted,
+                messages::E_INTERRUPTED,
+                format!("Interrupted `{}`", command_line),
+            ));
+        }
+        if let Some(timeout) = timeout
+            && start.elapsed() >= timeout
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            break Err(messages::error(
+                ErrorKind::TimedOut,
+                messages::E_TIMED_OUT,
+                format!(
+                    "`{}` timed out after {}s (raise it with --timeout)",
+                    command_line,
+                    timeout.as_secs()
+                ),
+            ));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+    stop_running();
+    result
+}
+
+/**
+ * @brief Counts a child as no longer waited on.
+ *
+ * Ctrl-C during `interactive` or `run_lines` goes to the child, and no `wait` is
+ * there to consume the flag; the last child to finish drops it, so it can't kill
+ * the next command the moment it starts.
+ */
+fn stop_running() {
+    if RUNNING.fetch_sub(1, Ordering::SeqCst) == 1 {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+}
+
+/**
+ * @brief Reports whether Ctrl-C was pressed while a child ran, and clears it.
+ *
+ * Lets a loop that runs commands, such as `--watch`, stop when the user interrupts
+ * one, however the command reported it.
+ *
+ * @return true if Ctrl-C was pressed since the last call.
+ */
+pub fn take_interrupted() -> bool {
+    PRESSED.swap(false, Ordering::SeqCst)
+}
+
+/**
+ * @brief Passes each line read from a pipe to `line`, until the pipe closes.
+ * @param pipe The child's stdout or stderr.
+ * @param line Receives each line.
+ */
+fn forward_lines(pipe: impl Read, line: &impl Fn(&str)) {
+    for text in BufReader::new(pipe).lines().map_while(Result::ok) {
+        line(&text);
+    }
+}
+
+/**
+ * @brief Installs the Ctrl-C handler once.
+ *
+ * While a child runs, Ctrl-C marks it for killing so the caller can clean up and
+ * report; otherwise it exits right away as it would without a handler.
+ */
+fn install_handler() {
+    HANDLER.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            if RUNNING.load(Ordering::SeqCst) == 0 {
+                std::process::exit(130);
+            }
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            PRESSED.store(true, Ordering::SeqCst);
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: could not install Ctrl-C handler: {}", e);
+        }
+    });
+}
+
+/**
+ * @brief Wraps a spawn failure with the program name.
+ * @param program The program that could not be started.
+ * @param e The underlying error.
+ */
+fn spawn_error(program: &str, e: Error) -> Error {
+    let code = if e.kind() == ErrorKind::NotFound {
+        messages::E_COMMAND_NOT_FOUND
+    } else {
+        messages::code(&e)
+    };
+    messages::error(e.kind(), code, format!("Could not run {}: {}", program, e))
+}
@@ -0,0 +1,167 @@
+use std::fs::{self, File, TryLockError};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::STATE_DIR;
+use crate::messages;
+
+/// @brief Lock file guarding project mutations, inside the state dir.
+const LOCK_FILE_NAME: &str = "lock";
+
+/// @brief Keeps per-machine files in the state dir out of git; settings.toml stays tracked.
+const STATE_GITIGNORE: &str = "/lock\n/shell-profile*\n";
+
+/// @brief Marks the project as frozen, holding the reason. Tracked in git, so a freeze
+/// (e.g. during release stabilization) applies to everyone.
+pub const FROZEN_FILE: &str = ".nix-shell-gen/frozen";
+
+/// @brief Turns on read-only mode like `--read-only`, for CI jobs that can't pass flags.
+pub const READ_ONLY_ENV_VAR: &str = "NIX_SHELL_GEN_READ_ONLY";
+
+static IGNORE_FREEZE: AtomicBool = AtomicBool::new(false);
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Lets mutating commands run in a frozen project (the `--ignore-freeze` flag).
+ * @param ignore Whether to ignore the freeze.
+ */
+pub fn set_ignore_freeze(ignore: bool) {
+    IGNORE_FREEZE.store(ignore, Ordering::Relaxed);
+}
+
+/**
+ * @brief Refuses every change to the project for this run (the `--read-only` flag).
+ * @param read_only Whether mutating commands should fail.
+ */
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+/**
+ * @brief Fails if this run is read-only, before a mutating command touches anything.
+ * @return Result<(), Error> PermissionDenied in read-only mode.
+ */
+pub fn check_writable() -> Result<(), Error> {
+    if READ_ONLY.load(Ordering::Relaxed) {
+        return Err(messages::error(
+            ErrorKind::PermissionDenied,
+            messages::E_READ_ONLY,
+            format!(
+                "This command changes the project, which read-only mode (--read-only or ${}) forbids; inspection commands such as show, check and render still work.",
+                READ_ONLY_ENV_VAR
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * @brief Reads why the project in a directory is frozen.
+ * @param dir The project directory.
+ * @return The reason (empty if none was given), or None if the project isn't frozen.
+ */
+pub fn frozen_reason(dir: &Path) -> Option<String> {
+    fs::read_to_string(dir.join(FROZEN_FILE))
+        .ok()
+        .map(|reason| reason.trim().to_string())
+}
+
+/**
+ * @struct ProjectLock
+ * @brief An advisory lock on the project, held until dropped.
+ *
+ * Mutating commands take it before reading the files they are going to rewrite,
+ * so two concurrent invocations (e.g. a watcher and a manual `add`) can't
+ * interleave their read-modify-write cycles.
+ */
+pub struct ProjectLock {
+    _file: File,
+}
+
+impl ProjectLock {
+    /**
+     * @brief Acquires the project lock, waiting if another invocation holds it.
+     * @return Result containing the held lock, or PermissionDenied if the project is frozen.
+     */
+    pub fn acquire() -> Result<Self, Error> {
+        if !IGNORE_FREEZE.load(Ordering::Relaxed)
+            && let Some(reason) = frozen_reason(Path::new("."))
+        {
+            let reason = if reason.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", reason)
+            };
+            return Err(messages::error(
+                ErrorKind::PermissionDenied,
+                messages::E_FROZEN,
+                format!(
+                    "The project is frozen{}; run `nix-shell-gen thaw` or pass --ignore-freeze.",
+                    reason
+                ),
+            ));
+        }
+        Self::acquire_even_if_frozen()
+    }
+
+    /**
+     * @brief Acquires the project lock without checking for a freeze, for `freeze` and `thaw`.
+     * @return Result containing the held lock.
+     */
+    pub fn acquire_even_if_frozen() -> Result<Self, Error> {
+        check_writable()?;
+        let state_dir = Path::new(STATE_DIR);
+        fs::create_dir_all(state_dir).map_err(read_only_filesystem)?;
+
+        let gitignore = state_dir.join(".gitignore");
+        if !gitignore.exists() {
+            fs::write(&gitignore, STATE_GITIGNORE).map_err(read_only_filesystem)?;
+        }
+
+        let path = state_dir.join(LOCK_FILE_NAME);
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+            .map_err(read_only_filesystem)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                eprintln!("Waiting for another nix-shell-gen command to finish...");
+                file.lock()?;
+            }
+            Err(TryLockError::Error(e)) if e.kind() == ErrorKind::Unsupported => {
+                // Some network filesystems don't support locking; carry on unlocked.
+                eprintln!("Warning: {} can't be locked: {}", path.display(), e);
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        Ok(ProjectLock { _file: file })
+    }
+}
+
+/**
+ * @brief Explains a write that failed because the project is on a read-only filesystem.
+ *
+ * The lock is the first thing a mutating command writes, so this is where it stops,
+ * before any project file is half rewritten.
+ *
+ * @param error The error of the write.
+ * @return Error The error, reworded if the filesystem is read-only.
+ */
+fn read_only_filesystem(error: Error) -> Error {
+    if error.kind() != ErrorKind::ReadOnlyFilesystem {
+        return error;
+    }
+    messages::error(
+        ErrorKind::ReadOnlyFilesystem,
+        messages::E_READ_ONLY_FILESYSTEM,
+        "The project is on a read-only filesystem, so this command can't change it; inspection commands such as show, check and render still work.",
+    )
+}
@@ -0,0 +1,117 @@
+use crate::templates::language_template;
+
+/**
+ * @struct Recipe
+ * @brief A worked example configuration shown by `nix-shell-gen guide`.
+ *
+ * Recipes are expressed as `init` arguments on top of the language templates, so
+ * the printed command and `guide --scaffold` always match what `init` generates.
+ */
+pub struct Recipe {
+    /// @brief The topic name passed to `guide`.
+    pub topic: &'static str,
+    /// @brief One-line description.
+    pub summary: &'static str,
+    /// @brief Language template to start from.
+    pub lang: Option<&'static str>,
+    /// @brief Extra Nixpkgs packages.
+    pub packages: &'static [&'static str],
+    /// @brief Shell hook, if any.
+    pub shell_hook: Option<&'static str>,
+    /// @brief What to do after scaffolding.
+    pub notes: &'static str,
+}
+
+/// @brief The built-in recipes.
+pub const RECIPES: &[Recipe] = &[
+    Recipe {
+        topic: "rust",
+        summary: "Rust toolchain with rust-analyzer",
+        lang: Some("rust"),
+        packages: &["clippy", "rustfmt"],
+        shell_hook: None,
+        notes: "Run `cargo init` inside the shell to start a crate.",
+    },
+    Recipe {
+        topic: "rust+postgres",
+        summary: "Rust service with a project-local PostgreSQL",
+        lang: Some("rust"),
+        packages: &["postgresql", "pkg-config", "openssl"],
+        shell_hook: Some(
+            "export PGDATA=\"$PWD/.pgdata\"; export PGHOST=\"$PGDATA\"; [ -d \"$PGDATA\" ] || initdb --auth=trust --no-locale >/dev/null",
+        ),
+        notes: "Start the database with `pg_ctl -o \"-k $PGDATA\" -l .pgdata/log start`; add .pgdata to .gitignore.",
+    },
+    Recipe {
+        topic: "python-ml",
+        summary: "Python with a virtualenv managed by uv, for ML libraries from PyPI",
+        lang: Some("python"),
+        packages: &["uv", "ruff"],
+        shell_hook: Some(
+            "[ -d .venv ] || uv venv --quiet; . .venv/bin/activate; export UV_PYTHON=python3",
+        ),
+        notes: "Install libraries with `uv pip install numpy torch`; add .venv to .gitignore.",
+    },
+    Recipe {
+        topic: "cpp-cmake",
+        summary: "C++ with CMake, Ninja and clang tooling",
+        lang: Some("cpp"),
+        packages: &["ninja", "pkg-config", "clang-tools"],
+        shell_hook: None,
+        notes: "Configure with `cmake -B build -G Ninja -DCMAKE_EXPORT_COMPILE_COMMANDS=ON`.",
+    },
+];
+
+impl Recipe {
+    /**
+     * @brief Returns the packages the recipe ends up with: the template's plus its own.
+     */
+    pub fn all_packages(&self) -> Vec<&'static str> {
+        let mut packages: Vec<&str> = self
+            .lang
+            .and_then(language_template)
+            .map(|template| template.packages.to_vec())
+            .unwrap_or_default();
+        packages.extend(self.packages);
+        packages
+    }
+
+    /**
+     * @brief Renders the `init` command that scaffolds this recipe.
+     */
+    pub fn init_command(&self) -> String {
+        let mut command = String::from("nix-shell-gen init");
+        if let Some(lang) = self.lang {
+            command.push_str(&format!(" --lang {}", lang));
+        }
+        if !self.packages.is_empty() {
+            command.push_str(&format!(" -p {}", shell_quote(&self.packages.join(" "))));
+        }
+        if let Some(hook) = self.shell_hook {
+            command.push_str(&format!(" -s {}", shell_quote(hook)));
+        }
+        command
+    }
+}
+
+/**
+ * @brief Looks up a recipe by topic.
+ * @param topic The topic name.
+ */
+pub fn find(topic: &str) -> Option<&'static Recipe> {
+    RECIPES.iter().find(|recipe| recipe.topic == topic)
+}
+
+/**
+ * @brief Quotes a string for a POSIX shell.
+ * @param value The string to quote.
+ */
+pub fn shell_quote(value: &str) -> String {
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./+".contains(c))
+    {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
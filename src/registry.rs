@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::{CONFIG_FILE_NAMES, DevShellConfig, ShellFormat};
+use crate::flake_editor;
+use crate::lockfile::{FlakeLock, LOCK_FILE};
+use crate::paths;
+use crate::project_lock;
+use crate::templates::FLAKE_FILE;
+
+/// @brief Name of the registry file in the user state dir.
+const REGISTRY_FILE: &str = "projects.toml";
+
+/**
+ * @struct Registry
+ * @brief The projects nix-shell-gen has set up or entered on this machine.
+ *
+ * Kept in the user state dir (see `paths::user_state_dir`) and filled in by `init` and
+ * `enter`, so `projects` and `status --all` can look across every checkout.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Registry {
+    /// @brief Registered projects, keyed by their absolute directory.
+    #[serde(default)]
+    pub projects: BTreeMap<PathBuf, RegisteredProject>,
+}
+
+/**
+ * @struct RegisteredProject
+ * @brief What the registry remembers about one project.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegisteredProject {
+    /// @brief When the project was last initialized or entered, in seconds since the epoch.
+    pub last_used: u64,
+
+    /// @brief How often the shell was entered with `enter`.
+    #[serde(default)]
+    pub entries: u64,
+
+    /// @brief When the shell was last entered, in seconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_entered: Option<u64>,
+
+    /// @brief Time spent in entered shells, summed over all entries, in seconds.
+    #[serde(default)]
+    pub seconds_entered: u64,
+}
+
+impl RegisteredProject {
+    /// @brief The average time spent per entry, if the shell was ever entered.
+    pub fn average_session(&self) -> Option<u64> {
+        (self.entries > 0).then(|| self.seconds_entered / self.entries)
+    }
+}
+
+impl Registry {
+    /**
+     * @brief Loads the registry, empty if it doesn't exist yet.
+     * @return Result<Self, Error> Returns an Error if the file can't be read or parsed.
+     */
+    pub fn load() -> Result<Self, Error> {
+        let Some(path) = registry_file() else {
+            return Ok(Registry::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Registry::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /**
+     * @brief Writes the registry back to the user state dir.
+     * @return Result<(), Error> Returns an Error if there is no state dir or it isn't writable.
+     */
+    pub fn save(&self) -> Result<(), Error> {
+        let path = registry_file().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "No state directory; set $NIX_SHELL_GEN_STATE_DIR.",
+            )
+        })?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = toml::to_string_pretty(self).map_err(Error::other)?;
+        fs::write(path, contents)
+    }
+}
+
+/**
+ * @brief Records the current directory as a project, or refreshes its last use.
+ *
+ * The registry is a convenience: callers ignore failures, so a read-only home
+ * never stops a shell from being created or entered.
+ *
+ * @return Result<(), Error> Returns an Error if the registry can't be read or written.
+ */
+pub fn record_current() -> Result<(), Error> {
+    let dir = std::env::current_dir()?.canonicalize()?;
+    let mut registry = Registry::load()?;
+    registry.projects.entry(dir).or_default().last_used = unix_time(SystemTime::now());
+    registry.save()
+}
+
+/**
+ * @brief Adds one `enter` session to the current project's usage statistics.
+ *
+ * The statistics never leave the state dir; they only feed `status`, to spot
+ * environments nobody uses any more. Like `record_current`, failures are for the
+ * caller to ignore.
+ *
+ * @param started When the shell was started; the session lasted until now.
+ * @return Result<(), Error> Returns an Error if the registry can't be read or written.
+ */
+pub fn record_session(started: SystemTime) -> Result<(), Error> {
+    let dir = std::env::current_dir()?.canonicalize()?;
+    let mut registry = Registry::load()?;
+    let project = registry.projects.entry(dir).or_default();
+    project.entries += 1;
+    project.last_entered = Some(unix_time(started));
+    project.seconds_entered += started.elapsed().map_or(0, |elapsed| elapsed.as_secs());
+    registry.save()
+}
+
+/**
+ * @brief Formats a duration in seconds for humans: "42s", "5m", "2h 10m".
+ * @param seconds The duration.
+ */
+pub fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/**
+ * @struct ProjectStatus
+ * @brief The state of one project, read from its files without entering it.
+ */
+#[derive(Debug)]
+pub struct ProjectStatus {
+    /// @brief The project directory.
+    pub dir: PathBuf,
+    /// @brief The config file name, or None if the project no longer has one.
+    pub config_file: Option<&'static str>,
+    /// @brief Whether the project generates flake.nix or shell.nix.
+    pub format: ShellFormat,
+    /// @brief Whether a flake project has flake.lock.
+    pub locked: bool,
+    /// @brief Flake inputs whose lock entry comes from a different URL than flake.nix declares.
+    pub stale_inputs: Vec<String>,
+    /// @brief Age of the locked nixpkgs in days, when the lock records it.
+    pub nixpkgs_age_days: Option<u64>,
+    /// @brief The `owners` from `[meta]`.
+    pub owners: Vec<String>,
+    /// @brief Why the project is frozen (empty without a reason), or None if it isn't.
+    pub frozen: Option<String>,
+    /// @brief The exported `[ports]`, with the personal layer's offset: variable and port.
+    pub ports: Vec<(String, u16)>,
+}
+
+impl ProjectStatus {
+    /**
+     * @brief Reads the status of the project in a directory.
+     *
+     * Unreadable files count as missing; this never fails, so one broken project
+     * doesn't hide the others.
+     *
+     * @param dir The project directory.
+     */
+    pub fn read(dir: &Path) -> Self {
+        let config_file = CONFIG_FILE_NAMES
+            .iter()
+            .copied()
+            .find(|name| dir.join(name).is_file());
+        let load = |name: &str| {
+            dir.join(name)
+                .to_str()
+                .and_then(|path| DevShellConfig::load(path).ok())
+                .unwrap_or_default()
+        };
+        let config =
+            config_file.map(|name| load(name).merged_with(load(&paths::local_file_name(name))));
+
+        let lock = fs::read_to_string(dir.join(LOCK_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<FlakeLock>(&contents).ok());
+        let stale_inputs = match (&lock, fs::read_to_string(dir.join(FLAKE_FILE))) {
+            (Some(lock), Ok(flake)) => {
+                let declared = flake_editor::parse_flake_inputs(&flake);
+                lock.stale_inputs(
+                    declared
+                        .iter()
+                        .filter_map(|input| Some((input.key.as_str(), input.url.as_deref()?))),
+                )
+                .into_iter()
+                .map(|input| input.key)
+                .collect()
+            }
+            _ => Vec::new(),
+        };
+        let nixpkgs_age_days = lock
+            .as_ref()
+            .and_then(|lock| lock.input_node("nixpkgs")?.locked.as_ref()?.last_modified)
+            .map(|locked_at| {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |now| now.as_secs());
+                now.saturating_sub(locked_at) / 86_400
+            });
+
+        ProjectStatus {
+            dir: dir.to_path_buf(),
+            config_file,
+            format: config
+                .as_ref()
+                .map(DevShellConfig::shell_format)
+                .unwrap_or_default(),
+            locked: lock.is_some(),
+            stale_inputs,
+            nixpkgs_age_days,
+            owners: config
+                .as_ref()
+                .map(|config| config.meta.owners.clone())
+                .unwrap_or_default(),
+            frozen: project_lock::frozen_reason(dir),
+            ports: config
+                .iter()
+                .flat_map(|config| config.port_variables())
+                .map(|(variable, port, _)| (variable, port))
+                .collect(),
+        }
+    }
+
+    /**
+     * @brief A one-word summary of whether the shell files agree with each other.
+     * @return "missing", "unlocked", "stale-lock" or "ok".
+     */
+    pub fn state(&self) -> &'static str {
+        if self.config_file.is_none() {
+            "missing"
+        } else if self.format == ShellFormat::Flake && !self.locked {
+            "unlocked"
+        } else if !self.stale_inputs.is_empty() {
+            "stale-lock"
+        } else {
+            "ok"
+        }
+    }
+}
+
+/// @brief Seconds since the epoch, 0 for times before it.
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// @brief The registry's location, if there is a state dir.
+fn registry_file() -> Option<PathBuf> {
+    paths::user_state_dir().map(|dir| dir.join(REGISTRY_FILE))
+}
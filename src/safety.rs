@@ -0,0 +1,252 @@
+/**
+ * @struct HookWarning
+ * @brief A potentially dangerous construct found in a shell hook.
+ */
+#[derive(Debug)]
+pub struct HookWarning {
+    /// @brief The offending line of the hook, trimmed.
+    pub line: String,
+    /// @brief Why the line is considered dangerous.
+    pub reason: &'static str,
+}
+
+/// @brief Programs that download content which is then piped into a shell.
+const DOWNLOADERS: &[&str] = &["curl", "wget"];
+
+/// @brief Programs that execute whatever is piped into them.
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "fish", "sudo", "python", "python3"];
+
+/// @brief Redirect targets that are outside the project but harmless.
+const SAFE_TARGETS: &[&str] = &["/dev/null", "/dev/stdout", "/dev/stderr"];
+
+/**
+ * @brief Scans a shell hook for patterns that are risky to run on someone else's machine.
+ *
+ * Hooks run automatically on every shell entry, so a shared hook is effectively
+ * code execution for everyone who enters the shell. This looks for piping
+ * downloads into a shell, privilege escalation, and writes or deletions outside
+ * the project directory. The scan is heuristic and errs on the side of warning.
+ *
+ * @param hook The shell hook source.
+ * @return The warnings found, in line order.
+ */
+pub fn scan_hook(hook: &str) -> Vec<HookWarning> {
+    let mut warnings = Vec::new();
+
+    for line in hook.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let mut warn = |reason| {
+            warnings.push(HookWarning {
+                line: line.to_string(),
+                reason,
+            })
+        };
+
+        if pipes_download_into_shell(line) {
+            warn("pipes a download straight into a shell");
+        }
+        if words.iter().any(|word| program_name(word) == "sudo") {
+            warn("runs commands as root");
+        }
+        if writes_outside_project(&words) {
+            warn("writes outside the project directory");
+        }
+        if removes_outside_project(&words) {
+            warn("deletes files outside the project directory");
+        }
+    }
+
+    warnings
+}
+
+/**
+ * @brief Detects `curl ... | sh` style pipelines.
+ * @param line A single hook line.
+ * @return true if a downloader's output is piped into a shell or interpreter.
+ */
+fn pipes_download_into_shell(line: &str) -> bool {
+    let mut stages = line.split('|').map(str::trim);
+    let Some(first) = stages.next() else {
+        return false;
+    };
+
+    let mut downloaded = first
+        .split_whitespace()
+        .any(|word| DOWNLOADERS.contains(&program_name(word)));
+    for stage in stages {
+        let program = stage.split_whitespace().next().map(program_name);
+        if downloaded && program.is_some_and(|p| SHELLS.contains(&p)) {
+            return true;
+        }
+        downloaded |= stage
+            .split_whitespace()
+            .any(|word| DOWNLOADERS.contains(&program_name(word)));
+    }
+
+    false
+}
+
+/**
+ * @brief Detects redirections and `tee` targets outside the project.
+ * @param words The whitespace-separated words of a hook line.
+ * @return true if the line writes to an absolute or home-relative path.
+ */
+fn writes_outside_project(words: &[&str]) -> bool {
+    let mut targets = Vec::new();
+    let mut after_tee = false;
+
+    for (i, word) in words.iter().enumerate() {
+        // Handles `>file`, `>>file`, `2>file` as well as `> file`.
+        if let Some(pos) = word.find('>') {
+            let target = word[pos..].trim_start_matches('>');
+            if target.is_empty() {
+                if let Some(next) = words.get(i + 1) {
+                    targets.push(*next);
+                }
+            } else if !target.starts_with('&') {
+                targets.push(target);
+            }
+        }
+
+        if after_tee && !word.starts_with('-') {
+            targets.push(word);
+        }
+        if program_name(word) == "tee" {
+            after_tee = true;
+        } else if word.contains('|') || word.ends_with(';') {
+            after_tee = false;
+        }
+    }
+
+    targets.into_iter().any(is_outside_project)
+}
+
+/**
+ * @brief Detects `rm` invocations targeting paths outside the project.
+ * @param words The whitespace-separated words of a hook line.
+ * @return true if an `rm` argument is absolute or home-relative.
+ */
+fn removes_outside_project(words: &[&str]) -> bool {
+    words
+        .iter()
+        .position(|word| program_name(word) == "rm")
+        .is_some_and(|start| {
+            words[start + 1..]
+                .iter()
+                .take_while(|word| !matches!(**word, ";" | "&&" | "||" | "|"))
+                .filter(|word| !word.starts_with('-'))
+                .any(|word| is_outside_project(word))
+        })
+}
+
+/**
+ * @brief Checks whether a path points outside the current project.
+ * @param path The path as written in the hook (quotes are stripped).
+ */
+fn is_outside_project(path: &str) -> bool {
+    let path = path.trim_matches(|c| c == '"' || c == '\'' || c == ';');
+    if SAFE_TARGETS.contains(&path) {
+        return false;
+    }
+    path.starts_with('/')
+        || path.starts_with('~')
+        || path.starts_with("$HOME")
+        || path.starts_with("${HOME}")
+        || path.starts_with("..")
+}
+
+/**
+ * @brief Strips directories from a command word (e.g. "/usr/bin/sudo" -> "sudo").
+ * @param word A word from a hook line.
+ */
+fn program_name(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// @brief The reasons the scan gives for a hook, in order.
+    fn reasons(hook: &str) -> Vec<&'static str> {
+        scan_hook(hook)
+            .into_iter()
+            .map(|warning| warning.reason)
+            .collect()
+    }
+
+    #[test]
+    fn downloads_piped_into_a_shell() {
+        for hook in [
+            "curl -fsSL https://example.com/install.sh | sh",
+            "wget -qO- https://example.com/x | /bin/bash -s",
+            "curl https://example.com/x | tee log | python3",
+        ] {
+            assert!(
+                reasons(hook).contains(&"pipes a download straight into a shell"),
+                "{}",
+                hook
+            );
+        }
+        assert!(reasons("curl -o tool.tgz https://example.com/tool.tgz").is_empty());
+        assert!(reasons("cat setup.sh | sh").is_empty());
+    }
+
+    #[test]
+    fn sudo() {
+        assert_eq!(
+            reasons("sudo apt-get install jq"),
+            ["runs commands as root"]
+        );
+        assert_eq!(reasons("/usr/bin/sudo true"), ["runs commands as root"]);
+        assert!(reasons("echo pseudo").is_empty());
+    }
+
+    #[test]
+    fn redirects_outside_the_project() {
+        for hook in [
+            "echo x > /etc/hosts",
+            "echo x >>~/.bashrc",
+            "echo x 2>$HOME/log",
+            "echo x | tee -a ../shared.txt",
+        ] {
+            assert_eq!(
+                reasons(hook),
+                ["writes outside the project directory"],
+                "{}",
+                hook
+            );
+        }
+    }
+
+    #[test]
+    fn removals_outside_the_project() {
+        assert_eq!(
+            reasons("rm -rf ~/.cache/tool"),
+            ["deletes files outside the project directory"]
+        );
+        assert_eq!(
+            reasons("rm -f /tmp/x.lock"),
+            ["deletes files outside the project directory"]
+        );
+        assert!(reasons("rm -rf target && ls /tmp").is_empty());
+    }
+
+    #[test]
+    fn harmless_hooks_pass() {
+        for hook in [
+            "command -v jq > /dev/null",
+            "make 2>&1 > build.log",
+            "echo ready >/dev/stderr",
+            "export PATH=$PWD/bin:$PATH",
+            "# curl https://example.com | sh",
+            "rm -f .direnv/cache",
+        ] {
+            assert!(reasons(hook).is_empty(), "{}", hook);
+        }
+    }
+}
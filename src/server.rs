@@ -0,0 +1,227 @@
+use clap::Parser;
+use serde_json::{Value, json};
+use std::fs;
+use std::io::{BufRead, Error, Write};
+
+use crate::commands;
+use crate::config::DevShellConfig;
+use crate::messages;
+use crate::package_index::PackageIndex;
+use crate::paths;
+use crate::{AddArgs, GlobalArgs, RemoveArgs};
+
+/// @brief JSON-RPC error codes used by the server.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+const POLICY_VIOLATION: i64 = -32001;
+
+/**
+ * @brief Serves JSON-RPC 2.0 requests, one message per line, until `exit` or end of input.
+ *
+ * Methods:
+ * - `config/get`: the devshell.toml contents.
+ * - `packages/add`, `packages/remove`: `{"packages": [...]}`, optionally with `"shell"` and
+ *   `"local"`; edits like `add -p` and `remove -p` and returns the new config.
+ * - `validate`: problems with the config, using the cached package index.
+ * - `shutdown`, `exit`: stop serving.
+ *
+ * Nothing but responses is written to `output`, so editors can read it as a stream.
+ *
+ * @param input Where requests are read from (stdin for `--stdio`).
+ * @param output Where responses are written.
+ * @param global Options shared by all subcommands, for the timeout.
+ * @return Result<(), Error> Returns an Error only if the streams fail.
+ */
+pub fn serve(
+    input: impl BufRead,
+    mut output: impl Write,
+    global: &GlobalArgs,
+) -> Result<(), Error> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                respond(&mut output, Value::Null, Err((PARSE_ERROR, e.to_string())))?;
+                continue;
+            }
+        };
+        let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        if method == "exit" {
+            break;
+        }
+
+        let result = handle(method, &params, global);
+        // Requests without an id are notifications and get no response.
+        if let Some(id) = request.get("id") {
+            respond(&mut output, id.clone(), result)?;
+        }
+        if method == "shutdown" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Dispatches one request.
+ * @param method The method name.
+ * @param params The request parameters.
+ * @param global Options shared by all subcommands.
+ * @return The result, or a JSON-RPC error code and message.
+ */
+fn handle(method: &str, params: &Value, global: &GlobalArgs) -> Result<Value, (i64, String)> {
+    match method {
+        "config/get" => config_json(&load_config().map_err(server_error)?),
+        "packages/add" => edit_packages(params, "add", global),
+        "packages/remove" => edit_packages(params, "remove", global),
+        "validate" => validate(),
+        "shutdown" => Ok(Value::Null),
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+    }
+}
+
+/**
+ * @brief Adds or removes the packages named in `params` with `add -p` or `remove -p`.
+ *
+ * The request is turned into the command's arguments, so editors get the same
+ * pins, verification, policy, `--local` and `--shell` handling as the CLI, and
+ * the same project lock.
+ *
+ * @param params `{"packages": [...], "shell": "name", "local": true}`; `shell` and `local` are optional.
+ * @param command "add" or "remove".
+ * @param global Options shared by all subcommands.
+ * @return The edited config layer, or a policy violation as its own error code.
+ */
+fn edit_packages(
+    params: &Value,
+    command: &str,
+    global: &GlobalArgs,
+) -> Result<Value, (i64, String)> {
+    let packages: Vec<String> = params
+        .get("packages")
+        .and_then(|packages| serde_json::from_value(packages.clone()).ok())
+        .filter(|packages: &Vec<String>| !packages.is_empty())
+        .ok_or((
+            INVALID_PARAMS,
+            "Expected {\"packages\": [\"name\", ...]}".to_string(),
+        ))?;
+    let local = params
+        .get("local")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let mut argv = vec![command.to_string()];
+    for pkg in packages {
+        argv.extend(["-p".to_string(), pkg]);
+    }
+    if let Some(shell) = params.get("shell").and_then(Value::as_str) {
+        argv.extend(["--shell".to_string(), shell.to_string()]);
+    }
+    if local {
+        argv.push("--local".to_string());
+    }
+
+    // Responses own stdout, and nobody is there to answer a prompt.
+    let global = GlobalArgs {
+        plan: false,
+        dry_run: false,
+        json: false,
+        yes: false,
+        ..global.clone()
+    };
+    let invalid = |e: clap::Error| (INVALID_PARAMS, e.to_string());
+    let edited = if command == "add" {
+        commands::handle_add(&AddArgs::try_parse_from(argv).map_err(invalid)?, &global)
+    } else {
+        commands::handle_remove(&RemoveArgs::try_parse_from(argv).map_err(invalid)?, &global)
+    };
+    edited.map_err(|e| match messages::code(&e) {
+        messages::E_POLICY_VIOLATION => (POLICY_VIOLATION, e.to_string()),
+        _ => server_error(e),
+    })?;
+
+    let layer = if local {
+        paths::local_config_file()
+    } else {
+        paths::config_file()
+    };
+    config_json(&DevShellConfig::load(layer).map_err(server_error)?)
+}
+
+/**
+ * @brief Reports problems with the config: parse errors and unknown packages.
+ * @return `{"diagnostics": [{"package": ..., "message": ...}]}`.
+ */
+fn validate() -> Result<Value, (i64, String)> {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            return Ok(json!({ "diagnostics": [{ "package": null, "message": e.to_string() }] }));
+        }
+    };
+
+    // Building the index takes minutes; only a cached one is consulted.
+    let diagnostics: Vec<Value> = match PackageIndex::load_cached() {
+        Some(index) => config
+            .packages
+            .iter()
+            .filter(|pkg| !pkg.contains("${system}") && !index.contains(pkg))
+            .map(|pkg| json!({ "package": pkg, "message": index.missing_message(pkg) }))
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(json!({ "diagnostics": diagnostics }))
+}
+
+/**
+ * @brief Loads the team config, failing if the project has none.
+ */
+fn load_config() -> Result<DevShellConfig, Error> {
+    let config_file = paths::config_file();
+    if !fs::exists(config_file)? {
+        return Err(messages::not_initialized(config_file));
+    }
+    DevShellConfig::load(config_file)
+}
+
+/**
+ * @brief Converts a config to the JSON returned by the server.
+ */
+fn config_json(config: &DevShellConfig) -> Result<Value, (i64, String)> {
+    serde_json::to_value(config).map_err(|e| (SERVER_ERROR, e.to_string()))
+}
+
+/**
+ * @brief Turns an I/O error into a JSON-RPC server error.
+ */
+fn server_error(e: Error) -> (i64, String) {
+    (SERVER_ERROR, e.to_string())
+}
+
+/**
+ * @brief Writes one response line.
+ * @param output The response stream.
+ * @param id The request id.
+ * @param result The result or error.
+ */
+fn respond(
+    output: &mut impl Write,
+    id: Value,
+    result: Result<Value, (i64, String)>,
+) -> Result<(), Error> {
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => {
+            json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+        }
+    };
+    writeln!(output, "{}", response)?;
+    output.flush()
+}
@@ -0,0 +1,35 @@
+use serde::Deserialize;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// @brief Settings read from the user-level or project-level settings file.
+///
+/// Both files share this format; project settings take precedence over user settings.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Settings {
+    /// @brief Preferred configuration file name (e.g. "nix-shell-gen.toml").
+    pub config_file: Option<String>,
+
+    /// @brief nixpkgs branch (e.g. "nixos-24.05") or flake URL that `init` uses by default.
+    pub nixpkgs_channel: Option<String>,
+
+    /// @brief Whether toolchain inputs added by `init` get `inputs.nixpkgs.follows = "nixpkgs"`.
+    pub follows_nixpkgs: Option<bool>,
+}
+
+impl Settings {
+    /// @brief Loads settings from a file, returning defaults if it doesn't exist.
+    /// @param path The settings file.
+    /// @return Result containing the settings or an I/O error.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Settings::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use rnix::Root;
+use rnix::ast::{Attr, AttrSet, AttrpathValue, BinOpKind, Expr, HasEntry, LetIn};
+use rowan::ast::AstNode;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::config::{DEFAULT_HOOK_NAME, DevShellConfig, EnvValue, is_env_name};
+use crate::flake_editor::{attr_names, string_literal, unparen};
+use crate::messages;
+use crate::templates::FLAKE_FILE;
+
+/// @brief mkShell attributes whose lists become packages.
+const PACKAGE_ATTRS: &[&str] = &[
+    "packages",
+    "buildInputs",
+    "nativeBuildInputs",
+    "propagatedBuildInputs",
+];
+
+/// @brief Attributes mkShell consumes itself, which aren't environment variables.
+const IGNORED_ATTRS: &[&str] = &["name", "pname", "version"];
+
+/// @brief Functions whose argument set describes a shell.
+const SHELL_FUNCTIONS: &[&str] = &["mkShell", "mkShellNoCC", "mkDerivation"];
+
+/**
+ * @struct ImportedShell
+ * @brief What a legacy shell.nix translates to, and what it couldn't.
+ */
+#[derive(Debug, Default)]
+pub struct ImportedShell {
+    /// @brief The equivalent devshell.toml.
+    pub config: DevShellConfig,
+    /// @brief Parts left out, as "attribute: reason", for the user to port by hand.
+    pub skipped: Vec<String>,
+}
+
+/**
+ * @brief Translates a `mkShell { ... }` expression into a devshell.toml config.
+ *
+ * Understands the usual shapes: an optional `{ pkgs ? import <nixpkgs> {} }:` header,
+ * `let ... in`, `with pkgs;`, and a call to `pkgs.mkShell` (or `mkShellNoCC`,
+ * `stdenv.mkDerivation`). Package lists may name packages bare (under `with pkgs;`)
+ * or as `pkgs.foo.bar`, directly or through a `let` binding. Other string
+ * attributes become `[env]` entries, as mkShell exports them.
+ *
+ * @param content The shell.nix source.
+ * @param file_name The file's name, for error messages.
+ * @return Result<ImportedShell, Error> Returns InvalidData if the file doesn't parse or has no shell in it.
+ */
+pub fn import_shell(content: &str, file_name: &str) -> Result<ImportedShell, Error> {
+    let parsed = Root::parse(content);
+    if let Some(error) = parsed.errors().first() {
+        return Err(messages::error(
+            ErrorKind::InvalidData,
+            messages::E_IMPORT_FAILED,
+            format!("{} has a syntax error ({})", file_name, error),
+        ));
+    }
+
+    let mut bindings = BTreeMap::new();
+    let Some(set) = parsed
+        .tree()
+        .expr()
+        .and_then(|expr| find_shell_set(expr, &mut bindings))
+    else {
+        return Err(messages::error(
+            ErrorKind::InvalidData,
+            messages::E_IMPORT_FAILED,
+            format!(
+                "{} doesn't call mkShell; only `pkgs.mkShell {{ ... }}` shells can be imported",
+                file_name
+            ),
+        ));
+    };
+
+    let mut imported = ImportedShell::default();
+    translate_shell(&set, &bindings, &mut imported);
+    Ok(imported)
+}
+
+/**
+ * @brief Translates the attributes of one mkShell call into the config.
+ * @param set The attribute set passed to mkShell.
+ * @param bindings The `let` bindings in scope.
+ * @param imported Receives the config entries and skipped parts.
+ */
+fn translate_shell(set: &AttrSet, bindings: &BTreeMap<String, Expr>, imported: &mut ImportedShell) {
+    for entry in set.attrpath_values() {
+        let Some(name) = entry
+            .attrpath()
+            .and_then(|path| attr_names(&path))
+            .map(|names| names.join("."))
+        else {
+            continue;
+        };
+        let Some(value) = entry.value().and_then(unparen) else {
+            continue;
+        };
+
+        if PACKAGE_ATTRS.contains(&name.as_str()) {
+            import_packages(&name, value, bindings, imported);
+        } else if name == "inputsFrom" {
+            for item in list_items(value, bindings, &name, &mut imported.skipped) {
+                match inputs_from_ref(&item).filter(|path| !bindings.contains_key(path)) {
+                    Some(reference) => {
+                        imported.config.inputs_from.insert(reference);
+                    }
+                    None => imported
+                        .skipped
+                        .push(format!("{}: `{}` isn't a nixpkgs package", name, item)),
+                }
+            }
+        } else if name == "shellHook" {
+            match string_literal(&value) {
+                Some(hook) => {
+                    imported.config.add_hook(DEFAULT_HOOK_NAME, &hook);
+                }
+                None => imported
+                    .skipped
+                    .push(format!("{}: it interpolates Nix values (`${{...}}`)", name)),
+            }
+        } else if IGNORED_ATTRS.contains(&name.as_str()) {
+            continue;
+        } else if !is_env_name(&name) {
+            imported
+                .skipped
+                .push(format!("{}: not a valid variable name", name));
+        } else {
+            match string_literal(&value) {
+                Some(text) => {
+                    imported.config.env.insert(name, EnvValue::Value(text));
+                }
+                None => imported
+                    .skipped
+                    .push(format!("{}: not a plain string", name)),
+            }
+        }
+    }
+}
+
+/**
+ * @brief Translates the dev shells of a hand-written flake.nix into a config.
+ *
+ * Every `mkShell` under `devShells` (or the older `devShell`) is found wherever
+ * it sits: behind `eachDefaultSystem`, a `forAllSystems` helper, `${system}`
+ * attributes or a `let` binding. `devShells.<system>.default` becomes the default
+ * shell, other names become `[shells.<name>]` with their packages and hook.
+ *
+ * @param content The flake source.
+ * @return Result<ImportedShell, Error> Returns InvalidData if the flake doesn't parse or defines no dev shell.
+ */
+pub fn adopt_flake(content: &str) -> Result<ImportedShell, Error> {
+    let parsed = Root::parse(content);
+    if let Some(error) = parsed.errors().first() {
+        return Err(messages::error(
+            ErrorKind::InvalidData,
+            messages::E_IMPORT_FAILED,
+            format!("{} has a syntax error ({})", FLAKE_FILE, error),
+        ));
+    }
+    let root = parsed.tree();
+
+    // All let bindings in the file; flakes rarely reuse names across scopes.
+    let mut bindings = BTreeMap::new();
+    for let_in in root.syntax().descendants().filter_map(LetIn::cast) {
+        for entry in let_in.attrpath_values() {
+            if let (Some(names), Some(value)) = (
+                entry.attrpath().and_then(|path| attr_names(&path)),
+                entry.value(),
+            ) && names.len() == 1
+            {
+                bindings.insert(names[0].clone(), value);
+            }
+        }
+    }
+
+    let mut imported = ImportedShell::default();
+    let mut found = false;
+    for entry in root.syntax().descendants().filter_map(AttrpathValue::cast) {
+        let Some(shell_name) = dev_shell_name(&entry) else {
+            continue;
+        };
+        let Some(set) = entry
+            .value()
+            .and_then(|value| find_shell_set(value, &mut bindings.clone()))
+        else {
+            continue;
+        };
+        found = true;
+        if shell_name == "default" {
+            translate_shell(&set, &bindings, &mut imported);
+            continue;
+        }
+
+        let mut named = ImportedShell::default();
+        translate_shell(&set, &bindings, &mut named);
+        let shell = imported
+            .config
+            .shells
+            .entry(shell_name.clone())
+            .or_default();
+        shell.packages.extend(named.config.packages);
+        shell.hooks.extend(named.config.hooks);
+        if !named.config.env.is_empty() || !named.config.inputs_from.is_empty() {
+            imported.skipped.push(format!(
+                "devShells.{}: environment variables and inputsFrom are only imported for the default shell",
+                shell_name
+            ));
+        }
+        imported.skipped.extend(
+            named
+                .skipped
+                .into_iter()
+                .map(|skipped| format!("devShells.{}: {}", shell_name, skipped)),
+        );
+    }
+
+    if !found {
+        return Err(messages::error(
+            ErrorKind::InvalidData,
+            messages::E_IMPORT_FAILED,
+            format!("{} has no `devShells` built with mkShell", FLAKE_FILE),
+        ));
+    }
+    Ok(imported)
+}
+
+/**
+ * @brief Returns the dev shell an attribute defines, if it sits under `devShells`.
+ *
+ * The path is assembled from the enclosing attributes, so `devShells = { default = ...; }`,
+ * `devShells.${system}.default` and `devShells.x86_64-linux.web` all count.
+ *
+ * @param entry An attribute of the flake.
+ * @return The shell name ("default" for `devShell` and unnamed shells), or None.
+ */
+fn dev_shell_name(entry: &AttrpathValue) -> Option<String> {
+    let mut path = Vec::new();
+    for node in entry.syntax().ancestors().filter_map(AttrpathValue::cast) {
+        let names: Vec<String> = node
+            .attrpath()?
+            .attrs()
+            .map(|attr| match attr {
+                Attr::Ident(ident) => ident.to_string(),
+                Attr::Str(s) => string_literal(&Expr::Str(s)).unwrap_or_default(),
+                Attr::Dynamic(_) => String::new(),
+            })
+            .collect();
+        path.splice(0..0, names);
+    }
+
+    let start = path
+        .iter()
+        .position(|name| name == "devShells" || name == "devShell")?;
+    let is_system = |name: &str| {
+        name.is_empty() || name.ends_with("-linux") || name.ends_with("-darwin") || name == "system"
+    };
+    let rest: Vec<&String> = path[start + 1..]
+        .iter()
+        .filter(|name| !is_system(name))
+        .collect();
+    match (path[start].as_str(), rest.as_slice()) {
+        ("devShell", []) => Some("default".to_string()),
+        ("devShells", []) => Some("default".to_string()),
+        ("devShells", [name]) => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+/**
+ * @brief Walks down to the attribute set passed to mkShell.
+ *
+ * Function headers, parentheses and `with` are stepped through; `let` bindings
+ * are remembered so package lists can refer to them.
+ *
+ * @param expr The expression to search.
+ * @param bindings Collects the `let` bindings seen on the way.
+ */
+fn find_shell_set(expr: Expr, bindings: &mut BTreeMap<String, Expr>) -> Option<AttrSet> {
+    match unparen(expr)? {
+        Expr::Lambda(lambda) => find_shell_set(lambda.body()?, bindings),
+        Expr::With(with) => find_shell_set(with.body()?, bindings),
+        Expr::LetIn(let_in) => {
+            for entry in let_in.attrpath_values() {
+                if let (Some(names), Some(value)) = (
+                    entry.attrpath().and_then(|path| attr_names(&path)),
+                    entry.value(),
+                ) && names.len() == 1
+                {
+                    bindings.insert(names[0].clone(), value);
+                }
+            }
+            find_shell_set(let_in.body()?, bindings)
+        }
+        Expr::Apply(apply) => {
+            let function = apply.lambda().and_then(unparen)?;
+            let name = function.syntax().text().to_string();
+            let called = name.rsplit('.').next().unwrap_or(&name);
+            if SHELL_FUNCTIONS.contains(&called) {
+                match apply.argument().and_then(unparen)? {
+                    Expr::AttrSet(set) => Some(set),
+                    _ => None,
+                }
+            } else {
+                // e.g. `(pkgs.mkShell.override { ... }) { ... }`: look inside the function
+                find_shell_set(function, bindings)
+            }
+        }
+        Expr::Ident(ident) => {
+            // Removed so `let shell = shell;` can't recurse forever
+            let value = bindings.remove(&ident.to_string())?;
+            find_shell_set(value, bindings)
+        }
+        _ => None,
+    }
+}
+
+/**
+ * @brief Adds the packages of one list attribute (`buildInputs = [ ... ];`).
+ */
+fn import_packages(
+    name: &str,
+    value: Expr,
+    bindings: &BTreeMap<String, Expr>,
+    imported: &mut ImportedShell,
+) {
+    for item in list_items(value, bindings, name, &mut imported.skipped) {
+        match package_path(&item).filter(|path| !bindings.contains_key(path)) {
+            Some(path) => {
+                imported.config.packages.insert(path);
+            }
+            None => imported.skipped.push(format!(
+                "{}: `{}` isn't a plain package reference",
+                name, item
+            )),
+        }
+    }
+}
+
+/**
+ * @brief Returns the items of a list, following `let` bindings and `a ++ b`.
+ * @return The items as source text; anything else is reported as skipped.
+ */
+fn list_items(
+    value: Expr,
+    bindings: &BTreeMap<String, Expr>,
+    name: &str,
+    skipped: &mut Vec<String>,
+) -> Vec<String> {
+    match value {
+        Expr::List(list) => list
+            .items()
+            .map(|item| item.syntax().text().to_string())
+            .collect(),
+        Expr::Ident(ident) if bindings.contains_key(&ident.to_string()) => {
+            let mut rest = bindings.clone();
+            match rest.remove(&ident.to_string()).and_then(unparen) {
+                Some(bound) => list_items(bound, &rest, name, skipped),
+                None => Vec::new(),
+            }
+        }
+        Expr::BinOp(op) if op.operator() == Some(BinOpKind::Concat) => {
+            let (Some(lhs), Some(rhs)) = (op.lhs().and_then(unparen), op.rhs().and_then(unparen))
+            else {
+                return Vec::new();
+            };
+            let mut items = list_items(lhs, bindings, name, skipped);
+            items.extend(list_items(rhs, bindings, name, skipped));
+            items
+        }
+        other => {
+            skipped.push(format!(
+                "{}: `{}` isn't a list",
+                name,
+                other.syntax().text()
+            ));
+            Vec::new()
+        }
+    }
+}
+
+/**
+ * @brief Turns an `inputsFrom` item into an `inputs-from` reference.
+ * @param item E.g. `pkgs.hello` (-> "nixpkgs#hello") or `self.packages.${system}.default`
+ *        (-> "packages.default").
+ */
+fn inputs_from_ref(item: &str) -> Option<String> {
+    let item = item.trim();
+    if let Some(output) = item.strip_prefix("self.") {
+        let parts: Vec<&str> = output
+            .split('.')
+            .filter(|part| *part != "${system}")
+            .collect();
+        return (parts.len() == 2 && parts.iter().all(|part| package_path(part).is_some()))
+            .then(|| parts.join("."));
+    }
+    package_path(item).map(|path| format!("nixpkgs#{}", path))
+}
+
+/**
+ * @brief Turns `foo`, `pkgs.foo` or `pkgs.python3Packages.foo` into a nixpkgs attribute path.
+ * @param item The list item as written.
+ * @return The path without the `pkgs.` prefix, or None for calls and other expressions.
+ */
+fn package_path(item: &str) -> Option<String> {
+    let path = item.trim();
+    let path = ["pkgs.", "nixpkgs.legacyPackages.${system}.", "inputs."]
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix))
+        .unwrap_or(path);
+    let plain = !path.is_empty()
+        && path.split('.').all(|part| {
+            // Input packages are written `foo.packages.${system}.default`, as in devshell.toml
+            part == "${system}"
+                || part
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''))
+        });
+    plain.then(|| path.to_string())
+}
@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::config::Script;
+use crate::messages::{self, sayln};
+use crate::process::Process;
+
+/**
+ * @enum State
+ * @brief Where a script of a `run --all` is.
+ */
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    /// @brief Waiting for its dependencies.
+    Pending,
+    /// @brief Started, not finished.
+    Running,
+    /// @brief Finished successfully, or a group whose dependencies did.
+    Passed,
+    /// @brief Finished unsuccessfully, with the reason.
+    Failed(String),
+    /// @brief Not run because a dependency failed.
+    Skipped,
+}
+
+/**
+ * @brief Lists the scripts to run for some targets: the targets and everything they depend on.
+ * @param scripts The `[scripts]` table.
+ * @param targets The scripts asked for.
+ * @return Result<BTreeSet<String>, Error> The scripts, or an Error if one depends on a
+ *         script that doesn't exist or the dependencies form a cycle.
+ */
+pub fn plan(
+    scripts: &BTreeMap<String, Script>,
+    targets: &[String],
+) -> Result<BTreeSet<String>, Error> {
+    let mut planned = BTreeSet::new();
+    let mut stack: Vec<String> = targets.to_vec();
+    while let Some(name) = stack.pop() {
+        if !planned.insert(name.clone()) {
+            continue;
+        }
+        for dep in scripts[&name].depends_on() {
+            if !scripts.contains_key(dep) {
+                return Err(messages::error(
+                    ErrorKind::InvalidData,
+                    messages::E_UNKNOWN_SCRIPT,
+                    format!(
+                        "Script '{}' depends on '{}', which doesn't exist.",
+                        name, dep
+                    ),
+                ));
+            }
+            stack.push(dep.clone());
+        }
+    }
+
+    // A cycle leaves scripts that never become ready.
+    let mut done = BTreeSet::new();
+    while done.len() < planned.len() {
+        let ready: Vec<&String> = planned
+            .iter()
+            .filter(|name| !done.contains(*name))
+            .filter(|name| {
+                scripts[*name]
+                    .depends_on()
+                    .iter()
+                    .all(|dep| done.contains(dep))
+            })
+            .collect();
+        if ready.is_empty() {
+            let stuck: Vec<&str> = planned
+                .iter()
+                .filter(|name| !done.contains(*name))
+                .map(String::as_str)
+                .collect();
+            return Err(messages::error(
+                ErrorKind::InvalidData,
+                messages::E_INVALID_CONFIG,
+                format!(
+                    "The depends-on of these scripts form a cycle: {}.",
+                    stuck.join(", ")
+                ),
+            ));
+        }
+        done.extend(ready.into_iter().cloned());
+    }
+    Ok(planned)
+}
+
+/**
+ * @brief Runs scripts, each as soon as its dependencies passed, independent ones at once.
+ *
+ * Every line a script prints is prefixed with its name. A script whose dependency
+ * failed is skipped; the others still run, and the failures are reported together.
+ *
+ * @param scripts The `[scripts]` table.
+ * @param planned The scripts to run, from `plan`.
+ * @param command Builds the command that runs a script by name in the dev shell.
+ * @return Result<(), Error> An Error naming the failed and skipped scripts, if any.
+ */
+pub fn run(
+    scripts: &BTreeMap<String, Script>,
+    planned: &BTreeSet<String>,
+    command: impl Fn(&str) -> Process + Sync,
+) -> Result<(), Error> {
+    let width = planned.iter().map(String::len).max().unwrap_or(0);
+    let mut states: BTreeMap<&str, State> = planned
+        .iter()
+        .map(|name| (name.as_str(), State::Pending))
+        .collect();
+    let (tx, rx) = mpsc::channel::<(&str, State)>();
+
+    thread::scope(|scope| {
+        loop {
+            // Start (or skip) everything that can; groups pass as soon as they are ready.
+            let mut progress = true;
+            while progress {
+                progress = false;
+                for name in planned {
+                    if states[name.as_str()] != State::Pending {
+                        continue;
+                    }
+                    let deps: Vec<&State> = scripts[name]
+                        .depends_on()
+                        .iter()
+                        .map(|dep| &states[dep.as_str()])
+                        .collect();
+                    if deps
+                        .iter()
+                        .any(|state| matches!(state, State::Failed(_) | State::Skipped))
+                    {
+                        sayln!("{:>width$} | skipped: a dependency failed", name);
+                        states.insert(name, State::Skipped);
+                        progress = true;
+                    } else if deps.iter().all(|state| **state == State::Passed) {
+                        if scripts[name].command().is_none() {
+                            states.insert(name, State::Passed);
+                            progress = true;
+                            continue;
+                        }
+                        states.insert(name, State::Running);
+                        let tx = tx.clone();
+                        let command = &command;
+                        scope.spawn(move || {
+                            let status = command(name).run_lines(|line| {
+                                sayln!("{:>width$} | {}", name, line);
+                            });
+                            let state = match status {
+                                Ok(status) if status.success() => State::Passed,
+                                Ok(status) => State::Failed(status.to_string()),
+                                Err(e) => State::Failed(e.to_string()),
+                            };
+                            let _ = tx.send((name, state));
+                        });
+                    }
+                }
+            }
+
+            if !states.values().any(|state| *state == State::Running) {
+                break;
+            }
+            let Ok((name, state)) = rx.recv() else {
+                break;
+            };
+            match &state {
+                State::Failed(reason) => sayln!("{:>width$} | failed: {}", name, reason),
+                _ => sayln!("{:>width$} | done", name),
+            }
+            states.insert(name, state);
+        }
+    });
+
+    let failed: Vec<String> = states
+        .iter()
+        .filter_map(|(name, state)| match state {
+            State::Failed(reason) => Some(format!("{} ({})", name, reason)),
+            _ => None,
+        })
+        .collect();
+    let skipped: Vec<&str> = states
+        .iter()
+        .filter(|(_, state)| **state == State::Skipped)
+        .map(|(name, _)| *name)
+        .collect();
+    if failed.is_empty() {
+        sayln!("All {} scripts passed.", planned.len());
+        return Ok(());
+    }
+    let mut message = format!(
+        "{} of {} scripts failed: {}",
+        failed.len(),
+        planned.len(),
+        failed.join(", ")
+    );
+    if !skipped.is_empty() {
+        message.push_str(&format!("; skipped {}", skipped.join(", ")));
+    }
+    Err(messages::error(
+        ErrorKind::Other,
+        messages::E_COMMAND_FAILED,
+        message,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// @brief A `[scripts]` table of `(name, depends-on)` pairs.
+    fn table(entries: &[(&str, &[&str])]) -> BTreeMap<String, Script> {
+        entries
+            .iter()
+            .map(|(name, deps)| {
+                let script = Script::Task {
+                    command: Some(format!("echo {}", name)),
+                    depends_on: deps.iter().map(|dep| dep.to_string()).collect(),
+                };
+                (name.to_string(), script)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plans_targets_and_their_dependencies() {
+        let scripts = table(&[
+            ("build", &[]),
+            ("test", &["build"]),
+            ("lint", &[]),
+            ("checks", &["test", "lint"]),
+        ]);
+        let planned = plan(&scripts, &["test".to_string()]).unwrap();
+        assert_eq!(planned, BTreeSet::from(["build".into(), "test".into()]));
+        let planned = plan(&scripts, &["checks".to_string()]).unwrap();
+        assert_eq!(planned.len(), 4);
+    }
+
+    #[test]
+    fn missing_dependencies() {
+        let scripts = table(&[("test", &["build"])]);
+        let error = plan(&scripts, &["test".to_string()]).unwrap_err();
+        assert_eq!(messages::code(&error), messages::E_UNKNOWN_SCRIPT);
+        assert!(error.to_string().contains("'build'"));
+    }
+
+    #[test]
+    fn cycles() {
+        let scripts = table(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &[])]);
+        let error = plan(&scripts, &["a".to_string(), "d".to_string()]).unwrap_err();
+        assert_eq!(messages::code(&error), messages::E_INVALID_CONFIG);
+        assert!(error.to_string().contains("a, b, c."));
+
+        let looped = table(&[("a", &["a"])]);
+        assert!(plan(&looped, &["a".to_string()]).is_err());
+    }
+}
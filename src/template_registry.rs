@@ -0,0 +1,261 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{DevShellConfig, EnvValue, Script, env_entries};
+use crate::paths;
+use crate::templates::{LANGUAGE_TEMPLATES, LanguageTemplate, package_name};
+
+/**
+ * @struct Template
+ * @brief A language template for `init --lang`, built in or loaded from a TOML file.
+ */
+#[derive(Debug, Clone)]
+pub struct Template {
+    /// @brief The name passed to `--lang`.
+    pub name: String,
+    /// @brief Other accepted names.
+    pub aliases: Vec<String>,
+    /// @brief Nixpkgs packages the template adds.
+    pub packages: Vec<String>,
+    /// @brief Flake inputs the template adds, by key.
+    pub inputs: BTreeMap<String, String>,
+    /// @brief Shell hook the template adds.
+    pub shell_hook: Option<String>,
+    /// @brief `[env]` entries the template adds.
+    pub env: BTreeMap<String, EnvValue>,
+    /// @brief Test, build and lint commands `init --scripts` puts in `[scripts]`, by name.
+    pub scripts: BTreeMap<String, String>,
+    /// @brief Packages the scripts need, added with them.
+    pub script_packages: Vec<String>,
+    /// @brief Starter project files for `init --scaffold`, by path; `{name}` in paths and
+    /// contents is the project name.
+    pub starter_files: BTreeMap<String, String>,
+    /// @brief The file the template was loaded from, or None for built-in templates.
+    pub source: Option<PathBuf>,
+}
+
+/**
+ * @struct TemplateFile
+ * @brief The format of `templates/<name>.toml` in the user's config directory.
+ */
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TemplateFile {
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    packages: Vec<String>,
+    #[serde(default)]
+    inputs: BTreeMap<String, String>,
+    shell_hook: Option<String>,
+    #[serde(default, deserialize_with = "env_entries")]
+    env: BTreeMap<String, EnvValue>,
+    #[serde(default)]
+    scripts: BTreeMap<String, String>,
+    #[serde(default)]
+    script_packages: Vec<String>,
+    #[serde(default)]
+    starter_files: BTreeMap<String, String>,
+}
+
+impl From<&LanguageTemplate> for Template {
+    fn from(template: &LanguageTemplate) -> Self {
+        Template {
+            name: template.name.to_string(),
+            aliases: template
+                .aliases
+                .iter()
+                .map(|alias| alias.to_string())
+                .collect(),
+            packages: template
+                .packages
+                .iter()
+                .map(|pkg| pkg.to_string())
+                .collect(),
+            inputs: template
+                .inputs
+                .iter()
+                .map(|(key, url)| (key.to_string(), url.to_string()))
+                .collect(),
+            shell_hook: template.shell_hook.map(str::to_string),
+            env: BTreeMap::new(),
+            scripts: template
+                .scripts
+                .iter()
+                .map(|(name, command)| (name.to_string(), command.to_string()))
+                .collect(),
+            script_packages: template
+                .script_packages
+                .iter()
+                .map(|pkg| pkg.to_string())
+                .collect(),
+            starter_files: template
+                .starter_files
+                .iter()
+                .map(|(path, contents)| (path.to_string(), contents.to_string()))
+                .collect(),
+            source: None,
+        }
+    }
+}
+
+impl Template {
+    /**
+     * @brief Loads a template file; the template is named after the file.
+     * @param path A `<name>.toml` file.
+     */
+    fn load(path: &Path) -> Result<Self, Error> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let file: TemplateFile = toml::from_str(&contents).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })?;
+        Ok(Template {
+            name,
+            aliases: file
+                .aliases
+                .iter()
+                .map(|alias| alias.to_lowercase())
+                .collect(),
+            packages: file.packages,
+            inputs: file.inputs,
+            shell_hook: file.shell_hook,
+            env: file.env,
+            scripts: file.scripts,
+            script_packages: file.script_packages,
+            starter_files: file.starter_files,
+            source: Some(path.to_path_buf()),
+        })
+    }
+
+    /**
+     * @brief Whether the template answers to a `--lang` value (already lowercased).
+     */
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+
+    /**
+     * @brief Adds the template's packages, env entries and hook to a config.
+     * @param config The config being built by `init`.
+     */
+    pub fn apply(&self, config: &mut DevShellConfig) {
+        config.packages.extend(self.packages.iter().cloned());
+        config.env.extend(self.env.clone());
+        if let Some(hook) = &self.shell_hook {
+            config.add_hook(&self.name, hook);
+        }
+    }
+
+    /**
+     * @brief Adds the template's scripts and the packages they need, for `init --scripts`.
+     *
+     * Scripts already in the config are kept, so with several templates the first
+     * one's `test` wins.
+     *
+     * @param config The config being built by `init`.
+     */
+    pub fn apply_scripts(&self, config: &mut DevShellConfig) {
+        for (name, command) in &self.scripts {
+            config
+                .scripts
+                .entry(name.clone())
+                .or_insert_with(|| Script::Command(command.clone()));
+        }
+        config.packages.extend(self.script_packages.iter().cloned());
+    }
+
+    /**
+     * @brief Renders the starter project files for a project.
+     * @param project_name The project name, e.g. the directory name.
+     * @return (path, contents) pairs; paths leaving the project directory are rejected.
+     */
+    pub fn render_starter_files(&self, project_name: &str) -> Result<Vec<(String, String)>, Error> {
+        let name = package_name(project_name);
+        self.starter_files
+            .iter()
+            .map(|(path, contents)| {
+                let path = path.replace("{name}", &name);
+                let inside = Path::new(&path)
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)));
+                if !inside {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Template '{}' has a starter file outside the project: {}",
+                            self.name, path
+                        ),
+                    ));
+                }
+                Ok((path, contents.replace("{name}", &name)))
+            })
+            .collect()
+    }
+}
+
+/**
+ * @brief Returns all templates: the user's (see `paths::templates_dir`), then the built-ins.
+ *
+ * A user template named like a built-in one replaces it. Files that can't be read
+ * are reported and skipped, so one broken template doesn't break `init` for others.
+ */
+pub fn all_templates() -> Vec<Template> {
+    let mut templates = user_templates();
+    for builtin in LANGUAGE_TEMPLATES {
+        if !templates
+            .iter()
+            .any(|template| template.name == builtin.name)
+        {
+            templates.push(Template::from(builtin));
+        }
+    }
+    templates
+}
+
+/**
+ * @brief Looks up a template by name or alias, ignoring case.
+ * @param name The requested language.
+ */
+pub fn find_template(name: &str) -> Option<Template> {
+    let name = name.to_lowercase();
+    all_templates()
+        .into_iter()
+        .find(|template| template.matches(&name))
+}
+
+/**
+ * @brief Loads the user's template files, sorted by name.
+ */
+fn user_templates() -> Vec<Template> {
+    let Some(dir) = paths::templates_dir() else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    files.sort();
+
+    files
+        .iter()
+        .filter_map(|path| match Template::load(path) {
+            Ok(template) => Some(template),
+            Err(e) => {
+                eprintln!("Warning: ignoring template: {}", e);
+                None
+            }
+        })
+        .collect()
+}
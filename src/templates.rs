@@ -1,8 +1,305 @@
+use clap::ValueEnum;
 use indoc::formatdoc;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::{DevShellConfig, ShellFormat};
+use crate::nix_syntax;
+use crate::paths;
+use crate::recipes::shell_quote;
 
 pub const FLAKE_FILE: &str = "flake.nix";
 
+/// @brief The nixpkgs branch new flakes track unless configured otherwise.
+pub const DEFAULT_NIXPKGS_CHANNEL: &str = "nixos-unstable";
+
+/// @brief Set by the generated shellHook to the name of the dev shell.
+pub const SHELL_ENV_VAR: &str = "NIX_SHELL_GEN_SHELL";
+/// @brief Set by the generated shellHook to the directory containing flake.nix.
+pub const ROOT_ENV_VAR: &str = "NIX_SHELL_GEN_ROOT";
+/// @brief Set by the generated shellHook to the Unix time the shell was entered.
+pub const ENTERED_ENV_VAR: &str = "NIX_SHELL_GEN_ENTERED";
+/// @brief Names the file the generated shellHook appends each hook's start and end time to.
+pub const HOOK_TIMES_ENV_VAR: &str = "NIX_SHELL_GEN_HOOK_TIMES";
+
+/**
+ * @struct LanguageTemplate
+ * @brief A language preset for `init --lang`.
+ */
+pub struct LanguageTemplate {
+    /// @brief The name passed to `--lang`.
+    pub name: &'static str,
+    /// @brief Other accepted names.
+    pub aliases: &'static [&'static str],
+    /// @brief Nixpkgs packages the template adds.
+    pub packages: &'static [&'static str],
+    /// @brief Toolchain flake inputs the template adds, as (key, url).
+    pub inputs: &'static [(&'static str, &'static str)],
+    /// @brief Shell hook the template adds.
+    pub shell_hook: Option<&'static str>,
+    /// @brief Canonical test, build and lint commands for `init --scripts`, as (name, command).
+    pub scripts: &'static [(&'static str, &'static str)],
+    /// @brief Nixpkgs packages the scripts need beyond `packages`, added with them.
+    pub script_packages: &'static [&'static str],
+    /// @brief Starter project files for `init --scaffold`, as (path, contents); `{name}` is the project name.
+    pub starter_files: &'static [(&'static str, &'static str)],
+}
+
+/// @brief The built-in language templates.
+pub const LANGUAGE_TEMPLATES: &[LanguageTemplate] = &[
+    LanguageTemplate {
+        name: "cpp",
+        aliases: &["c++"],
+        packages: &["clang", "cmake", "gdb"],
+        inputs: &[],
+        shell_hook: None,
+        scripts: &[
+            ("build", "cmake -B build && cmake --build build"),
+            ("test", "ctest --test-dir build --output-on-failure"),
+        ],
+        script_packages: &[],
+        starter_files: &[
+            (
+                "CMakeLists.txt",
+                "cmake_minimum_required(VERSION 3.20)\nproject({name} CXX)\n\nset(CMAKE_CXX_STANDARD 20)\nset(CMAKE_CXX_STANDARD_REQUIRED ON)\nset(CMAKE_EXPORT_COMPILE_COMMANDS ON)\n\nadd_executable({name} src/main.cpp)\n",
+            ),
+            (
+                "src/main.cpp",
+                "#include <iostream>\n\nint main() {\n    std::cout << \"Hello, world!\" << std::endl;\n    return 0;\n}\n",
+            ),
+        ],
+    },
+    LanguageTemplate {
+        name: "rust",
+        aliases: &[],
+        packages: &["rustc", "cargo", "rust-analyzer"],
+        inputs: &[],
+        shell_hook: None,
+        scripts: &[
+            ("build", "cargo build"),
+            ("test", "cargo test"),
+            ("lint", "cargo clippy --all-targets -- -D warnings"),
+        ],
+        script_packages: &["clippy"],
+        starter_files: &[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[dependencies]\n",
+            ),
+            (
+                "src/main.rs",
+                "fn main() {\n    println!(\"Hello, world!\");\n}\n",
+            ),
+        ],
+    },
+    LanguageTemplate {
+        name: "python",
+        aliases: &[],
+        packages: &["python3"],
+        inputs: &[],
+        shell_hook: None,
+        scripts: &[("test", "pytest"), ("lint", "ruff check .")],
+        script_packages: &["python3Packages.pytest", "ruff"],
+        starter_files: &[
+            (
+                "pyproject.toml",
+                "[project]\nname = \"{name}\"\nversion = \"0.1.0\"\nrequires-python = \">=3.11\"\ndependencies = []\n",
+            ),
+            (
+                "main.py",
+                "def main() -> None:\n    print(\"Hello, world!\")\n\n\nif __name__ == \"__main__\":\n    main()\n",
+            ),
+        ],
+    },
+    LanguageTemplate {
+        name: "zig",
+        aliases: &[],
+        packages: &["zig-overlay.packages.${system}.default", "zls"],
+        inputs: &[("zig-overlay", "github:mitchellh/zig-overlay")],
+        shell_hook: None,
+        scripts: &[("build", "zig build"), ("test", "zig build test")],
+        script_packages: &[],
+        starter_files: &[
+            (
+                "build.zig",
+                "const std = @import(\"std\");\n\npub fn build(b: *std.Build) void {\n    const exe = b.addExecutable(.{\n        .name = \"{name}\",\n        .root_source_file = b.path(\"src/main.zig\"),\n        .target = b.standardTargetOptions(.{}),\n        .optimize = b.standardOptimizeOption(.{}),\n    });\n    b.installArtifact(exe);\n}\n",
+            ),
+            (
+                "src/main.zig",
+                "const std = @import(\"std\");\n\npub fn main() void {\n    std.debug.print(\"Hello, world!\\n\", .{});\n}\n",
+            ),
+        ],
+    },
+    LanguageTemplate {
+        name: "go",
+        aliases: &["golang"],
+        packages: &["go", "gopls", "delve"],
+        inputs: &[],
+        // Keep modules and installed tools inside the project.
+        shell_hook: Some("export GOPATH=\"$PWD/.go\"; export PATH=\"$GOPATH/bin:$PATH\""),
+        scripts: &[
+            ("build", "go build ./..."),
+            ("test", "go test ./..."),
+            ("lint", "go vet ./..."),
+        ],
+        script_packages: &[],
+        starter_files: &[
+            ("go.mod", "module {name}\n\ngo 1.22\n"),
+            (
+                "main.go",
+                "package main\n\nimport \"fmt\"\n\nfunc main() {\n\tfmt.Println(\"Hello, world!\")\n}\n",
+            ),
+        ],
+    },
+    LanguageTemplate {
+        name: "node",
+        aliases: &["nodejs", "js", "ts"],
+        packages: &["nodejs", "pnpm"],
+        inputs: &[],
+        shell_hook: Some("export PATH=\"$PWD/node_modules/.bin:$PATH\""),
+        scripts: &[("test", "pnpm test"), ("build", "pnpm run build")],
+        script_packages: &[],
+        starter_files: &[
+            (
+                "package.json",
+                "{\n  \"name\": \"{name}\",\n  \"version\": \"0.1.0\",\n  \"private\": true,\n  \"type\": \"module\",\n  \"scripts\": {\n    \"start\": \"node index.js\"\n  }\n}\n",
+            ),
+            ("index.js", "console.log(\"Hello, world!\");\n"),
+        ],
+    },
+    LanguageTemplate {
+        name: "haskell",
+        aliases: &["hs"],
+        packages: &["ghc", "cabal-install", "haskell-language-server"],
+        inputs: &[],
+        shell_hook: None,
+        scripts: &[("build", "cabal build"), ("test", "cabal test")],
+        script_packages: &[],
+        starter_files: &[
+            (
+                "{name}.cabal",
+                "cabal-version: 3.0\nname:          {name}\nversion:       0.1.0\nbuild-type:    Simple\n\nexecutable {name}\n    main-is:          Main.hs\n    hs-source-dirs:   app\n    build-depends:    base\n    default-language: Haskell2010\n",
+            ),
+            (
+                "app/Main.hs",
+                "module Main where\n\nmain :: IO ()\nmain = putStrLn \"Hello, world!\"\n",
+            ),
+        ],
+    },
+    LanguageTemplate {
+        name: "java",
+        aliases: &[],
+        packages: &["jdk", "maven"],
+        inputs: &[],
+        // The jdk package sets JAVA_HOME itself.
+        shell_hook: None,
+        scripts: &[("build", "mvn package"), ("test", "mvn test")],
+        script_packages: &[],
+        starter_files: &[],
+    },
+    LanguageTemplate {
+        name: "latex",
+        aliases: &["tex"],
+        packages: &["texlive.combined.scheme-medium", "texlab"],
+        inputs: &[],
+        shell_hook: None,
+        scripts: &[("build", "latexmk -pdf main.tex")],
+        script_packages: &[],
+        starter_files: &[(
+            "main.tex",
+            "\\documentclass{article}\n\n\\begin{document}\nHello, world!\n\\end{document}\n",
+        )],
+    },
+];
+
+/// @brief The flake `rust-toolchain` takes Rust from.
+pub const RUST_OVERLAY_URL: &str = "github:oxalica/rust-overlay";
+
+/// @brief Files rustup reads the toolchain from, the preferred one first.
+pub const RUST_TOOLCHAIN_FILES: &[&str] = &["rust-toolchain.toml", "rust-toolchain"];
+
+/// @brief nixpkgs packages a `rust-toolchain` replaces; left in, they would shadow it or be shadowed.
+pub const NIXPKGS_RUST_PACKAGES: &[&str] = &["rustc", "cargo", "clippy", "rustfmt"];
+
+/// @brief Well-known toolchain flakes, by input key, that `init` makes follow nixpkgs.
+///
+/// They all take a nixpkgs input; sharing the project's keeps flake.lock small and
+/// avoids evaluating a second nixpkgs.
+pub const TOOLCHAIN_INPUTS: &[&str] = &[
+    "fenix",
+    "rust-overlay",
+    "zig-overlay",
+    "pre-commit-hooks",
+    "pre-commit-hooks.nix",
+    "git-hooks.nix",
+];
+
+/**
+ * @brief Looks up a built-in language template by name or alias, ignoring case.
+ * @details `template_registry::find_template` also sees the user's templates.
+ * @param name The requested language.
+ */
+pub fn language_template(name: &str) -> Option<&'static LanguageTemplate> {
+    let name = name.to_lowercase();
+    LANGUAGE_TEMPLATES
+        .iter()
+        .find(|template| template.name == name || template.aliases.contains(&name.as_str()))
+}
+
+/**
+ * @brief Turns a directory name into a name Cargo, pip and CMake all accept.
+ * @param project_name The raw name.
+ * @return Lowercase letters, digits and dashes, starting with a letter.
+ */
+pub fn package_name(project_name: &str) -> String {
+    let mut name = String::new();
+    for c in project_name.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_end_matches('-');
+    if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.to_string()
+    } else {
+        format!("app{}{}", if name.is_empty() { "" } else { "-" }, name)
+    }
+}
+
+/**
+ * @brief Expands a bare NixOS release to its branch: "24.05" becomes "nixos-24.05".
+ * @param channel A release, a branch or a flake URL; anything but a release is returned unchanged.
+ */
+pub fn nixpkgs_branch(channel: &str) -> String {
+    let is_release = channel.split_once('.').is_some_and(|(year, month)| {
+        year.len() == 2
+            && month.len() == 2
+            && year
+                .chars()
+                .chain(month.chars())
+                .all(|c| c.is_ascii_digit())
+    });
+    if is_release {
+        format!("nixos-{}", channel)
+    } else {
+        channel.to_string()
+    }
+}
+
+/**
+ * @brief Turns a channel into the nixpkgs flake URL.
+ * @param channel A nixpkgs branch ("nixos-24.05", "nixpkgs-unstable") or a full flake URL.
+ * @return E.g. "github:NixOS/nixpkgs/nixos-24.05", or the URL unchanged.
+ */
+pub fn nixpkgs_url(channel: &str) -> String {
+    if channel.contains(':') {
+        channel.to_string()
+    } else {
+        format!("github:NixOS/nixpkgs/{}", channel)
+    }
+}
+
 /**
  * @brief Generates the content of the flake.nix file.
  *
@@ -11,26 +308,47 @@ pub const FLAKE_FILE: &str = "flake.nix";
  * the logic for handling packages and shell hooks as specified in the devshell.toml configuration.
  *
  * @param flake_inputs A map of input names to their corresponding URLs.
+ * @param config_file The configuration file name the flake reads (e.g. "devshell.toml").
+ * @param nixpkgs_url The URL of the nixpkgs input (see `nixpkgs_url`).
  * @return The generated flake.nix file content as a String.
  */
-pub fn generate_flake_nix(flake_inputs: &BTreeMap<String, String>) -> String {
+pub fn generate_flake_nix(
+    flake_inputs: &BTreeMap<String, String>,
+    config_file: &str,
+    nixpkgs_url: &str,
+) -> String {
+    let local_config_file = paths::local_file_name(config_file);
+
     let inputs_block = flake_inputs
         .iter()
-        .map(|(key, url)| format!("    {}.url = \"{}\";", key, url))
+        .map(|(key, url)| {
+            format!(
+                "    {}.url = {};",
+                nix_syntax::attr_name(key),
+                nix_syntax::string(url)
+            )
+        })
         .collect::<Vec<_>>()
         .join("\n");
 
+    // Keys that aren't identifiers can't be named as arguments; they are still
+    // reachable through `inputs`.
     let inputs_args = flake_inputs
         .keys()
+        .filter(|key| nix_syntax::is_identifier(key))
         .map(|key| format!(", {}", key))
         .collect::<String>();
+    let config_path = nix_syntax::relative_path(config_file);
+    let local_config_suffix = nix_syntax::string(&format!("/{}", local_config_file));
+    let local_config_comment = local_config_file.escape_debug();
+    let nixpkgs_url = nix_syntax::string(nixpkgs_url);
 
     formatdoc! {"
         {{
           description = \"A development shell generated by nix-shell-gen\";
 
           inputs = {{
-            nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";
+            nixpkgs.url = {nixpkgs_url};
             flake-utils.url = \"github:numtide/flake-utils\";
 
             # --- Generated Inputs ---
@@ -41,33 +359,821 @@ pub fn generate_flake_nix(flake_inputs: &BTreeMap<String, String>) -> String {
           outputs = {{ self, nixpkgs, flake-utils{inputs_args}, ... }}@inputs:
             flake-utils.lib.eachDefaultSystem (system:
               let
-                pkgs = nixpkgs.legacyPackages.${{system}};
-                config = builtins.fromTOML (builtins.readFile ./devshell.toml);
+                # Instantiating nixpkgs is only needed when overlays are used.
+                overlays = map (path:
+                  nixpkgs.lib.getAttrFromPath (nixpkgs.lib.splitString \".\" path) inputs
+                ) (config.overlays or []);
+                pkgs =
+                  if overlays == [] then nixpkgs.legacyPackages.${{system}}
+                  else import nixpkgs {{ inherit system overlays; }};
+                teamConfig = builtins.fromTOML (builtins.readFile {config_path});
+
+                # {local_config_comment} is gitignored, so it is only visible with --impure.
+                pwd = builtins.getEnv \"PWD\";
+                localConfigFile = pwd + {local_config_suffix};
+                localConfig =
+                  if pwd != \"\" && builtins.pathExists localConfigFile
+                  then builtins.fromTOML (builtins.readFile localConfigFile)
+                  else {{}};
+                config = teamConfig // localConfig // {{
+                  packages = (teamConfig.packages or []) ++ (localConfig.packages or []);
+                  overlays = (teamConfig.overlays or []) ++ (localConfig.overlays or []);
+                  local-packages = (teamConfig.local-packages or []) ++ (localConfig.local-packages or []);
+                  libraries = (teamConfig.libraries or []) ++ (localConfig.libraries or []);
+                  tools = (teamConfig.tools or []) ++ (localConfig.tools or []);
+                  platform-packages = nixpkgs.lib.zipAttrsWith (platform: lists: builtins.concatLists lists) [
+                    (teamConfig.platform-packages or {{}})
+                    (localConfig.platform-packages or {{}})
+                  ];
+                  inputs-from = (teamConfig.inputs-from or []) ++ (localConfig.inputs-from or []);
+                  env = (teamConfig.env or {{}}) // (localConfig.env or {{}});
+                  processes = (teamConfig.processes or {{}}) // (localConfig.processes or {{}});
+                  scripts = (teamConfig.scripts or {{}}) // (localConfig.scripts or {{}});
+                  python = (teamConfig.python or {{}}) // (localConfig.python or {{}}) // {{
+                    packages = (teamConfig.python.packages or []) ++ (localConfig.python.packages or []);
+                  }};
+                  node = (teamConfig.node or {{}}) // (localConfig.node or {{}}) // {{
+                    packages = (teamConfig.node.packages or []) ++ (localConfig.node.packages or []);
+                  }};
+                  ports = (teamConfig.ports or {{}}) // (localConfig.ports or {{}});
+                  hardening-disable = (teamConfig.hardening-disable or []) ++ (localConfig.hardening-disable or []);
+                  mk-shell = (teamConfig.mk-shell or {{}}) // (localConfig.mk-shell or {{}});
+                  hooks = hooksOf teamConfig ++ hooksOf localConfig;
+                }};
+                # [[hooks]] run in order, the team's first; older configs have one shell-hook string.
+                hooksOf = layer: layer.hooks or (nixpkgs.lib.optional (layer ? shell-hook) {{
+                  name = \"shell-hook\";
+                  run = layer.shell-hook;
+                }});
+
+                # --- Package Expressions ---
+                exprPackages = [
+                ];
+                # --- End of Package Expressions ---
 
-                pkgList = map (pkgName:
+                # local-packages: Nix files in the project, relative to it, built with callPackage.
+                localPackages = map (file:
+                  pkgs.callPackage (./. + \"/${{nixpkgs.lib.removePrefix \"./\" file}}\") {{ }}
+                ) (config.local-packages or []);
+
+                resolvePackages = map (pkgName:
                   let
                     interpolatedPkgName = builtins.replaceStrings [\"${{system}}\"] [system] pkgName;
-                    path = builtins.split \".\" interpolatedPkgName;
+                    # \"python3Packages.numpy\" -> [ \"python3Packages\" \"numpy\" ]
+                    path = nixpkgs.lib.splitString \".\" interpolatedPkgName;
                     inputName = builtins.head path;
                     isInput = builtins.hasAttr inputName inputs;
                   in
                     if isInput then
-                      let
-                        inputFlake = builtins.getAttr inputName inputs;
-                        attrPath = builtins.tail path;
-                      in
-                        builtins.getAttrByPath attrPath inputFlake
+                      nixpkgs.lib.getAttrFromPath (builtins.tail path) (builtins.getAttr inputName inputs)
+                    else
+                      nixpkgs.lib.getAttrFromPath path pkgs
+                );
+
+                # inputs-from: \"<input>#<attr>\" names a package of an input (nixpkgs: an attribute
+                # of pkgs); anything else is an output of this flake, e.g. \"packages.default\".
+                resolveInputsFrom = map (ref:
+                  let
+                    parts = nixpkgs.lib.splitString \"#\" ref;
+                    inputName = builtins.head parts;
+                    attrPath = nixpkgs.lib.splitString \".\" (nixpkgs.lib.last parts);
+                  in
+                    if builtins.length parts == 1 then
+                      nixpkgs.lib.getAttrFromPath ([ (builtins.head attrPath) system ] ++ builtins.tail attrPath) self
+                    else if inputName == \"nixpkgs\" then
+                      nixpkgs.lib.getAttrFromPath attrPath pkgs
+                    else
+                      nixpkgs.lib.getAttrFromPath attrPath (builtins.getAttr inputName inputs).packages.${{system}}
+                );
+
+                # [platform-packages]: \"linux\" and \"darwin\" follow stdenv, other keys name a system.
+                onPlatform = platform:
+                  if platform == \"linux\" then pkgs.stdenv.isLinux
+                  else if platform == \"darwin\" then pkgs.stdenv.isDarwin
+                  else platform == system;
+                platformPackages = byPlatform: builtins.concatLists (nixpkgs.lib.mapAttrsToList (platform: names:
+                  nixpkgs.lib.optionals (onPlatform platform) names
+                ) byPlatform);
+
+                # Named shells ([shells.<name>]), merged with the personal layer like the top level.
+                teamShells = teamConfig.shells or {{}};
+                localShells = localConfig.shells or {{}};
+                shells = nixpkgs.lib.genAttrs (builtins.attrNames (teamShells // localShells)) (name:
+                  let
+                    team = teamShells.${{name}} or {{}};
+                    local = localShells.${{name}} or {{}};
+                  in {{
+                    packages = (team.packages or []) ++ (local.packages or []);
+                    hooks = hooksOf team ++ hooksOf local;
+                  }});
+
+                # With NIX_SHELL_GEN_HOOK_TIMES naming a file (`nix-shell-gen bench --hooks`), each hook
+                # appends \"<name> <start> <end>\" to it, so slow hooks can be found.
+                timedHooks = hooks: builtins.concatStringsSep \"\" (map (hook: nixpkgs.lib.optionalString (hook.text != \"\") ''
+                  if [ -n \"''${{NIX_SHELL_GEN_HOOK_TIMES:-}}\" ]; then nix_shell_gen_started=$EPOCHREALTIME; fi
+                  ${{hook.text}}
+                  if [ -n \"''${{NIX_SHELL_GEN_HOOK_TIMES:-}}\" ]; then
+                    echo \"${{hook.name}} $nix_shell_gen_started $EPOCHREALTIME\" >> \"$NIX_SHELL_GEN_HOOK_TIMES\"
+                  fi
+                '') hooks);
+
+                # Lets prompts and tools detect the shell (see `nix-shell-gen current`).
+                metadataHook = name: ''
+                  export NIX_SHELL_GEN_SHELL=${{name}}
+                  NIX_SHELL_GEN_ROOT=\"$PWD\"
+                  while [ \"$NIX_SHELL_GEN_ROOT\" != / ] && [ ! -e \"$NIX_SHELL_GEN_ROOT/flake.nix\" ]; do
+                    NIX_SHELL_GEN_ROOT=\"$(dirname \"$NIX_SHELL_GEN_ROOT\")\"
+                  done
+                  export NIX_SHELL_GEN_ROOT
+                  export NIX_SHELL_GEN_ENTERED=\"$(date +%s)\"
+                '';
+
+                # libraries are linked against: buildInputs, found through pkg-config (whose hook also
+                # sees them) and PKG_CONFIG_PATH for build systems that read it directly. tools run
+                # during the build, so they go in nativeBuildInputs.
+                libraries = resolvePackages config.libraries;
+                tools = resolvePackages config.tools;
+                libraryHook = nixpkgs.lib.optionalString (libraries != []) ''
+                  export PKG_CONFIG_PATH=\"${{nixpkgs.lib.makeSearchPathOutput \"dev\" \"lib/pkgconfig\" libraries}}:${{nixpkgs.lib.makeSearchPathOutput \"dev\" \"share/pkgconfig\" libraries}}''${{PKG_CONFIG_PATH:+:$PKG_CONFIG_PATH}}\"
+                '';
+
+                # Pure shells don't see the system's CA bundle, so HTTPS needs cacert there.
+                useCacert = config.cacert or (config.pure or false);
+                cacertHook = if useCacert then ''
+                  export SSL_CERT_FILE=${{pkgs.cacert}}/etc/ssl/certs/ca-bundle.crt
+                  export NIX_SSL_CERT_FILE=\"$SSL_CERT_FILE\"
+                '' else \"\";
+
+                # [env] entries, exported before the user's hooks run. Values given as
+                # {{ command = \"...\"; }} are computed on entry; a failing command leaves the
+                # variable unset instead of aborting the shell. Names go into the hook as they
+                # are, so anything but a variable name is refused.
+                envHook = builtins.concatStringsSep \"\" (nixpkgs.lib.mapAttrsToList (name: value:
+                  if builtins.match \"[A-Za-z_][A-Za-z0-9_]*\" name == null then
+                    throw \"nix-shell-gen: [env] name ${{builtins.toJSON name}} is not a valid variable name\"
+                  else if builtins.isAttrs value then ''
+                    if ${{name}}=\"$(${{value.command}})\"; then
+                      export ${{name}}
                     else
-                      builtins.getAttr interpolatedPkgName pkgs
-                ) config.packages;
+                      unset ${{name}}
+                      echo \"nix-shell-gen: the command for ${{name}} failed\" >&2
+                    fi
+                  '' else ''
+                    export ${{name}}=${{nixpkgs.lib.escapeShellArg (toString value)}}
+                  ''
+                ) config.env);
+
+                # [ports]: each entry is exported as <NAME>_PORT, shifted by port-offset.
+                portOffset = config.port-offset or 0;
+                portHook = builtins.concatStringsSep \"\" (nixpkgs.lib.mapAttrsToList (name: port:
+                  nixpkgs.lib.optionalString (port + portOffset <= 65535) ''
+                    export ${{nixpkgs.lib.toUpper (builtins.replaceStrings [\"-\"] [\"_\"] name)}}_PORT=${{toString (port + portOffset)}}
+                  ''
+                ) (config.ports or {{}}));
+
+                # Opt-in reminder (update-reminder-days) when the locked nixpkgs gets old.
+                reminderDays = config.\"update-reminder-days\" or 0;
+                reminderHook = if reminderDays > 0 && nixpkgs ? lastModified then ''
+                  nix_shell_gen_age=$(( ($(date +%s) - ${{toString nixpkgs.lastModified}}) / 86400 ))
+                  if [ \"$nix_shell_gen_age\" -ge ${{toString reminderDays}} ]; then
+                    echo \"nixpkgs is $nix_shell_gen_age days old, run \\`nix-shell-gen update\\`\" >&2
+                  fi
+                  unset nix_shell_gen_age
+                '' else \"\";
+
+                # [processes]: `dev up` runs them together with process-compose, from the project root.
+                processes = config.processes or {{}};
+                processCompose = pkgs.writeText \"process-compose.yaml\" (builtins.toJSON {{
+                  version = \"0.5\";
+                  processes = builtins.mapAttrs (name: command: {{ inherit command; }}) processes;
+                }});
+                devScript = pkgs.writeShellScriptBin \"dev\" ''
+                  case \"''${{1:-}}\" in
+                    up)
+                      shift
+                      cd \"$NIX_SHELL_GEN_ROOT\" || exit 1
+                      exec ${{pkgs.process-compose}}/bin/process-compose up --config ${{processCompose}} \"$@\"
+                      ;;
+                    *)
+                      echo \"usage: dev up [process-compose options]\" >&2
+                      echo \"processes: ${{builtins.concatStringsSep \" \" (builtins.attrNames processes)}}\" >&2
+                      exit 1
+                      ;;
+                  esac
+                '';
+                processPackages = nixpkgs.lib.optionals (processes != {{}}) [ pkgs.process-compose devScript ];
+
+                # [scripts]: project tasks, each a command in the shell (`nix-shell-gen run <name>` runs them too).
+                scripts = config.scripts or {{}};
+                # A script is a command, or {{ command, depends-on }}; one without a command runs its
+                # dependencies in order. `nix-shell-gen run --all` runs dependencies side by side.
+                scriptCommand = script:
+                  if builtins.isString script then script
+                  else script.command or (builtins.concatStringsSep \"\\n\" (map (dep: \"${{dep}} || exit $?\") (script.depends-on or [])));
+                # Each is a shell application (strict mode, checked by shellcheck) with the default shell's
+                # packages on PATH, so `nix run .#<name>` works outside the shell too.
+                scriptInputs = resolvePackages (config.packages ++ platformPackages (config.platform-packages or {{}}))
+                  ++ libraries ++ tools ++ pythonEnv ++ nodeEnv ++ rustPackages ++ exprPackages ++ localPackages;
+                scriptApps = builtins.mapAttrs (name: script: pkgs.writeShellApplication {{
+                  inherit name;
+                  runtimeInputs = scriptInputs
+                    ++ map (dep: scriptApps.${{dep}}) (if builtins.isString script then [] else script.depends-on or []);
+                  text = scriptCommand script;
+                }}) scripts;
+                scriptPackages = builtins.attrValues scriptApps;
+                # Completion of script names after `nix-shell-gen run`, for bash, zsh and fish.
+                scriptNames = builtins.concatStringsSep \" \" (builtins.attrNames scripts);
+                scriptCompletions = pkgs.symlinkJoin {{
+                  name = \"nix-shell-gen-script-completions\";
+                  paths = [
+                    (pkgs.writeTextDir \"share/bash-completion/completions/nix-shell-gen\" ''
+                      _nix_shell_gen_scripts() {{
+                        if [ \"$COMP_CWORD\" -eq 2 ] && [ \"''${{COMP_WORDS[1]}}\" = run ]; then
+                          COMPREPLY=($(compgen -W \"${{scriptNames}}\" -- \"''${{COMP_WORDS[2]}}\"))
+                        fi
+                      }}
+                      complete -F _nix_shell_gen_scripts nix-shell-gen
+                    '')
+                    (pkgs.writeTextDir \"share/zsh/site-functions/_nix-shell-gen\" ''
+                      #compdef nix-shell-gen
+                      if (( CURRENT == 3 )) && [[ ''${{words[2]}} == run ]]; then
+                        compadd -- ${{scriptNames}}
+                      fi
+                    '')
+                    (pkgs.writeTextDir \"share/fish/vendor_completions.d/nix-shell-gen.fish\" ''
+                      complete -c nix-shell-gen -n \"__fish_seen_subcommand_from run\" -f -a \"${{scriptNames}}\"
+                    '')
+                  ];
+                }};
+                # nix develop runs bash, so its completion is sourced here. Through XDG_DATA_DIRS, fish finds
+                # its own when the environment is loaded with direnv; zsh users add
+                # $NIX_SHELL_GEN_COMPLETIONS/share/zsh/site-functions to their fpath.
+                scriptHook = nixpkgs.lib.optionalString (scripts != {{}}) ''
+                  export NIX_SHELL_GEN_COMPLETIONS=${{scriptCompletions}}
+                  export XDG_DATA_DIRS=\"${{scriptCompletions}}/share''${{XDG_DATA_DIRS:+:$XDG_DATA_DIRS}}\"
+                  if [ -n \"''${{BASH_VERSION:-}}\" ]; then
+                    source ${{scriptCompletions}}/share/bash-completion/completions/nix-shell-gen
+                  fi
+                '';
+                scriptTools = nixpkgs.lib.optionals (scripts != {{}}) (scriptPackages ++ [ scriptCompletions ]);
+
+                # [python]: one interpreter with the listed packages (withPackages), so they can be
+                # imported; `version` picks the interpreter, e.g. \"3.11\" for python311.
+                python = config.python;
+                usePython = python.packages != [] || python ? version;
+                pythonInterpreter =
+                  if python ? version
+                  then pkgs.${{\"python\" + builtins.replaceStrings [\".\"] [\"\"] python.version}}
+                  else pkgs.python3;
+                pythonEnv = nixpkgs.lib.optional usePython (pythonInterpreter.withPackages (ps:
+                  map (name: nixpkgs.lib.getAttrFromPath (nixpkgs.lib.splitString \".\" name) ps) python.packages
+                ));
+
+                # [node]: Node.js of a major version (\"20\" for nodejs_20), the package manager picked by
+                # `package-manager` and global packages from that Node's package set.
+                node = config.node;
+                useNode = node.packages != [] || node ? version || node ? package-manager;
+                nodeInterpreter = if node ? version then pkgs.${{\"nodejs_\" + node.version}} else pkgs.nodejs;
+                nodePackageSet = if node ? version then nodeInterpreter.pkgs else pkgs.nodePackages;
+                nodeManager = {{
+                  npm = [];
+                  pnpm = [ pkgs.pnpm ];
+                  yarn = [ (pkgs.yarn.override {{ nodejs = nodeInterpreter; }}) ];
+                  corepack = [ (if node ? version then pkgs.${{\"corepack_\" + node.version}} else pkgs.corepack) ];
+                }}.${{node.package-manager or \"npm\"}};
+                nodeEnv = nixpkgs.lib.optionals useNode (
+                  [ nodeInterpreter ] ++ nodeManager ++ map (name: nodePackageSet.${{name}}) node.packages
+                );
+
+                # rust-toolchain: Rust from rust-overlay instead of nixpkgs, in every shell. A \"./file\"
+                # is read the way rustup reads rust-toolchain.toml; anything else is a channel such as
+                # \"stable\", \"nightly-2025-01-01\" or \"1.78.0\".
+                rustChannel = config.rust-toolchain or null;
+                rustToolchain =
+                  let
+                    rustBin = (pkgs.extend inputs.rust-overlay.overlays.default).rust-bin;
+                    parts = builtins.match \"(stable|beta|nightly)(-(.*))?\" rustChannel;
+                    release = if parts == null then null else builtins.elemAt parts 2;
+                  in
+                    if nixpkgs.lib.hasPrefix \"./\" rustChannel then
+                      rustBin.fromRustupToolchainFile (./. + \"/${{nixpkgs.lib.removePrefix \"./\" rustChannel}}\")
+                    else if parts == null then rustBin.stable.${{rustChannel}}.default
+                    else if release == null then rustBin.${{builtins.head parts}}.latest.default
+                    else rustBin.${{builtins.head parts}}.${{release}}.default;
+                rustPackages = nixpkgs.lib.optional (rustChannel != null) rustToolchain;
+
+                # [mk-shell] attributes go in first, so they can't replace the generated ones.
+                mkDevShell = name: shell: pkgs.mkShell (config.mk-shell // {{
+                  hardeningDisable = config.hardening-disable;
+                  buildInputs = resolvePackages ((shell.packages or []) ++ platformPackages (shell.platform-packages or {{}}))
+                    ++ nixpkgs.lib.optional useCacert pkgs.cacert
+                    ++ nixpkgs.lib.optionals (name == \"default\") (exprPackages ++ localPackages)
+                    ++ processPackages
+                    ++ scriptTools
+                    ++ pythonEnv
+                    ++ nodeEnv
+                    ++ rustPackages
+                    ++ libraries;
+                  nativeBuildInputs = tools ++ nixpkgs.lib.optional (libraries != []) pkgs.pkg-config;
+                  inputsFrom = resolveInputsFrom (shell.inputs-from or []);
+                  shellHook = timedHooks ([
+                    {{ name = \"metadata\"; text = metadataHook name; }}
+                    {{ name = \"cacert\"; text = cacertHook; }}
+                    {{ name = \"libraries\"; text = libraryHook; }}
+                    {{ name = \"ports\"; text = portHook; }}
+                    {{ name = \"env\"; text = envHook; }}
+                    {{ name = \"reminder\"; text = reminderHook; }}
+                    {{ name = \"scripts\"; text = scriptHook; }}
+                  ] ++ map (hook: {{ name = \"hooks.${{hook.name}}\"; text = hook.run; }}) (shell.hooks or []));
+                  pure = if builtins.hasAttr \"pure\" config then config.pure else false;
+                }});
               in
               {{
-                devShells.default = pkgs.mkShell {{
-                  buildInputs = pkgList;
-                  shellHook = if builtins.hasAttr \"shell-hook\" config then config.\"shell-hook\" else \"\";
-                  pure = if builtins.hasAttr \"pure\" config then config.pure else false;
+                devShells = builtins.mapAttrs mkDevShell shells // {{
+                  default = mkDevShell \"default\" config;
                 }};
+                # `nix run .#<script>`
+                apps = builtins.mapAttrs (name: app: {{
+                  type = \"app\";
+                  program = \"${{app}}/bin/${{name}}\";
+                }}) scriptApps;
               }});
         }}
     "}
 }
+
+/// @brief Opens the block generated flake.nix and shell.nix files keep `expr-packages` in.
+const EXPR_PACKAGES_START: &str = "# --- Package Expressions ---";
+/// @brief Closes the `expr-packages` block.
+const EXPR_PACKAGES_END: &str = "# --- End of Package Expressions ---";
+
+/**
+ * @brief Writes the `expr-packages` of the config into the marked block of a generated file.
+ *
+ * Nix can't evaluate expressions read from TOML, so unlike everything else in the config
+ * they are copied into flake.nix (or shell.nix) itself. Each one is parenthesized, so a
+ * function call stays a single list element.
+ *
+ * @param content The generated flake.nix or shell.nix.
+ * @param exprs The expressions, verbatim.
+ * @return The updated content, or None if the file has no block (it predates `expr-packages`).
+ */
+pub fn set_expr_packages(content: &str, exprs: &BTreeSet<String>) -> Option<String> {
+    let start = content.find(EXPR_PACKAGES_START)?;
+    let indent = &content[content[..start].rfind('\n').map_or(0, |i| i + 1)..start];
+    let body_start = start + content[start..].find('\n')? + 1;
+    let end = body_start + content[body_start..].find(EXPR_PACKAGES_END)?;
+    let body_end = content[..end].rfind('\n')? + 1;
+
+    let mut body = format!("{}exprPackages = [\n", indent);
+    for expr in exprs {
+        body.push_str(&format!("{}  ({})\n", indent, expr.trim()));
+    }
+    body.push_str(&format!("{}];\n", indent));
+    Some(format!(
+        "{}{}{}",
+        &content[..body_start],
+        body,
+        &content[body_end..]
+    ))
+}
+
+/// @brief The file `init --format shell-nix` generates instead of flake.nix.
+pub const SHELL_NIX_FILE: &str = "shell.nix";
+
+/**
+ * @struct NixpkgsPin
+ * @brief The nixpkgs tarball a generated shell.nix is pinned to.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct NixpkgsPin {
+    /// @brief The tarball URL, e.g. `https://github.com/NixOS/nixpkgs/archive/<rev>.tar.gz`.
+    pub url: String,
+    /// @brief The unpacked hash (`nix-prefetch-url --unpack`), when known.
+    pub sha256: Option<String>,
+}
+
+impl NixpkgsPin {
+    /**
+     * @brief Reads the pin back from a shell.nix generated by `generate_shell_nix`.
+     * @param content The shell.nix source.
+     * @return The pin, or None if the file wasn't generated by this tool.
+     */
+    pub fn read(content: &str) -> Option<Self> {
+        let value = |key: &str| {
+            content.lines().find_map(|line| {
+                let rest = line.trim().strip_prefix(key)?.trim_start();
+                let rest = rest.strip_prefix('=')?.trim().strip_suffix(';')?;
+                Some(rest.trim_matches('"').to_string())
+            })
+        };
+        Some(NixpkgsPin {
+            url: value("url")?,
+            sha256: value("sha256"),
+        })
+    }
+}
+
+/**
+ * @brief Generates a classic shell.nix reading the same config as the flake.
+ *
+ * For machines without flakes: nixpkgs comes from a pinned tarball instead of
+ * flake.lock, and only nixpkgs packages are available (no flake inputs, overlays
+ * or named shells).
+ *
+ * @param config_file The configuration file name (e.g. "devshell.toml").
+ * @param pin The nixpkgs tarball to use.
+ * @return The generated shell.nix content.
+ */
+pub fn generate_shell_nix(config_file: &str, pin: &NixpkgsPin) -> String {
+    let local_config_file = paths::local_file_name(config_file);
+    let config_path = nix_syntax::relative_path(config_file);
+    let local_path = nix_syntax::relative_path(&local_config_file);
+    let config_comment = config_file.escape_debug();
+    let local_comment = local_config_file.escape_debug();
+    let pin_url = nix_syntax::string(&pin.url);
+    let pin_sha256 = match &pin.sha256 {
+        Some(hash) => format!("\n    sha256 = {};", nix_syntax::string(hash)),
+        None => String::new(),
+    };
+
+    formatdoc! {"
+        # Generated by nix-shell-gen for Nix without flakes; edit {config_comment} instead.
+        # `nix-shell-gen sync` regenerates this file and keeps the nixpkgs pin.
+        let
+          nixpkgs = fetchTarball {{
+            url = {pin_url};{pin_sha256}
+          }};
+          pkgs = import nixpkgs {{ }};
+          lib = pkgs.lib;
+          system = pkgs.stdenv.hostPlatform.system;
+
+          teamConfig = builtins.fromTOML (builtins.readFile {config_path});
+          # {local_comment} is gitignored; nix-shell evaluates impurely, so it is always read.
+          localConfigFile = {local_path};
+          localConfig =
+            if builtins.pathExists localConfigFile
+            then builtins.fromTOML (builtins.readFile localConfigFile)
+            else {{}};
+          config = teamConfig // localConfig // {{
+            packages = (teamConfig.packages or []) ++ (localConfig.packages or []);
+            local-packages = (teamConfig.local-packages or []) ++ (localConfig.local-packages or []);
+            libraries = (teamConfig.libraries or []) ++ (localConfig.libraries or []);
+            tools = (teamConfig.tools or []) ++ (localConfig.tools or []);
+            platform-packages = lib.zipAttrsWith (platform: lists: builtins.concatLists lists) [
+              (teamConfig.platform-packages or {{}})
+              (localConfig.platform-packages or {{}})
+            ];
+            env = (teamConfig.env or {{}}) // (localConfig.env or {{}});
+            ports = (teamConfig.ports or {{}}) // (localConfig.ports or {{}});
+            scripts = (teamConfig.scripts or {{}}) // (localConfig.scripts or {{}});
+            python = (teamConfig.python or {{}}) // (localConfig.python or {{}}) // {{
+              packages = (teamConfig.python.packages or []) ++ (localConfig.python.packages or []);
+            }};
+            node = (teamConfig.node or {{}}) // (localConfig.node or {{}}) // {{
+              packages = (teamConfig.node.packages or []) ++ (localConfig.node.packages or []);
+            }};
+            hardening-disable = (teamConfig.hardening-disable or []) ++ (localConfig.hardening-disable or []);
+            mk-shell = (teamConfig.mk-shell or {{}}) // (localConfig.mk-shell or {{}});
+            hooks = hooksOf teamConfig ++ hooksOf localConfig;
+          }};
+          # [[hooks]] run in order, the team's first; older configs have one shell-hook string.
+          hooksOf = layer: layer.hooks or (lib.optional (layer ? shell-hook) {{
+            name = \"shell-hook\";
+            run = layer.shell-hook;
+          }});
+
+          # --- Package Expressions ---
+          exprPackages = [
+          ];
+          # --- End of Package Expressions ---
+
+          localPackages = map (file:
+            pkgs.callPackage (./. + \"/${{lib.removePrefix \"./\" file}}\") {{ }}
+          ) config.local-packages;
+
+          # Without flake inputs, every package is an attribute path in nixpkgs.
+          resolvePackages = map (pkgName: lib.getAttrFromPath (lib.splitString \".\" pkgName) pkgs);
+
+          onPlatform = platform:
+            if platform == \"linux\" then pkgs.stdenv.isLinux
+            else if platform == \"darwin\" then pkgs.stdenv.isDarwin
+            else platform == system;
+          platformPackages = builtins.concatLists (lib.mapAttrsToList (platform: names:
+            lib.optionals (onPlatform platform) names
+          ) config.platform-packages);
+
+          # libraries are linked against: buildInputs, found through pkg-config (whose hook also
+          # sees them) and PKG_CONFIG_PATH for build systems that read it directly. tools run
+          # during the build, so they go in nativeBuildInputs.
+          libraries = resolvePackages config.libraries;
+          tools = resolvePackages config.tools;
+          libraryHook = lib.optionalString (libraries != []) ''
+            export PKG_CONFIG_PATH=\"${{lib.makeSearchPathOutput \"dev\" \"lib/pkgconfig\" libraries}}:${{lib.makeSearchPathOutput \"dev\" \"share/pkgconfig\" libraries}}''${{PKG_CONFIG_PATH:+:$PKG_CONFIG_PATH}}\"
+          '';
+
+          # With NIX_SHELL_GEN_HOOK_TIMES naming a file (`nix-shell-gen bench --hooks`), each hook
+          # appends \"<name> <start> <end>\" to it, so slow hooks can be found.
+          timedHooks = hooks: builtins.concatStringsSep \"\" (map (hook: lib.optionalString (hook.text != \"\") ''
+            if [ -n \"''${{NIX_SHELL_GEN_HOOK_TIMES:-}}\" ]; then nix_shell_gen_started=$EPOCHREALTIME; fi
+            ${{hook.text}}
+            if [ -n \"''${{NIX_SHELL_GEN_HOOK_TIMES:-}}\" ]; then
+              echo \"${{hook.name}} $nix_shell_gen_started $EPOCHREALTIME\" >> \"$NIX_SHELL_GEN_HOOK_TIMES\"
+            fi
+          '') hooks);
+
+          useCacert = config.cacert or (config.pure or false);
+          cacertHook = if useCacert then ''
+            export SSL_CERT_FILE=${{pkgs.cacert}}/etc/ssl/certs/ca-bundle.crt
+            export NIX_SSL_CERT_FILE=\"$SSL_CERT_FILE\"
+          '' else \"\";
+
+          portOffset = config.port-offset or 0;
+          portHook = builtins.concatStringsSep \"\" (lib.mapAttrsToList (name: port:
+            lib.optionalString (port + portOffset <= 65535) ''
+              export ${{lib.toUpper (builtins.replaceStrings [\"-\"] [\"_\"] name)}}_PORT=${{toString (port + portOffset)}}
+            ''
+          ) config.ports);
+
+          envHook = builtins.concatStringsSep \"\" (lib.mapAttrsToList (name: value:
+            if builtins.match \"[A-Za-z_][A-Za-z0-9_]*\" name == null then
+              throw \"nix-shell-gen: [env] name ${{builtins.toJSON name}} is not a valid variable name\"
+            else if builtins.isAttrs value then ''
+              if ${{name}}=\"$(${{value.command}})\"; then
+                export ${{name}}
+              else
+                unset ${{name}}
+                echo \"nix-shell-gen: the command for ${{name}} failed\" >&2
+              fi
+            '' else ''
+              export ${{name}}=${{lib.escapeShellArg (toString value)}}
+            ''
+          ) config.env);
+
+          # [scripts]: project tasks, each a command in the shell (`nix-shell-gen run <name>` runs them too).
+          scripts = config.scripts;
+          # A script is a command, or {{ command, depends-on }}; one without a command runs its
+          # dependencies in order. `nix-shell-gen run --all` runs dependencies side by side.
+          scriptCommand = script:
+            if builtins.isString script then script
+            else script.command or (builtins.concatStringsSep \"\\n\" (map (dep: \"${{dep}} || exit $?\") (script.depends-on or [])));
+          # Each is a shell application (strict mode, checked by shellcheck) with the shell's packages on PATH.
+          scriptInputs = resolvePackages (config.packages ++ platformPackages)
+            ++ libraries ++ tools ++ pythonEnv ++ nodeEnv ++ exprPackages ++ localPackages;
+          scriptApps = builtins.mapAttrs (name: script: pkgs.writeShellApplication {{
+            inherit name;
+            runtimeInputs = scriptInputs
+              ++ map (dep: scriptApps.${{dep}}) (if builtins.isString script then [] else script.depends-on or []);
+            text = scriptCommand script;
+          }}) scripts;
+          scriptPackages = builtins.attrValues scriptApps;
+          # Completion of script names after `nix-shell-gen run`, for bash, zsh and fish.
+          scriptNames = builtins.concatStringsSep \" \" (builtins.attrNames scripts);
+          scriptCompletions = pkgs.symlinkJoin {{
+            name = \"nix-shell-gen-script-completions\";
+            paths = [
+              (pkgs.writeTextDir \"share/bash-completion/completions/nix-shell-gen\" ''
+                _nix_shell_gen_scripts() {{
+                  if [ \"$COMP_CWORD\" -eq 2 ] && [ \"''${{COMP_WORDS[1]}}\" = run ]; then
+                    COMPREPLY=($(compgen -W \"${{scriptNames}}\" -- \"''${{COMP_WORDS[2]}}\"))
+                  fi
+                }}
+                complete -F _nix_shell_gen_scripts nix-shell-gen
+              '')
+              (pkgs.writeTextDir \"share/zsh/site-functions/_nix-shell-gen\" ''
+                #compdef nix-shell-gen
+                if (( CURRENT == 3 )) && [[ ''${{words[2]}} == run ]]; then
+                  compadd -- ${{scriptNames}}
+                fi
+              '')
+              (pkgs.writeTextDir \"share/fish/vendor_completions.d/nix-shell-gen.fish\" ''
+                complete -c nix-shell-gen -n \"__fish_seen_subcommand_from run\" -f -a \"${{scriptNames}}\"
+              '')
+            ];
+          }};
+          # nix develop runs bash, so its completion is sourced here. Through XDG_DATA_DIRS, fish finds
+          # its own when the environment is loaded with direnv; zsh users add
+          # $NIX_SHELL_GEN_COMPLETIONS/share/zsh/site-functions to their fpath.
+          scriptHook = lib.optionalString (scripts != {{}}) ''
+            export NIX_SHELL_GEN_COMPLETIONS=${{scriptCompletions}}
+            export XDG_DATA_DIRS=\"${{scriptCompletions}}/share''${{XDG_DATA_DIRS:+:$XDG_DATA_DIRS}}\"
+            if [ -n \"''${{BASH_VERSION:-}}\" ]; then
+              source ${{scriptCompletions}}/share/bash-completion/completions/nix-shell-gen
+            fi
+          '';
+          scriptTools = lib.optionals (scripts != {{}}) (scriptPackages ++ [ scriptCompletions ]);
+
+          # [python]: one interpreter with the listed packages (withPackages), so they can be
+          # imported; `version` picks the interpreter, e.g. \"3.11\" for python311.
+          python = config.python;
+          usePython = python.packages != [] || python ? version;
+          pythonInterpreter =
+            if python ? version
+            then pkgs.${{\"python\" + builtins.replaceStrings [\".\"] [\"\"] python.version}}
+            else pkgs.python3;
+          pythonEnv = lib.optional usePython (pythonInterpreter.withPackages (ps:
+            map (name: lib.getAttrFromPath (lib.splitString \".\" name) ps) python.packages
+          ));
+
+          # [node]: Node.js of a major version (\"20\" for nodejs_20), the package manager picked by
+          # `package-manager` and global packages from that Node's package set.
+          node = config.node;
+          useNode = node.packages != [] || node ? version || node ? package-manager;
+          nodeInterpreter = if node ? version then pkgs.${{\"nodejs_\" + node.version}} else pkgs.nodejs;
+          nodePackageSet = if node ? version then nodeInterpreter.pkgs else pkgs.nodePackages;
+          nodeManager = {{
+            npm = [];
+            pnpm = [ pkgs.pnpm ];
+            yarn = [ (pkgs.yarn.override {{ nodejs = nodeInterpreter; }}) ];
+            corepack = [ (if node ? version then pkgs.${{\"corepack_\" + node.version}} else pkgs.corepack) ];
+          }}.${{node.package-manager or \"npm\"}};
+          nodeEnv = lib.optionals useNode (
+            [ nodeInterpreter ] ++ nodeManager ++ map (name: nodePackageSet.${{name}}) node.packages
+          );
+        in
+        pkgs.mkShell (config.mk-shell // {{
+          hardeningDisable = config.hardening-disable;
+          buildInputs = resolvePackages (config.packages ++ platformPackages)
+            ++ lib.optional useCacert pkgs.cacert
+            ++ exprPackages
+            ++ localPackages
+            ++ scriptTools
+            ++ pythonEnv
+            ++ nodeEnv
+            ++ libraries;
+          nativeBuildInputs = tools ++ lib.optional (libraries != []) pkgs.pkg-config;
+          shellHook = timedHooks ([
+            {{ name = \"metadata\"; text = ''
+              export NIX_SHELL_GEN_SHELL=default
+              export NIX_SHELL_GEN_ROOT=${{toString ./.}}
+              export NIX_SHELL_GEN_ENTERED=\"$(date +%s)\"
+            ''; }}
+            {{ name = \"cacert\"; text = cacertHook; }}
+            {{ name = \"libraries\"; text = libraryHook; }}
+            {{ name = \"ports\"; text = portHook; }}
+            {{ name = \"env\"; text = envHook; }}
+            {{ name = \"scripts\"; text = scriptHook; }}
+          ] ++ map (hook: {{ name = \"hooks.${{hook.name}}\"; text = hook.run; }}) config.hooks);
+        }})
+    "}
+}
+
+/// @brief Where `devcontainer` writes the container definition.
+pub const DEVCONTAINER_FILE: &str = ".devcontainer/devcontainer.json";
+
+/**
+ * @brief Generates a devcontainer.json whose terminals open inside the dev shell.
+ *
+ * Nix comes from the devcontainers Nix feature on a stock base image, so no
+ * Dockerfile is needed. The shell is built once when the container is created;
+ * `[ports]` are forwarded (without the personal port-offset, which is per machine).
+ *
+ * @param name The container's display name, usually the project directory.
+ * @param config The team config.
+ * @param shell A named shell for the terminals, or None for the default one.
+ * @return The devcontainer.json content.
+ */
+pub fn generate_devcontainer(name: &str, config: &DevShellConfig, shell: Option<&str>) -> String {
+    let (enter, prebuild): (Vec<String>, String) = match config.shell_format() {
+        ShellFormat::Flake => {
+            let mut enter = vec!["develop".to_string()];
+            if let Some(shell) = shell {
+                enter.push(format!(".#{}", shell));
+            }
+            let prebuild = format!("nix {} --command true", enter.join(" "));
+            (enter, prebuild)
+        }
+        ShellFormat::ShellNix => (Vec::new(), "nix-shell --run true".to_string()),
+    };
+    let program = match config.shell_format() {
+        ShellFormat::Flake => "nix",
+        ShellFormat::ShellNix => "nix-shell",
+    };
+
+    let mut devcontainer = serde_json::json!({
+        "name": name,
+        "image": "mcr.microsoft.com/devcontainers/base:ubuntu",
+        "features": {
+            "ghcr.io/devcontainers/features/nix:1": {
+                "extraNixConfig": "experimental-features = nix-command flakes"
+            }
+        },
+        "postCreateCommand": prebuild,
+        "customizations": {
+            "vscode": {
+                "extensions": ["jnoortheen.nix-ide"],
+                "settings": {
+                    "terminal.integrated.profiles.linux": {
+                        "dev shell": { "path": program, "args": enter }
+                    },
+                    "terminal.integrated.defaultProfile.linux": "dev shell"
+                }
+            }
+        }
+    });
+    if !config.ports.is_empty() {
+        devcontainer["forwardPorts"] = config.ports.values().copied().collect();
+    }
+    format!("{:#}\n", devcontainer)
+}
+
+/// @brief Where `export bootstrap` writes the onboarding script.
+pub const BOOTSTRAP_FILE: &str = "bootstrap.sh";
+
+/// @brief The Nix installer a bootstrap script runs where Nix is missing.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NixInstaller {
+    /// @brief Determinate Systems' installer, which enables flakes and can uninstall.
+    Determinate,
+    /// @brief The nixos.org installer, as a multi-user (daemon) install.
+    Upstream,
+}
+
+/**
+ * @brief Generates the onboarding script of `export bootstrap`.
+ *
+ * The POSIX sh script installs Nix where it is missing, adds the `[substituters]`
+ * to the system nix.conf (the daemon only uses caches configured there, so that
+ * step asks for sudo) and enters the dev shell. Steps that are done already are
+ * skipped, so it is safe to run again.
+ *
+ * @param config The team config.
+ * @param shell A named shell to enter, or None for the default one.
+ * @param installer The installer to use where Nix is missing.
+ * @return The script.
+ */
+pub fn generate_bootstrap_script(
+    config: &DevShellConfig,
+    shell: Option<&str>,
+    installer: NixInstaller,
+) -> String {
+    let (installer_name, install) = match installer {
+        NixInstaller::Determinate => (
+            "the Determinate Systems installer",
+            "curl --proto '=https' --tlsv1.2 -sSf -L https://install.determinate.systems/nix | sh -s -- install --no-confirm",
+        ),
+        NixInstaller::Upstream => (
+            "the nixos.org installer",
+            "curl --proto '=https' --tlsv1.2 -sSf -L https://nixos.org/nix/install | sh -s -- --daemon --yes",
+        ),
+    };
+
+    let caches = if config.substituters.is_empty() {
+        String::new()
+    } else {
+        let urls: Vec<&str> = config.substituters.keys().map(String::as_str).collect();
+        let keys: Vec<&str> = config.substituters.values().map(String::as_str).collect();
+        let substituters = shell_quote(&format!("extra-substituters = {}", urls.join(" ")));
+        let trusted_keys = shell_quote(&format!("extra-trusted-public-keys = {}", keys.join(" ")));
+        formatdoc! {"
+
+            # The project's binary caches. The daemon only uses caches from the system
+            # nix.conf and reads it on start; Determinate's installer keeps additions in
+            # nix.custom.conf.
+            conf=/etc/nix/nix.conf
+            if [ -e /etc/nix/nix.custom.conf ]; then conf=/etc/nix/nix.custom.conf; fi
+            if ! grep -qxF {substituters} \"$conf\" 2>/dev/null; then
+              echo \"Adding the project's binary caches to $conf (needs sudo)...\"
+              printf '%s\\n' {substituters} {trusted_keys} | sudo tee -a \"$conf\" >/dev/null
+              if command -v systemctl >/dev/null 2>&1; then
+                sudo systemctl restart nix-daemon
+              elif command -v launchctl >/dev/null 2>&1; then
+                sudo launchctl kickstart -k system/systems.determinate.nix-daemon 2>/dev/null ||
+                  sudo launchctl kickstart -k system/org.nixos.nix-daemon
+              fi
+            fi
+        "}
+    };
+
+    let enter = match config.shell_format() {
+        ShellFormat::Flake => {
+            let mut enter =
+                "exec nix --extra-experimental-features 'nix-command flakes' develop".to_string();
+            if let Some(shell) = shell {
+                enter.push_str(&format!(" .#{}", shell));
+            }
+            enter
+        }
+        ShellFormat::ShellNix => "exec nix-shell".to_string(),
+    };
+
+    formatdoc! {"
+        #!/bin/sh
+        # Generated by nix-shell-gen (`nix-shell-gen export bootstrap`). Installs Nix if needed,
+        # sets up the project's binary caches and enters the dev shell: sh {BOOTSTRAP_FILE}
+        set -eu
+        cd \"$(dirname \"$0\")\"
+
+        profile=/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh
+        if ! command -v nix >/dev/null 2>&1; then
+          if [ ! -e \"$profile\" ]; then
+            echo \"Installing Nix with {installer_name}...\"
+            {install}
+          fi
+          . \"$profile\"
+        fi
+        {caches}
+        echo \"Entering the dev shell (the first time downloads its packages)...\"
+        {enter}
+    "}
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::{PermissionsExt, symlink};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::messages;
+use crate::process::Process;
+
+/// @brief Records what `vendor` changed, so `unvendor` can undo it. Tracked in git.
+pub const VENDOR_RECORD: &str = ".nix-shell-gen/vendored.toml";
+
+/**
+ * @struct VendorRecord
+ * @brief The inputs `vendor` copied into the project and what they pointed at before.
+ */
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct VendorRecord {
+    /// @brief The directory the inputs were copied into, relative to the project.
+    pub dir: PathBuf,
+
+    /// @brief The original URL of each vendored input, by input key.
+    pub inputs: BTreeMap<String, String>,
+
+    /// @brief flake.lock as it was before vendoring, restored by `unvendor`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<String>,
+}
+
+impl VendorRecord {
+    /**
+     * @brief Reads the record, if the project is vendored.
+     * @return Result<Option<Self>, Error> Returns an Error if the record exists but can't be read or parsed.
+     */
+    pub fn load() -> Result<Option<Self>, Error> {
+        match fs::read_to_string(VENDOR_RECORD) {
+            Ok(contents) => toml::from_str(&contents).map(Some).map_err(|e| {
+                messages::error(
+                    ErrorKind::InvalidData,
+                    messages::E_INVALID_CONFIG,
+                    format!("{}: {}", VENDOR_RECORD, e),
+                )
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// @brief Serializes the record to TOML.
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string_pretty(self).map_err(Error::other)
+    }
+}
+
+/**
+ * @brief Fetches the locked sources of the flake's inputs.
+ *
+ * Runs `nix flake archive --json`, which fetches exactly what flake.lock pins and
+ * reports the store path of every input.
+ *
+ * @param timeout Timeout for the nix call.
+ * @return The store path of each direct input, by input key.
+ */
+pub fn fetch_inputs(timeout: Option<Duration>) -> Result<BTreeMap<String, PathBuf>, Error> {
+    let output = Process::nix(["flake", "archive", "--json"])
+        .timeout(timeout)
+        .output()?;
+    let archive: Value = serde_json::from_slice(&output).map_err(Error::other)?;
+    Ok(archive
+        .get("inputs")
+        .and_then(Value::as_object)
+        .map(|inputs| {
+            inputs
+                .iter()
+                .filter_map(|(key, input)| {
+                    let path = input.get("path")?.as_str()?;
+                    Some((key.clone(), PathBuf::from(path)))
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/**
+ * @brief Copies fetched sources into the vendor directory, all or none.
+ *
+ * The sources are copied into a staging directory next to `dir` first and only
+ * renamed into place once every copy succeeded, so a failed copy leaves no
+ * half-vendored tree behind.
+ *
+ * @param sources The store path of each input to copy, by input key.
+ * @param dir The vendor directory; each input ends up in `dir/<key>`.
+ * @return Result<(), Error> Returns an Error if a source can't be copied or moved into place.
+ */
+pub fn copy_sources(sources: &BTreeMap<String, PathBuf>, dir: &Path) -> Result<(), Error> {
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "vendor".to_string());
+    let staging = dir.with_file_name(format!(".{}.partial", name));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    let copied = sources
+        .iter()
+        .try_for_each(|(key, from)| copy_source(from, &staging.join(key)));
+    if let Err(e) = copied {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    if !dir.exists() {
+        return fs::rename(&staging, dir);
+    }
+    for key in sources.keys() {
+        let target = dir.join(key);
+        match fs::symlink_metadata(&target) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&target)?,
+            Ok(_) => fs::remove_file(&target)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        fs::rename(staging.join(key), target)?;
+    }
+    fs::remove_dir(&staging)
+}
+
+/**
+ * @brief Copies a fetched source into the project, replacing an older copy.
+ *
+ * Store files are read-only; the copies are made writable so they can be
+ * deleted and updated like any other file in the repository.
+ *
+ * @param from The store path.
+ * @param to The destination directory.
+ * @return Result<(), Error> Returns an Error if a file can't be copied.
+ */
+fn copy_source(from: &Path, to: &Path) -> Result<(), Error> {
+    if to.exists() {
+        fs::remove_dir_all(to)?;
+    }
+    // Non-flake inputs can be a single file.
+    if from.is_file() {
+        if let Some(dir) = to.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        return fs::copy(from, to).map(|_| ());
+    }
+    copy_tree(from, to)
+}
+
+/// @brief Recursively copies a directory, keeping symlinks as links.
+fn copy_tree(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+            let mut permissions = fs::metadata(&target)?.permissions();
+            permissions.set_mode(permissions.mode() | 0o200);
+            fs::set_permissions(&target, permissions)?;
+        }
+    }
+    Ok(())
+}
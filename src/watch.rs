@@ -0,0 +1,103 @@
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::messages::{self, sayln};
+use crate::process;
+
+/// @brief How long the files must stay unchanged before the task runs again, so a
+/// save that touches several files runs it once.
+const SETTLE: Duration = Duration::from_millis(200);
+
+/**
+ * @brief Runs a task, then again whenever files under the current directory that
+ *        match one of the patterns change, until Ctrl-C.
+ *
+ * Changes made while the task runs are ignored, so a task that writes files the
+ * patterns match doesn't keep restarting itself. Ctrl-C while the task runs stops
+ * the task and the watching both.
+ *
+ * @param patterns Globs, relative to the current directory, e.g. "*.py".
+ * @param task Runs the task once; it reports its own failures.
+ * @return Result<(), Error> An Error if a pattern is invalid, the files can't be watched,
+ *         or Interrupted after Ctrl-C during the task.
+ */
+pub fn watch(patterns: &[String], mut task: impl FnMut()) -> Result<(), Error> {
+    let globs = glob_set(patterns)?;
+    let root = std::env::current_dir()?;
+
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(tx).map_err(Error::other)?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(Error::other)?;
+
+    let matches = |event: &notify::Result<Event>| match event {
+        Ok(event) => {
+            !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|path| {
+                    path.strip_prefix(&root)
+                        .is_ok_and(|relative| globs.is_match(relative))
+                })
+        }
+        Err(_) => false,
+    };
+
+    loop {
+        task();
+        if process::take_interrupted() {
+            return Err(messages::error(
+                ErrorKind::Interrupted,
+                messages::E_INTERRUPTED,
+                "Stopped watching after Ctrl-C",
+            ));
+        }
+        rx.try_iter().for_each(drop);
+        sayln!(
+            "Watching {} for changes (Ctrl-C to stop)...",
+            patterns.join(", ")
+        );
+
+        loop {
+            let event = rx
+                .recv()
+                .map_err(|_| Error::other("the file watcher stopped"))?;
+            if matches(&event) {
+                break;
+            }
+        }
+        loop {
+            match rx.recv_timeout(SETTLE) {
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::other("the file watcher stopped"));
+                }
+            }
+        }
+    }
+}
+
+/**
+ * @brief Compiles the patterns of `--watch`.
+ * @param patterns The globs.
+ */
+fn glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+        let glob = Glob::new(pattern).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid --watch pattern '{}': {}", pattern, e),
+            )
+        })?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}